@@ -0,0 +1,25 @@
+ALTER TABLE submissions
+DROP COLUMN accepted;
+
+ALTER TABLE submissions
+DROP COLUMN created_at;
+
+ALTER TABLE users
+DROP COLUMN is_admin;
+
+ALTER TABLE contests
+DROP COLUMN penalty_minutes;
+
+ALTER TABLE contests
+DROP COLUMN freeze_minutes;
+
+ALTER TABLE contests
+DROP COLUMN end_time;
+
+ALTER TABLE contests
+DROP COLUMN start_time;
+
+ALTER TABLE contests
+DROP COLUMN format;
+
+DROP TYPE contest_format;
@@ -0,0 +1,27 @@
+CREATE TYPE contest_format AS ENUM (
+  'ioi',
+  'icpc');
+
+ALTER TABLE contests
+ADD COLUMN format contest_format NOT NULL DEFAULT 'ioi';
+
+ALTER TABLE contests
+ADD COLUMN start_time TIMESTAMP DEFAULT NULL;
+
+ALTER TABLE contests
+ADD COLUMN end_time TIMESTAMP DEFAULT NULL;
+
+ALTER TABLE contests
+ADD COLUMN freeze_minutes INTEGER NOT NULL DEFAULT 60 CHECK(freeze_minutes >= 0);
+
+ALTER TABLE contests
+ADD COLUMN penalty_minutes INTEGER NOT NULL DEFAULT 20 CHECK(penalty_minutes >= 0);
+
+ALTER TABLE users
+ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE submissions
+ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc');
+
+ALTER TABLE submissions
+ADD COLUMN accepted BOOLEAN DEFAULT NULL;
@@ -0,0 +1,10 @@
+DELETE FROM participations WHERE practice;
+
+DROP INDEX participations_contest_user_practice_unique;
+CREATE UNIQUE INDEX participations_contest_user_unique ON participations(contest_id, user_id);
+
+ALTER TABLE participations
+DROP COLUMN practice;
+
+ALTER TABLE tasks
+DROP COLUMN archived;
@@ -0,0 +1,8 @@
+ALTER TABLE tasks
+ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE participations
+ADD COLUMN practice BOOLEAN NOT NULL DEFAULT FALSE;
+
+DROP INDEX participations_contest_user_unique;
+CREATE UNIQUE INDEX participations_contest_user_practice_unique ON participations(contest_id, user_id, practice);
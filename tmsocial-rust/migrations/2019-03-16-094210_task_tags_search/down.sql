@@ -0,0 +1,11 @@
+DROP INDEX tasks_search_idx;
+DROP FUNCTION task_search_document(VARCHAR, TEXT ARRAY, TEXT);
+
+ALTER TABLE tasks
+DROP COLUMN statement;
+
+ALTER TABLE tasks
+DROP COLUMN difficulty;
+
+ALTER TABLE tasks
+DROP COLUMN tags;
@@ -0,0 +1,20 @@
+ALTER TABLE tasks
+ADD COLUMN tags TEXT ARRAY NOT NULL DEFAULT '{}';
+
+ALTER TABLE tasks
+ADD COLUMN difficulty INTEGER DEFAULT NULL CHECK(difficulty BETWEEN 1 AND 10);
+
+ALTER TABLE tasks
+ADD COLUMN statement TEXT NOT NULL DEFAULT '';
+
+-- The document used by the full-text search. It's declared IMMUTABLE in
+-- order to be usable in the index.
+CREATE FUNCTION task_search_document(title VARCHAR, tags TEXT ARRAY, statement TEXT)
+RETURNS tsvector AS $$
+  SELECT setweight(to_tsvector('simple', title), 'A') ||
+         setweight(to_tsvector('simple', array_to_string(tags, ' ')), 'B') ||
+         setweight(to_tsvector('simple', statement), 'C')
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE INDEX tasks_search_idx ON tasks
+USING GIN(task_search_document(title, tags, statement));
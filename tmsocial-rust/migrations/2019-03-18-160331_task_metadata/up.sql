@@ -0,0 +1,3 @@
+CREATE TABLE task_metadata (
+  task_id INTEGER PRIMARY KEY REFERENCES tasks(id) ON DELETE CASCADE,
+  metadata JSONB NOT NULL);
@@ -0,0 +1,8 @@
+ALTER TABLE submissions
+DROP COLUMN task_version;
+
+ALTER TABLE subtasks
+DROP COLUMN active;
+
+ALTER TABLE tasks
+DROP COLUMN version;
@@ -0,0 +1,8 @@
+ALTER TABLE tasks
+ADD COLUMN version INTEGER NOT NULL DEFAULT 1 CHECK(version >= 1);
+
+ALTER TABLE subtasks
+ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE submissions
+ADD COLUMN task_version INTEGER NULL;
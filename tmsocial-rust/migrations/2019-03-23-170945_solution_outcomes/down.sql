@@ -0,0 +1,2 @@
+DROP INDEX solution_outcomes_task_version_solution_unique;
+DROP TABLE solution_outcomes;
@@ -0,0 +1,9 @@
+CREATE TABLE solution_outcomes (
+  id SERIAL PRIMARY KEY,
+  task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+  task_version INTEGER NOT NULL,
+  solution VARCHAR NOT NULL,
+  score FLOAT NOT NULL,
+  subtask_scores JSONB NOT NULL);
+
+CREATE UNIQUE INDEX solution_outcomes_task_version_solution_unique ON solution_outcomes(task_id, task_version, solution);
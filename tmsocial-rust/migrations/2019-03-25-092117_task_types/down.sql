@@ -0,0 +1,4 @@
+ALTER TABLE tasks
+DROP COLUMN task_type;
+
+DROP TYPE task_type;
@@ -0,0 +1,7 @@
+CREATE TYPE task_type AS ENUM (
+  'batch',
+  'communication',
+  'output_only');
+
+ALTER TABLE tasks
+ADD COLUMN task_type task_type NOT NULL DEFAULT 'batch';
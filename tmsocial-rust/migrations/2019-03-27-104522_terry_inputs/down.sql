@@ -0,0 +1,4 @@
+DROP INDEX terry_inputs_one_pending;
+DROP INDEX terry_inputs_submission_unique;
+DROP INDEX terry_inputs_participation_task;
+DROP TABLE terry_inputs;
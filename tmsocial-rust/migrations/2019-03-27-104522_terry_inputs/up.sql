@@ -0,0 +1,15 @@
+CREATE TABLE terry_inputs (
+  id SERIAL PRIMARY KEY,
+  participation_id INTEGER NOT NULL REFERENCES participations(id) ON DELETE CASCADE,
+  task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+  seed BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc'),
+  expires_at TIMESTAMP NOT NULL,
+  submission_id INTEGER REFERENCES submissions(id) ON DELETE SET NULL,
+  -- the output was submitted or the input expired
+  closed BOOLEAN NOT NULL DEFAULT FALSE);
+
+CREATE INDEX terry_inputs_participation_task ON terry_inputs(participation_id, task_id);
+CREATE UNIQUE INDEX terry_inputs_submission_unique ON terry_inputs(submission_id);
+-- a single input in progress for each participant and task
+CREATE UNIQUE INDEX terry_inputs_one_pending ON terry_inputs(participation_id, task_id) WHERE NOT closed;
@@ -0,0 +1,2 @@
+DROP INDEX plagiarism_pairs_task;
+DROP TABLE plagiarism_pairs;
@@ -0,0 +1,10 @@
+CREATE TABLE plagiarism_pairs (
+  id SERIAL PRIMARY KEY,
+  task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+  first_submission_id INTEGER NOT NULL REFERENCES submissions(id) ON DELETE CASCADE,
+  second_submission_id INTEGER NOT NULL REFERENCES submissions(id) ON DELETE CASCADE,
+  similarity DOUBLE PRECISION NOT NULL,
+  regions JSONB NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc'));
+
+CREATE INDEX plagiarism_pairs_task ON plagiarism_pairs(task_id);
@@ -0,0 +1,4 @@
+DROP TRIGGER audit_log_append_only ON audit_log;
+DROP FUNCTION audit_log_append_only();
+DROP INDEX audit_log_site;
+DROP TABLE audit_log;
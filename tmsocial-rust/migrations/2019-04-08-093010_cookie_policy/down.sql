@@ -0,0 +1,5 @@
+ALTER TABLE sites
+DROP COLUMN cookie_secure,
+DROP COLUMN cookie_same_site;
+
+DROP TYPE same_site_policy;
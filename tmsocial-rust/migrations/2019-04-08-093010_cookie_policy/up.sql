@@ -0,0 +1,8 @@
+CREATE TYPE same_site_policy AS ENUM (
+  'strict',
+  'lax',
+  'none');
+
+ALTER TABLE sites
+ADD COLUMN cookie_secure BOOLEAN NOT NULL DEFAULT TRUE,
+ADD COLUMN cookie_same_site same_site_policy NOT NULL DEFAULT 'strict';
@@ -0,0 +1,3 @@
+DROP INDEX api_tokens_user_name;
+DROP TABLE api_tokens;
+DROP TYPE api_token_scope;
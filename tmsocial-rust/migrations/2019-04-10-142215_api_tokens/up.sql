@@ -0,0 +1,17 @@
+CREATE TYPE api_token_scope AS ENUM (
+  'read_only',
+  'submit');
+
+CREATE TABLE api_tokens (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name VARCHAR NOT NULL,
+  token_hash VARCHAR NOT NULL UNIQUE,
+  scope api_token_scope NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc'),
+  last_used_at TIMESTAMP,
+  revoked_at TIMESTAMP);
+
+-- the name of a revoked token can be used again
+CREATE UNIQUE INDEX api_tokens_user_name ON api_tokens(user_id, name)
+WHERE revoked_at IS NULL;
@@ -0,0 +1,3 @@
+DROP TABLE oidc_logins;
+DROP TABLE oidc_identities;
+DROP TABLE oidc_providers;
@@ -0,0 +1,28 @@
+CREATE TABLE oidc_providers (
+  id SERIAL PRIMARY KEY,
+  site_id INTEGER NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+  -- used in the URLs of the login
+  name VARCHAR NOT NULL,
+  issuer VARCHAR NOT NULL,
+  client_id VARCHAR NOT NULL,
+  client_secret VARCHAR NOT NULL,
+  UNIQUE (site_id, name));
+
+-- the accounts of the providers linked to the users
+CREATE TABLE oidc_identities (
+  id SERIAL PRIMARY KEY,
+  provider_id INTEGER NOT NULL REFERENCES oidc_providers(id) ON DELETE CASCADE,
+  subject VARCHAR NOT NULL,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc'),
+  UNIQUE (provider_id, subject));
+
+-- the logins started and not yet completed
+CREATE TABLE oidc_logins (
+  state VARCHAR PRIMARY KEY,
+  provider_id INTEGER NOT NULL REFERENCES oidc_providers(id) ON DELETE CASCADE,
+  code_verifier VARCHAR NOT NULL,
+  nonce VARCHAR NOT NULL,
+  -- the user logged in when the login started, to be linked to the identity
+  link_user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+  created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc'));
@@ -0,0 +1,175 @@
+use std::env;
+
+use diesel::pg::PgConnection;
+use diesel::{QueryDsl, QueryResult, RunQueryDsl};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::models::{AuditEntry, NewAuditEntry};
+
+/// Where an action comes from: the address and the user agent of the request,
+/// or the command line tool that did it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    /// The client of a command line tool, with the system user running it.
+    pub fn command_line(tool: &str) -> ClientInfo {
+        let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+        ClientInfo {
+            ip: None,
+            user_agent: Some(format!("{} ({})", tool, user)),
+        }
+    }
+}
+
+/// The actions recorded in the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    CreateApiToken,
+    RevokeApiToken,
+    /// An account of an OpenID Connect provider was linked to a user.
+    LinkIdentity,
+    JoinContest,
+    Submit,
+    PublishTask,
+    ClassifyTask,
+    CheckPlagiarism,
+    /// An admin downloaded the files of a submission of someone else.
+    ReadSubmission,
+    AddSite,
+    AddOidcProvider,
+    AddContest,
+    AddTask,
+    UpdateTask,
+    AddUser,
+    AddParticipation,
+    AddSubmission,
+}
+
+impl AuditAction {
+    /// The name of the action stored in the log, the same used by serde.
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::CreateApiToken => "create_api_token",
+            AuditAction::RevokeApiToken => "revoke_api_token",
+            AuditAction::LinkIdentity => "link_identity",
+            AuditAction::JoinContest => "join_contest",
+            AuditAction::Submit => "submit",
+            AuditAction::PublishTask => "publish_task",
+            AuditAction::ClassifyTask => "classify_task",
+            AuditAction::CheckPlagiarism => "check_plagiarism",
+            AuditAction::ReadSubmission => "read_submission",
+            AuditAction::AddSite => "add_site",
+            AuditAction::AddOidcProvider => "add_oidc_provider",
+            AuditAction::AddContest => "add_contest",
+            AuditAction::AddTask => "add_task",
+            AuditAction::UpdateTask => "update_task",
+            AuditAction::AddUser => "add_user",
+            AuditAction::AddParticipation => "add_participation",
+            AuditAction::AddSubmission => "add_submission",
+        }
+    }
+}
+
+/// Append an entry to the audit log of the site. The entries cannot be
+/// changed or deleted afterwards, only the deletion of the site removes them.
+pub fn record(
+    conn: &PgConnection,
+    site_id: i32,
+    actor_id: Option<i32>,
+    client: &ClientInfo,
+    action: AuditAction,
+    details: serde_json::Value,
+) -> QueryResult<AuditEntry> {
+    use crate::schema::audit_log::dsl::audit_log;
+
+    diesel::insert_into(audit_log)
+        .values(&NewAuditEntry {
+            site_id,
+            actor_id,
+            action: action.name(),
+            ip: client.ip.as_ref().map(|ip| ip.as_str()),
+            user_agent: client.user_agent.as_ref().map(|ua| ua.as_str()),
+            details,
+        })
+        .get_result(conn)
+}
+
+/// Append to the audit log of the site an action done by a command line tool.
+pub fn record_command(
+    conn: &PgConnection,
+    site_id: i32,
+    tool: &str,
+    action: AuditAction,
+    details: serde_json::Value,
+) -> QueryResult<AuditEntry> {
+    let client = ClientInfo::command_line(tool);
+    record(conn, site_id, None, &client, action, details)
+}
+
+/// The site of a contest, to which its actions are logged.
+pub fn contest_site(conn: &PgConnection, contest_id: i32) -> QueryResult<i32> {
+    use crate::schema::contests::dsl::*;
+
+    contests.find(contest_id).select(site_id).first(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::ExpressionMethods;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_utils::FakeSite;
+
+    #[test]
+    fn action_names() {
+        for action in &[
+            AuditAction::Login,
+            AuditAction::LoginFailed,
+            AuditAction::CheckPlagiarism,
+            AuditAction::AddParticipation,
+        ] {
+            assert_eq!(
+                serde_json::to_value(action).unwrap(),
+                json!(action.name())
+            );
+        }
+    }
+
+    #[test]
+    fn append_only() {
+        use crate::schema::audit_log::dsl::*;
+
+        let site = FakeSite::new();
+        let entry = record_command(
+            &site.conn,
+            site.site.id,
+            "test",
+            AuditAction::AddSite,
+            json!({"domain": site.site.domain}),
+        )
+        .unwrap();
+        assert_eq!(entry.action, "add_site");
+        assert_eq!(entry.actor_id, None);
+        assert!(entry.user_agent.unwrap().starts_with("test ("));
+
+        let updated = diesel::update(audit_log.find(entry.id))
+            .set(action.eq("login"))
+            .execute(&site.conn);
+        assert!(updated.is_err());
+        let deleted =
+            diesel::delete(audit_log.find(entry.id)).execute(&site.conn);
+        assert!(deleted.is_err());
+    }
+}
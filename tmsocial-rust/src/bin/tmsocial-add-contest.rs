@@ -1,15 +1,20 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 extern crate diesel;
-extern crate pretty_env_logger;
 extern crate tmsocial;
 
-use diesel::{QueryDsl, RunQueryDsl};
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use diesel::{Connection, QueryDsl, RunQueryDsl};
 use dotenv::dotenv;
 use failure::Error;
+use serde_json::json;
 use structopt::StructOpt;
 
+use tmsocial::audit::{record_command, AuditAction};
 use tmsocial::models::Contest;
+use tmsocial::models::ContestFormat;
 use tmsocial::models::NewContest;
 use tmsocial::models::Site;
 use tmsocial::schema::contests::dsl::contests;
@@ -24,14 +29,38 @@ struct Opt {
     /// Name of the contest to add.
     #[structopt(short = "n", long = "name")]
     name: String,
+    /// Use the ICPC format instead of the IOI one.
+    #[structopt(long = "icpc")]
+    icpc: bool,
+    /// Start of the contest in UTC, like 2019-03-15T10:00:00.
+    #[structopt(long = "start", parse(try_from_str = "parse_time"))]
+    start_time: Option<NaiveDateTime>,
+    /// End of the contest in UTC, like 2019-03-15T15:00:00.
+    #[structopt(long = "end", parse(try_from_str = "parse_time"))]
+    end_time: Option<NaiveDateTime>,
+    /// Minutes before the end in which the scoreboard is frozen.
+    #[structopt(long = "freeze", default_value = "60")]
+    freeze_minutes: i32,
+    /// Penalty minutes for each rejected submission.
+    #[structopt(long = "penalty", default_value = "20")]
+    penalty_minutes: i32,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+fn parse_time(time: &str) -> Result<NaiveDateTime, chrono::ParseError> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S")
 }
 
 fn main() -> Result<(), Error> {
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
 
-    let conn = tmsocial::establish_connection();
+    let conn = tmsocial::establish_connection(&config);
     let site = match opt.site_id {
         Some(id) => sites.find(id).first::<Site>(&conn)?,
         None => {
@@ -47,11 +76,31 @@ fn main() -> Result<(), Error> {
     let contest = NewContest {
         site_id: site.id,
         name: opt.name,
+        format: if opt.icpc {
+            ContestFormat::ICPC
+        } else {
+            ContestFormat::IOI
+        },
+        start_time: opt.start_time,
+        end_time: opt.end_time,
+        freeze_minutes: opt.freeze_minutes,
+        penalty_minutes: opt.penalty_minutes,
     };
 
-    let info = diesel::insert_into(contests)
-        .values(&contest)
-        .get_result::<Contest>(&conn)?;
+    let info =
+        conn.transaction(|| -> Result<Contest, diesel::result::Error> {
+            let info = diesel::insert_into(contests)
+                .values(&contest)
+                .get_result::<Contest>(&conn)?;
+            record_command(
+                &conn,
+                site.id,
+                "tmsocial-add-contest",
+                AuditAction::AddContest,
+                json!({ "contest_id": info.id, "name": info.name }),
+            )?;
+            Ok(info)
+        })?;
     println!(
         "Adding contest with id {:?} and to the site {}",
         info.id, site.id
@@ -0,0 +1,100 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+extern crate diesel;
+extern crate tmsocial;
+
+use std::path::PathBuf;
+
+use diesel::{Connection, QueryDsl, RunQueryDsl};
+use dotenv::dotenv;
+use failure::Error;
+use serde_json::json;
+use structopt::StructOpt;
+
+use tmsocial::audit::{record_command, AuditAction};
+use tmsocial::models::NewOidcProvider;
+use tmsocial::models::OidcProvider;
+use tmsocial::models::Site;
+use tmsocial::oidc::check_secure_url;
+use tmsocial::schema::oidc_providers::dsl::oidc_providers;
+use tmsocial::schema::sites::dsl::sites;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tmsocial-add-oidc-provider")]
+struct Opt {
+    /// Site id of the site we should add the provider to.
+    #[structopt(short = "s", long = "site")]
+    site_id: Option<i32>,
+    /// Name of the provider, used in the URLs of the login.
+    #[structopt(short = "n", long = "name")]
+    name: String,
+    /// Issuer of the provider, where its metadata is published. Must be an
+    /// HTTPS URL.
+    #[structopt(long = "issuer")]
+    issuer: String,
+    /// Id of the client registered on the provider.
+    #[structopt(long = "client-id")]
+    client_id: String,
+    /// Secret of the client registered on the provider.
+    #[structopt(long = "client-secret")]
+    client_secret: String,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Error> {
+    tmsocial::logging::init();
+    let opt = Opt::from_args();
+    dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
+
+    check_secure_url(&opt.issuer)?;
+
+    let conn = tmsocial::establish_connection(&config);
+    let site = match opt.site_id {
+        Some(id) => sites.find(id).first::<Site>(&conn)?,
+        None => {
+            let mut ids = sites.get_results::<Site>(&conn)?;
+            match ids.len() {
+                0 => panic!("No sites found"),
+                1 => ids.swap_remove(0),
+                _ => panic!("More than a site present, use -s option"),
+            }
+        }
+    };
+
+    let provider = NewOidcProvider {
+        site_id: site.id,
+        name: opt.name,
+        issuer: opt.issuer,
+        client_id: opt.client_id,
+        client_secret: opt.client_secret,
+    };
+
+    let info =
+        conn.transaction(|| -> Result<OidcProvider, diesel::result::Error> {
+            let info = diesel::insert_into(oidc_providers)
+                .values(&provider)
+                .get_result::<OidcProvider>(&conn)?;
+            record_command(
+                &conn,
+                site.id,
+                "tmsocial-add-oidc-provider",
+                AuditAction::AddOidcProvider,
+                json!({
+                    "provider_id": info.id,
+                    "name": info.name,
+                    "issuer": info.issuer,
+                }),
+            )?;
+            Ok(info)
+        })?;
+    println!(
+        "Adding provider {:?} with id {} to the site {}",
+        info.name, info.id, site.id
+    );
+
+    Ok(())
+}
@@ -1,14 +1,17 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 extern crate diesel;
-extern crate pretty_env_logger;
 extern crate tmsocial;
 
-use diesel::{QueryDsl, RunQueryDsl};
+use std::path::PathBuf;
+
+use diesel::{Connection, QueryDsl, RunQueryDsl};
 use dotenv::dotenv;
 use failure::Error;
+use serde_json::json;
 use structopt::StructOpt;
 
+use tmsocial::audit::{record_command, AuditAction};
 use tmsocial::models::Contest;
 use tmsocial::models::NewParticipation;
 use tmsocial::models::Participation;
@@ -26,25 +29,43 @@ struct Opt {
     /// User id of the user we should add the participation to.
     #[structopt(short = "u", long = "user-id")]
     user_id: i32,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
 }
 
 fn main() -> Result<(), Error> {
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
 
-    let conn = tmsocial::establish_connection();
+    let conn = tmsocial::establish_connection(&config);
     let contest = contests.find(opt.contest_id).first::<Contest>(&conn)?;
     let user = users.find(opt.user_id).first::<User>(&conn)?;
 
     let participation = NewParticipation {
         contest_id: contest.id,
         user_id: user.id,
+        practice: false,
     };
 
-    let info = diesel::insert_into(participations)
-        .values(&participation)
-        .get_result::<Participation>(&conn)?;
+    let info = conn.transaction(
+        || -> Result<Participation, diesel::result::Error> {
+            let info = diesel::insert_into(participations)
+                .values(&participation)
+                .get_result::<Participation>(&conn)?;
+            record_command(
+                &conn,
+                contest.site_id,
+                "tmsocial-add-participation",
+                AuditAction::AddParticipation,
+                json!({ "contest_id": contest.id, "user_id": user.id }),
+            )?;
+            Ok(info)
+        },
+    )?;
     println!(
         "Adding participation with id {} and to the contest {} ({}) \
          and to the user {} ({})",
@@ -1,15 +1,19 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 extern crate diesel;
-extern crate pretty_env_logger;
 extern crate tmsocial;
 
-use diesel::RunQueryDsl;
+use std::path::PathBuf;
+
+use diesel::{Connection, RunQueryDsl};
 use dotenv::dotenv;
 use failure::Error;
+use serde_json::json;
 use structopt::StructOpt;
 
+use tmsocial::audit::{record_command, AuditAction};
 use tmsocial::models::NewSite;
+use tmsocial::models::SameSitePolicy;
 use tmsocial::models::Site;
 
 #[derive(StructOpt, Debug)]
@@ -18,21 +22,60 @@ struct Opt {
     /// Domain of the new site to create.
     #[structopt(short = "d", long = "domain")]
     domain: String,
+    /// Send the auth cookie also over plain HTTP, only for development.
+    #[structopt(long = "insecure-cookie")]
+    insecure_cookie: bool,
+    /// SameSite attribute of the auth cookie: strict, lax or none.
+    #[structopt(
+        long = "same-site",
+        default_value = "strict",
+        parse(try_from_str = "parse_same_site")
+    )]
+    same_site: SameSitePolicy,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+fn parse_same_site(policy: &str) -> Result<SameSitePolicy, String> {
+    match policy {
+        "strict" => Ok(SameSitePolicy::Strict),
+        "lax" => Ok(SameSitePolicy::Lax),
+        "none" => Ok(SameSitePolicy::None),
+        _ => Err(format!("Invalid SameSite policy: {}", policy)),
+    }
 }
 
 fn main() -> Result<(), Error> {
     use tmsocial::schema::sites::dsl::*;
 
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
 
-    let conn = tmsocial::establish_connection();
-    let site = NewSite { domain: opt.domain };
+    let conn = tmsocial::establish_connection(&config);
+    let site = NewSite {
+        domain: opt.domain,
+        cookie_secure: !opt.insecure_cookie,
+        cookie_same_site: opt.same_site,
+    };
 
-    let info = diesel::insert_into(sites)
-        .values(&site)
-        .get_result::<Site>(&conn)?;
+    let info =
+        conn.transaction(|| -> Result<Site, diesel::result::Error> {
+            let info = diesel::insert_into(sites)
+                .values(&site)
+                .get_result::<Site>(&conn)?;
+            record_command(
+                &conn,
+                info.id,
+                "tmsocial-add-site",
+                AuditAction::AddSite,
+                json!({ "domain": info.domain }),
+            )?;
+            Ok(info)
+        })?;
     println!(
         "Adding site with domain {:?} and id {}",
         info.domain, info.id
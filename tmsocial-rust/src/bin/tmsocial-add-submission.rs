@@ -1,7 +1,6 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 extern crate diesel;
-extern crate pretty_env_logger;
 extern crate serde_json;
 extern crate tmsocial;
 
@@ -11,8 +10,10 @@ use std::path::PathBuf;
 
 use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
 use dotenv::dotenv;
+use serde_json::json;
 use structopt::StructOpt;
 
+use tmsocial::audit::{contest_site, record_command, AuditAction};
 use tmsocial::create_submission_dir;
 use tmsocial::models::*;
 use tmsocial::schema::participations::dsl::participations;
@@ -33,14 +34,20 @@ struct Opt {
     /// Path of the files of the submission that should be added.
     #[structopt(name = "FILE", parse(from_os_str), required = true)]
     files: Vec<PathBuf>,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
 }
 
 fn main() {
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))
+            .unwrap_or_else(|e| panic!("{}", e));
 
-    let conn = tmsocial::establish_connection();
+    let conn = tmsocial::establish_connection(&config);
 
     if opt.task.is_none() == opt.task_id.is_none() {
         panic!("You must specify exactly one of --task and --task-id");
@@ -74,6 +81,7 @@ fn main() {
             tmsocial::schema::participations::contest_id.eq(task.contest_id),
         )
         .filter(tmsocial::schema::participations::user_id.eq(opt.user_id))
+        .filter(tmsocial::schema::participations::practice.eq(false))
         .first::<Participation>(&conn)
         .expect("No such participation");
 
@@ -106,11 +114,22 @@ fn main() {
             .values(&submission_info)
             .get_result::<Submission>(&conn)?;
         println!("Adding submission with id {}", info.id);
-        let path = create_submission_dir(info.id);
+        let path = create_submission_dir(&config, info.id);
 
         for file in opt.files {
             copy(&file, path.join(file.file_name().unwrap())).unwrap();
         }
+        record_command(
+            &conn,
+            contest_site(&conn, task.contest_id)?,
+            "tmsocial-add-submission",
+            AuditAction::AddSubmission,
+            json!({
+                "submission_id": info.id,
+                "task_id": info.task_id,
+                "user_id": participation.user_id,
+            }),
+        )?;
         Ok(())
     })
     .unwrap();
@@ -1,67 +1,106 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 extern crate diesel;
-extern crate pretty_env_logger;
-extern crate serde_json;
 extern crate tmsocial;
 
 use std::env;
-use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process;
 
 use diesel::{Connection, QueryDsl, RunQueryDsl};
 use dotenv::dotenv;
 use failure::Error;
-use fs_extra::dir::{copy, create_all, CopyOptions};
+use serde_derive::Serialize;
+use serde_json::json;
 use structopt::StructOpt;
+use tempfile::TempDir;
 
-use tmsocial::models::Contest;
-use tmsocial::models::{NewSubtask, NewTask};
-use tmsocial::models::{Task, TaskFormat};
+use tmsocial::audit::{record_command, AuditAction};
+use tmsocial::models::{Contest, Task};
 use tmsocial::schema::contests::dsl::contests;
+use tmsocial::solutions::{check_solutions, store_outcomes};
+use tmsocial::task_import::*;
 use tmsocial::task_maker_ui::TaskInfo;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "tmsocial-add-task")]
 struct Opt {
-    /// Path of the task that should be added.
-    #[structopt(name = "DIR", parse(from_os_str))]
+    /// Path of the task that should be added, either a directory or a zip or
+    /// tar.gz archive.
+    #[structopt(name = "PATH", parse(from_os_str))]
     task: PathBuf,
     /// Contest id of the contest we should add the task to.
     #[structopt(short = "c", long = "contest-id")]
     contest_id: Option<i32>,
+    /// Tag of the task, can be repeated.
+    #[structopt(short = "t", long = "tag")]
+    tags: Vec<String>,
+    /// Difficulty of the task, from 1 to 10.
+    #[structopt(short = "d", long = "difficulty")]
+    difficulty: Option<i32>,
+    /// Only check the task and print a report, without adding it.
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// Evaluate the reference solutions and store their scores.
+    #[structopt(long = "check-solutions")]
+    check_solutions: bool,
+    /// Only warn when a solution doesn't get its expected score.
+    #[structopt(long = "allow-contradictions")]
+    allow_contradictions: bool,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct DryRunReport<'a> {
+    task: &'a TaskInfo,
+    validation: &'a ValidationReport,
 }
 
 fn main() -> Result<(), Error> {
-    use tmsocial::schema::subtasks::dsl::*;
     use tmsocial::schema::tasks::dsl::*;
 
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
+
+    let task_maker = config.task_maker.clone();
+    let extract_dir = TempDir::new()?;
+    let task_dir = if opt.task.is_dir() {
+        opt.task.clone()
+    } else {
+        extract_archive(&opt.task, extract_dir.path())?
+    };
+    let task_info = read_task_info(&task_maker, &task_dir)?;
 
-    let task_maker = env::var("TASK_MAKER").expect("TASK_MAKER must be set");
-    let storage_dir = PathBuf::new().join(Path::new(
-        &env::var("STORAGE_DIR").expect("STORAGE_DIR must be set"),
-    ));
-    let task_dir = storage_dir.join(Path::new("tasks"));
-
-    create_all(&task_dir, false)?;
-
-    let task_info = Command::new(task_maker)
-        .arg("--ui=json")
-        .arg("--task-info")
-        .arg("--task-dir")
-        .arg(&opt.task)
-        .output()
-        .unwrap();
-
-    let task_info = task_info.stdout;
-    let task_info = String::from_utf8(task_info)?;
-    let task_info: TaskInfo = serde_json::from_str(&task_info)?;
+    if opt.dry_run {
+        let validation = validate_task(&task_maker, &task_dir)?;
+        let report = DryRunReport {
+            task: &task_info,
+            validation: &validation,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !validation.is_valid() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let outcomes = if opt.check_solutions {
+        Some(check_solutions(
+            &task_maker,
+            &task_dir,
+            &task_info,
+            opt.allow_contradictions,
+        )?)
+    } else {
+        None
+    };
 
-    let conn = tmsocial::establish_connection();
+    let conn = tmsocial::establish_connection(&config);
     let contest = match opt.contest_id {
         Some(id) => contests.find(id).first::<Contest>(&conn)?,
         None => {
@@ -74,32 +113,16 @@ fn main() -> Result<(), Error> {
         }
     };
 
-    let task = match &task_info {
-        TaskInfo::IOITask(task) => NewTask {
-            name: &task.name,
-            title: &task.title,
-            time_limit: task.time_limit.into(),
-            memory_limit: task.memory_limit as i32,
-            max_score: task
-                .subtasks
-                .iter()
-                .map(|st| st.1.max_score as f64)
-                .sum(),
-            format: TaskFormat::IOI,
-            contest_id: contest.id,
-        },
-        TaskInfo::TerryTask(task) => NewTask {
-            name: &task.name,
-            title: &task.title,
-            time_limit: 10.0,
-            memory_limit: 64 * 1024,
-            max_score: task.max_score.into(),
-            format: TaskFormat::Terry,
-            contest_id: contest.id,
-        },
-    };
+    let statement = read_statement(&task_dir);
+    let task = new_task(
+        &task_info,
+        contest.id,
+        &statement,
+        opt.tags.clone(),
+        opt.difficulty,
+    );
 
-    conn.transaction(|| -> Result<(), diesel::result::Error> {
+    conn.transaction(|| -> Result<(), Error> {
         // create the task
         let info = diesel::insert_into(tasks)
             .values(&task)
@@ -109,40 +132,24 @@ fn main() -> Result<(), Error> {
             info.name, info.id, contest.id, contest.name
         );
 
-        // build and create the subtasks
-        let subs: Vec<NewSubtask> = match &task_info {
-            TaskInfo::IOITask(task) => task
-                .subtasks
-                .iter()
-                .map(|(st_num, subtask)| NewSubtask {
-                    task_id: info.id,
-                    num: *st_num,
-                    max_score: subtask.max_score.into(),
-                })
-                .collect(),
-            TaskInfo::TerryTask(task) => vec![NewSubtask {
-                task_id: info.id,
-                num: 0,
-                max_score: task.max_score.into(),
-            }],
-        };
-        let subtask_ids = diesel::insert_into(subtasks)
-            .values(&subs)
-            .returning(tmsocial::schema::subtasks::dsl::id)
-            .get_results::<i32>(&conn)?;
-        println!(
-            "Added {} subtasks with ids {:?}",
-            subtask_ids.len(),
-            subtask_ids
-        );
+        // create the subtasks
+        let changes =
+            reconcile_subtasks(&conn, info.id, &subtask_scores(&task_info))?;
+        println!("Added subtasks {:?}", changes.added);
 
-        // copy the task directory
-        let path = task_dir.join(Path::new(&info.id.to_string()));
-        let copy_options = CopyOptions {
-            copy_inside: true,
-            ..CopyOptions::new()
-        };
-        copy(opt.task, path, &copy_options).unwrap();
+        // copy the task directory and store its metadata
+        let path = copy_task_dir(&config, &task_dir, info.id, info.version)?;
+        store_metadata(&conn, info.id, &task_info, &path)?;
+        if let Some(outcomes) = &outcomes {
+            store_outcomes(&conn, info.id, info.version, outcomes)?;
+        }
+        record_command(
+            &conn,
+            contest.site_id,
+            "tmsocial-add-task",
+            AuditAction::AddTask,
+            json!({ "task_id": info.id, "name": info.name }),
+        )?;
 
         // commit the transaction
         Ok(())
@@ -1,14 +1,17 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 extern crate diesel;
-extern crate pretty_env_logger;
 extern crate tmsocial;
 
-use diesel::{QueryDsl, RunQueryDsl};
+use std::path::PathBuf;
+
+use diesel::{Connection, QueryDsl, RunQueryDsl};
 use dotenv::dotenv;
 use failure::Error;
+use serde_json::json;
 use structopt::StructOpt;
 
+use tmsocial::audit::{record_command, AuditAction};
 use tmsocial::models::NewUser;
 use tmsocial::models::Site;
 use tmsocial::models::User;
@@ -24,14 +27,22 @@ struct Opt {
     /// Username of the user to add.
     #[structopt(short = "u", long = "username")]
     username: String,
+    /// Make the user an administrator of the site.
+    #[structopt(long = "admin")]
+    admin: bool,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
 }
 
 fn main() -> Result<(), Error> {
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
 
-    let conn = tmsocial::establish_connection();
+    let conn = tmsocial::establish_connection(&config);
     let site = match opt.site_id {
         Some(id) => sites.find(id).first::<Site>(&conn)?,
         None => {
@@ -47,11 +58,27 @@ fn main() -> Result<(), Error> {
     let user = NewUser {
         site_id: site.id,
         username: opt.username,
+        is_admin: opt.admin,
     };
 
-    let info = diesel::insert_into(users)
-        .values(&user)
-        .get_result::<User>(&conn)?;
+    let info =
+        conn.transaction(|| -> Result<User, diesel::result::Error> {
+            let info = diesel::insert_into(users)
+                .values(&user)
+                .get_result::<User>(&conn)?;
+            record_command(
+                &conn,
+                site.id,
+                "tmsocial-add-user",
+                AuditAction::AddUser,
+                json!({
+                    "user_id": info.id,
+                    "username": info.username,
+                    "is_admin": info.is_admin,
+                }),
+            )?;
+            Ok(info)
+        })?;
     println!(
         "Adding user with id {:?} and to the site {}",
         info.id, site.id
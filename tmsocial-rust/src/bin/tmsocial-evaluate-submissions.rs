@@ -1,15 +1,25 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
-extern crate pretty_env_logger;
 extern crate serde_json;
 extern crate tmsocial;
 
 use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use actix::prelude::*;
+use failure::Error;
 use futures::future::Future;
 use log::info;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tmsocial-evaluate-submissions")]
+struct Opt {
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
 
 struct PrintMessageHandler;
 
@@ -29,27 +39,49 @@ impl Handler<tmsocial::events::SubmissionUpdate> for PrintMessageHandler {
     }
 }
 
-fn main() {
-    pretty_env_logger::init();
+impl Handler<tmsocial::events::ContestUpdate> for PrintMessageHandler {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: tmsocial::events::ContestUpdate,
+        _ctx: &mut Self::Context,
+    ) {
+        info!("{:?}", msg.balloon);
+    }
+}
+
+fn main() -> Result<(), Error> {
+    tmsocial::logging::init();
+    let opt = Opt::from_args();
     dotenv::dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
 
     let sys = actix::System::new("tmsocial");
     let in_evaluation = Arc::new(Mutex::new(HashSet::<i32>::new()));
-    let evaluator_addr = SyncArbiter::start(3, move || {
-        tmsocial::evaluation::Evaluator::new(
-            tmsocial::establish_connection(),
-            Arc::clone(&in_evaluation),
-        )
-    });
+    let pool = tmsocial::pool::establish_pool(&config.database);
+    let evaluator_pool = pool.clone();
+    let evaluator_config = config.clone();
+    let evaluator_addr =
+        SyncArbiter::start(config.evaluation.workers, move || {
+            tmsocial::evaluation::Evaluator::new(
+                evaluator_pool.clone(),
+                evaluator_config.clone(),
+                tmsocial::shutdown::Shutdown::new(),
+                Arc::clone(&in_evaluation),
+            )
+        });
     let check_pending =
-        tmsocial::evaluation::CheckPending(evaluator_addr).start();
+        tmsocial::evaluation::CheckPending(evaluator_addr, pool).start();
     let print_message_handler = PrintMessageHandler {}.start();
 
     actix::spawn(
         check_pending
-            .send(tmsocial::evaluation::EvaluatePending(
-                print_message_handler.recipient(),
-            ))
+            .send(tmsocial::evaluation::EvaluatePending {
+                notify: print_message_handler.clone().recipient(),
+                contest_notify: print_message_handler.recipient(),
+            })
             .then(|_| {
                 actix::System::current().stop();
                 futures::future::ok(())
@@ -57,4 +89,5 @@ fn main() {
     );
 
     sys.run();
+    Ok(())
 }
@@ -0,0 +1,85 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+extern crate diesel;
+extern crate tmsocial;
+
+use std::path::PathBuf;
+
+use diesel::{Connection, QueryDsl, RunQueryDsl};
+use dotenv::dotenv;
+use failure::Error;
+use serde_json::json;
+use structopt::StructOpt;
+
+use tmsocial::audit::{contest_site, record_command, AuditAction};
+use tmsocial::models::Task;
+use tmsocial::plagiarism::{check_task, MatchedRegion, DEFAULT_THRESHOLD};
+use tmsocial::schema::tasks::dsl::tasks;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tmsocial-plagiarism")]
+struct Opt {
+    /// Id of the task whose final submissions are compared.
+    #[structopt(short = "t", long = "task-id")]
+    task_id: i32,
+    /// Minimum similarity of the reported pairs, between 0 and 1.
+    #[structopt(long = "threshold")]
+    threshold: Option<f64>,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Error> {
+    tmsocial::logging::init();
+    let opt = Opt::from_args();
+    dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
+
+    let conn = tmsocial::establish_connection(&config);
+    let task = tasks.find(opt.task_id).first::<Task>(&conn)?;
+    let threshold = opt.threshold.unwrap_or(DEFAULT_THRESHOLD);
+    let pairs = conn.transaction(|| -> Result<_, Error> {
+        let pairs = check_task(&conn, &config, task.id, threshold)?;
+        record_command(
+            &conn,
+            contest_site(&conn, task.contest_id)?,
+            "tmsocial-plagiarism",
+            AuditAction::CheckPlagiarism,
+            json!({
+                "task_id": task.id,
+                "threshold": threshold,
+                "pairs": pairs.len(),
+            }),
+        )?;
+        Ok(pairs)
+    })?;
+    println!(
+        "Found {} suspicious pairs in task {:?} (id {})",
+        pairs.len(),
+        task.name,
+        task.id
+    );
+    for pair in pairs {
+        println!(
+            "{:5.1}% submissions {} and {}",
+            pair.similarity * 100.0,
+            pair.first_submission_id,
+            pair.second_submission_id
+        );
+        let regions: Vec<MatchedRegion> = serde_json::from_value(pair.regions)?;
+        for region in regions {
+            println!(
+                "       {}:{}-{} ~ {}:{}-{}",
+                region.first.file,
+                region.first.first_line,
+                region.first.last_line,
+                region.second.file,
+                region.second.first_line,
+                region.second.last_line
+            );
+        }
+    }
+    Ok(())
+}
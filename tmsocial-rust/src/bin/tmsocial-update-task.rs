@@ -0,0 +1,114 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+extern crate diesel;
+extern crate tmsocial;
+
+use std::env;
+use std::path::PathBuf;
+
+use diesel::Connection;
+use dotenv::dotenv;
+use failure::Error;
+use serde_json::json;
+use structopt::StructOpt;
+use tempfile::TempDir;
+
+use tmsocial::audit::{contest_site, record_command, AuditAction};
+use tmsocial::solutions::{check_solutions, store_outcomes};
+use tmsocial::task_import::*;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tmsocial-update-task")]
+struct Opt {
+    /// Path of the new version of the task, either a directory or a zip or
+    /// tar.gz archive.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    task: PathBuf,
+    /// Id of the task to update.
+    #[structopt(short = "t", long = "task-id")]
+    task_id: i32,
+    /// Evaluate again all the submissions of the task.
+    #[structopt(long = "rejudge")]
+    rejudge: bool,
+    /// Evaluate the reference solutions and store their scores.
+    #[structopt(long = "check-solutions")]
+    check_solutions: bool,
+    /// Only warn when a solution doesn't get its expected score.
+    #[structopt(long = "allow-contradictions")]
+    allow_contradictions: bool,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Error> {
+    tmsocial::logging::init();
+    let opt = Opt::from_args();
+    dotenv().ok();
+    let config =
+        tmsocial::config::setup(opt.config.as_ref().map(|p| p.as_path()))?;
+
+    let task_maker = config.task_maker.clone();
+    let extract_dir = TempDir::new()?;
+    let task_dir = if opt.task.is_dir() {
+        opt.task.clone()
+    } else {
+        extract_archive(&opt.task, extract_dir.path())?
+    };
+    let task_info = read_task_info(&task_maker, &task_dir)?;
+
+    let outcomes = if opt.check_solutions {
+        Some(check_solutions(
+            &task_maker,
+            &task_dir,
+            &task_info,
+            opt.allow_contradictions,
+        )?)
+    } else {
+        None
+    };
+
+    let conn = tmsocial::establish_connection(&config);
+    // the new version is recorded only together with its outcomes and its
+    // entry in the audit log
+    let (task, changes, staged) =
+        conn.transaction(|| -> Result<_, Error> {
+            let (task, changes, staged) = update_task(
+                &conn,
+                &config,
+                opt.task_id,
+                &task_dir,
+                &task_info,
+            )?;
+            if let Some(outcomes) = &outcomes {
+                store_outcomes(&conn, task.id, task.version, outcomes)?;
+            }
+            record_command(
+                &conn,
+                contest_site(&conn, task.contest_id)?,
+                "tmsocial-update-task",
+                AuditAction::UpdateTask,
+                json!({
+                    "task_id": task.id,
+                    "version": task.version,
+                    "rejudge": opt.rejudge,
+                }),
+            )?;
+            Ok((task, changes, staged))
+        })?;
+    staged.publish()?;
+    println!(
+        "Updated task {:?} (id {}) to version {}",
+        task.name, task.id, task.version
+    );
+    println!(
+        "Subtasks added: {:?}, removed: {:?}, changed: {:?}",
+        changes.added, changes.removed, changes.changed
+    );
+
+    if opt.rejudge {
+        let count = rejudge_task(&conn, task.id)?;
+        println!("Marked {} submissions for evaluation", count);
+    }
+    Ok(())
+}
@@ -1,5 +1,3 @@
-extern crate pretty_env_logger;
-
 use tmsocial::web::web_main;
 
 use dotenv::dotenv;
@@ -11,28 +9,40 @@ use structopt::StructOpt;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "tmsocial")]
 struct Opt {
-    /// Path to the web root.
-    #[structopt(
-        short = "w",
-        long = "web-root",
-        default_value = "./web/",
-        parse(from_os_str)
-    )]
-    web_root: PathBuf,
-    /// Address to listen on.
-    #[structopt(short = "l", long = "listen", default_value = "0.0.0.0")]
-    address: IpAddr,
-    /// Port to listen on.
-    #[structopt(short = "p", long = "port", default_value = "8083")]
-    port: u16,
+    /// Path of the configuration file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Path to the web root, overrides the one of the configuration.
+    #[structopt(short = "w", long = "web-root", parse(from_os_str))]
+    web_root: Option<PathBuf>,
+    /// Address to listen on, overrides the one of the configuration.
+    #[structopt(short = "l", long = "listen")]
+    address: Option<IpAddr>,
+    /// Port to listen on, overrides the one of the configuration.
+    #[structopt(short = "p", long = "port")]
+    port: Option<u16>,
 }
 
 fn main() -> Result<(), Error> {
-    pretty_env_logger::init();
+    tmsocial::logging::init();
     let opt = Opt::from_args();
     dotenv().ok();
 
-    web_main(opt.web_root, opt.address, opt.port)?;
+    let mut config = tmsocial::config::Config::load(
+        opt.config.as_ref().map(|p| p.as_path()),
+    )?;
+    if let Some(web_root) = opt.web_root {
+        config.web.root = web_root;
+    }
+    if let Some(address) = opt.address {
+        config.web.address = address;
+    }
+    if let Some(port) = opt.port {
+        config.web.port = port;
+    }
+    let config = tmsocial::config::install(config);
+
+    web_main(config)?;
 
     Ok(())
 }
@@ -0,0 +1,471 @@
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use failure::Error;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+
+/// Configuration file used when neither `--config` nor TMSOCIAL_CONFIG are
+/// given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "tmsocial.toml";
+
+#[derive(Debug, Fail)]
+pub enum ConfigError {
+    #[fail(display = "cannot read the config file {:?}: {}", path, cause)]
+    Read { path: PathBuf, cause: String },
+    #[fail(display = "invalid config file {:?}: {}", path, cause)]
+    Parse { path: PathBuf, cause: String },
+    #[fail(display = "invalid value {:?} of the {} variable", value, name)]
+    InvalidEnv { name: String, value: String },
+    #[fail(display = "invalid config: {} {}", field, reason)]
+    Invalid { field: String, reason: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Overridden by DATABASE_URL.
+    pub url: String,
+    /// Overridden by DATABASE_POOL_SIZE.
+    pub pool_size: u32,
+    /// Overridden by DATABASE_STATEMENT_TIMEOUT.
+    pub statement_timeout_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    pub root: PathBuf,
+    pub address: IpAddr,
+    pub port: u16,
+    /// Number of threads running the database queries.
+    pub db_workers: usize,
+    /// Addresses of the reverse proxies allowed to tell the address of the
+    /// client with X-Forwarded-For.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Token to send as `Authorization: Bearer` to read `/metrics`. Without
+    /// it the metrics are served only to the local clients.
+    pub metrics_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvaluationConfig {
+    /// Number of submissions evaluated at the same time.
+    pub workers: usize,
+    /// Seconds between two checks for submissions waiting for evaluation.
+    pub check_interval_secs: u64,
+    /// Waiting submissions above which the server is reported as not ready.
+    pub max_backlog: i64,
+    /// Seconds the running evaluations are given to finish when shutting
+    /// down, the ones still running are evaluated again at the next start.
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    /// Seconds the submission events are kept for the clients that
+    /// reconnect.
+    pub history_secs: u64,
+}
+
+/// A token bucket: up to `burst` requests at once, then `per_minute`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Login attempts per IP, and failed ones per username.
+    pub login: RateLimit,
+    /// Lookups of the users by username, per IP.
+    pub lookup: RateLimit,
+    /// Submissions and input requests, per IP and per user.
+    pub submit: RateLimit,
+}
+
+/// Configuration of tmsocial, read from a TOML file. Some values can be
+/// overridden by environment variables, as documented on the fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Overridden by STORAGE_DIR.
+    pub storage_dir: PathBuf,
+    /// Path of the task-maker executable, overridden by TASK_MAKER.
+    pub task_maker: String,
+    pub database: DatabaseConfig,
+    pub web: WebConfig,
+    pub evaluation: EvaluationConfig,
+    pub events: EventsConfig,
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            url: String::new(),
+            pool_size: 8,
+            statement_timeout_ms: 30_000,
+        }
+    }
+}
+
+impl Default for WebConfig {
+    fn default() -> WebConfig {
+        WebConfig {
+            root: PathBuf::from("./web/"),
+            address: "0.0.0.0".parse().unwrap(),
+            port: 8083,
+            db_workers: 3,
+            trusted_proxies: vec![],
+            metrics_token: None,
+        }
+    }
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> EvaluationConfig {
+        EvaluationConfig {
+            workers: 3,
+            check_interval_secs: 20,
+            max_backlog: 100,
+            shutdown_timeout_secs: 60,
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> EventsConfig {
+        EventsConfig { history_secs: 300 }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            login: RateLimit {
+                burst: 10,
+                per_minute: 5,
+            },
+            lookup: RateLimit {
+                burst: 30,
+                per_minute: 30,
+            },
+            submit: RateLimit {
+                burst: 20,
+                per_minute: 10,
+            },
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            storage_dir: PathBuf::new(),
+            task_maker: "task-maker".to_string(),
+            database: DatabaseConfig::default(),
+            web: WebConfig::default(),
+            evaluation: EvaluationConfig::default(),
+            events: EventsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+/// Parse the value of an environment variable, if set.
+fn env_override<T: std::str::FromStr>(
+    name: &str,
+) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => {
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| ConfigError::InvalidEnv {
+                    name: name.to_string(),
+                    value,
+                })
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+fn invalid(field: &str, reason: &str) -> Error {
+    ConfigError::Invalid {
+        field: field.to_string(),
+        reason: reason.to_string(),
+    }
+    .into()
+}
+
+impl Config {
+    /// Parse a TOML configuration, the missing values get their default.
+    ///
+    /// # Example
+    /// ```
+    /// use tmsocial::config::Config;
+    ///
+    /// let config =
+    ///     Config::parse("storage_dir = \"/storage\"\n[web]\nport = 80")
+    ///         .unwrap();
+    /// assert_eq!(config.web.port, 80);
+    /// assert_eq!(config.evaluation.workers, 3);
+    /// ```
+    pub fn parse(content: &str) -> Result<Config, Error> {
+        toml::from_str(content).map_err(|e| {
+            ConfigError::Parse {
+                path: PathBuf::new(),
+                cause: e.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Read the configuration file, if any, and apply the environment
+    /// overrides, without validating the result. Without a path the one in
+    /// TMSOCIAL_CONFIG is used, or `DEFAULT_CONFIG_FILE` if present.
+    pub fn read(path: Option<&Path>) -> Result<Config, Error> {
+        let path = match path {
+            Some(path) => Some(path.to_owned()),
+            None => env::var("TMSOCIAL_CONFIG")
+                .ok()
+                .map(PathBuf::from)
+                .or_else(|| {
+                    Some(PathBuf::from(DEFAULT_CONFIG_FILE))
+                        .filter(|p| p.is_file())
+                }),
+        };
+        let mut config = match path {
+            Some(path) => {
+                let content = fs::read_to_string(&path).map_err(|e| {
+                    ConfigError::Read {
+                        path: path.clone(),
+                        cause: e.to_string(),
+                    }
+                })?;
+                toml::from_str(&content).map_err(|e| ConfigError::Parse {
+                    path: path.clone(),
+                    cause: e.to_string(),
+                })?
+            }
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Read the configuration and validate it.
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let config = Config::read(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(url) = env_override("DATABASE_URL")? {
+            self.database.url = url;
+        }
+        if let Some(size) = env_override("DATABASE_POOL_SIZE")? {
+            self.database.pool_size = size;
+        }
+        if let Some(timeout) = env_override("DATABASE_STATEMENT_TIMEOUT")? {
+            self.database.statement_timeout_ms = timeout;
+        }
+        if let Some(dir) = env_override::<String>("STORAGE_DIR")? {
+            self.storage_dir = PathBuf::from(dir);
+        }
+        if let Some(task_maker) = env_override("TASK_MAKER")? {
+            self.task_maker = task_maker;
+        }
+        Ok(())
+    }
+
+    /// Check that the configuration is usable.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.database.url.is_empty() {
+            return Err(invalid("database.url", "must be set"));
+        }
+        if self.storage_dir.as_os_str().is_empty() {
+            return Err(invalid("storage_dir", "must be set"));
+        }
+        if !self.storage_dir.is_dir() {
+            return Err(invalid(
+                "storage_dir",
+                "must be an existing directory",
+            ));
+        }
+        if self.task_maker.is_empty() {
+            return Err(invalid("task_maker", "must be set"));
+        }
+        if self.web.db_workers == 0 {
+            return Err(invalid("web.db_workers", "must be at least 1"));
+        }
+        if self.evaluation.workers == 0 {
+            return Err(invalid("evaluation.workers", "must be at least 1"));
+        }
+        if self.evaluation.check_interval_secs == 0 {
+            return Err(invalid(
+                "evaluation.check_interval_secs",
+                "must be at least 1",
+            ));
+        }
+        if self.events.history_secs == 0 {
+            return Err(invalid("events.history_secs", "must be at least 1"));
+        }
+        let limits = [
+            ("login", &self.rate_limit.login),
+            ("lookup", &self.rate_limit.lookup),
+            ("submit", &self.rate_limit.submit),
+        ];
+        for (name, limit) in limits.iter() {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                return Err(invalid(
+                    &format!("rate_limit.{}", name),
+                    "must allow at least 1 request",
+                ));
+            }
+        }
+        // every worker, the plagiarism checks and the pending checker may
+        // hold a connection
+        let needed = self.web.db_workers + self.evaluation.workers + 2;
+        if (self.database.pool_size as usize) < needed {
+            return Err(invalid(
+                "database.pool_size",
+                &format!("must be at least {} with these workers", needed),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn evaluation_check_interval(&self) -> Duration {
+        Duration::from_secs(self.evaluation.check_interval_secs)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.evaluation.shutdown_timeout_secs)
+    }
+
+    pub fn events_history(&self) -> Duration {
+        Duration::from_secs(self.events.history_secs)
+    }
+}
+
+lazy_static! {
+    static ref CURRENT: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+}
+
+/// Make the configuration the one of the process.
+pub fn install(config: Config) -> Arc<Config> {
+    let config = Arc::new(config);
+    *CURRENT.write().unwrap() = Some(config.clone());
+    config
+}
+
+/// Load, validate and install the configuration, as done at startup.
+pub fn setup(path: Option<&Path>) -> Result<Arc<Config>, Error> {
+    Ok(install(Config::load(path)?))
+}
+
+/// The configuration of the process. If none was installed it's read from the
+/// environment, like in the tests.
+pub fn get() -> Arc<Config> {
+    if let Some(config) = CURRENT.read().unwrap().as_ref() {
+        return config.clone();
+    }
+    let mut current = CURRENT.write().unwrap();
+    current
+        .get_or_insert_with(|| {
+            Arc::new(Config::read(None).expect("Invalid configuration"))
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn valid_config(storage_dir: &Path) -> Config {
+        let mut config = Config::default();
+        config.storage_dir = storage_dir.to_owned();
+        config.database.url = "postgres://localhost/tmsocial".to_string();
+        config
+    }
+
+    #[test]
+    fn parse_sections() {
+        let config = Config::parse(
+            "task_maker = \"/usr/bin/task-maker\"\n\
+             [database]\n\
+             pool_size = 20\n\
+             [evaluation]\n\
+             workers = 8\n",
+        )
+        .unwrap();
+        assert_eq!(config.task_maker, "/usr/bin/task-maker");
+        assert_eq!(config.database.pool_size, 20);
+        assert_eq!(config.database.statement_timeout_ms, 30_000);
+        assert_eq!(config.evaluation.workers, 8);
+        assert_eq!(config.web, WebConfig::default());
+    }
+
+    #[test]
+    fn parse_unknown_field() {
+        assert!(Config::parse("[web]\nprot = 80\n").is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let dir = TempDir::new().unwrap();
+        assert!(valid_config(dir.path()).validate().is_ok());
+
+        let mut config = valid_config(dir.path());
+        config.database.url = String::new();
+        assert!(config.validate().is_err());
+
+        let config = valid_config(&dir.path().join("missing"));
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: storage_dir must be an existing directory"
+        );
+
+        let mut config = valid_config(dir.path());
+        config.evaluation.workers = 10;
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: database.pool_size must be at least 15 with \
+             these workers"
+        );
+
+        let mut config = valid_config(dir.path());
+        config.rate_limit.login.per_minute = 0;
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: rate_limit.login must allow at least 1 request"
+        );
+
+        let mut config = valid_config(dir.path());
+        config.events.history_secs = 0;
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: events.history_secs must be at least 1"
+        );
+    }
+}
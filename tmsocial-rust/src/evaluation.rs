@@ -2,12 +2,12 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::env;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 use actix_derive::Message;
@@ -18,14 +18,22 @@ use futures::future::{join_all, ok, Future};
 use itertools::Itertools;
 use log::{debug, error, info};
 use scopeguard::defer;
+use tempfile::TempDir;
 
-use crate::events::{Event, SubmissionUpdate};
+use crate::config::Config;
+use crate::events::{ContestUpdate, Event, SubmissionUpdate};
+use crate::logging::{self, LogContext};
 use crate::mark_internal_error;
+use crate::metrics;
 use crate::models::*;
+use crate::pool::DbPool;
+use crate::process::output_with_timeout;
+use crate::shutdown::{own_process_group, Shutdown};
 use crate::task_maker_ui::ioi::IOIResult;
 use crate::task_maker_ui::terry::TerryResult;
 use crate::task_maker_ui::{
-    SourceFileCompilationStatus, State, SubtaskNum, TaskMakerMessage,
+    SourceFileCompilationStatus, State, SubtaskNum, TaskInfo, TaskMakerMessage,
+    TestcaseNum,
 };
 
 #[derive(Debug, Fail)]
@@ -34,22 +42,47 @@ pub enum EvaluationError {
     WrongNumberOfFiles { number: usize },
     #[fail(display = "submission is already being evaluated")]
     AlreadyEvaluating,
+    #[fail(display = "the task is not an output-only IOI task")]
+    NotOutputOnly,
+    #[fail(display = "the checker didn't print a valid score")]
+    InvalidCheckerOutput,
+    #[fail(display = "no input was generated for the submission")]
+    MissingInput,
+    #[fail(display = "the submission has no output file")]
+    MissingOutput,
+    #[fail(display = "the server is shutting down")]
+    ShuttingDown,
+    #[fail(display = "the evaluation was interrupted by the shutdown")]
+    Interrupted,
+    #[fail(display = "the task has no subtask {}", subtask)]
+    MissingSubtask { subtask: SubtaskNum },
+    #[fail(display = "task-maker exited with {}", status)]
+    TaskMakerFailed { status: String },
 }
 
+/// How long the checker of an output-only task can run on an output.
+const CHECKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Evaluate the submission, taking a connection from the pool only to load
+/// and store the data, not while task-maker or a checker run.
 fn evaluate_submission(
-    conn: &PgConnection,
+    pool: &DbPool,
+    config: &Config,
+    shutdown: &Shutdown,
     submission: &Submission,
     notify: &Recipient<SubmissionUpdate>,
     user_id: i32,
 ) -> Result<f64, Error> {
-    let task_maker = env::var("TASK_MAKER").expect("TASK_MAKER must be set");
-    let storage_dir = PathBuf::new().join(Path::new(
-        &env::var("STORAGE_DIR").expect("STORAGE_DIR must be set"),
-    ));
-    let submission_dir = storage_dir.join(Path::new("submissions"));
-    let task_dir = storage_dir.join(Path::new("tasks"));
-
-    let path = task_dir.join(Path::new(&submission.task_id.to_string()));
+    let task_maker = &config.task_maker;
+    let submission_dir = config.storage_dir.join(Path::new("submissions"));
+
+    let task = {
+        use crate::schema::tasks::dsl::*;
+        tasks
+            .find(submission.task_id)
+            .get_result::<Task>(&*pool.get()?)?
+    };
+    let path = crate::task_dir(config, task.id, task.version);
 
     let mut event_count = 0;
     let mut update_status = |status| {
@@ -67,13 +100,47 @@ fn evaluate_submission(
         event_count += 1;
     };
 
+    if task.format == TaskFormat::Terry
+        || task.task_type == TaskType::OutputOnly
+    {
+        update_status(crate::events::SubmissionStatus::Started);
+        let submission_dir =
+            submission_dir.join(Path::new(&submission.id.to_string()));
+        let score = if task.format == TaskFormat::Terry {
+            evaluate_terry(pool, submission, &task, &path, &submission_dir)
+        } else {
+            evaluate_output_only(
+                pool,
+                submission,
+                &task,
+                task_maker,
+                &path,
+                &submission_dir,
+            )
+        };
+        return score.map_err(|err| {
+            error!(
+                "Failed to evaluate the outputs of submission {}, \
+                 marking as internal error",
+                submission.id
+            );
+            let marked = pool
+                .get()
+                .and_then(|conn| mark_internal_error(&conn, submission));
+            if let Err(e) = marked {
+                return e;
+            }
+            err
+        });
+    }
+
     if submission.files.len() != 1 {
         error!(
             "Multi-file submissions are not supported yet! \
              Marking {} as InternalError",
             submission.id
         );
-        mark_internal_error(conn, submission)?;
+        mark_internal_error(&*pool.get()?, submission)?;
         return Err(EvaluationError::WrongNumberOfFiles {
             number: submission.files.len(),
         }
@@ -86,7 +153,7 @@ fn evaluate_submission(
 
     let submission_path = std::fs::canonicalize(submission_path)?;
 
-    let mut tm = Command::new(task_maker)
+    let mut tm = own_process_group(&mut Command::new(task_maker))
         .arg("--ui=json")
         // unneeded checks for the evaluation
         .arg("--no-statement")
@@ -95,14 +162,26 @@ fn evaluate_submission(
         .arg(&path)
         .arg(&submission_path)
         .stdout(Stdio::piped())
-        .spawn()?;
+        .spawn()
+        .map_err(|e| {
+            metrics::TASK_MAKER_FAILURES
+                .with_label_values(&["spawn"])
+                .inc();
+            e
+        })?;
+
+    let stdout = tm.stdout.take().unwrap();
+    let tm = Arc::new(Mutex::new(tm));
+    shutdown.track(submission.id, tm.clone());
+    defer! {{
+        shutdown.untrack(submission.id);
+    }};
 
     update_status(crate::events::SubmissionStatus::Started);
 
     let mut score = 0.0;
 
     {
-        let stdout = tm.stdout.as_mut().unwrap();
         let stdout_reader = BufReader::new(stdout);
         let stdout_lines = stdout_reader.lines();
 
@@ -137,7 +216,10 @@ fn evaluate_submission(
                 // TODO: other events (TestcaseScored, SubtaskScored)
                 Ok(TaskMakerMessage::IOIResult(result)) => {
                     score = populate_ioi_submission_results(
-                        &conn, submission, &result,
+                        &*pool.get()?,
+                        submission,
+                        &task,
+                        &result,
                     )
                     .map_err(|err| {
                         error!(
@@ -145,7 +227,10 @@ fn evaluate_submission(
                              for submission {}, marking as internal error",
                             submission.id
                         );
-                        match crate::mark_internal_error(conn, submission) {
+                        let marked = pool.get().and_then(|conn| {
+                            crate::mark_internal_error(&conn, submission)
+                        });
+                        match marked {
                             Err(e) => return e,
                             _ => {}
                         };
@@ -154,7 +239,9 @@ fn evaluate_submission(
                 }
                 Ok(TaskMakerMessage::TerryResult(result)) => {
                     score = populate_terry_submission_results(
-                        &conn, submission, &result,
+                        &*pool.get()?,
+                        submission,
+                        &result,
                     )
                     .map_err(|err| {
                         error!(
@@ -162,7 +249,10 @@ fn evaluate_submission(
                              for submission {}, marking as internal error",
                             submission.id
                         );
-                        match crate::mark_internal_error(conn, submission) {
+                        let marked = pool.get().and_then(|conn| {
+                            crate::mark_internal_error(&conn, submission)
+                        });
+                        match marked {
                             Err(e) => return e,
                             _ => {}
                         };
@@ -170,6 +260,9 @@ fn evaluate_submission(
                     })?;
                 }
                 Err(err) => {
+                    metrics::TASK_MAKER_FAILURES
+                        .with_label_values(&["invalid_output"])
+                        .inc();
                     error!("err: {} {}", err, line);
                     update_status(crate::events::SubmissionStatus::Error {
                         message: err.to_string(),
@@ -180,17 +273,45 @@ fn evaluate_submission(
         }
     }
 
-    tm.wait()?;
+    let exit_status = tm.lock().unwrap().wait()?;
+    if shutdown.is_killed(submission.id) {
+        requeue(&*pool.get()?, submission)?;
+        return Err(EvaluationError::Interrupted.into());
+    }
+    if !exit_status.success() {
+        metrics::TASK_MAKER_FAILURES
+            .with_label_values(&["exit_status"])
+            .inc();
+        error!(
+            "task-maker exited with {} evaluating submission {}, marking as \
+             internal error",
+            exit_status, submission.id
+        );
+        mark_internal_error(&*pool.get()?, submission)?;
+        return Err(EvaluationError::TaskMakerFailed {
+            status: exit_status.to_string(),
+        }
+        .into());
+    }
 
     Ok(score)
 }
 
+/// Put a submission back among the ones waiting for an evaluation.
+fn requeue(conn: &PgConnection, submission: &Submission) -> Result<(), Error> {
+    use crate::schema::submissions::dsl::*;
+    diesel::update(submissions.find(submission.id))
+        .set(status.eq(SubmissionStatus::Waiting))
+        .execute(conn)?;
+    Ok(())
+}
+
 fn populate_ioi_submission_results(
     conn: &PgConnection,
     submission: &Submission,
+    task: &Task,
     result: &IOIResult,
 ) -> Result<f64, Error> {
-    use crate::schema::tasks::dsl::*;
     let compilation = result
         .solutions
         .get(&submission.files[0])
@@ -215,14 +336,16 @@ fn populate_ioi_submission_results(
                 status.eq(SubmissionStatus::CompilationError),
                 crate::schema::submissions::dsl::score.eq(0.0),
                 compilation_messages.eq(compilation_stderr),
+                accepted.eq(false),
+                task_version.eq(task.version),
             ))
             .execute(conn)?;
         debug!("Evaluation of submission {} completed", submission.id);
         return Ok(0.0);
     }
 
-    let task = tasks.find(submission.task_id).get_result::<Task>(conn)?;
-    let subtasks: HashMap<SubtaskNum, Subtask> = Subtask::belonging_to(&task)
+    let subtasks: HashMap<SubtaskNum, Subtask> = Subtask::belonging_to(task)
+        .filter(crate::schema::subtasks::dsl::active.eq(true))
         .load::<Subtask>(conn)?
         .into_iter()
         .map(|st| (st.num, st))
@@ -238,6 +361,14 @@ fn populate_ioi_submission_results(
         })
         .collect();
 
+    let is_accepted = solves_all(
+        solution_result
+            .testcase_results
+            .values()
+            .flat_map(|subtask| subtask.values())
+            .map(|testcase| f64::from(testcase.score)),
+    );
+
     conn.transaction(|| -> Result<(), diesel::result::Error> {
         use crate::schema::submissions::dsl::*;
         use crate::schema::subtask_results::dsl::*;
@@ -250,6 +381,8 @@ fn populate_ioi_submission_results(
                 crate::schema::submissions::dsl::score
                     .eq(solution_result.score as f64),
                 compilation_messages.eq(compilation_stderr),
+                accepted.eq(is_accepted),
+                task_version.eq(task.version),
             ))
             .execute(conn)?;
 
@@ -285,6 +418,7 @@ fn populate_ioi_submission_results(
                                 .expect("Result of subtask not inserted"),
                             *tc_num,
                             testcase,
+                            task.task_type,
                         )
                     })
                     .collect()
@@ -301,6 +435,251 @@ fn populate_ioi_submission_results(
     Ok(solution_result.score as f64)
 }
 
+/// Whether a submission with these testcase scores is accepted: it must solve
+/// every testcase, and there must be at least one.
+fn solves_all<I: Iterator<Item = f64>>(mut scores: I) -> bool {
+    let mut any = false;
+    let all = scores.all(|score| {
+        any = true;
+        score >= 1.0
+    });
+    any && all
+}
+
+/// Evaluate the outputs submitted for an output-only task, running only the
+/// checker on each testcase. The outputs may also be sent inside a zip.
+fn evaluate_output_only(
+    pool: &DbPool,
+    submission: &Submission,
+    task: &Task,
+    task_maker: &str,
+    path: &Path,
+    submission_dir: &Path,
+) -> Result<f64, Error> {
+    let info = match crate::task_import::read_task_info(task_maker, path)? {
+        TaskInfo::IOITask(info) => info,
+        _ => return Err(EvaluationError::NotOutputOnly.into()),
+    };
+
+    let extract_dir = TempDir::new()?;
+    let outputs_dir = match submission.files.as_slice() {
+        [file] if file.ends_with(".zip") => {
+            crate::task_import::extract_archive(
+                &submission_dir.join(file),
+                extract_dir.path(),
+            )?
+        }
+        _ => submission_dir.to_owned(),
+    };
+    let mut outputs: HashMap<TestcaseNum, PathBuf> = HashMap::new();
+    for entry in std::fs::read_dir(&outputs_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(tc) = output_testcase(&name) {
+            outputs.insert(tc, entry.path());
+        }
+    }
+
+    let subtasks: HashMap<SubtaskNum, Subtask> = Subtask::belonging_to(task)
+        .filter(crate::schema::subtasks::dsl::active.eq(true))
+        .load::<Subtask>(&*pool.get()?)?
+        .into_iter()
+        .map(|st| (st.num, st))
+        .collect();
+    let mut results: Vec<(i32, f64, Vec<(TestcaseNum, f64, String)>)> = vec![];
+    for (st_num, subtask) in &info.subtasks {
+        let st = subtasks
+            .get(st_num)
+            .ok_or(EvaluationError::MissingSubtask { subtask: *st_num })?;
+        let mut testcases = vec![];
+        for tc in subtask.cases.keys() {
+            let (score, message) = match outputs.get(tc) {
+                Some(output) => check_output(path, *tc, output)?,
+                None => (0.0, "Missing output file".to_string()),
+            };
+            testcases.push((*tc, score, message));
+        }
+        let min_score = testcases
+            .iter()
+            .map(|(_, score, _)| *score)
+            .fold(1.0, f64::min);
+        results.push((st.id, min_score * st.max_score, testcases));
+    }
+    store_checked_results(&*pool.get()?, submission, task, &results)
+}
+
+/// Results of a subtask scored without running the solution: the id of the
+/// subtask, its score and the score and message of each testcase.
+type CheckedSubtask = (i32, f64, Vec<(TestcaseNum, f64, String)>);
+
+/// Store the results of a submission scored by a checker only, like the ones
+/// of output-only and Terry tasks.
+fn store_checked_results(
+    conn: &PgConnection,
+    submission: &Submission,
+    task: &Task,
+    results: &Vec<CheckedSubtask>,
+) -> Result<f64, Error> {
+    let total: f64 = results.iter().map(|(_, score, _)| score).sum();
+    let is_accepted = solves_all(
+        results
+            .iter()
+            .flat_map(|(_, _, testcases)| testcases.iter())
+            .map(|(_, score, _)| *score),
+    );
+
+    conn.transaction(|| -> Result<(), diesel::result::Error> {
+        use crate::schema::submissions::dsl::*;
+        use crate::schema::subtask_results::dsl::subtask_results;
+        use crate::schema::testcase_results::dsl::testcase_results;
+
+        diesel::update(submissions.find(submission.id))
+            .set((
+                status.eq(SubmissionStatus::Success),
+                score.eq(total),
+                accepted.eq(is_accepted),
+                task_version.eq(task.version),
+            ))
+            .execute(conn)?;
+        for (subtask_id, subtask_score, testcases) in results {
+            let result_id = diesel::insert_into(subtask_results)
+                .values(NewSubtaskResult {
+                    submission_id: submission.id,
+                    score: *subtask_score,
+                    subtask_id: *subtask_id,
+                })
+                .returning(crate::schema::subtask_results::dsl::id)
+                .get_result::<i32>(conn)?;
+            let new_testcase_results: Vec<NewTestcaseResult> = testcases
+                .iter()
+                .map(|(tc, tc_score, message)| NewTestcaseResult {
+                    subtask_result_id: result_id,
+                    running_time: 0.0,
+                    memory_usage: 0,
+                    message,
+                    score: *tc_score,
+                    num: *tc,
+                })
+                .collect();
+            diesel::insert_into(testcase_results)
+                .values(new_testcase_results)
+                .execute(conn)?;
+        }
+        Ok(())
+    })?;
+    debug!("Evaluation of submission {} completed", submission.id);
+    Ok(total)
+}
+
+/// Evaluate the output of a Terry task against the input generated with the
+/// seed assigned to the submission.
+fn evaluate_terry(
+    pool: &DbPool,
+    submission: &Submission,
+    task: &Task,
+    path: &Path,
+    submission_dir: &Path,
+) -> Result<f64, Error> {
+    let conn = pool.get()?;
+    let input = {
+        use crate::schema::terry_inputs::dsl::*;
+        terry_inputs
+            .filter(submission_id.eq(submission.id))
+            .first::<TerryInput>(&conn)
+            .optional()?
+            .ok_or(EvaluationError::MissingInput)?
+    };
+    let subtask = Subtask::belonging_to(task)
+        .filter(crate::schema::subtasks::dsl::active.eq(true))
+        .first::<Subtask>(&conn)?;
+    // not held while the managers run
+    drop(conn);
+    let output = submission
+        .files
+        .iter()
+        .find(|f| Path::new(f).file_stem().map_or(false, |s| s == "output"))
+        .ok_or(EvaluationError::MissingOutput)?;
+    let input_dir = TempDir::new()?;
+    let input_path = input_dir.path().join("input.txt");
+    crate::terry::generate_input(path, input.seed, &input_path)?;
+    let outcome = crate::terry::check_output(
+        path,
+        &input_path,
+        &submission_dir.join(output),
+    )?;
+
+    let mut testcases: Vec<(TestcaseNum, f64, String)> = outcome
+        .feedback
+        .cases
+        .iter()
+        .enumerate()
+        .map(|(num, case)| {
+            let score = if case.correct { 1.0 } else { 0.0 };
+            let message = case.message.clone().unwrap_or_default();
+            (num as TestcaseNum, score, message)
+        })
+        .collect();
+    // without feedback the whole output counts as a single testcase
+    if testcases.is_empty() {
+        testcases.push((0, outcome.score, String::new()));
+    }
+    let results =
+        vec![(subtask.id, outcome.score * subtask.max_score, testcases)];
+    store_checked_results(&*pool.get()?, submission, task, &results)
+}
+
+/// The testcase of a submitted output file, named like `output_007.txt` or
+/// after the field of the testcase, like `output_7.out`.
+pub fn output_testcase(name: &str) -> Option<TestcaseNum> {
+    let stem = Path::new(name).file_stem()?.to_str()?;
+    if !stem.starts_with("output") {
+        return None;
+    }
+    stem["output".len()..].trim_start_matches('_').parse().ok()
+}
+
+/// Score an output with the checker of the task, `check/checker`, or by
+/// comparing it with the correct output if the task has no checker.
+fn check_output(
+    task_dir: &Path,
+    testcase: TestcaseNum,
+    output: &Path,
+) -> Result<(f64, String), Error> {
+    let input = task_dir.join(format!("input/input{}.txt", testcase));
+    let correct = task_dir.join(format!("output/output{}.txt", testcase));
+    let checker = task_dir.join("check/checker");
+    if !checker.is_file() {
+        let same =
+            white_diff(&std::fs::read(correct)?, &std::fs::read(output)?);
+        return Ok(match same {
+            true => (1.0, "Output is correct".to_string()),
+            false => (0.0, "Output isn't correct".to_string()),
+        });
+    }
+    let result = output_with_timeout(
+        Command::new(checker).arg(input).arg(correct).arg(output),
+        CHECKER_TIMEOUT,
+    )?;
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let score = stdout
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| EvaluationError::InvalidCheckerOutput)?;
+    let message = String::from_utf8_lossy(&result.stderr).trim().to_string();
+    Ok((score.max(0.0).min(1.0), message))
+}
+
+/// Compare two outputs ignoring the whitespaces.
+fn white_diff(a: &[u8], b: &[u8]) -> bool {
+    let tokens = |s: &[u8]| -> Vec<Vec<u8>> {
+        s.split(|c| c.is_ascii_whitespace())
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_vec())
+            .collect()
+    };
+    tokens(a) == tokens(b)
+}
+
 fn populate_terry_submission_results(
     _conn: &PgConnection,
     _submission: &Submission,
@@ -313,7 +692,9 @@ fn populate_terry_submission_results(
 /// when the evaluation is completed the results are stored in the DB.
 /// If an error condition occurs the submission is flagged as internal_error.
 pub struct Evaluator {
-    conn: PgConnection,
+    pool: DbPool,
+    config: Arc<Config>,
+    shutdown: Shutdown,
     in_evaluation: Arc<Mutex<HashSet<i32>>>,
 }
 
@@ -322,16 +703,23 @@ pub struct Evaluator {
 pub struct Evaluate {
     pub submission: Submission,
     pub user_id: i32,
+    /// The request that created the submission, if any.
+    pub request_id: Option<String>,
     pub notify: Recipient<SubmissionUpdate>,
+    pub contest_notify: Recipient<ContestUpdate>,
 }
 
 impl Evaluator {
     pub fn new(
-        conn: PgConnection,
+        pool: DbPool,
+        config: Arc<Config>,
+        shutdown: Shutdown,
         in_eval: Arc<Mutex<HashSet<i32>>>,
     ) -> Evaluator {
         Evaluator {
-            conn: conn,
+            pool: pool,
+            config: config,
+            shutdown: shutdown,
             in_evaluation: in_eval,
         }
     }
@@ -344,6 +732,22 @@ impl Actor for Evaluator {
 impl Handler<Evaluate> for Evaluator {
     type Result = Result<(), Error>;
     fn handle(&mut self, msg: Evaluate, _: &mut Self::Context) -> Self::Result {
+        metrics::EVALUATION_QUEUE.dec();
+        let _context = logging::scope(LogContext {
+            request_id: msg.request_id.clone(),
+            submission_id: Some(msg.submission.id),
+            task_id: Some(msg.submission.task_id),
+            user_id: Some(msg.user_id),
+        });
+        if self.shutdown.is_started() {
+            // the submission stays waiting, for the next start
+            info!(
+                "Shutting down, not evaluating submission {}",
+                msg.submission.id
+            );
+            metrics::EVALUATIONS.with_label_values(&["requeued"]).inc();
+            return Err(EvaluationError::ShuttingDown.into());
+        }
         let send_status = |status| {
             let err = msg.notify.do_send(SubmissionUpdate {
                 event: Event {
@@ -367,6 +771,7 @@ impl Handler<Evaluate> for Evaluator {
                 "Submission {} is already being evaluated!",
                 msg.submission.id
             );
+            metrics::EVALUATIONS.with_label_values(&["duplicate"]).inc();
             let err: Error = EvaluationError::AlreadyEvaluating.into();
             send_status(crate::events::SubmissionStatus::Error {
                 message: err.to_string(),
@@ -374,15 +779,29 @@ impl Handler<Evaluate> for Evaluator {
             return Err(err);
         }
         self.in_evaluation.lock().unwrap().insert(msg.submission.id);
+        metrics::EVALUATIONS_RUNNING.inc();
         defer! {{
             self.in_evaluation.lock().unwrap().remove(&msg.submission.id);
+            metrics::EVALUATIONS_RUNNING.dec();
         }};
+        let start = Instant::now();
         let result = evaluate_submission(
-            &self.conn,
+            &self.pool,
+            &self.config,
+            &self.shutdown,
             &msg.submission,
             &msg.notify,
             msg.user_id,
         );
+        metrics::EVALUATION_DURATION.observe(metrics::seconds(start.elapsed()));
+        let outcome = match &result {
+            Ok(_) => "done",
+            Err(e) => match e.downcast_ref::<EvaluationError>() {
+                Some(EvaluationError::Interrupted) => "requeued",
+                _ => "failed",
+            },
+        };
+        metrics::EVALUATIONS.with_label_values(&[outcome]).inc();
         match result {
             Err(e) => {
                 send_status(crate::events::SubmissionStatus::Error {
@@ -394,6 +813,21 @@ impl Handler<Evaluate> for Evaluator {
                 send_status(crate::events::SubmissionStatus::Done {
                     score: score,
                 });
+                let balloon = self.pool.get().and_then(|conn| {
+                    crate::icpc::make_balloon(&conn, msg.submission.id)
+                });
+                match balloon {
+                    Ok(Some(update)) => {
+                        if let Err(e) = msg.contest_notify.do_send(update) {
+                            error!("Error sending balloon: {}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!(
+                        "Failed to check the balloon of submission {}: {}",
+                        msg.submission.id, e
+                    ),
+                }
                 Ok(())
             }
         }
@@ -402,13 +836,16 @@ impl Handler<Evaluate> for Evaluator {
 
 /// Actor that will look for pending submissions and evaluate them when it receives the
 /// EvaluatePending message.
-pub struct CheckPending(pub Addr<Evaluator>);
+pub struct CheckPending(pub Addr<Evaluator>, pub DbPool);
 
 impl Actor for CheckPending {
     type Context = Context<Self>;
 }
 
-pub struct EvaluatePending(pub Recipient<crate::events::SubmissionUpdate>);
+pub struct EvaluatePending {
+    pub notify: Recipient<SubmissionUpdate>,
+    pub contest_notify: Recipient<ContestUpdate>,
+}
 
 impl Message for EvaluatePending {
     type Result = Result<(), Error>;
@@ -427,7 +864,10 @@ impl Handler<EvaluatePending> for CheckPending {
 
         // TODO: remove blocking
 
-        let conn = crate::establish_connection();
+        let conn = match self.1.get() {
+            Ok(conn) => conn,
+            Err(e) => return Box::new(actix::fut::err(e)),
+        };
 
         let results = submissions
             .filter(status.eq(SubmissionStatus::Waiting))
@@ -450,10 +890,13 @@ impl Handler<EvaluatePending> for CheckPending {
                 .expect("Error loading participation");
             assert!(participation.len() == 1);
             let participation = &participation[0];
+            metrics::EVALUATION_QUEUE.inc();
             futs.push(self.0.send(crate::evaluation::Evaluate {
                 user_id: participation.user_id,
+                request_id: None,
                 submission: sub,
-                notify: msg.0.clone(),
+                notify: msg.notify.clone(),
+                contest_notify: msg.contest_notify.clone(),
             }));
         }
 
@@ -470,3 +913,71 @@ impl Handler<EvaluatePending> for CheckPending {
         Box::new(actix::fut::wrap_future::<_, Self>(fut))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepted() {
+        assert!(solves_all(vec![1.0, 1.0].into_iter()));
+        assert!(!solves_all(vec![1.0, 0.5].into_iter()));
+        assert!(!solves_all(vec![].into_iter()));
+    }
+
+    #[test]
+    fn output_testcases() {
+        assert_eq!(output_testcase("output_007.txt"), Some(7));
+        assert_eq!(output_testcase("output12.txt"), Some(12));
+        assert_eq!(output_testcase("input_007.txt"), None);
+        assert_eq!(output_testcase("output_a.txt"), None);
+        // uploaded in the field of the testcase, with any extension
+        assert_eq!(output_testcase("output_3.out"), Some(3));
+        assert_eq!(output_testcase("output_3"), Some(3));
+    }
+
+    #[test]
+    fn communication_resources() {
+        let process = |time: f32, memory: u32| {
+            format!(
+                r#"{{"status": "SUCCESS", "signal": null, "return_code": 0,
+                "error": null, "was_cached": false, "was_killed": false,
+                "resources": {{"cpu_time": {}, "sys_time": 0.0,
+                "wall_time": 1.0, "memory": {}}}}}"#,
+                time, memory
+            )
+        };
+        let testcase: crate::task_maker_ui::ioi::IOISolutionTestCaseResult =
+            serde_json::from_str(&format!(
+                r#"{{"status": "ACCEPTED", "result": [{}, {}, null],
+                "score": 1.0, "message": "Output is correct",
+                "checker_outcome": "Correct, 42 queries",
+                "checker_result": null}}"#,
+                process(0.5, 1000),
+                process(0.25, 2000)
+            ))
+            .unwrap();
+        let result = NewTestcaseResult::from_ioi_testcase_result(
+            1,
+            0,
+            &testcase,
+            TaskType::Communication,
+        );
+        assert_eq!(result.running_time, 0.75);
+        assert_eq!(result.memory_usage, 3000);
+        assert_eq!(result.message, "Correct, 42 queries");
+        let result = NewTestcaseResult::from_ioi_testcase_result(
+            1,
+            0,
+            &testcase,
+            TaskType::Batch,
+        );
+        assert_eq!(result.message, "Output is correct");
+    }
+
+    #[test]
+    fn white_diff_ignores_spaces() {
+        assert!(white_diff(b"1 2\n3\n", b"1  2 3"));
+        assert!(!white_diff(b"1 2 3", b"1 2"));
+    }
+}
@@ -7,8 +7,7 @@ use serde_derive::Serialize;
 use std::collections::{HashMap, VecDeque};
 use std::time::{Duration, Instant};
 
-/// How long events are kept.
-const HISTORY_DURATION: Duration = Duration::from_secs(300);
+use crate::metrics;
 
 #[derive(Serialize, Debug, Clone)]
 pub enum SubmissionStatus {
@@ -50,33 +49,71 @@ pub struct SubmissionUpdate {
     pub event: Event,
 }
 
+/// Notification that a participant of an ICPC contest solved a task.
+#[derive(Message, Serialize, Debug, Clone)]
+pub struct Balloon {
+    pub contest_id: i32,
+    pub task_id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub submission_id: i32,
+    /// Whether this is the first accepted submission of the task.
+    pub first_solve: bool,
+}
+
+#[derive(Message, Debug)]
+pub struct ContestUpdate {
+    /// Users that should receive the balloon.
+    pub recipients: Vec<i32>,
+    pub balloon: Balloon,
+}
+
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
     pub user_id: i32,
     pub rcp: Recipient<Event>,
+    pub balloon_rcp: Recipient<Balloon>,
+    pub close_rcp: Recipient<CloseSession>,
 }
 
+/// Ask a session to close, as the server is shutting down.
+#[derive(Message, Debug, Clone)]
+pub struct CloseSession;
+
+/// Close all the sessions, as the server is shutting down.
+#[derive(Message, Debug)]
+pub struct CloseSessions;
+
 #[derive(Message)]
 pub struct Disconnect {
     pub user_id: i32,
     pub session_id: usize,
 }
 
+struct Session {
+    event: Recipient<Event>,
+    balloon: Recipient<Balloon>,
+    close: Recipient<CloseSession>,
+}
+
 pub struct EventManager {
     // user_id to (session_id to handler).
-    sessions: HashMap<i32, HashMap<usize, Recipient<Event>>>,
+    sessions: HashMap<i32, HashMap<usize, Session>>,
     // enqueued events: user_id to deque (timestamp, event).
     events: HashMap<i32, VecDeque<(Instant, Event)>>,
     rng: ThreadRng,
+    /// How long events are kept.
+    history: Duration,
 }
 
 impl EventManager {
-    pub fn new() -> EventManager {
+    pub fn new(history: Duration) -> EventManager {
         EventManager {
             sessions: HashMap::new(),
             events: HashMap::new(),
             rng: rand::thread_rng(),
+            history: history,
         }
     }
 
@@ -92,7 +129,7 @@ impl EventManager {
                     break;
                 }
                 let evt = evt.unwrap();
-                if now.duration_since(evt.0) > HISTORY_DURATION {
+                if now.duration_since(evt.0) > self.history {
                     evts.pop_front();
                 } else {
                     break;
@@ -102,6 +139,14 @@ impl EventManager {
         for uid in to_erase {
             self.events.remove(&uid);
         }
+        self.update_metrics();
+    }
+
+    fn update_metrics(&self) {
+        let sessions: usize = self.sessions.values().map(|s| s.len()).sum();
+        let events: usize = self.events.values().map(|e| e.len()).sum();
+        metrics::WEBSOCKET_SESSIONS.set(sessions as i64);
+        metrics::BUFFERED_EVENTS.set(events as i64);
     }
 }
 
@@ -110,7 +155,7 @@ impl Actor for EventManager {
 
     /// Method is called on actor start.
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(HISTORY_DURATION, |act, _| {
+        ctx.run_interval(self.history, |act, _| {
             act.cleanup();
         });
     }
@@ -127,7 +172,14 @@ impl Handler<Connect> for EventManager {
             self.sessions.insert(msg.user_id, HashMap::new());
         }
         let user_sessions = self.sessions.get_mut(&msg.user_id).unwrap();
-        user_sessions.insert(id, msg.rcp.clone());
+        user_sessions.insert(
+            id,
+            Session {
+                event: msg.rcp.clone(),
+                balloon: msg.balloon_rcp,
+                close: msg.close_rcp,
+            },
+        );
 
         // Send waiting events
         let user_events = self.events.get(&msg.user_id);
@@ -140,6 +192,8 @@ impl Handler<Connect> for EventManager {
             }
         }
 
+        self.update_metrics();
+
         // Return session id
         id
     }
@@ -171,6 +225,7 @@ impl Handler<Disconnect> for EventManager {
                 msg.user_id, msg.session_id
             );
         }
+        self.update_metrics();
     }
 }
 
@@ -187,7 +242,7 @@ impl Handler<SubmissionUpdate> for EventManager {
         let user_sessions = self.sessions.get(&msg.user_id);
         if let Some(sessions) = user_sessions {
             for session in sessions.values() {
-                let result = session.do_send(msg.event.clone());
+                let result = session.event.do_send(msg.event.clone());
                 if let Err(error) = result {
                     error!("{}", error);
                 }
@@ -200,5 +255,43 @@ impl Handler<SubmissionUpdate> for EventManager {
         }
         let user_events = self.events.get_mut(&msg.user_id).unwrap();
         user_events.push_back((Instant::now(), msg.event));
+        self.update_metrics();
+    }
+}
+
+impl Handler<ContestUpdate> for EventManager {
+    type Result = ();
+    fn handle(
+        &mut self,
+        msg: ContestUpdate,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        info!(
+            "Balloon for user {} on task {}",
+            msg.balloon.user_id, msg.balloon.task_id
+        );
+        for user_id in &msg.recipients {
+            if let Some(sessions) = self.sessions.get(user_id) {
+                for session in sessions.values() {
+                    let result = session.balloon.do_send(msg.balloon.clone());
+                    if let Err(error) = result {
+                        error!("{}", error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Handler<CloseSessions> for EventManager {
+    type Result = ();
+    fn handle(&mut self, _: CloseSessions, _: &mut Context<Self>) {
+        let sessions: usize = self.sessions.values().map(|s| s.len()).sum();
+        info!("Closing {} websocket sessions", sessions);
+        for session in self.sessions.values().flat_map(|s| s.values()) {
+            if let Err(error) = session.close.do_send(CloseSession) {
+                error!("{}", error);
+            }
+        }
     }
 }
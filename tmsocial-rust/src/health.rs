@@ -0,0 +1,231 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::models::SubmissionStatus;
+use crate::process::{output_with_timeout, TimedOut};
+
+/// How long `task-maker --version` can take before task-maker is considered
+/// not usable.
+pub const TASK_MAKER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long all the checks can take, waiting for a database executor too.
+pub const READINESS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of the check of a dependency of the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Whether the server can handle requests, with the checks of its
+/// dependencies.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: Check,
+    pub storage: Check,
+    pub task_maker: Check,
+    pub evaluator: Check,
+}
+
+impl Check {
+    pub fn ok<S: Into<String>>(detail: S) -> Check {
+        Check {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn failed<S: Into<String>>(detail: S) -> Check {
+        Check {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Readiness {
+    pub fn new(
+        database: Check,
+        storage: Check,
+        task_maker: Check,
+        evaluator: Check,
+    ) -> Readiness {
+        Readiness {
+            ready: database.ok && storage.ok && task_maker.ok && evaluator.ok,
+            database,
+            storage,
+            task_maker,
+            evaluator,
+        }
+    }
+}
+
+/// Check that a file can be written in the storage directory.
+///
+/// # Example
+/// ```
+/// use tempfile::TempDir;
+/// use tmsocial::health::check_storage;
+///
+/// let dir = TempDir::new().unwrap();
+/// assert!(check_storage(dir.path()).ok);
+/// assert!(!check_storage(&dir.path().join("missing")).ok);
+/// ```
+pub fn check_storage(storage_dir: &Path) -> Check {
+    let written = NamedTempFile::new_in(storage_dir)
+        .and_then(|mut file| file.write_all(b"tmsocial"));
+    match written {
+        Ok(()) => Check::ok(format!("{} is writable", storage_dir.display())),
+        Err(e) => Check::failed(format!(
+            "cannot write in {}: {}",
+            storage_dir.display(),
+            e
+        )),
+    }
+}
+
+/// Check that task-maker can be run within `timeout`, reporting its version.
+pub fn check_task_maker(task_maker: &str, timeout: Duration) -> Check {
+    let output =
+        output_with_timeout(Command::new(task_maker).arg("--version"), timeout);
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => match e.downcast_ref::<TimedOut>() {
+            Some(_) => {
+                return Check::failed(format!(
+                    "{} --version did not exit within {:?}",
+                    task_maker, timeout
+                ))
+            }
+            None => {
+                return Check::failed(format!(
+                    "cannot run {}: {}",
+                    task_maker, e
+                ))
+            }
+        },
+    };
+    if !output.status.success() {
+        return Check::failed(format!(
+            "{} --version exited with {}",
+            task_maker, output.status
+        ));
+    }
+    // older versions print it on stderr
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let version = stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("unknown version");
+    Check::ok(version)
+}
+
+/// Check the connection to the database and, using it, that the submissions
+/// waiting for an evaluation are at most `max_backlog`.
+pub fn check_database(conn: &PgConnection, max_backlog: i64) -> (Check, Check) {
+    use crate::schema::submissions::dsl::*;
+
+    let waiting = submissions
+        .filter(status.eq(SubmissionStatus::Waiting))
+        .count()
+        .get_result::<i64>(conn);
+    match waiting {
+        Ok(waiting) => {
+            (Check::ok("connected"), check_backlog(waiting, max_backlog))
+        }
+        Err(e) => (
+            Check::failed(e.to_string()),
+            Check::failed("cannot count the waiting submissions"),
+        ),
+    }
+}
+
+/// Check that the evaluators are keeping up with the submissions.
+///
+/// # Example
+/// ```
+/// use tmsocial::health::check_backlog;
+///
+/// assert!(check_backlog(3, 100).ok);
+/// assert!(!check_backlog(101, 100).ok);
+/// ```
+pub fn check_backlog(waiting: i64, max_backlog: i64) -> Check {
+    let detail = format!("{} submissions waiting", waiting);
+    if waiting > max_backlog {
+        Check::failed(detail)
+    } else {
+        Check::ok(detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::test_utils::{fake_task_maker, FakeSite};
+
+    use super::*;
+
+    #[test]
+    fn task_maker_missing() {
+        let check =
+            check_task_maker("/nonexistent/task-maker", TASK_MAKER_TIMEOUT);
+        assert!(!check.ok);
+        assert!(check.detail.starts_with("cannot run"));
+    }
+
+    #[test]
+    fn task_maker_hanging() {
+        let dir = TempDir::new().unwrap();
+        let task_maker = fake_task_maker(dir.path(), "sleep 10");
+        let check = check_task_maker(&task_maker, Duration::from_millis(100));
+        assert!(!check.ok);
+        assert!(check.detail.ends_with("did not exit within 100ms"));
+    }
+
+    #[test]
+    fn task_maker_version() {
+        let dir = TempDir::new().unwrap();
+        let task_maker = fake_task_maker(dir.path(), "echo task-maker 1.2");
+        let check = check_task_maker(&task_maker, TASK_MAKER_TIMEOUT);
+        assert_eq!(check, Check::ok("task-maker 1.2"));
+    }
+
+    #[test]
+    fn database() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let participation = site.participation(&contest, &site.user("user"));
+        site.submission(&task, &participation);
+        let (database, evaluator) =
+            check_database(&site.conn, i64::max_value());
+        assert!(database.ok);
+        assert!(evaluator.ok);
+        let (_, evaluator) = check_database(&site.conn, 0);
+        assert!(!evaluator.ok);
+    }
+
+    #[test]
+    fn readiness() {
+        let readiness = Readiness::new(
+            Check::ok("connected"),
+            Check::ok("writable"),
+            Check::failed("missing"),
+            Check::ok("0 submissions waiting"),
+        );
+        assert!(!readiness.ready);
+    }
+}
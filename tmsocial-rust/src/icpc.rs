@@ -0,0 +1,455 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::events::{Balloon, ContestUpdate};
+use crate::models::*;
+use crate::task_maker_ui::hash_map_serializers::*;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct IcpcTaskResult {
+    pub solved: bool,
+    /// Number of rejected submissions before the first accepted one.
+    pub rejected: i32,
+    /// Number of submissions whose verdict is hidden by the freeze or that
+    /// are still waiting for the evaluation.
+    pub pending: i32,
+    /// Minutes from the start of the contest to the first accepted
+    /// submission.
+    pub solve_time: Option<i64>,
+    pub first_solve: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IcpcScoreboardRow {
+    pub rank: usize,
+    pub user_id: i32,
+    pub username: String,
+    pub solved: i32,
+    pub penalty: i64,
+    #[serde(with = "serialize_hash_map")]
+    pub tasks: HashMap<i32, IcpcTaskResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IcpcScoreboard {
+    pub frozen: bool,
+    pub tasks: Vec<Task>,
+    pub rows: Vec<IcpcScoreboardRow>,
+}
+
+/// The moment from which the public scoreboard of the contest stops being
+/// updated, if the contest has an end time.
+pub fn freeze_start(contest: &Contest) -> Option<NaiveDateTime> {
+    contest
+        .end_time
+        .map(|end| end - Duration::minutes(contest.freeze_minutes.into()))
+}
+
+/// Whether the public scoreboard is frozen at the given time. The freeze is
+/// lifted as soon as the contest ends.
+pub fn is_frozen(contest: &Contest, now: NaiveDateTime) -> bool {
+    match (freeze_start(contest), contest.end_time) {
+        (Some(start), Some(end)) => now >= start && now < end,
+        _ => false,
+    }
+}
+
+/// Minutes elapsed from the start of the contest to the submission.
+fn submission_minute(contest: &Contest, submission: &Submission) -> i64 {
+    contest
+        .start_time
+        .map(|start| (submission.created_at - start).num_minutes().max(0))
+        .unwrap_or(0)
+}
+
+/// Whether the submission was sent after the end of the contest.
+fn is_after_end(contest: &Contest, submission: &Submission) -> bool {
+    contest
+        .end_time
+        .map(|end| submission.created_at >= end)
+        .unwrap_or(false)
+}
+
+/// Whether submission `a` was sent before submission `b`.
+fn sent_before(a: &Submission, b: &Submission) -> bool {
+    (a.created_at, a.id) < (b.created_at, b.id)
+}
+
+/// Compute the ICPC ranking of the participants. Compilation errors and
+/// internal errors are not penalized, the submissions sent after the end of
+/// the contest are ignored. If `frozen_at` is provided the verdicts of the
+/// submissions sent after that moment are hidden.
+///
+/// # Example
+/// ```
+/// use tmsocial::icpc::compute_scoreboard;
+/// use tmsocial::test_utils::*;
+///
+/// # let site = FakeSite::new();
+/// let contest = site.contest("contest");
+/// let scoreboard = compute_scoreboard(&contest, vec![], vec![], vec![], None);
+/// assert!(scoreboard.rows.is_empty());
+/// ```
+pub fn compute_scoreboard(
+    contest: &Contest,
+    tasks: Vec<Task>,
+    participants: Vec<(Participation, User)>,
+    mut submissions: Vec<Submission>,
+    frozen_at: Option<NaiveDateTime>,
+) -> IcpcScoreboard {
+    submissions.sort_by_key(|s| (s.created_at, s.id));
+
+    // (participation_id, task_id) to result
+    let mut results: HashMap<(i32, i32), IcpcTaskResult> = HashMap::new();
+    let mut solved_tasks: HashSet<i32> = HashSet::new();
+    for sub in &submissions {
+        if sub.status == SubmissionStatus::CompilationError
+            || sub.status == SubmissionStatus::InternalError
+        {
+            continue;
+        }
+        if is_after_end(contest, sub) {
+            continue;
+        }
+        let result = results
+            .entry((sub.participation_id, sub.task_id))
+            .or_insert_with(IcpcTaskResult::default);
+        if result.solved {
+            continue;
+        }
+        let hidden = frozen_at.map(|f| sub.created_at >= f).unwrap_or(false);
+        if hidden || sub.status == SubmissionStatus::Waiting {
+            result.pending += 1;
+        } else if sub.accepted == Some(true) {
+            result.solved = true;
+            result.solve_time = Some(submission_minute(contest, sub));
+            result.first_solve = solved_tasks.insert(sub.task_id);
+        } else {
+            result.rejected += 1;
+        }
+    }
+
+    let mut rows: Vec<IcpcScoreboardRow> = participants
+        .into_iter()
+        .map(|(part, user)| {
+            let tasks: HashMap<i32, IcpcTaskResult> = tasks
+                .iter()
+                .filter_map(|t| {
+                    results.get(&(part.id, t.id)).map(|r| (t.id, r.clone()))
+                })
+                .collect();
+            let solved = tasks.values().filter(|r| r.solved).count() as i32;
+            let penalty = tasks
+                .values()
+                .filter(|r| r.solved)
+                .map(|r| {
+                    r.solve_time.unwrap_or(0)
+                        + i64::from(r.rejected)
+                            * i64::from(contest.penalty_minutes)
+                })
+                .sum();
+            IcpcScoreboardRow {
+                rank: 0,
+                user_id: user.id,
+                username: user.username,
+                solved,
+                penalty,
+                tasks,
+            }
+        })
+        .collect();
+    rows.sort_by_key(|r| (-r.solved, r.penalty, r.user_id));
+    for i in 0..rows.len() {
+        rows[i].rank = if i > 0
+            && rows[i - 1].solved == rows[i].solved
+            && rows[i - 1].penalty == rows[i].penalty
+        {
+            rows[i - 1].rank
+        } else {
+            i + 1
+        };
+    }
+
+    IcpcScoreboard {
+        frozen: frozen_at.is_some(),
+        tasks,
+        rows,
+    }
+}
+
+/// Load from the DB everything needed and compute the scoreboard of the
+/// contest. Unless `unfrozen` is set the freeze is applied.
+pub fn load_scoreboard(
+    conn: &PgConnection,
+    contest: &Contest,
+    unfrozen: bool,
+) -> Result<IcpcScoreboard, Error> {
+    use crate::schema::{participations, submissions, tasks, users};
+
+    let now = Utc::now().naive_utc();
+    let frozen_at = if !unfrozen && is_frozen(contest, now) {
+        freeze_start(contest)
+    } else {
+        None
+    };
+    let contest_tasks = Task::belonging_to(contest)
+        .order(tasks::id)
+        .load::<Task>(conn)?;
+    let participants = Participation::belonging_to(contest)
+        .filter(participations::practice.eq(false))
+        .inner_join(users::table)
+        .load::<(Participation, User)>(conn)?;
+    let task_ids: Vec<i32> = contest_tasks.iter().map(|t| t.id).collect();
+    let subs = submissions::table
+        .inner_join(participations::table)
+        .filter(submissions::task_id.eq_any(task_ids))
+        .filter(participations::practice.eq(false))
+        .select(submissions::all_columns)
+        .load::<Submission>(conn)?;
+    Ok(compute_scoreboard(
+        contest,
+        contest_tasks,
+        participants,
+        subs,
+        frozen_at,
+    ))
+}
+
+/// Check whether the submission is the first accepted one of its participant
+/// on the task and, if so, build the balloon to send. During the freeze only
+/// the site admins and the solver are notified.
+pub fn make_balloon(
+    conn: &PgConnection,
+    submission_id: i32,
+) -> Result<Option<ContestUpdate>, Error> {
+    use crate::schema::{contests, participations, submissions, tasks, users};
+
+    let submission = submissions::table
+        .find(submission_id)
+        .first::<Submission>(conn)?;
+    if submission.accepted != Some(true) {
+        return Ok(None);
+    }
+    let task = tasks::table.find(submission.task_id).first::<Task>(conn)?;
+    let contest = contests::table
+        .find(task.contest_id)
+        .first::<Contest>(conn)?;
+    if contest.format != ContestFormat::ICPC
+        || is_after_end(&contest, &submission)
+    {
+        return Ok(None);
+    }
+
+    let (participation, user) = participations::table
+        .inner_join(users::table)
+        .filter(participations::id.eq(submission.participation_id))
+        .first::<(Participation, User)>(conn)?;
+    if participation.practice {
+        return Ok(None);
+    }
+
+    let accepted = submissions::table
+        .inner_join(participations::table)
+        .filter(submissions::task_id.eq(task.id))
+        .filter(submissions::accepted.eq(true))
+        .filter(submissions::id.ne(submission.id))
+        .filter(participations::practice.eq(false))
+        .select(submissions::all_columns)
+        .load::<Submission>(conn)?;
+    let already_solved = accepted.iter().any(|s| {
+        s.participation_id == submission.participation_id
+            && sent_before(s, &submission)
+    });
+    if already_solved {
+        return Ok(None);
+    }
+    let first_solve = !accepted.iter().any(|s| sent_before(s, &submission));
+
+    let mut recipients: Vec<i32> = users::table
+        .filter(users::site_id.eq(contest.site_id))
+        .filter(users::is_admin.eq(true))
+        .select(users::id)
+        .load::<i32>(conn)?;
+    recipients.push(user.id);
+    if !is_frozen(&contest, Utc::now().naive_utc()) {
+        recipients.extend(
+            Participation::belonging_to(&contest)
+                .filter(participations::practice.eq(false))
+                .select(participations::user_id)
+                .load::<i32>(conn)?,
+        );
+    }
+    recipients.sort();
+    recipients.dedup();
+
+    Ok(Some(ContestUpdate {
+        recipients,
+        balloon: Balloon {
+            contest_id: contest.id,
+            task_id: task.id,
+            user_id: user.id,
+            username: user.username,
+            submission_id: submission.id,
+            first_solve,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn contest() -> Contest {
+        Contest {
+            id: 1,
+            site_id: 1,
+            name: "contest".to_string(),
+            format: ContestFormat::ICPC,
+            start_time: Some(NaiveDate::from_ymd(2019, 3, 1).and_hms(10, 0, 0)),
+            end_time: Some(NaiveDate::from_ymd(2019, 3, 1).and_hms(15, 0, 0)),
+            freeze_minutes: 60,
+            penalty_minutes: 20,
+        }
+    }
+
+    fn participant(id: i32) -> (Participation, User) {
+        (
+            Participation {
+                id,
+                contest_id: 1,
+                user_id: id,
+                practice: false,
+            },
+            User {
+                id,
+                site_id: 1,
+                username: format!("user{}", id),
+                login_token: None,
+                is_admin: false,
+            },
+        )
+    }
+
+    fn task(id: i32) -> Task {
+        Task {
+            id,
+            name: format!("task{}", id),
+            title: "The Task".to_string(),
+            time_limit: 1.0,
+            memory_limit: 123,
+            max_score: 100.0,
+            format: TaskFormat::IOI,
+            contest_id: 1,
+            archived: false,
+            tags: vec![],
+            difficulty: None,
+            statement: String::new(),
+            version: 1,
+            task_type: TaskType::Batch,
+        }
+    }
+
+    fn submission(
+        id: i32,
+        participation_id: i32,
+        task_id: i32,
+        minute: u32,
+        accepted: bool,
+    ) -> Submission {
+        Submission {
+            id,
+            task_id,
+            files: vec!["sol.cpp".to_string()],
+            status: SubmissionStatus::Success,
+            compilation_messages: None,
+            score: None,
+            participation_id,
+            created_at: NaiveDate::from_ymd(2019, 3, 1).and_hms(
+                10 + minute / 60,
+                minute % 60,
+                0,
+            ),
+            accepted: Some(accepted),
+            task_version: Some(1),
+        }
+    }
+
+    #[test]
+    fn ranking_and_penalty() {
+        let contest = contest();
+        let scoreboard = compute_scoreboard(
+            &contest,
+            vec![task(1), task(2)],
+            vec![participant(1), participant(2)],
+            vec![
+                submission(1, 1, 1, 10, false),
+                submission(2, 1, 1, 30, true),
+                submission(3, 2, 1, 20, true),
+                submission(4, 2, 2, 40, true),
+                submission(5, 2, 2, 50, false),
+            ],
+            None,
+        );
+        assert!(!scoreboard.frozen);
+        let first = &scoreboard.rows[0];
+        assert_eq!(first.user_id, 2);
+        assert_eq!(first.rank, 1);
+        assert_eq!(first.solved, 2);
+        assert_eq!(first.penalty, 60);
+        assert!(first.tasks[&1].first_solve);
+        let second = &scoreboard.rows[1];
+        assert_eq!(second.user_id, 1);
+        assert_eq!(second.rank, 2);
+        assert_eq!(second.solved, 1);
+        assert_eq!(second.penalty, 50);
+        assert_eq!(second.tasks[&1].rejected, 1);
+        assert!(!second.tasks[&1].first_solve);
+    }
+
+    #[test]
+    fn frozen_submissions_are_pending() {
+        let contest = contest();
+        let scoreboard = compute_scoreboard(
+            &contest,
+            vec![task(1)],
+            vec![participant(1)],
+            vec![
+                submission(1, 1, 1, 200, false),
+                submission(2, 1, 1, 250, true),
+            ],
+            freeze_start(&contest),
+        );
+        assert!(scoreboard.frozen);
+        let result = &scoreboard.rows[0].tasks[&1];
+        assert!(!result.solved);
+        assert_eq!(result.rejected, 1);
+        assert_eq!(result.pending, 1);
+    }
+
+    #[test]
+    fn after_the_end() {
+        let contest = contest();
+        let scoreboard = compute_scoreboard(
+            &contest,
+            vec![task(1)],
+            vec![participant(1)],
+            vec![
+                submission(1, 1, 1, 290, false),
+                submission(2, 1, 1, 300, true),
+            ],
+            None,
+        );
+        let row = &scoreboard.rows[0];
+        assert_eq!(row.solved, 0);
+        assert_eq!(row.tasks[&1].rejected, 1);
+    }
+}
@@ -8,44 +8,63 @@ extern crate dotenv;
 extern crate failure;
 extern crate accept_language;
 extern crate base64;
+extern crate chrono;
+extern crate diff;
+extern crate flate2;
 extern crate fs_extra;
 extern crate itertools;
+extern crate libc;
 extern crate rand;
+extern crate tar;
 extern crate tempfile;
-
-use std::env;
+extern crate zip;
 
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use failure::Error;
 
+use crate::config::Config;
 use crate::models::*;
 use fs_extra::dir::create_all;
 use std::path::Path;
 use std::path::PathBuf;
 
+pub mod audit;
+pub mod config;
 pub mod evaluation;
 pub mod events;
+pub mod health;
+pub mod icpc;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
 pub mod models;
+pub mod oidc;
+pub mod plagiarism;
+pub mod pool;
+pub mod process;
 pub mod schema;
+pub mod shutdown;
+pub mod solutions;
+pub mod task_import;
 pub mod task_maker_ui;
+pub mod terry;
 pub mod test_utils;
+pub mod text_diff;
 pub mod web;
 
-/// Connect to the Postgres database. The DATABASE_URL environment variable must
-/// be set.
+/// Connect to the Postgres database of the configuration.
 ///
 /// # Example
 /// ```
 /// use diesel::query_dsl::RunQueryDsl;
 ///
-/// let conn = tmsocial::establish_connection();
+/// let conn = tmsocial::establish_connection(&tmsocial::config::get());
 /// diesel::sql_query("SELECT 1;").execute(&conn);
 /// ```
-pub fn establish_connection() -> PgConnection {
-    let database_url =
-        env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url)
+pub fn establish_connection(config: &Config) -> PgConnection {
+    let database_url = &config.database.url;
+    PgConnection::establish(database_url)
         .expect(&format!("Error connecting to {}", database_url))
 }
 
@@ -79,28 +98,99 @@ pub fn mark_internal_error(
 }
 
 /// Create and returns the path where the files of the provided submission
-/// should be stored. The STORAGE_DIR environment var should be set and that
-/// directory must be writable.
+/// should be stored. The storage directory of the configuration must be
+/// writable.
 ///
 /// # Example
 /// ```
 /// use tempfile::TempDir;
-/// use std::env;
+/// use tmsocial::config::Config;
 /// use tmsocial::create_submission_dir;
 ///
 /// # let dir = TempDir::new().unwrap();
-/// # env::set_var("STORAGE_DIR", dir.path().as_os_str());
-/// create_submission_dir(42);
+/// let mut config = Config::default();
+/// config.storage_dir = dir.path().to_owned();
+/// create_submission_dir(&config, 42);
 /// ```
-pub fn create_submission_dir(submission_id: i32) -> PathBuf {
-    let storage_dir = PathBuf::new().join(Path::new(
-        &env::var("STORAGE_DIR").expect("STORAGE_DIR must be set"),
-    ));
-    let submission_dir = storage_dir
-        .join(Path::new("submissions"))
-        .join(Path::new(&submission_id.to_string()));
+pub fn create_submission_dir(config: &Config, submission_id: i32) -> PathBuf {
+    let submission_dir = submission_dir(config, submission_id);
 
     create_all(&submission_dir, false).unwrap();
 
     submission_dir
 }
+
+/// Path of the directory with the files of a submission, inside the storage
+/// directory of the configuration.
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use tmsocial::config::Config;
+/// use tmsocial::submission_dir;
+///
+/// let mut config = Config::default();
+/// config.storage_dir = "/storage".into();
+/// assert_eq!(
+///     submission_dir(&config, 42),
+///     Path::new("/storage/submissions/42")
+/// );
+/// ```
+pub fn submission_dir(config: &Config, submission_id: i32) -> PathBuf {
+    config
+        .storage_dir
+        .join(Path::new("submissions"))
+        .join(Path::new(&submission_id.to_string()))
+}
+
+/// Path of the directory with the files of the given version of a task,
+/// inside the storage directory of the configuration. The tasks imported
+/// before the versions were added keep the files of their first version
+/// directly in the directory of the task.
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use tmsocial::config::Config;
+/// use tmsocial::task_dir;
+///
+/// let mut config = Config::default();
+/// config.storage_dir = "/storage".into();
+/// assert_eq!(task_dir(&config, 42, 3), Path::new("/storage/tasks/42/3"));
+/// ```
+pub fn task_dir(config: &Config, task_id: i32, version: i32) -> PathBuf {
+    let root = config
+        .storage_dir
+        .join(Path::new("tasks"))
+        .join(Path::new(&task_id.to_string()));
+    let path = root.join(Path::new(&version.to_string()));
+    if version == 1 && !path.exists() && root.is_dir() {
+        return root;
+    }
+    path
+}
+
+/// Path of the file with a generated input of a Terry task, inside the storage
+/// directory of the configuration.
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use tmsocial::config::Config;
+/// use tmsocial::terry_input_path;
+///
+/// let mut config = Config::default();
+/// config.storage_dir = "/storage".into();
+/// assert_eq!(
+///     terry_input_path(&config, 42),
+///     Path::new("/storage/inputs/42.txt")
+/// );
+/// ```
+pub fn terry_input_path(config: &Config, input_id: i32) -> PathBuf {
+    terry_inputs_dir(config).join(Path::new(&format!("{}.txt", input_id)))
+}
+
+/// Directory of the generated inputs of the Terry tasks.
+pub fn terry_inputs_dir(config: &Config) -> PathBuf {
+    config.storage_dir.join(Path::new("inputs"))
+}
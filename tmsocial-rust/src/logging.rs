@@ -0,0 +1,214 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::Write;
+
+use env_logger::filter::{Builder, Filter};
+use futures::task_local;
+use log::{Log, Metadata, Record};
+use serde_derive::Serialize;
+
+/// The fields added to the log lines written while evaluating a submission or
+/// answering a request.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct LogContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i32>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default());
+}
+
+// the requests of a connection are answered by the same task, which may move
+// between the requests of other connections while waiting
+task_local! {
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None)
+}
+
+/// Restores the previous context of the thread when dropped.
+pub struct ContextGuard(Option<LogContext>);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            CONTEXT.with(|c| *c.borrow_mut() = previous);
+        }
+    }
+}
+
+/// Tag the log lines of the thread with the context, until the guard is
+/// dropped.
+///
+/// # Example
+/// ```
+/// use tmsocial::logging::{context, scope, LogContext};
+///
+/// {
+///     let _guard = scope(LogContext {
+///         submission_id: Some(42),
+///         ..Default::default()
+///     });
+///     assert_eq!(context().submission_id, Some(42));
+/// }
+/// assert_eq!(context().submission_id, None);
+/// ```
+pub fn scope(context: LogContext) -> ContextGuard {
+    let previous = CONTEXT.with(|c| c.replace(context));
+    ContextGuard(Some(previous))
+}
+
+/// The context of the thread.
+pub fn context() -> LogContext {
+    CONTEXT.with(|c| c.borrow().clone())
+}
+
+/// The context of the log lines: the one of the thread, tagged with the id of
+/// the request answered by the current task if the thread has none.
+pub fn current_context() -> LogContext {
+    let mut context = context();
+    if context.request_id.is_none() {
+        context.request_id = request_id();
+    }
+    context
+}
+
+/// Set the id of the request being answered by the current task. Must be
+/// called inside a task.
+pub fn set_request_id(request_id: Option<String>) {
+    REQUEST_ID.with(|id| *id.borrow_mut() = request_id);
+}
+
+/// The id of the request being answered by the current task, none outside of
+/// a task.
+pub fn request_id() -> Option<String> {
+    if !futures::task::is_in_task() {
+        return None;
+    }
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// A new random request id.
+pub fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+#[derive(Serialize)]
+struct LogLine<'a> {
+    timestamp: String,
+    level: String,
+    target: &'a str,
+    message: String,
+    #[serde(flatten)]
+    context: LogContext,
+}
+
+/// Logger writing a JSON object per line on stderr, with the context of the
+/// thread or of the request being answered. The lines are filtered by
+/// RUST_LOG, like with env_logger.
+pub struct JsonLogger {
+    filter: Filter,
+}
+
+impl JsonLogger {
+    pub fn from_env() -> JsonLogger {
+        let mut builder = Builder::new();
+        if let Ok(spec) = env::var("RUST_LOG") {
+            builder.parse(&spec);
+        }
+        JsonLogger {
+            filter: builder.build(),
+        }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = LogLine {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            level: record.level().to_string(),
+            target: record.target(),
+            message: record.args().to_string(),
+            context: current_context(),
+        };
+        if let Ok(json) = serde_json::to_string(&line) {
+            let _ = writeln!(std::io::stderr(), "{}", json);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Set up the logger of the process: JSON lines, or the human readable ones of
+/// pretty_env_logger with LOG_FORMAT=pretty.
+pub fn init() {
+    if env::var("LOG_FORMAT")
+        .map(|f| f == "pretty")
+        .unwrap_or(false)
+    {
+        pretty_env_logger::init();
+        return;
+    }
+    let logger = JsonLogger::from_env();
+    log::set_max_level(logger.filter.filter());
+    log::set_boxed_logger(Box::new(logger)).expect("Logger already set");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_scopes() {
+        let _outer = scope(LogContext {
+            request_id: Some("abc".to_string()),
+            ..Default::default()
+        });
+        {
+            let _inner = scope(LogContext {
+                submission_id: Some(1),
+                ..context()
+            });
+            assert_eq!(context().request_id, Some("abc".to_string()));
+            assert_eq!(context().submission_id, Some(1));
+        }
+        assert_eq!(context().submission_id, None);
+    }
+
+    #[test]
+    fn request_id_outside_task() {
+        assert_eq!(request_id(), None);
+        assert_eq!(current_context().request_id, None);
+    }
+
+    #[test]
+    fn log_line() {
+        let line = LogLine {
+            timestamp: "now".to_string(),
+            level: "INFO".to_string(),
+            target: "tmsocial",
+            message: "hello".to_string(),
+            context: LogContext {
+                submission_id: Some(42),
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&line).unwrap(),
+            "{\"timestamp\":\"now\",\"level\":\"INFO\",\"target\":\"tmsocial\",\
+             \"message\":\"hello\",\"submission_id\":42}"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::task_maker_ui::ioi::{IOITask, IOITaskType};
+use crate::task_maker_ui::{TaskInfo, TestcaseNum};
+
+/// Formats of the statement, in order of preference.
+const STATEMENT_FORMATS: &[&str] = &["html", "pdf", "md"];
+
+/// Metadata of a task, as described by the `TaskMetadata` interface of the
+/// web UI. The paths are relative to the `assets` directory of the task.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskMetadata {
+    pub title: String,
+    pub statements: Vec<AssetFile>,
+    pub attachments: Vec<AssetFile>,
+    pub submission_form: SubmissionForm,
+    pub scorables: Vec<Scorable>,
+}
+
+/// A file of the task which may be localized, the localized versions are
+/// stored next to it as `name.<language>.ext`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AssetFile {
+    pub name: String,
+    pub path: String,
+    pub content_type: String,
+    /// The languages of the localized versions.
+    pub languages: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubmissionForm {
+    pub fields: Vec<SubmissionField>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubmissionField {
+    pub id: String,
+    pub required: bool,
+    pub title: String,
+    pub types: Vec<SubmissionFileType>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubmissionFileType {
+    pub id: String,
+    pub title: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Scorable {
+    pub key: String,
+    pub title: String,
+    pub max_score: f64,
+    pub precision: u32,
+}
+
+/// Generate the metadata of the task in `task_dir`, looking for the statement
+/// files (`assets/statement.<format>`) and the attachments (the files inside
+/// `assets/attachments`).
+pub fn generate_metadata(info: &TaskInfo, task_dir: &Path) -> TaskMetadata {
+    let assets = task_dir.join("assets");
+    let mut statements: Vec<AssetFile> = list_assets(&assets, "")
+        .into_iter()
+        .filter(|f| {
+            let (stem, ext) = split_name(&f.name);
+            stem == "statement" && STATEMENT_FORMATS.contains(&ext)
+        })
+        .collect();
+    statements.sort_by_key(|f| {
+        STATEMENT_FORMATS
+            .iter()
+            .position(|ext| *ext == split_name(&f.name).1)
+    });
+    let attachments = list_assets(&assets, "attachments");
+    match info {
+        TaskInfo::IOITask(task) => {
+            let mut subtasks: Vec<_> = task.subtasks.iter().collect();
+            subtasks.sort_by_key(|(num, _)| **num);
+            TaskMetadata {
+                title: task.title.clone(),
+                statements,
+                attachments,
+                submission_form: SubmissionForm {
+                    fields: match task.task_type {
+                        IOITaskType::OutputOnly => output_fields(task),
+                        _ => vec![source_field(true)],
+                    },
+                },
+                scorables: subtasks
+                    .into_iter()
+                    .map(|(num, subtask)| Scorable {
+                        key: format!("subtask.{}", num),
+                        title: format!("Subtask {}", num),
+                        max_score: subtask.max_score.into(),
+                        precision: 0,
+                    })
+                    .collect(),
+            }
+        }
+        TaskInfo::TerryTask(task) => TaskMetadata {
+            title: task.title.clone(),
+            statements,
+            attachments,
+            submission_form: SubmissionForm {
+                fields: vec![
+                    SubmissionField {
+                        id: "output".to_string(),
+                        required: true,
+                        title: "Output file".to_string(),
+                        types: vec![SubmissionFileType {
+                            id: "text".to_string(),
+                            title: "Text".to_string(),
+                            extensions: vec![".txt".to_string()],
+                        }],
+                    },
+                    source_field(false),
+                ],
+            },
+            scorables: vec![Scorable {
+                key: "score".to_string(),
+                title: "Score".to_string(),
+                max_score: task.max_score.into(),
+                precision: 2,
+            }],
+        },
+    }
+}
+
+/// The field with the source file of the solution.
+fn source_field(required: bool) -> SubmissionField {
+    let types = vec![
+        ("cpp", "C++", vec![".cpp", ".cc"]),
+        ("c", "C", vec![".c"]),
+        ("pascal", "Pascal", vec![".pas"]),
+        ("python", "Python", vec![".py"]),
+    ];
+    SubmissionField {
+        id: "source".to_string(),
+        required,
+        title: "Source file".to_string(),
+        types: types
+            .into_iter()
+            .map(|(id, title, extensions)| SubmissionFileType {
+                id: id.to_string(),
+                title: title.to_string(),
+                extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            })
+            .collect(),
+    }
+}
+
+/// The name of the output file of a testcase of an output-only task.
+///
+/// # Example
+/// ```
+/// use tmsocial::metadata::output_file_name;
+///
+/// assert_eq!(output_file_name(7), "output_007.txt");
+/// ```
+pub fn output_file_name(testcase: TestcaseNum) -> String {
+    format!("output_{:03}.txt", testcase)
+}
+
+/// The fields of an output-only task: one output file for each testcase, or
+/// a zip with all of them.
+fn output_fields(task: &IOITask) -> Vec<SubmissionField> {
+    let text = SubmissionFileType {
+        id: "text".to_string(),
+        title: "Text".to_string(),
+        extensions: vec![".txt".to_string()],
+    };
+    let mut testcases: Vec<TestcaseNum> = task
+        .subtasks
+        .values()
+        .flat_map(|st| st.cases.keys().cloned())
+        .collect();
+    testcases.sort();
+    let mut fields: Vec<SubmissionField> = testcases
+        .into_iter()
+        .map(|tc| SubmissionField {
+            id: format!("output_{}", tc),
+            required: false,
+            title: output_file_name(tc),
+            types: vec![text.clone()],
+        })
+        .collect();
+    fields.push(SubmissionField {
+        id: "outputs".to_string(),
+        required: false,
+        title: "Zip with the output files".to_string(),
+        types: vec![SubmissionFileType {
+            id: "zip".to_string(),
+            title: "Zip".to_string(),
+            extensions: vec![".zip".to_string()],
+        }],
+    });
+    fields
+}
+
+/// List the files inside `assets/dir`, merging the localized versions.
+fn list_assets(assets: &Path, dir: &str) -> Vec<AssetFile> {
+    let entries = match fs::read_dir(assets.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        if !entry.path().is_file() {
+            continue;
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let (stem, ext) = split_name(&name);
+        let (base, lang) = split_name(stem);
+        if is_language(lang) {
+            files
+                .entry(format!("{}.{}", base, ext))
+                .or_default()
+                .push(lang.to_string());
+        } else {
+            files.entry(name).or_default();
+        }
+    }
+    files
+        .into_iter()
+        .map(|(name, mut languages)| {
+            languages.sort();
+            AssetFile {
+                path: Path::new(dir).join(&name).to_string_lossy().into(),
+                content_type: content_type(&name).to_string(),
+                name,
+                languages,
+            }
+        })
+        .collect()
+}
+
+/// Split the name of a file at its last dot.
+fn split_name(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, ""),
+    }
+}
+
+/// Whether the string looks like a language code, like `it` or `en-US`.
+fn is_language(lang: &str) -> bool {
+    let mut parts = lang.split('-');
+    let lang_ok = parts.next().map_or(false, |l| {
+        l.len() == 2 && l.chars().all(|c| c.is_ascii_lowercase())
+    });
+    let region_ok = parts.next().map_or(true, |r| {
+        r.len() == 2 && r.chars().all(|c| c.is_ascii_uppercase())
+    });
+    lang_ok && region_ok && parts.next().is_none()
+}
+
+fn content_type(name: &str) -> &'static str {
+    match split_name(name).1 {
+        "html" => "text/html",
+        "pdf" => "application/pdf",
+        "md" => "text/markdown",
+        "txt" | "in" | "out" => "text/plain",
+        "zip" => "application/zip",
+        "c" | "cpp" | "cc" | "h" | "hpp" | "py" | "pas" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn touch(dir: &Path, path: &str) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    fn ioi_task() -> TaskInfo {
+        serde_json::from_str(
+            r#"{"type": "IOI", "name": "task", "title": "The Task",
+            "time_limit": 1.0, "memory_limit": 256, "input_file": "",
+            "output_file": "", "task_type": "Batch",
+            "official_solution": "sol.cpp", "checker": null,
+            "subtasks": {
+                "1": {"name": "", "max_score": 60.0, "cases": {}},
+                "0": {"name": "", "max_score": 40.0, "cases": {}}}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn statements_and_attachments() {
+        let dir = TempDir::new().unwrap();
+        touch(dir.path(), "assets/statement.pdf");
+        touch(dir.path(), "assets/statement.it.pdf");
+        touch(dir.path(), "assets/statement.en-US.html");
+        touch(dir.path(), "assets/logo.png");
+        touch(dir.path(), "assets/attachments/input_output.zip");
+        touch(dir.path(), "assets/attachments/grader.it.cpp");
+        let metadata = generate_metadata(&ioi_task(), dir.path());
+        assert_eq!(metadata.title, "The Task");
+        assert_eq!(
+            metadata.statements,
+            vec![
+                AssetFile {
+                    name: "statement.html".to_string(),
+                    path: "statement.html".to_string(),
+                    content_type: "text/html".to_string(),
+                    languages: vec!["en-US".to_string()],
+                },
+                AssetFile {
+                    name: "statement.pdf".to_string(),
+                    path: "statement.pdf".to_string(),
+                    content_type: "application/pdf".to_string(),
+                    languages: vec!["it".to_string()],
+                },
+            ]
+        );
+        let attachments: Vec<(&str, &Vec<String>)> = metadata
+            .attachments
+            .iter()
+            .map(|a| (a.path.as_str(), &a.languages))
+            .collect();
+        assert_eq!(
+            attachments,
+            vec![
+                ("attachments/grader.cpp", &vec!["it".to_string()]),
+                ("attachments/input_output.zip", &vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ioi_scorables() {
+        let dir = TempDir::new().unwrap();
+        let metadata = generate_metadata(&ioi_task(), dir.path());
+        assert!(metadata.statements.is_empty());
+        let keys: Vec<(&str, f64)> = metadata
+            .scorables
+            .iter()
+            .map(|s| (s.key.as_str(), s.max_score))
+            .collect();
+        assert_eq!(keys, vec![("subtask.0", 40.0), ("subtask.1", 60.0)]);
+        assert_eq!(metadata.submission_form.fields.len(), 1);
+        assert!(metadata.submission_form.fields[0].required);
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, Encoder, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+
+use crate::pool::PoolStats;
+
+lazy_static! {
+    pub static ref EVALUATIONS: IntCounterVec = register_int_counter_vec!(
+        "tmsocial_evaluations_total",
+        "Evaluations of submissions, by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref EVALUATION_DURATION: Histogram = register_histogram!(
+        "tmsocial_evaluation_duration_seconds",
+        "Time spent evaluating a submission",
+        vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]
+    )
+    .unwrap();
+    pub static ref EVALUATION_QUEUE: IntGauge = register_int_gauge!(
+        "tmsocial_evaluation_queue_length",
+        "Submissions sent to the evaluators and not started yet"
+    )
+    .unwrap();
+    pub static ref EVALUATIONS_RUNNING: IntGauge = register_int_gauge!(
+        "tmsocial_evaluations_running",
+        "Submissions being evaluated"
+    )
+    .unwrap();
+    pub static ref TASK_MAKER_FAILURES: IntCounterVec =
+        register_int_counter_vec!(
+            "tmsocial_task_maker_failures_total",
+            "Failures of task-maker while evaluating, by reason",
+            &["reason"]
+        )
+        .unwrap();
+    pub static ref WEBSOCKET_SESSIONS: IntGauge = register_int_gauge!(
+        "tmsocial_websocket_sessions",
+        "Open websocket sessions"
+    )
+    .unwrap();
+    pub static ref BUFFERED_EVENTS: IntGauge = register_int_gauge!(
+        "tmsocial_buffered_events",
+        "Submission events kept for the clients that reconnect"
+    )
+    .unwrap();
+    pub static ref DB_QUERY_DURATION: Histogram = register_histogram!(
+        "tmsocial_db_query_duration_seconds",
+        "Time to answer a message of the database executors"
+    )
+    .unwrap();
+    pub static ref DB_ERRORS: IntCounter = register_int_counter!(
+        "tmsocial_db_errors_total",
+        "Messages of the database executors that failed with an internal error"
+    )
+    .unwrap();
+    pub static ref DB_POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "tmsocial_db_pool_connections",
+        "Connections open by the pool"
+    )
+    .unwrap();
+    pub static ref DB_POOL_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "tmsocial_db_pool_idle_connections",
+        "Connections of the pool not checked out"
+    )
+    .unwrap();
+    pub static ref DB_POOL_CHECKOUT_FAILURES: IntCounter =
+        register_int_counter!(
+            "tmsocial_db_pool_checkout_failures_total",
+            "Failed attempts to get a connection from the pool"
+        )
+        .unwrap();
+    pub static ref DB_POOL_WAIT: Histogram = register_histogram!(
+        "tmsocial_db_pool_wait_seconds",
+        "Time spent waiting for a connection of the pool"
+    )
+    .unwrap();
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "tmsocial_http_requests_total",
+        "HTTP requests, by route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec =
+        register_histogram_vec!(
+            "tmsocial_http_request_duration_seconds",
+            "Time to answer an HTTP request, by route",
+            &["route"]
+        )
+        .unwrap();
+}
+
+/// The duration in seconds, as observed by the histograms.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use tmsocial::metrics::seconds;
+///
+/// assert_eq!(seconds(Duration::from_millis(1500)), 1.5);
+/// ```
+pub fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// Update the gauges of the pool with its current state.
+pub fn observe_pool(stats: &PoolStats) {
+    DB_POOL_CONNECTIONS.set(i64::from(stats.connections));
+    DB_POOL_IDLE_CONNECTIONS.set(i64::from(stats.idle_connections));
+}
+
+/// All the metrics in the Prometheus text format, with its content type.
+pub fn render() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Cannot encode the metrics");
+    (encoder.format_type().to_string(), buffer)
+}
@@ -1,23 +1,80 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
+use chrono::NaiveDateTime;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::schema::{
-    contests, participations, sites, submissions, subtask_results, subtasks,
-    tasks, testcase_results, users,
+    api_tokens, audit_log, contests, oidc_identities, oidc_logins,
+    oidc_providers, participations, plagiarism_pairs, sites, solution_outcomes,
+    submissions, subtask_results, subtasks, task_metadata, tasks, terry_inputs,
+    testcase_results, users,
 };
 use crate::task_maker_ui::ioi::IOISolutionTestCaseResult;
 
+/// The `SameSite` attribute of the auth cookie of a site.
+#[derive(Deserialize, Serialize, DbEnum, Debug, PartialEq, Clone, Copy)]
+#[PgType = "same_site_policy"]
+#[DieselType = "Same_site_policy"]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    /// The attribute is not set at all.
+    None,
+}
+
 #[derive(Queryable, Identifiable, Debug)]
 pub struct Site {
     pub id: i32,
     pub domain: String,
+    /// Send the auth cookie only over HTTPS.
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSitePolicy,
 }
 
 #[derive(Insertable, Debug)]
 #[table_name = "sites"]
 pub struct NewSite {
     pub domain: String,
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSitePolicy,
+}
+
+/// An entry of the audit log of a site, see `audit::record`.
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Serialize, Deserialize, Clone,
+)]
+#[belongs_to(Site)]
+#[table_name = "audit_log"]
+pub struct AuditEntry {
+    pub id: i32,
+    pub site_id: i32,
+    /// The user who did the action, missing for the command line tools and
+    /// the failed logins.
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "audit_log"]
+pub struct NewAuditEntry<'a> {
+    pub site_id: i32,
+    pub actor_id: Option<i32>,
+    pub action: &'a str,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub details: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, DbEnum, Debug, PartialEq, Clone, Copy)]
+#[PgType = "contest_format"]
+#[DieselType = "Contest_format"]
+pub enum ContestFormat {
+    IOI,
+    ICPC,
 }
 
 #[derive(
@@ -28,6 +85,11 @@ pub struct Contest {
     pub id: i32,
     pub site_id: i32,
     pub name: String,
+    pub format: ContestFormat,
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+    pub freeze_minutes: i32,
+    pub penalty_minutes: i32,
 }
 
 #[derive(Insertable, Debug)]
@@ -35,6 +97,11 @@ pub struct Contest {
 pub struct NewContest {
     pub site_id: i32,
     pub name: String,
+    pub format: ContestFormat,
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+    pub freeze_minutes: i32,
+    pub penalty_minutes: i32,
 }
 
 #[derive(
@@ -47,6 +114,7 @@ pub struct User {
     pub username: String,
     #[serde(skip)]
     pub login_token: Option<String>,
+    pub is_admin: bool,
 }
 
 #[derive(Insertable, Debug)]
@@ -54,6 +122,113 @@ pub struct User {
 pub struct NewUser {
     pub site_id: i32,
     pub username: String,
+    pub is_admin: bool,
+}
+
+/// What an API token can do.
+#[derive(Deserialize, Serialize, DbEnum, Debug, PartialEq, Clone, Copy)]
+#[PgType = "api_token_scope"]
+#[DieselType = "Api_token_scope"]
+pub enum ApiTokenScope {
+    /// Only requests without side effects.
+    ReadOnly,
+    /// Also join contests, request inputs and submit.
+    Submit,
+}
+
+/// A personal token of a user, sent as `Authorization: Bearer` by the command
+/// line tools and the editors. Only its hash is stored.
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Serialize, Deserialize, Clone,
+)]
+#[belongs_to(User)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scope: ApiTokenScope,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "api_tokens"]
+pub struct NewApiToken {
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scope: ApiTokenScope,
+}
+
+/// An OpenID Connect provider the users of a site can log in with.
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Serialize, Deserialize,
+)]
+#[belongs_to(Site)]
+pub struct OidcProvider {
+    pub id: i32,
+    pub site_id: i32,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(skip)]
+    pub client_secret: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "oidc_providers"]
+pub struct NewOidcProvider {
+    pub site_id: i32,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// An account of a provider linked to a user.
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[belongs_to(OidcProvider, foreign_key = "provider_id")]
+#[belongs_to(User)]
+#[table_name = "oidc_identities"]
+pub struct OidcIdentity {
+    pub id: i32,
+    pub provider_id: i32,
+    pub subject: String,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "oidc_identities"]
+pub struct NewOidcIdentity {
+    pub provider_id: i32,
+    pub subject: String,
+    pub user_id: i32,
+}
+
+/// A login with a provider waiting for the user to come back.
+#[derive(Queryable, Identifiable, Debug)]
+#[primary_key(state)]
+pub struct OidcLogin {
+    pub state: String,
+    pub provider_id: i32,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub link_user_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "oidc_logins"]
+pub struct NewOidcLogin {
+    pub state: String,
+    pub provider_id: i32,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub link_user_id: Option<i32>,
 }
 
 #[derive(Queryable, Identifiable, Associations, Debug)]
@@ -63,6 +238,8 @@ pub struct Participation {
     pub id: i32,
     pub contest_id: i32,
     pub user_id: i32,
+    /// Practice participations hold the submissions sent from the archive.
+    pub practice: bool,
 }
 
 #[derive(Insertable, Debug)]
@@ -70,9 +247,10 @@ pub struct Participation {
 pub struct NewParticipation {
     pub contest_id: i32,
     pub user_id: i32,
+    pub practice: bool,
 }
 
-#[derive(Deserialize, Serialize, DbEnum, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, DbEnum, Debug, PartialEq, Clone, Copy)]
 #[PgType = "task_format"]
 #[DieselType = "Task_format"]
 pub enum TaskFormat {
@@ -80,6 +258,17 @@ pub enum TaskFormat {
     Terry,
 }
 
+/// How the solutions of a IOI task interact with the testcases.
+#[derive(Deserialize, Serialize, DbEnum, Debug, PartialEq, Clone, Copy)]
+#[PgType = "task_type"]
+#[DieselType = "Task_type"]
+pub enum TaskType {
+    Batch,
+    Communication,
+    /// The contestants submit the outputs instead of a program.
+    OutputOnly,
+}
+
 #[derive(
     Queryable, Identifiable, Associations, Debug, Serialize, Deserialize,
 )]
@@ -93,6 +282,17 @@ pub struct Task {
     pub max_score: f64,
     pub format: TaskFormat,
     pub contest_id: i32,
+    /// Whether the task is published in the practice archive.
+    pub archived: bool,
+    pub tags: Vec<String>,
+    /// From 1 (easiest) to 10 (hardest).
+    pub difficulty: Option<i32>,
+    /// Plain text of the statement, used only by the search.
+    #[serde(skip)]
+    pub statement: String,
+    /// Incremented every time the task is updated.
+    pub version: i32,
+    pub task_type: TaskType,
 }
 
 #[derive(Insertable, Debug)]
@@ -105,6 +305,43 @@ pub struct NewTask<'a> {
     pub max_score: f64,
     pub format: TaskFormat,
     pub contest_id: i32,
+    pub tags: Vec<String>,
+    pub difficulty: Option<i32>,
+    pub statement: &'a str,
+    pub task_type: TaskType,
+}
+
+/// The `TaskMetadata` of a task, generated when the task is imported.
+#[derive(Queryable, Insertable, Identifiable, Associations, Debug)]
+#[belongs_to(Task)]
+#[primary_key(task_id)]
+#[table_name = "task_metadata"]
+pub struct StoredTaskMetadata {
+    pub task_id: i32,
+    pub metadata: serde_json::Value,
+}
+
+/// Scores obtained by a reference solution when a version of the task was
+/// imported.
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[belongs_to(Task)]
+pub struct SolutionOutcome {
+    pub id: i32,
+    pub task_id: i32,
+    pub task_version: i32,
+    pub solution: String,
+    pub score: f64,
+    pub subtask_scores: serde_json::Value,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "solution_outcomes"]
+pub struct NewSolutionOutcome {
+    pub task_id: i32,
+    pub task_version: i32,
+    pub solution: String,
+    pub score: f64,
+    pub subtask_scores: serde_json::Value,
 }
 
 #[derive(DbEnum, Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -129,6 +366,11 @@ pub struct Submission {
     pub compilation_messages: Option<String>,
     pub score: Option<f64>,
     pub participation_id: i32,
+    pub created_at: NaiveDateTime,
+    /// ICPC verdict: whether every testcase was solved correctly.
+    pub accepted: Option<bool>,
+    /// The version of the task used for the evaluation.
+    pub task_version: Option<i32>,
 }
 
 #[derive(Insertable, Associations)]
@@ -147,6 +389,8 @@ pub struct Subtask {
     pub task_id: i32,
     pub num: i32,
     pub max_score: f64,
+    /// Subtasks removed by a task update are kept for the old results.
+    pub active: bool,
 }
 
 #[derive(Insertable, Debug)]
@@ -175,6 +419,61 @@ pub struct NewSubtaskResult {
     pub subtask_id: i32,
 }
 
+/// Two final submissions of a task found similar by the plagiarism check.
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Serialize, Deserialize, Clone,
+)]
+#[belongs_to(Task)]
+pub struct PlagiarismPair {
+    pub id: i32,
+    pub task_id: i32,
+    pub first_submission_id: i32,
+    pub second_submission_id: i32,
+    pub similarity: f64,
+    /// The matched regions, as a list of `plagiarism::MatchedRegion`.
+    pub regions: serde_json::Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "plagiarism_pairs"]
+pub struct NewPlagiarismPair {
+    pub task_id: i32,
+    pub first_submission_id: i32,
+    pub second_submission_id: i32,
+    pub similarity: f64,
+    pub regions: serde_json::Value,
+}
+
+/// An input of a Terry task generated for a participant, whose output must be
+/// submitted before `expires_at`.
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Serialize, Deserialize, Clone,
+)]
+#[belongs_to(Participation)]
+#[belongs_to(Task)]
+pub struct TerryInput {
+    pub id: i32,
+    pub participation_id: i32,
+    pub task_id: i32,
+    pub seed: i64,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    /// The submission of the output of this input, if already sent.
+    pub submission_id: Option<i32>,
+    /// Whether the output was submitted or the input expired.
+    pub closed: bool,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "terry_inputs"]
+pub struct NewTerryInput {
+    pub participation_id: i32,
+    pub task_id: i32,
+    pub seed: i64,
+    pub expires_at: NaiveDateTime,
+}
+
 #[derive(Queryable, Identifiable, Associations, Debug)]
 #[belongs_to(SubtaskResult)]
 pub struct TestcaseResult {
@@ -200,22 +499,38 @@ pub struct NewTestcaseResult<'a> {
 
 impl<'a> NewTestcaseResult<'a> {
     /// Build a NewTestcaseResult from the result of a IOI solution. The running
-    /// time is the sum of user and sys time.
+    /// time is the sum of user and sys time. On communication tasks the
+    /// resources of all the processes are added up, since they run at the
+    /// same time, and the message is the one of the manager.
     pub fn from_ioi_testcase_result(
         subtask_result_id: i32,
         tc_num: i32,
-        testcase: &IOISolutionTestCaseResult,
-    ) -> NewTestcaseResult {
-        let resources = &testcase.result[0]
-            .as_ref()
-            .expect("Constructing NewTestcaseResult with an invalid state")
-            .resources;
-        let running_time = resources.cpu_time + resources.sys_time;
+        testcase: &'a IOISolutionTestCaseResult,
+        task_type: TaskType,
+    ) -> NewTestcaseResult<'a> {
+        let processes: Vec<_> = testcase
+            .result
+            .iter()
+            .filter_map(|res| res.as_ref())
+            .map(|res| &res.resources)
+            .collect();
+        let running_time: f32 = processes
+            .iter()
+            .map(|resources| resources.cpu_time + resources.sys_time)
+            .sum();
+        let memory_usage: u32 =
+            processes.iter().map(|resources| resources.memory).sum();
+        let message = match task_type {
+            TaskType::Communication if !testcase.checker_outcome.is_empty() => {
+                &testcase.checker_outcome
+            }
+            _ => &testcase.message,
+        };
         NewTestcaseResult {
             subtask_result_id: subtask_result_id,
             running_time: running_time as f64,
-            memory_usage: resources.memory as i32,
-            message: &testcase.message,
+            memory_usage: memory_usage as i32,
+            message: message,
             score: testcase.score as f64,
             num: tc_num,
         }
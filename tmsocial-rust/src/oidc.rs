@@ -0,0 +1,336 @@
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Scopes asked to the providers, enough for a username.
+pub const SCOPES: &str = "openid profile email";
+
+/// Seconds the user has to log in with the provider.
+pub const LOGIN_TIMEOUT_SECS: i64 = 600;
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum OidcError {
+    #[fail(display = "malformed ID token")]
+    Malformed,
+    #[fail(display = "ID token with the wrong {}", _0)]
+    InvalidClaim(&'static str),
+    #[fail(display = "{} is not an HTTPS URL", _0)]
+    Insecure(String),
+}
+
+/// The part of the metadata of a provider used for the login.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+}
+
+/// The form sent to the token endpoint, with the client authenticated by
+/// `client_secret_post`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: String,
+    pub redirect_uri: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub code_verifier: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse {
+    pub id_token: String,
+}
+
+/// One or more clients the ID token is meant for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The claims of an ID token used for the login.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IdClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: i64,
+    pub nonce: Option<String>,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+}
+
+/// Where the metadata of the provider with this issuer is published.
+pub fn discovery_url(issuer: &str) -> String {
+    format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    )
+}
+
+/// A new random value for the state, the nonce or the PKCE verifier.
+pub fn random_secret() -> String {
+    let mut arr = [0u8; 32];
+    rand::thread_rng().fill(&mut arr[..]);
+    base64::encode_config(&arr, base64::URL_SAFE_NO_PAD)
+}
+
+/// The PKCE challenge of a verifier, with the `S256` method.
+///
+/// # Example
+/// ```
+/// use tmsocial::oidc::code_challenge;
+///
+/// // the example of RFC 7636
+/// assert_eq!(
+///     code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+///     "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+/// );
+/// ```
+pub fn code_challenge(verifier: &str) -> String {
+    base64::encode_config(
+        &Sha256::digest(verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Where to send the user to log in with the provider.
+pub fn authorization_url(
+    discovery: &Discovery,
+    client_id: &str,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> Result<String, url::ParseError> {
+    let challenge = code_challenge(code_verifier);
+    let url = Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+            ("scope", SCOPES),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )?;
+    Ok(url.into_string())
+}
+
+/// Check that the URL reaches the provider over HTTPS. The unit tests can use
+/// plain HTTP on the loopback address, where the mock provider runs.
+pub fn check_secure_url(url: &str) -> Result<(), OidcError> {
+    let secure = match Url::parse(url) {
+        Ok(url) => match url.scheme() {
+            "https" => true,
+            "http" => cfg!(test) && url.host_str() == Some("127.0.0.1"),
+            _ => false,
+        },
+        Err(_) => false,
+    };
+    if secure {
+        Ok(())
+    } else {
+        Err(OidcError::Insecure(url.to_string()))
+    }
+}
+
+/// The claims of an ID token. The signature is not checked, which OpenID
+/// Connect Core 3.1.3.7 allows only for a token received straight from the
+/// token endpoint over TLS: the endpoint must pass `check_secure_url`.
+pub fn decode_id_token(id_token: &str) -> Result<IdClaims, OidcError> {
+    let payload = id_token.split('.').nth(1).ok_or(OidcError::Malformed)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| OidcError::Malformed)?;
+    serde_json::from_slice(&payload).map_err(|_| OidcError::Malformed)
+}
+
+/// Check that the ID token was issued by the provider, to this client, for
+/// this login and that it's not expired at `now`, a Unix timestamp.
+pub fn validate_claims(
+    claims: &IdClaims,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> Result<(), OidcError> {
+    if claims.iss.trim_end_matches('/') != issuer.trim_end_matches('/') {
+        return Err(OidcError::InvalidClaim("issuer"));
+    }
+    let audience_ok = match &claims.aud {
+        Audience::One(aud) => aud == client_id,
+        Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+    };
+    if !audience_ok {
+        return Err(OidcError::InvalidClaim("audience"));
+    }
+    if claims.exp <= now {
+        return Err(OidcError::InvalidClaim("expiration"));
+    }
+    if claims.nonce.as_ref().map(|n| n.as_str()) != Some(nonce) {
+        return Err(OidcError::InvalidClaim("nonce"));
+    }
+    Ok(())
+}
+
+/// The username for a new user with these claims, made only of letters,
+/// digits, `.`, `_` and `-`.
+///
+/// # Example
+/// ```
+/// use tmsocial::oidc::{suggested_username, Audience, IdClaims};
+///
+/// let claims = IdClaims {
+///     iss: "https://accounts.example.com".to_string(),
+///     sub: "1234".to_string(),
+///     aud: Audience::One("tmsocial".to_string()),
+///     exp: 0,
+///     nonce: None,
+///     preferred_username: None,
+///     email: Some("mario.rossi@school.example.com".to_string()),
+/// };
+/// assert_eq!(suggested_username(&claims), "mario.rossi");
+/// ```
+pub fn suggested_username(claims: &IdClaims) -> String {
+    let name = claims
+        .preferred_username
+        .as_ref()
+        .map(|name| name.as_str())
+        .or_else(|| {
+            claims
+                .email
+                .as_ref()
+                .and_then(|email| email.split('@').next())
+        })
+        .unwrap_or("");
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || "._-".contains(*c))
+        .take(32)
+        .collect();
+    if name.is_empty() {
+        "user".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::fake_id_token;
+
+    use super::*;
+
+    fn claims() -> IdClaims {
+        IdClaims {
+            iss: "https://accounts.example.com".to_string(),
+            sub: "1234".to_string(),
+            aud: Audience::One("tmsocial".to_string()),
+            exp: 1000,
+            nonce: Some("nonce".to_string()),
+            preferred_username: Some("mario".to_string()),
+            email: None,
+        }
+    }
+
+    fn encode(claims: &IdClaims) -> String {
+        fake_id_token(&serde_json::to_value(claims).unwrap())
+    }
+
+    #[test]
+    fn id_token() {
+        let claims = claims();
+        assert_eq!(decode_id_token(&encode(&claims)), Ok(claims));
+        assert_eq!(decode_id_token("nope"), Err(OidcError::Malformed));
+        assert_eq!(decode_id_token("a.b$.c"), Err(OidcError::Malformed));
+    }
+
+    #[test]
+    fn secure_urls() {
+        assert_eq!(check_secure_url("https://accounts.example.com"), Ok(()));
+        assert_eq!(check_secure_url("http://127.0.0.1:8080/token"), Ok(()));
+        let insecure = |url: &str| Err(OidcError::Insecure(url.to_string()));
+        let url = "http://accounts.example.com";
+        assert_eq!(check_secure_url(url), insecure(url));
+        assert_eq!(check_secure_url("nope"), insecure("nope"));
+    }
+
+    #[test]
+    fn validation() {
+        let issuer = "https://accounts.example.com/";
+        let valid = |claims: &IdClaims| {
+            validate_claims(claims, issuer, "tmsocial", "nonce", 500)
+        };
+        assert_eq!(valid(&claims()), Ok(()));
+
+        let mut other = claims();
+        other.aud = Audience::Many(vec!["a".to_string(), "tmsocial".into()]);
+        assert_eq!(valid(&other), Ok(()));
+
+        let mut other = claims();
+        other.iss = "https://evil.example.com".to_string();
+        assert_eq!(valid(&other), Err(OidcError::InvalidClaim("issuer")));
+        let mut other = claims();
+        other.aud = Audience::One("other".to_string());
+        assert_eq!(valid(&other), Err(OidcError::InvalidClaim("audience")));
+        let mut other = claims();
+        other.exp = 500;
+        assert_eq!(valid(&other), Err(OidcError::InvalidClaim("expiration")));
+        let mut other = claims();
+        other.nonce = None;
+        assert_eq!(valid(&other), Err(OidcError::InvalidClaim("nonce")));
+    }
+
+    #[test]
+    fn authorization() {
+        let discovery = Discovery {
+            issuer: "https://accounts.example.com".to_string(),
+            authorization_endpoint: "https://accounts.example.com/auth?x=1"
+                .to_string(),
+            token_endpoint: "https://accounts.example.com/token".to_string(),
+        };
+        let url = authorization_url(
+            &discovery,
+            "tmsocial",
+            "https://site/api/oidc/school/callback",
+            "state",
+            "nonce",
+            "verifier",
+        )
+        .unwrap();
+        let url = Url::parse(&url).unwrap();
+        let params: Vec<(String, String)> =
+            url.query_pairs().into_owned().collect();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(param("x"), Some("1"));
+        assert_eq!(param("state"), Some("state"));
+        assert_eq!(param("scope"), Some(SCOPES));
+        assert_eq!(
+            param("code_challenge"),
+            Some(code_challenge("verifier").as_str())
+        );
+        assert_eq!(param("code_challenge_method"), Some("S256"));
+    }
+
+    #[test]
+    fn usernames() {
+        let mut claims = claims();
+        assert_eq!(suggested_username(&claims), "mario");
+        claims.preferred_username = Some("Mario Rossi!".to_string());
+        assert_eq!(suggested_username(&claims), "MarioRossi");
+        claims.preferred_username = None;
+        assert_eq!(suggested_username(&claims), "user");
+    }
+}
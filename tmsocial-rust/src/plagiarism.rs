@@ -0,0 +1,572 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use failure::Error;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::models::*;
+
+/// Minimum similarity of the pairs that are reported.
+pub const DEFAULT_THRESHOLD: f64 = 0.5;
+/// Number of tokens hashed together.
+const KGRAM_SIZE: usize = 5;
+/// Number of consecutive hashes among which a fingerprint is picked.
+const WINDOW_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    C,
+    Python,
+    Pascal,
+}
+
+const C_KEYWORDS: &[&str] = &[
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "false",
+    "float",
+    "for",
+    "if",
+    "int",
+    "long",
+    "namespace",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "true",
+    "typedef",
+    "unsigned",
+    "using",
+    "void",
+    "while",
+];
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "break", "class", "continue", "def", "elif", "else", "except",
+    "False", "for", "from", "if", "import", "in", "is", "lambda", "None",
+    "not", "or", "pass", "return", "True", "try", "while", "with", "yield",
+];
+const PASCAL_KEYWORDS: &[&str] = &[
+    "and",
+    "array",
+    "begin",
+    "const",
+    "div",
+    "do",
+    "downto",
+    "else",
+    "end",
+    "for",
+    "function",
+    "if",
+    "mod",
+    "not",
+    "of",
+    "or",
+    "procedure",
+    "program",
+    "record",
+    "repeat",
+    "then",
+    "to",
+    "type",
+    "until",
+    "var",
+    "while",
+];
+
+/// The language of a source file, from its extension.
+pub fn language(file_name: &str) -> Option<Language> {
+    let ext = Path::new(file_name).extension()?.to_str()?;
+    match ext {
+        "c" | "cpp" | "cc" | "h" | "hpp" => Some(Language::C),
+        "py" => Some(Language::Python),
+        "pas" => Some(Language::Pascal),
+        _ => None,
+    }
+}
+
+/// A normalized token of a source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub line: usize,
+}
+
+/// Split the source in tokens, dropping the comments and the preprocessor
+/// directives and replacing identifiers, numbers and strings with
+/// placeholders, so that renaming a variable doesn't change the tokens.
+pub fn tokenize(source: &str, language: Language) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let keywords = match language {
+        Language::C => C_KEYWORDS,
+        Language::Python => PYTHON_KEYWORDS,
+        Language::Pascal => PASCAL_KEYWORDS,
+    };
+    let starts_with = |i: usize, s: &str| {
+        s.chars()
+            .enumerate()
+            .all(|(j, c)| chars.get(i + j) == Some(&c))
+    };
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut line_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let is_line_comment = match language {
+            Language::C => starts_with(i, "//") || (c == '#' && line_start),
+            Language::Python => c == '#',
+            Language::Pascal => starts_with(i, "//"),
+        };
+        line_start = false;
+        if is_line_comment {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        let comment_end = match language {
+            Language::C if starts_with(i, "/*") => Some("*/"),
+            Language::Pascal if starts_with(i, "(*") => Some("*)"),
+            Language::Pascal if c == '{' => Some("}"),
+            _ => None,
+        };
+        if let Some(comment_end) = comment_end {
+            // skip the whole comment, counting its lines
+            while i < chars.len() && !starts_with(i, comment_end) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += comment_end.len();
+            continue;
+        }
+        let token_line = line;
+        let text = if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                if chars[i] == '\\' && language != Language::Pascal {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i += 1;
+            "STR".to_string()
+        } else if c.is_ascii_digit() {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '.')
+            {
+                i += 1;
+            }
+            "NUM".to_string()
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_')
+            {
+                i += 1;
+            }
+            let mut word: String = chars[start..i].iter().collect();
+            if language == Language::Pascal {
+                word = word.to_lowercase();
+            }
+            if keywords.contains(&word.as_str()) {
+                word
+            } else {
+                "ID".to_string()
+            }
+        } else {
+            i += 1;
+            c.to_string()
+        };
+        tokens.push(Token {
+            text,
+            line: token_line,
+        });
+    }
+    tokens
+}
+
+/// The hash of a k-gram of tokens, with the lines of the source it spans.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    pub hash: u64,
+    pub file: usize,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+/// Select the fingerprints of the tokens with winnowing: the minimum hash of
+/// every window of consecutive k-grams is kept.
+pub fn fingerprints(tokens: &[Token], file: usize) -> Vec<Fingerprint> {
+    if tokens.len() < KGRAM_SIZE {
+        return vec![];
+    }
+    let kgrams: Vec<Fingerprint> = tokens
+        .windows(KGRAM_SIZE)
+        .map(|kgram| {
+            let mut hasher = DefaultHasher::new();
+            for token in kgram {
+                token.text.hash(&mut hasher);
+            }
+            Fingerprint {
+                hash: hasher.finish(),
+                file,
+                first_line: kgram[0].line,
+                last_line: kgram[KGRAM_SIZE - 1].line,
+            }
+        })
+        .collect();
+    let mut selected: Vec<Fingerprint> = vec![];
+    let mut last = None;
+    for (start, window) in
+        kgrams.windows(WINDOW_SIZE.min(kgrams.len())).enumerate()
+    {
+        // the rightmost minimum, so that the same k-gram is kept while it
+        // stays in the window
+        let (pos, min) = window
+            .iter()
+            .enumerate()
+            .rev()
+            .min_by_key(|(_, fp)| fp.hash)
+            .unwrap();
+        if last != Some(start + pos) {
+            last = Some(start + pos);
+            selected.push(min.clone());
+        }
+    }
+    selected
+}
+
+/// The fingerprinted files of a submission.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub submission_id: i32,
+    pub files: Vec<String>,
+    pub fingerprints: Vec<Fingerprint>,
+}
+
+impl Document {
+    /// Fingerprint the given files, the ones in an unknown language are
+    /// ignored.
+    pub fn new(submission_id: i32, files: Vec<(String, String)>) -> Document {
+        let mut names = vec![];
+        let mut prints = vec![];
+        for (name, source) in files {
+            if let Some(language) = language(&name) {
+                let tokens = tokenize(&source, language);
+                prints.extend(fingerprints(&tokens, names.len()));
+                names.push(name);
+            }
+        }
+        Document {
+            submission_id,
+            files: names,
+            fingerprints: prints,
+        }
+    }
+}
+
+/// A range of lines of a file of a submission.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineRange {
+    pub file: String,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+/// A region of the first submission which matches one of the second.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchedRegion {
+    pub first: LineRange,
+    pub second: LineRange,
+}
+
+/// Two submissions sharing a suspicious part of their fingerprints.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SuspiciousPair {
+    pub first_submission_id: i32,
+    pub second_submission_id: i32,
+    /// Fraction of the fingerprints of the smaller submission that are also
+    /// in the other one.
+    pub similarity: f64,
+    pub regions: Vec<MatchedRegion>,
+}
+
+/// Compare the fingerprints of two documents.
+pub fn compare(first: &Document, second: &Document) -> SuspiciousPair {
+    let hashes = |doc: &Document| -> HashSet<u64> {
+        doc.fingerprints.iter().map(|fp| fp.hash).collect()
+    };
+    let (first_hashes, second_hashes) = (hashes(first), hashes(second));
+    let common = first_hashes.intersection(&second_hashes).count();
+    let smaller = first_hashes.len().min(second_hashes.len());
+    let similarity = if smaller == 0 {
+        0.0
+    } else {
+        common as f64 / smaller as f64
+    };
+
+    let mut by_hash: HashMap<u64, &Fingerprint> = HashMap::new();
+    for fp in &second.fingerprints {
+        by_hash.entry(fp.hash).or_insert(fp);
+    }
+    let mut regions: Vec<(&Fingerprint, &Fingerprint)> = first
+        .fingerprints
+        .iter()
+        .filter_map(|fp| by_hash.get(&fp.hash).map(|other| (fp, *other)))
+        .collect();
+    regions.sort_by_key(|(fp, _)| (fp.file, fp.first_line));
+    let mut merged: Vec<(Fingerprint, Fingerprint)> = vec![];
+    for (a, b) in regions {
+        if let Some((last_a, last_b)) = merged.last_mut() {
+            let near = |last: &Fingerprint, fp: &Fingerprint| {
+                last.file == fp.file
+                    && fp.first_line <= last.last_line + 1
+                    && fp.last_line + 1 >= last.first_line
+            };
+            if near(last_a, a) && near(last_b, b) {
+                last_a.last_line = last_a.last_line.max(a.last_line);
+                last_b.first_line = last_b.first_line.min(b.first_line);
+                last_b.last_line = last_b.last_line.max(b.last_line);
+                continue;
+            }
+        }
+        merged.push((a.clone(), b.clone()));
+    }
+    let range = |doc: &Document, fp: &Fingerprint| LineRange {
+        file: doc.files[fp.file].clone(),
+        first_line: fp.first_line,
+        last_line: fp.last_line,
+    };
+    SuspiciousPair {
+        first_submission_id: first.submission_id,
+        second_submission_id: second.submission_id,
+        similarity,
+        regions: merged
+            .iter()
+            .map(|(a, b)| MatchedRegion {
+                first: range(first, a),
+                second: range(second, b),
+            })
+            .collect(),
+    }
+}
+
+/// Compare all the pairs of documents, returning the ones at least as similar
+/// as the threshold, the most similar first.
+pub fn find_suspicious_pairs(
+    documents: &[Document],
+    threshold: f64,
+) -> Vec<SuspiciousPair> {
+    let mut pairs = vec![];
+    for (i, first) in documents.iter().enumerate() {
+        for second in &documents[i + 1..] {
+            let pair = compare(first, second);
+            if pair.similarity >= threshold && pair.similarity > 0.0 {
+                pairs.push(pair);
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+    pairs
+}
+
+/// The last submission of each contestant to the task, practice submissions
+/// excluded.
+pub fn final_submissions(
+    conn: &PgConnection,
+    task_id: i32,
+) -> Result<Vec<Submission>, Error> {
+    use crate::schema::{participations, submissions};
+
+    let subs = submissions::table
+        .inner_join(participations::table)
+        .filter(submissions::task_id.eq(task_id))
+        .filter(participations::practice.eq(false))
+        .order((submissions::created_at, submissions::id))
+        .select(submissions::all_columns)
+        .load::<Submission>(conn)?;
+    let mut last: HashMap<i32, Submission> = HashMap::new();
+    for sub in subs {
+        last.insert(sub.participation_id, sub);
+    }
+    let mut subs: Vec<Submission> = last.into_iter().map(|(_, s)| s).collect();
+    subs.sort_by_key(|s| s.id);
+    Ok(subs)
+}
+
+/// Compare the final submissions of the task, replacing the pairs found by a
+/// previous check.
+pub fn check_task(
+    conn: &PgConnection,
+    config: &Config,
+    task_id: i32,
+    threshold: f64,
+) -> Result<Vec<PlagiarismPair>, Error> {
+    use crate::schema::plagiarism_pairs::dsl;
+
+    let mut documents = vec![];
+    for sub in final_submissions(conn, task_id)? {
+        let dir = crate::submission_dir(config, sub.id);
+        let mut files = vec![];
+        for name in &sub.files {
+            match fs::read(dir.join(name)) {
+                Ok(content) => files.push((
+                    name.clone(),
+                    String::from_utf8_lossy(&content).to_string(),
+                )),
+                Err(e) => {
+                    warn!(
+                        "Cannot read {} of submission {}: {}",
+                        name, sub.id, e
+                    )
+                }
+            }
+        }
+        documents.push(Document::new(sub.id, files));
+    }
+    let pairs = find_suspicious_pairs(&documents, threshold);
+    let new_pairs = pairs
+        .iter()
+        .map(|pair| -> Result<NewPlagiarismPair, Error> {
+            Ok(NewPlagiarismPair {
+                task_id,
+                first_submission_id: pair.first_submission_id,
+                second_submission_id: pair.second_submission_id,
+                similarity: pair.similarity,
+                regions: serde_json::to_value(&pair.regions)?,
+            })
+        })
+        .collect::<Result<Vec<NewPlagiarismPair>, Error>>()?;
+    let stored =
+        conn.transaction(|| -> Result<Vec<PlagiarismPair>, Error> {
+            diesel::delete(
+                dsl::plagiarism_pairs.filter(dsl::task_id.eq(task_id)),
+            )
+            .execute(conn)?;
+            if new_pairs.is_empty() {
+                return Ok(vec![]);
+            }
+            Ok(diesel::insert_into(dsl::plagiarism_pairs)
+                .values(&new_pairs)
+                .get_results::<PlagiarismPair>(conn)?)
+        })?;
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "#include <stdio.h>\n\
+                          int main() {\n\
+                          \x20   int n, sum = 0; // the sum\n\
+                          \x20   scanf(\"%d\", &n);\n\
+                          \x20   for (int i = 0; i < n; i++) sum += i * i;\n\
+                          \x20   printf(\"%d\\n\", sum);\n\
+                          }\n";
+
+    const RENAMED: &str = "#include <cstdio>\n\
+                           /* copied */ int main() {\n\
+                           \x20   int k, total = 0;\n\
+                           \x20   scanf(\"%d\", &k);\n\
+                           \x20   for (int j = 0; j < k; j++) total += j * j;\n\
+                           \x20   printf(\"%d\\n\", total);\n\
+                           }\n";
+
+    const DIFFERENT: &str = "def solve(values):\n\
+                             \x20   best = max(values)\n\
+                             \x20   return [v for v in values if v == best]\n";
+
+    fn texts(tokens: &[Token]) -> Vec<&str> {
+        tokens.iter().map(|t| t.text.as_str()).collect()
+    }
+
+    #[test]
+    fn tokenize_normalizes() {
+        let tokens = tokenize(SOURCE, Language::C);
+        assert_eq!(texts(&tokens[..5]), vec!["int", "ID", "(", ")", "{"]);
+        assert_eq!(tokens[0].line, 2);
+        assert_eq!(
+            texts(&tokenize(SOURCE, Language::C)),
+            texts(&tokenize(RENAMED, Language::C))
+        );
+        let tokens = tokenize("x = 'a' # comment\n", Language::Python);
+        assert_eq!(texts(&tokens), vec!["ID", "=", "STR"]);
+        let tokens =
+            tokenize("{ comment } BEGIN x := 1 END.", Language::Pascal);
+        assert_eq!(
+            texts(&tokens),
+            vec!["begin", "ID", ":", "=", "NUM", "end", "."]
+        );
+    }
+
+    #[test]
+    fn similar_documents() {
+        let doc = |id, name: &str, source: &str| {
+            Document::new(id, vec![(name.to_string(), source.to_string())])
+        };
+        let documents = vec![
+            doc(1, "a.cpp", SOURCE),
+            doc(2, "b.cpp", RENAMED),
+            doc(3, "c.py", DIFFERENT),
+        ];
+        let pairs = find_suspicious_pairs(&documents, DEFAULT_THRESHOLD);
+        assert_eq!(pairs.len(), 1);
+        let pair = &pairs[0];
+        assert_eq!(
+            (pair.first_submission_id, pair.second_submission_id),
+            (1, 2)
+        );
+        assert_eq!(pair.similarity, 1.0);
+        assert_eq!(pair.regions.len(), 1);
+        assert_eq!(pair.regions[0].first.file, "a.cpp");
+        assert_eq!(pair.regions[0].second.file, "b.cpp");
+        assert_eq!(pair.regions[0].first.first_line, 2);
+        assert!(pair.regions[0].first.last_line >= 6);
+    }
+
+    #[test]
+    fn unknown_language() {
+        let doc =
+            Document::new(1, vec![("out.txt".to_string(), "1 2 3".into())]);
+        assert!(doc.files.is_empty());
+        assert!(doc.fingerprints.is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+use diesel::RunQueryDsl;
+use failure::Error;
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::DatabaseConfig;
+use crate::metrics;
+
+pub type PooledConnection =
+    diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>;
+
+/// How long to wait for a free connection before giving up.
+pub const CHECKOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct StatementTimeout(u64);
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error>
+    for StatementTimeout
+{
+    fn on_acquire(
+        &self,
+        conn: &mut PgConnection,
+    ) -> Result<(), diesel::r2d2::Error> {
+        diesel::sql_query(format!("SET statement_timeout = {}", self.0))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Counters of the usage of the pool.
+#[derive(Debug, Default)]
+struct PoolMetrics {
+    checkouts: AtomicUsize,
+    checkout_failures: AtomicUsize,
+    wait_micros: AtomicUsize,
+}
+
+/// Snapshot of the state of the pool.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    pub max_size: u32,
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub checkouts: usize,
+    pub checkout_failures: usize,
+    /// Total time spent waiting for a connection.
+    pub wait_ms: usize,
+}
+
+/// Pool of connections to the database, shared by the web executors, the
+/// evaluators and the pending submissions checker. The connections are checked
+/// before being handed out, so a dropped one is replaced transparently.
+#[derive(Clone)]
+pub struct DbPool {
+    pool: Pool<ConnectionManager<PgConnection>>,
+    metrics: Arc<PoolMetrics>,
+}
+
+impl DbPool {
+    pub fn new(
+        database_url: &str,
+        size: u32,
+        statement_timeout_ms: u64,
+    ) -> Result<DbPool, Error> {
+        let pool = Pool::builder()
+            .max_size(size)
+            .min_idle(Some(1))
+            .test_on_check_out(true)
+            .connection_timeout(CHECKOUT_TIMEOUT)
+            .connection_customizer(Box::new(StatementTimeout(
+                statement_timeout_ms,
+            )))
+            .build(ConnectionManager::new(database_url))?;
+        Ok(DbPool {
+            pool,
+            metrics: Arc::new(PoolMetrics::default()),
+        })
+    }
+
+    /// Take a connection from the pool, waiting at most `CHECKOUT_TIMEOUT`.
+    pub fn get(&self) -> Result<PooledConnection, Error> {
+        let start = Instant::now();
+        let conn = self.pool.get();
+        let waited = start.elapsed();
+        metrics::DB_POOL_WAIT.observe(metrics::seconds(waited));
+        let waited = waited.as_secs() as usize * 1_000_000
+            + waited.subsec_micros() as usize;
+        self.metrics
+            .wait_micros
+            .fetch_add(waited, Ordering::Relaxed);
+        self.metrics.checkouts.fetch_add(1, Ordering::Relaxed);
+        conn.map_err(|e| {
+            self.metrics
+                .checkout_failures
+                .fetch_add(1, Ordering::Relaxed);
+            metrics::DB_POOL_CHECKOUT_FAILURES.inc();
+            error!("Cannot get a database connection: {}", e);
+            e.into()
+        })
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.pool.state();
+        PoolStats {
+            max_size: self.pool.max_size(),
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            checkouts: self.metrics.checkouts.load(Ordering::Relaxed),
+            checkout_failures: self
+                .metrics
+                .checkout_failures
+                .load(Ordering::Relaxed),
+            wait_ms: self.metrics.wait_micros.load(Ordering::Relaxed) / 1000,
+        }
+    }
+}
+
+/// Create the pool of connections to the Postgres database.
+pub fn establish_pool(config: &DatabaseConfig) -> DbPool {
+    DbPool::new(&config.url, config.pool_size, config.statement_timeout_ms)
+        .expect(&format!("Error connecting to {}", config.url))
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::sql_query;
+
+    use super::*;
+
+    #[test]
+    fn checkout_stats() {
+        let pool = establish_pool(&crate::config::get().database);
+        {
+            let conn = pool.get().unwrap();
+            sql_query("SELECT 1").execute(&*conn).unwrap();
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.checkouts, 1);
+        assert_eq!(stats.checkout_failures, 0);
+        assert!(stats.connections >= 1);
+    }
+
+    #[test]
+    fn statement_timeout() {
+        let database_url = crate::config::get().database.url.clone();
+        let pool = DbPool::new(&database_url, 1, 100).unwrap();
+        let conn = pool.get().unwrap();
+        assert!(sql_query("SELECT pg_sleep(1)").execute(&*conn).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use failure::Error;
+use wait_timeout::ChildExt;
+
+#[derive(Debug, Fail)]
+#[fail(display = "the process did not exit within {:?}", timeout)]
+pub struct TimedOut {
+    pub timeout: Duration,
+}
+
+/// Run the command like `Command::output`, killing it if it's still running
+/// after `timeout`.
+///
+/// # Example
+/// ```
+/// use std::process::Command;
+/// use std::time::Duration;
+/// use tmsocial::process::output_with_timeout;
+///
+/// let timeout = Duration::from_millis(100);
+/// let output =
+///     output_with_timeout(Command::new("echo").arg("hi"), timeout).unwrap();
+/// assert_eq!(output.stdout, b"hi\n");
+/// assert!(output_with_timeout(Command::new("sleep").arg("5"), timeout)
+///     .is_err());
+/// ```
+pub fn output_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> Result<Output, Error> {
+    run_with_timeout(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+        timeout,
+    )
+}
+
+/// Like `output_with_timeout`, but keeping the streams set on the command: only
+/// the piped ones are read, the output of the others is empty.
+pub fn run_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> Result<Output, Error> {
+    let mut child = command.spawn()?;
+    // read the pipes while waiting, a full pipe would block the process
+    let read_all = |pipe: Option<Box<Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = vec![];
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = read_all(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = read_all(child.stderr.take().map(|p| Box::new(p) as _));
+    let status = match child.wait_timeout(timeout)? {
+        Some(status) => status,
+        None => {
+            child.kill()?;
+            child.wait()?;
+            return Err(TimedOut { timeout }.into());
+        }
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
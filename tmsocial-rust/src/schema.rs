@@ -1,6 +1,38 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 #![allow(unused_imports)]
 
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        scope -> Api_token_scope,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    audit_log (id) {
+        id -> Int4,
+        site_id -> Int4,
+        actor_id -> Nullable<Int4>,
+        action -> Varchar,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        details -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     use crate::models::*;
     use diesel::sql_types::*;
@@ -9,6 +41,52 @@ table! {
         id -> Int4,
         site_id -> Int4,
         name -> Varchar,
+        format -> Contest_format,
+        start_time -> Nullable<Timestamp>,
+        end_time -> Nullable<Timestamp>,
+        freeze_minutes -> Int4,
+        penalty_minutes -> Int4,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    oidc_identities (id) {
+        id -> Int4,
+        provider_id -> Int4,
+        subject -> Varchar,
+        user_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    oidc_logins (state) {
+        state -> Varchar,
+        provider_id -> Int4,
+        code_verifier -> Varchar,
+        nonce -> Varchar,
+        link_user_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    oidc_providers (id) {
+        id -> Int4,
+        site_id -> Int4,
+        name -> Varchar,
+        issuer -> Varchar,
+        client_id -> Varchar,
+        client_secret -> Varchar,
     }
 }
 
@@ -20,6 +98,22 @@ table! {
         id -> Int4,
         contest_id -> Int4,
         user_id -> Int4,
+        practice -> Bool,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    plagiarism_pairs (id) {
+        id -> Int4,
+        task_id -> Int4,
+        first_submission_id -> Int4,
+        second_submission_id -> Int4,
+        similarity -> Float8,
+        regions -> Jsonb,
+        created_at -> Timestamp,
     }
 }
 
@@ -30,6 +124,22 @@ table! {
     sites (id) {
         id -> Int4,
         domain -> Varchar,
+        cookie_secure -> Bool,
+        cookie_same_site -> Same_site_policy,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    solution_outcomes (id) {
+        id -> Int4,
+        task_id -> Int4,
+        task_version -> Int4,
+        solution -> Varchar,
+        score -> Float8,
+        subtask_scores -> Jsonb,
     }
 }
 
@@ -45,6 +155,9 @@ table! {
         compilation_messages -> Nullable<Text>,
         score -> Nullable<Float8>,
         participation_id -> Int4,
+        created_at -> Timestamp,
+        accepted -> Nullable<Bool>,
+        task_version -> Nullable<Int4>,
     }
 }
 
@@ -69,6 +182,17 @@ table! {
         task_id -> Int4,
         num -> Int4,
         max_score -> Float8,
+        active -> Bool,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    task_metadata (task_id) {
+        task_id -> Int4,
+        metadata -> Jsonb,
     }
 }
 
@@ -85,6 +209,28 @@ table! {
         max_score -> Float8,
         format -> Task_format,
         contest_id -> Int4,
+        archived -> Bool,
+        tags -> Array<Text>,
+        difficulty -> Nullable<Int4>,
+        statement -> Text,
+        version -> Int4,
+        task_type -> Task_type,
+    }
+}
+
+table! {
+    use crate::models::*;
+    use diesel::sql_types::*;
+
+    terry_inputs (id) {
+        id -> Int4,
+        participation_id -> Int4,
+        task_id -> Int4,
+        seed -> Int8,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        submission_id -> Nullable<Int4>,
+        closed -> Bool,
     }
 }
 
@@ -112,29 +258,52 @@ table! {
         site_id -> Int4,
         username -> Varchar,
         login_token -> Nullable<Varchar>,
+        is_admin -> Bool,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
+joinable!(audit_log -> sites (site_id));
 joinable!(contests -> sites (site_id));
+joinable!(oidc_identities -> oidc_providers (provider_id));
+joinable!(oidc_identities -> users (user_id));
+joinable!(oidc_logins -> oidc_providers (provider_id));
+joinable!(oidc_logins -> users (link_user_id));
+joinable!(oidc_providers -> sites (site_id));
 joinable!(participations -> contests (contest_id));
 joinable!(participations -> users (user_id));
+joinable!(plagiarism_pairs -> tasks (task_id));
 joinable!(submissions -> participations (participation_id));
+joinable!(solution_outcomes -> tasks (task_id));
 joinable!(submissions -> tasks (task_id));
 joinable!(subtask_results -> submissions (submission_id));
 joinable!(subtask_results -> subtasks (subtask_id));
 joinable!(subtasks -> tasks (task_id));
+joinable!(task_metadata -> tasks (task_id));
 joinable!(tasks -> contests (contest_id));
+joinable!(terry_inputs -> participations (participation_id));
+joinable!(terry_inputs -> submissions (submission_id));
+joinable!(terry_inputs -> tasks (task_id));
 joinable!(testcase_results -> subtask_results (subtask_result_id));
 joinable!(users -> sites (site_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_log,
     contests,
+    oidc_identities,
+    oidc_logins,
+    oidc_providers,
     participations,
+    plagiarism_pairs,
     sites,
+    solution_outcomes,
     submissions,
     subtask_results,
     subtasks,
+    task_metadata,
     tasks,
+    terry_inputs,
     testcase_results,
     users,
 );
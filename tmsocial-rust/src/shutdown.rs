@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::{error, warn};
+
+#[derive(Default)]
+struct ShutdownState {
+    started: AtomicBool,
+    /// The task-maker processes running, by submission id.
+    children: Mutex<HashMap<i32, Arc<Mutex<Child>>>>,
+    /// The submissions whose evaluation was killed.
+    killed: Mutex<HashSet<i32>>,
+}
+
+/// State of the graceful shutdown, shared by the web server and the
+/// evaluators. Once started no new submissions or evaluations are accepted,
+/// and the evaluations still running at the deadline are killed.
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<ShutdownState>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn start(&self) {
+        self.0.started.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.0.started.load(Ordering::SeqCst)
+    }
+
+    /// Keep track of the task-maker process evaluating a submission, so that
+    /// it can be killed at the deadline.
+    pub fn track(&self, submission_id: i32, child: Arc<Mutex<Child>>) {
+        self.0.children.lock().unwrap().insert(submission_id, child);
+    }
+
+    /// Stop tracking the process of a submission, telling whether it was
+    /// killed.
+    pub fn untrack(&self, submission_id: i32) -> bool {
+        self.0.children.lock().unwrap().remove(&submission_id);
+        self.0.killed.lock().unwrap().remove(&submission_id)
+    }
+
+    pub fn is_killed(&self, submission_id: i32) -> bool {
+        self.0.killed.lock().unwrap().contains(&submission_id)
+    }
+
+    /// Kill the task-maker processes still running, with the processes they
+    /// started, returning how many.
+    pub fn kill_children(&self) -> usize {
+        let children = self.0.children.lock().unwrap();
+        let mut killed = self.0.killed.lock().unwrap();
+        for (submission_id, child) in children.iter() {
+            warn!(
+                "Killing the evaluation of submission {} for the shutdown",
+                submission_id
+            );
+            if let Err(e) = kill_process_group(&mut child.lock().unwrap()) {
+                error!("Cannot kill task-maker: {}", e);
+            }
+            killed.insert(*submission_id);
+        }
+        children.len()
+    }
+}
+
+/// Run the command in a process group of its own, so that it can be killed
+/// with its children by `kill_process_group`. The sandboxes of task-maker
+/// would otherwise keep its output open after it's killed.
+pub fn own_process_group(command: &mut Command) -> &mut Command {
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Kill the process group of a child started with `own_process_group`.
+fn kill_process_group(child: &mut Child) -> io::Result<()> {
+    if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    use super::*;
+
+    #[test]
+    fn kill_children() {
+        let shutdown = Shutdown::new();
+        // the shell forks the sleep, which keeps the output open
+        let mut child = own_process_group(
+            Command::new("sh").arg("-c").arg("sleep 60; true"),
+        )
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let child = Arc::new(Mutex::new(child));
+        shutdown.track(42, child.clone());
+        assert!(!shutdown.is_started());
+        shutdown.start();
+        assert!(shutdown.is_started());
+        assert_eq!(shutdown.kill_children(), 1);
+        assert!(!child.lock().unwrap().wait().unwrap().success());
+        let mut output = vec![];
+        stdout.read_to_end(&mut output).unwrap();
+        assert!(shutdown.is_killed(42));
+        assert!(shutdown.untrack(42));
+        assert!(!shutdown.untrack(42));
+        assert_eq!(shutdown.kill_children(), 0);
+    }
+}
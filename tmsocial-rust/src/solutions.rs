@@ -0,0 +1,359 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use failure::Error;
+use log::warn;
+use serde_derive::Serialize;
+
+use crate::models::*;
+use crate::task_maker_ui::ioi::{IOIResult, IOITask};
+use crate::task_maker_ui::{SubtaskNum, TaskInfo, TaskMakerMessage};
+
+#[derive(Debug, Fail)]
+pub enum SolutionsError {
+    #[fail(display = "task-maker did not send the results of the solutions")]
+    MissingResults,
+    #[fail(display = "{} unexpected results of the solutions", count)]
+    Contradictions { count: usize },
+    #[fail(display = "only IOI tasks have reference solutions")]
+    NotIOITask,
+    #[fail(display = "task-maker exited with {}", status)]
+    TaskMakerFailed { status: ExitStatus },
+}
+
+/// The expected outcome of a solution on some subtasks, declared in its
+/// source with a comment like `@check-accepted: 1 2` or `@check-zero-score: *`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Expectation {
+    Accepted,
+    PartialScore,
+    ZeroScore,
+}
+
+impl Expectation {
+    /// Whether the score matches the expectation.
+    pub fn matches(&self, score: f64, max_score: f64) -> bool {
+        match self {
+            Expectation::Accepted => score >= max_score,
+            Expectation::PartialScore => score > 0.0 && score < max_score,
+            Expectation::ZeroScore => score <= 0.0,
+        }
+    }
+}
+
+/// A subtask where a solution didn't obtain the expected score.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Contradiction {
+    pub solution: String,
+    pub subtask: SubtaskNum,
+    pub expected: Expectation,
+    pub score: f64,
+}
+
+/// Parse the expectations declared in the source of a solution. A `*` means
+/// every subtask of the task.
+pub fn parse_expectations(
+    source: &str,
+    subtasks: &Vec<SubtaskNum>,
+) -> HashMap<SubtaskNum, Expectation> {
+    let tags = vec![
+        ("@check-accepted:", Expectation::Accepted),
+        ("@check-partial-score:", Expectation::PartialScore),
+        ("@check-zero-score:", Expectation::ZeroScore),
+    ];
+    let mut expectations = HashMap::new();
+    for line in source.lines() {
+        for (tag, expectation) in &tags {
+            let pos = match line.find(tag) {
+                Some(pos) => pos + tag.len(),
+                None => continue,
+            };
+            for word in line[pos..].split_whitespace() {
+                if word == "*" {
+                    for st in subtasks {
+                        expectations.insert(*st, *expectation);
+                    }
+                } else if let Ok(st) = word.parse::<SubtaskNum>() {
+                    expectations.insert(st, *expectation);
+                }
+            }
+        }
+    }
+    expectations
+}
+
+/// Compare the scores of the solutions with their expectations. Without
+/// annotations the official solution is expected to solve every subtask.
+pub fn find_contradictions(
+    task: &IOITask,
+    result: &IOIResult,
+) -> Result<Vec<Contradiction>, Error> {
+    let mut subtasks: Vec<SubtaskNum> = task.subtasks.keys().cloned().collect();
+    subtasks.sort();
+    let mut contradictions = vec![];
+    let mut names: Vec<&String> = result.testing.keys().collect();
+    names.sort();
+    for name in names {
+        let solution = &result.testing[name];
+        let source = fs::read_to_string(&solution.path)?;
+        let mut expectations = parse_expectations(&source, &subtasks);
+        let is_official = Path::new(&task.official_solution)
+            .file_name()
+            .map_or(false, |f| f.to_string_lossy() == *name);
+        if expectations.is_empty() && is_official {
+            for st in &subtasks {
+                expectations.insert(*st, Expectation::Accepted);
+            }
+        }
+        for st in &subtasks {
+            let expected = match expectations.get(st) {
+                Some(expected) => *expected,
+                None => continue,
+            };
+            let max_score = task.subtasks[st].max_score as f64;
+            let score =
+                solution.subtask_scores.get(st).cloned().unwrap_or(0.0) as f64;
+            if !expected.matches(score, max_score) {
+                contradictions.push(Contradiction {
+                    solution: name.clone(),
+                    subtask: *st,
+                    expected,
+                    score,
+                });
+            }
+        }
+    }
+    Ok(contradictions)
+}
+
+/// Evaluate all the solutions of the task in `dir` with task-maker.
+pub fn evaluate_solutions(
+    task_maker: &str,
+    dir: &Path,
+) -> Result<IOIResult, Error> {
+    let mut tm = Command::new(task_maker)
+        .arg("--ui=json")
+        .arg("--dry-run")
+        .arg("--no-statement")
+        .arg("--task-dir")
+        .arg(dir)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut result = None;
+    {
+        let stdout = BufReader::new(tm.stdout.as_mut().unwrap());
+        for line in stdout.lines() {
+            if let Ok(TaskMakerMessage::IOIResult(res)) =
+                serde_json::from_str::<TaskMakerMessage>(&line?)
+            {
+                result = Some(res);
+            }
+        }
+    }
+    let status = tm.wait()?;
+    if !status.success() {
+        return Err(SolutionsError::TaskMakerFailed { status }.into());
+    }
+    result.ok_or(SolutionsError::MissingResults.into())
+}
+
+/// Evaluate the solutions of the task and check them against their
+/// expectations. The contradictions are logged and, unless they are allowed,
+/// make the check fail.
+pub fn check_solutions(
+    task_maker: &str,
+    dir: &Path,
+    info: &TaskInfo,
+    allow_contradictions: bool,
+) -> Result<IOIResult, Error> {
+    let task = match info {
+        TaskInfo::IOITask(task) => task,
+        _ => return Err(SolutionsError::NotIOITask.into()),
+    };
+    let result = evaluate_solutions(task_maker, dir)?;
+    let contradictions = find_contradictions(task, &result)?;
+    for c in &contradictions {
+        warn!(
+            "Solution {} scored {} on subtask {}, expected {:?}",
+            c.solution, c.score, c.subtask, c.expected
+        );
+    }
+    if !contradictions.is_empty() && !allow_contradictions {
+        return Err(SolutionsError::Contradictions {
+            count: contradictions.len(),
+        }
+        .into());
+    }
+    Ok(result)
+}
+
+/// Store the scores of the solutions as the expected outcomes of the given
+/// version of the task.
+pub fn store_outcomes(
+    conn: &PgConnection,
+    task_id: i32,
+    task_version: i32,
+    result: &IOIResult,
+) -> Result<(), Error> {
+    use crate::schema::solution_outcomes::dsl::solution_outcomes;
+
+    let mut outcomes = vec![];
+    for (name, solution) in &result.testing {
+        let scores: HashMap<String, f64> = solution
+            .subtask_scores
+            .iter()
+            .map(|(st, score)| (st.to_string(), *score as f64))
+            .collect();
+        outcomes.push(NewSolutionOutcome {
+            task_id,
+            task_version,
+            solution: name.clone(),
+            score: solution.score.into(),
+            subtask_scores: serde_json::to_value(scores)?,
+        });
+    }
+    if outcomes.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(solution_outcomes)
+        .values(&outcomes)
+        .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::task_maker_ui::ioi::{
+        IOISolutionResults, IOISubtask, IOITaskType,
+    };
+
+    use super::*;
+
+    fn task() -> IOITask {
+        let subtask = |max_score| IOISubtask {
+            name: String::new(),
+            max_score,
+            cases: HashMap::new(),
+        };
+        IOITask {
+            name: "task".to_string(),
+            title: "The Task".to_string(),
+            time_limit: 1.0,
+            memory_limit: 256,
+            input_file: String::new(),
+            output_file: String::new(),
+            task_type: IOITaskType::Batch,
+            official_solution: "sol/solution.cpp".to_string(),
+            checker: None,
+            subtasks: vec![(0, subtask(30.0)), (1, subtask(70.0))]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// The results of the solutions, given their sources and their scores.
+    fn result(dir: &Path, solutions: Vec<(&str, &str, f32, f32)>) -> IOIResult {
+        let mut testing = HashMap::new();
+        for (name, source, st0, st1) in solutions {
+            let path = dir.join(name);
+            fs::write(&path, source).unwrap();
+            testing.insert(
+                name.to_string(),
+                IOISolutionResults {
+                    name: name.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                    language: "C++".to_string(),
+                    score: st0 + st1,
+                    subtask_scores: vec![(0, st0), (1, st1)]
+                        .into_iter()
+                        .collect(),
+                    subtask_results: HashMap::new(),
+                    testcase_results: HashMap::new(),
+                },
+            );
+        }
+        IOIResult {
+            task: TaskInfo::IOITask(task()),
+            subtasks: HashMap::new(),
+            solutions: HashMap::new(),
+            non_solutions: HashMap::new(),
+            testing,
+        }
+    }
+
+    #[test]
+    fn contradictions() {
+        let dir = TempDir::new().unwrap();
+        let result = result(
+            dir.path(),
+            vec![
+                ("solution.cpp", "int main() {}", 30.0, 35.0),
+                ("slow.cpp", "// @check-accepted: 0\n", 30.0, 0.0),
+                ("wrong.cpp", "// @check-zero-score: *\n", 0.0, 10.0),
+            ],
+        );
+        let contradictions = find_contradictions(&task(), &result).unwrap();
+        assert_eq!(
+            contradictions,
+            vec![
+                Contradiction {
+                    solution: "solution.cpp".to_string(),
+                    subtask: 1,
+                    expected: Expectation::Accepted,
+                    score: 35.0,
+                },
+                Contradiction {
+                    solution: "wrong.cpp".to_string(),
+                    subtask: 1,
+                    expected: Expectation::ZeroScore,
+                    score: 10.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn contradictions_unreadable_solution() {
+        let dir = TempDir::new().unwrap();
+        let result = result(dir.path(), vec![("solution.cpp", "", 30.0, 70.0)]);
+        fs::remove_file(dir.path().join("solution.cpp")).unwrap();
+        assert!(find_contradictions(&task(), &result).is_err());
+    }
+
+    #[test]
+    fn expectations() {
+        let source = "// @check-accepted: 0 1\n\
+                      // @check-zero-score: 2\n\
+                      int main() {}\n";
+        let expectations = parse_expectations(source, &vec![0, 1, 2, 3]);
+        assert_eq!(expectations.len(), 3);
+        assert_eq!(expectations[&0], Expectation::Accepted);
+        assert_eq!(expectations[&2], Expectation::ZeroScore);
+    }
+
+    #[test]
+    fn expectations_star() {
+        let source = "# @check-partial-score: *\n# @check-accepted: 0\n";
+        let expectations = parse_expectations(source, &vec![0, 1]);
+        assert_eq!(expectations[&0], Expectation::Accepted);
+        assert_eq!(expectations[&1], Expectation::PartialScore);
+    }
+
+    #[test]
+    fn matches() {
+        assert!(Expectation::Accepted.matches(30.0, 30.0));
+        assert!(!Expectation::Accepted.matches(29.0, 30.0));
+        assert!(Expectation::PartialScore.matches(10.0, 30.0));
+        assert!(!Expectation::PartialScore.matches(0.0, 30.0));
+        assert!(Expectation::ZeroScore.matches(0.0, 30.0));
+    }
+}
@@ -0,0 +1,898 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use failure::Error;
+use flate2::read::GzDecoder;
+use fs_extra::dir::{copy, create_all, CopyOptions};
+use serde_derive::Serialize;
+use tar::EntryType;
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+use crate::config::Config;
+use crate::metadata::generate_metadata;
+use crate::models::*;
+use crate::task_maker_ui::ioi::IOITaskType;
+use crate::task_maker_ui::{
+    Result as TmResult, State, TaskInfo, TaskMakerMessage,
+};
+
+#[derive(Debug, Fail)]
+pub enum ImportError {
+    #[fail(
+        display = "the task format cannot change from {:?} to {:?}",
+        from, to
+    )]
+    FormatChanged { from: TaskFormat, to: TaskFormat },
+    #[fail(display = "task-maker failed: {}", stderr)]
+    TaskMakerFailed { stderr: String },
+    #[fail(display = "unsupported archive: {}", path)]
+    UnsupportedArchive { path: String },
+    #[fail(display = "the archive contains an unsafe entry: {}", path)]
+    UnsafePath { path: String },
+    #[fail(display = "the archive is larger than {} bytes", limit)]
+    TooLarge { limit: u64 },
+}
+
+/// Maximum size of the files extracted from an archive.
+const MAX_EXTRACTED_SIZE: u64 = 1 << 30;
+
+/// Subtasks touched by an update, identified by their number.
+#[derive(Debug, Default, PartialEq)]
+pub struct SubtaskChanges {
+    pub added: Vec<i32>,
+    pub removed: Vec<i32>,
+    pub changed: Vec<i32>,
+}
+
+/// Ask task-maker the information about the task in `dir`.
+pub fn read_task_info(task_maker: &str, dir: &Path) -> Result<TaskInfo, Error> {
+    let output = Command::new(task_maker)
+        .arg("--ui=json")
+        .arg("--task-info")
+        .arg("--task-dir")
+        .arg(dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(ImportError::TaskMakerFailed {
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        }
+        .into());
+    }
+    let output = String::from_utf8(output.stdout)?;
+    Ok(serde_json::from_str(&output)?)
+}
+
+/// Read the text of the statements of the task, in every language, so that
+/// it can be indexed by the search.
+pub fn read_statement(task_dir: &Path) -> String {
+    let mut files = vec![];
+    for dir in &["statement", "testo"] {
+        if let Ok(entries) = fs::read_dir(task_dir.join(dir)) {
+            files.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+    }
+    files.sort();
+    files
+        .iter()
+        .filter(|f| {
+            f.extension()
+                .map(|ext| ext == "md" || ext == "tex" || ext == "txt")
+                .unwrap_or(false)
+        })
+        .filter_map(|f| fs::read_to_string(f).ok())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Build the row of a new task from its information.
+pub fn new_task<'a>(
+    info: &'a TaskInfo,
+    contest_id: i32,
+    statement: &'a str,
+    tags: Vec<String>,
+    difficulty: Option<i32>,
+) -> NewTask<'a> {
+    match info {
+        TaskInfo::IOITask(task) => NewTask {
+            name: &task.name,
+            title: &task.title,
+            time_limit: task.time_limit.into(),
+            memory_limit: task.memory_limit as i32,
+            max_score: subtask_scores(info).values().sum(),
+            format: TaskFormat::IOI,
+            contest_id,
+            tags,
+            difficulty,
+            statement,
+            task_type: match task.task_type {
+                IOITaskType::Batch => TaskType::Batch,
+                IOITaskType::Communication => TaskType::Communication,
+                IOITaskType::OutputOnly => TaskType::OutputOnly,
+            },
+        },
+        TaskInfo::TerryTask(task) => NewTask {
+            name: &task.name,
+            title: &task.title,
+            time_limit: 10.0,
+            memory_limit: 64 * 1024,
+            max_score: task.max_score.into(),
+            format: TaskFormat::Terry,
+            contest_id,
+            tags,
+            difficulty,
+            statement,
+            task_type: TaskType::Batch,
+        },
+    }
+}
+
+/// The maximum score of each subtask of the task.
+pub fn subtask_scores(info: &TaskInfo) -> HashMap<i32, f64> {
+    match info {
+        TaskInfo::IOITask(task) => task
+            .subtasks
+            .iter()
+            .map(|(num, subtask)| (*num, subtask.max_score.into()))
+            .collect(),
+        TaskInfo::TerryTask(task) => {
+            vec![(0, task.max_score.into())].into_iter().collect()
+        }
+    }
+}
+
+/// Copy the files of the task into the directory of the given version.
+pub fn copy_task_dir(
+    config: &Config,
+    source: &Path,
+    task_id: i32,
+    version: i32,
+) -> Result<PathBuf, Error> {
+    let path = crate::task_dir(config, task_id, version);
+    if let Some(parent) = path.parent() {
+        create_all(parent, false)?;
+    }
+    let copy_options = CopyOptions {
+        copy_inside: true,
+        ..CopyOptions::new()
+    };
+    copy(source, &path, &copy_options)?;
+    Ok(path)
+}
+
+/// Copy the graders and the stubs of the task (`sol/grader.*`, `sol/stub.*`
+/// and the header `sol/<task name>.h`) into the attachments, unless the task
+/// already provides its own version of them. The other files of `sol`, like
+/// the headers of the solutions, stay private. Returns the names of the copied
+/// files.
+pub fn publish_graders(
+    dir: &Path,
+    task_name: &str,
+) -> Result<Vec<String>, Error> {
+    let entries = match fs::read_dir(dir.join("sol")) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(vec![]),
+    };
+    let attachments = dir.join("assets").join("attachments");
+    let mut published = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_grader = name.starts_with("grader.")
+            || name.starts_with("stub.")
+            || name == format!("{}.h", task_name);
+        if !is_grader || !entry.path().is_file() {
+            continue;
+        }
+        let target = attachments.join(&name);
+        if target.exists() {
+            continue;
+        }
+        fs::create_dir_all(&attachments)?;
+        fs::copy(entry.path(), target)?;
+        published.push(name);
+    }
+    published.sort();
+    Ok(published)
+}
+
+/// Store the metadata of the task generated from the files in `dir`, after
+/// publishing the graders of the IOI tasks.
+pub fn store_metadata(
+    conn: &PgConnection,
+    task_id: i32,
+    info: &TaskInfo,
+    dir: &Path,
+) -> Result<(), Error> {
+    use crate::schema::task_metadata::dsl;
+
+    if let TaskInfo::IOITask(task) = info {
+        publish_graders(dir, &task.name)?;
+    }
+    let metadata = serde_json::to_value(generate_metadata(info, dir))?;
+    diesel::insert_into(dsl::task_metadata)
+        .values(StoredTaskMetadata {
+            task_id,
+            metadata: metadata.clone(),
+        })
+        .on_conflict(dsl::task_id)
+        .do_update()
+        .set(dsl::metadata.eq(metadata))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Make the subtasks of the task match the new maximum scores. The subtasks
+/// that are gone are only deactivated since old results may refer to them.
+pub fn reconcile_subtasks(
+    conn: &PgConnection,
+    task_id: i32,
+    scores: &HashMap<i32, f64>,
+) -> Result<SubtaskChanges, diesel::result::Error> {
+    use crate::schema::subtasks::dsl;
+
+    let current = dsl::subtasks
+        .filter(dsl::task_id.eq(task_id))
+        .load::<Subtask>(conn)?;
+    let mut changes = SubtaskChanges::default();
+    for subtask in &current {
+        match scores.get(&subtask.num) {
+            Some(max_score) => {
+                if !subtask.active || subtask.max_score != *max_score {
+                    diesel::update(dsl::subtasks.find(subtask.id))
+                        .set((
+                            dsl::max_score.eq(max_score),
+                            dsl::active.eq(true),
+                        ))
+                        .execute(conn)?;
+                    changes.changed.push(subtask.num);
+                }
+            }
+            None if subtask.active => {
+                diesel::update(dsl::subtasks.find(subtask.id))
+                    .set(dsl::active.eq(false))
+                    .execute(conn)?;
+                changes.removed.push(subtask.num);
+            }
+            None => {}
+        }
+    }
+    let new_subtasks: Vec<NewSubtask> = scores
+        .iter()
+        .filter(|(num, _)| current.iter().all(|st| st.num != **num))
+        .map(|(num, max_score)| NewSubtask {
+            task_id,
+            num: *num,
+            max_score: *max_score,
+        })
+        .collect();
+    changes.added = new_subtasks.iter().map(|st| st.num).collect();
+    if !new_subtasks.is_empty() {
+        diesel::insert_into(dsl::subtasks)
+            .values(&new_subtasks)
+            .execute(conn)?;
+    }
+    changes.added.sort();
+    changes.removed.sort();
+    changes.changed.sort();
+    Ok(changes)
+}
+
+/// The files of a new version of a task, copied next to the directory of the
+/// version and moved there by `publish` once the version is committed. They
+/// are deleted if dropped before.
+pub struct StagedTaskDir {
+    staging: TempDir,
+    path: PathBuf,
+}
+
+impl StagedTaskDir {
+    /// Copy the files of the task for the given version.
+    fn new(
+        config: &Config,
+        source: &Path,
+        task_id: i32,
+        version: i32,
+    ) -> Result<StagedTaskDir, Error> {
+        let path = crate::task_dir(config, task_id, version);
+        let parent = path.parent().expect("The task dir has no parent");
+        create_all(parent, false)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".staging")
+            .tempdir_in(parent)?;
+        let copy_options = CopyOptions {
+            copy_inside: true,
+            ..CopyOptions::new()
+        };
+        copy(source, staging.path().join("task"), &copy_options)?;
+        Ok(StagedTaskDir { staging, path })
+    }
+
+    /// Where the files are until they are published.
+    pub fn staged_path(&self) -> PathBuf {
+        self.staging.path().join("task")
+    }
+
+    /// Move the files to the directory of the version.
+    pub fn publish(self) -> Result<PathBuf, Error> {
+        fs::rename(self.staged_path(), &self.path)?;
+        Ok(self.path)
+    }
+}
+
+/// Import the task in `dir` as a new version of the task, returning the
+/// updated task, the changes to its subtasks and its files, to be published
+/// after the commit. The task stays locked until the transaction is committed,
+/// so that the concurrent updates get different versions.
+pub fn update_task(
+    conn: &PgConnection,
+    config: &Config,
+    task_id: i32,
+    dir: &Path,
+    info: &TaskInfo,
+) -> Result<(Task, SubtaskChanges, StagedTaskDir), Error> {
+    let statement = read_statement(dir);
+    conn.transaction(
+        || -> Result<(Task, SubtaskChanges, StagedTaskDir), Error> {
+            use crate::schema::tasks::dsl;
+
+            let task =
+                dsl::tasks.find(task_id).for_update().first::<Task>(conn)?;
+            let new = new_task(info, task.contest_id, &statement, vec![], None);
+            if new.format != task.format {
+                return Err(ImportError::FormatChanged {
+                    from: task.format,
+                    to: new.format,
+                }
+                .into());
+            }
+            let version = task.version + 1;
+            let staged = StagedTaskDir::new(config, dir, task.id, version)?;
+            let updated = diesel::update(dsl::tasks.find(task.id))
+                .set((
+                    dsl::name.eq(new.name),
+                    dsl::title.eq(new.title),
+                    dsl::time_limit.eq(new.time_limit),
+                    dsl::memory_limit.eq(new.memory_limit),
+                    dsl::max_score.eq(new.max_score),
+                    dsl::statement.eq(new.statement),
+                    dsl::task_type.eq(new.task_type),
+                    dsl::version.eq(version),
+                ))
+                .get_result::<Task>(conn)?;
+            let changes =
+                reconcile_subtasks(conn, task.id, &subtask_scores(info))?;
+            store_metadata(conn, task.id, info, &staged.staged_path())?;
+            Ok((updated, changes, staged))
+        },
+    )
+}
+
+/// Mark all the submissions of the task as waiting for an evaluation,
+/// dropping their old results. Returns the number of submissions.
+pub fn rejudge_task(
+    conn: &PgConnection,
+    task_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::submissions::dsl;
+    use crate::schema::subtask_results;
+
+    conn.transaction(|| {
+        let ids = dsl::submissions
+            .filter(dsl::task_id.eq(task_id))
+            .select(dsl::id);
+        diesel::delete(
+            subtask_results::table
+                .filter(subtask_results::submission_id.eq_any(ids)),
+        )
+        .execute(conn)?;
+        diesel::update(dsl::submissions.filter(dsl::task_id.eq(task_id)))
+            .set((
+                dsl::status.eq(SubmissionStatus::Waiting),
+                dsl::score.eq(None::<f64>),
+                dsl::compilation_messages.eq(None::<String>),
+                dsl::accepted.eq(None::<bool>),
+                dsl::task_version.eq(None::<i32>),
+            ))
+            .execute(conn)
+    })
+}
+
+/// Extract the archive (zip or tar.gz) into `dest`, refusing the entries that
+/// would end up outside of it. Returns the directory of the task: the only
+/// directory inside the archive, if it doesn't contain anything else.
+pub fn extract_archive(archive: &Path, dest: &Path) -> Result<PathBuf, Error> {
+    let name = archive.to_string_lossy();
+    if name.ends_with(".zip") {
+        extract_zip(archive, dest, MAX_EXTRACTED_SIZE)?;
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        extract_tar_gz(archive, dest)?;
+    } else {
+        return Err(ImportError::UnsupportedArchive {
+            path: name.to_string(),
+        }
+        .into());
+    }
+    let entries: Vec<PathBuf> = fs::read_dir(dest)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect();
+    match entries.as_slice() {
+        [dir] if dir.is_dir() => Ok(dir.clone()),
+        _ => Ok(dest.to_owned()),
+    }
+}
+
+/// Path inside `dest` of an entry of an archive, `None` if it's not safe.
+fn safe_path(dest: &Path, name: &Path) -> Option<PathBuf> {
+    let mut path = dest.to_owned();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if path == dest {
+        return None;
+    }
+    Some(path)
+}
+
+fn check_size(
+    total: &mut u64,
+    size: u64,
+    limit: u64,
+) -> Result<(), ImportError> {
+    *total += size;
+    if *total > limit {
+        return Err(ImportError::TooLarge { limit });
+    }
+    Ok(())
+}
+
+/// Extract the zip, writing at most `limit` bytes: the sizes declared in the
+/// archive are not trusted.
+fn extract_zip(archive: &Path, dest: &Path, limit: u64) -> Result<(), Error> {
+    let mut zip = ZipArchive::new(fs::File::open(archive)?)?;
+    let mut total = 0;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let path = match safe_path(dest, Path::new(file.name())) {
+            Some(path) => path,
+            None => {
+                return Err(ImportError::UnsafePath {
+                    path: file.name().to_string(),
+                }
+                .into())
+            }
+        };
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // one byte more than allowed is enough to know the limit is exceeded
+        let written = io::copy(
+            &mut (&mut file).take(limit - total + 1),
+            &mut fs::File::create(&path)?,
+        )?;
+        check_size(&mut total, written, limit)?;
+        if let Some(mode) = file.unix_mode() {
+            fs::set_permissions(
+                &path,
+                fs::Permissions::from_mode(mode & 0o755),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar_gz(archive: &Path, dest: &Path) -> Result<(), Error> {
+    let mut tar = tar::Archive::new(GzDecoder::new(fs::File::open(archive)?));
+    let mut total = 0;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_path_buf();
+        let path = match safe_path(dest, &name) {
+            Some(path) => path,
+            None => {
+                return Err(ImportError::UnsafePath {
+                    path: name.to_string_lossy().to_string(),
+                }
+                .into())
+            }
+        };
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                fs::create_dir_all(&path)?;
+            }
+            EntryType::Regular => {
+                check_size(
+                    &mut total,
+                    entry.header().size()?,
+                    MAX_EXTRACTED_SIZE,
+                )?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                entry.unpack(&path)?;
+            }
+            // links could point outside of the task
+            _ => {
+                return Err(ImportError::UnsafePath {
+                    path: name.to_string_lossy().to_string(),
+                }
+                .into())
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of a step of the validation of a task.
+#[derive(Serialize, Debug)]
+pub struct ReportEntry {
+    pub name: String,
+    pub state: State,
+    pub message: Option<String>,
+}
+
+/// Result of the checks made by task-maker on a task, without evaluating
+/// any submission.
+#[derive(Serialize, Debug, Default)]
+pub struct ValidationReport {
+    pub statements: Vec<ReportEntry>,
+    pub sanity_checks: Vec<ReportEntry>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Record a message of task-maker, only the final states are kept.
+    pub fn add_message(&mut self, message: TaskMakerMessage) {
+        fn is_final(state: &State) -> bool {
+            match state {
+                State::Waiting | State::Start => false,
+                _ => true,
+            }
+        }
+        fn error(result: &Option<TmResult>) -> Option<String> {
+            result.as_ref().and_then(|r| r.error.clone())
+        }
+        match message {
+            TaskMakerMessage::StatementCompilation(comp) => {
+                if is_final(&comp.state) {
+                    self.statements.push(ReportEntry {
+                        name: comp.data.language,
+                        message: error(&comp.data.result),
+                        state: comp.state,
+                    });
+                }
+            }
+            TaskMakerMessage::SanityCheckSolution(check) => {
+                if is_final(&check.state) {
+                    self.sanity_checks.push(ReportEntry {
+                        name: format!(
+                            "solution on testcase {}",
+                            check.data.sample_testcase
+                        ),
+                        message: error(&check.data.result),
+                        state: check.state,
+                    });
+                }
+            }
+            TaskMakerMessage::SanityCheckValidation(check) => {
+                if is_final(&check.state) {
+                    self.sanity_checks.push(ReportEntry {
+                        name: format!(
+                            "validation of testcase {}",
+                            check.data.sample_testcase
+                        ),
+                        message: error(&check.data.result),
+                        state: check.state,
+                    });
+                }
+            }
+            TaskMakerMessage::Warning(warning) => {
+                self.warnings.push(warning.data.message)
+            }
+            TaskMakerMessage::Error(err) => self.errors.push(err.data.message),
+            _ => {}
+        }
+    }
+
+    /// Whether the task can be imported.
+    pub fn is_valid(&self) -> bool {
+        let failed = |entry: &ReportEntry| match entry.state {
+            State::Error | State::Failure => true,
+            _ => false,
+        };
+        self.errors.is_empty()
+            && !self.statements.iter().any(failed)
+            && !self.sanity_checks.iter().any(failed)
+    }
+}
+
+/// Run task-maker on the task in `dir` without modifying it, collecting the
+/// results of the sanity checks and of the compilation of the statements.
+pub fn validate_task(
+    task_maker: &str,
+    dir: &Path,
+) -> Result<ValidationReport, Error> {
+    let mut tm = Command::new(task_maker)
+        .arg("--ui=json")
+        .arg("--dry-run")
+        .arg("--task-dir")
+        .arg(dir)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut report = ValidationReport::default();
+    {
+        let stdout = BufReader::new(tm.stdout.as_mut().unwrap());
+        for line in stdout.lines() {
+            match serde_json::from_str::<TaskMakerMessage>(&line?) {
+                Ok(message) => report.add_message(message),
+                Err(e) => report.errors.push(e.to_string()),
+            }
+        }
+    }
+    let status = tm.wait()?;
+    if !status.success() {
+        report
+            .errors
+            .push(format!("task-maker exited with {}", status));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::{FileOptions, ZipWriter};
+
+    use crate::test_utils::*;
+
+    use super::*;
+
+    fn subtasks(site: &FakeSite, task: &Task) -> Vec<(i32, f64, bool)> {
+        use crate::schema::subtasks::dsl;
+        dsl::subtasks
+            .filter(dsl::task_id.eq(task.id))
+            .order(dsl::num)
+            .load::<Subtask>(&site.conn)
+            .unwrap()
+            .into_iter()
+            .map(|st| (st.num, st.max_score, st.active))
+            .collect()
+    }
+
+    #[test]
+    fn reconcile() {
+        let site = FakeSite::new();
+        let task = site.make_task();
+        let scores = vec![(0, 30.0), (1, 70.0)].into_iter().collect();
+        reconcile_subtasks(&site.conn, task.id, &scores).unwrap();
+        let scores = vec![(0, 30.0), (2, 20.0), (1, 50.0)];
+        let changes = reconcile_subtasks(
+            &site.conn,
+            task.id,
+            &scores.into_iter().collect(),
+        )
+        .unwrap();
+        assert_eq!(
+            changes,
+            SubtaskChanges {
+                added: vec![2],
+                removed: vec![],
+                changed: vec![1],
+            }
+        );
+        let scores = vec![(0, 100.0)].into_iter().collect();
+        let changes = reconcile_subtasks(&site.conn, task.id, &scores).unwrap();
+        assert_eq!(changes.removed, vec![1, 2]);
+        assert_eq!(
+            subtasks(&site, &task),
+            vec![(0, 100.0, true), (1, 50.0, false), (2, 20.0, false)]
+        );
+    }
+
+    #[test]
+    fn rejudge() {
+        use crate::schema::submissions::dsl;
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let other = site.task(&contest, "other");
+        let part = site.participation(&contest, &user);
+        let now = chrono::Utc::now().naive_utc();
+        let sub = site.evaluated_submission(&task, &part, 42.0, false, now);
+        let untouched =
+            site.evaluated_submission(&other, &part, 1.0, false, now);
+        assert_eq!(rejudge_task(&site.conn, task.id).unwrap(), 1);
+        let sub = dsl::submissions
+            .find(sub.id)
+            .first::<Submission>(&site.conn)
+            .unwrap();
+        assert_eq!(sub.status, SubmissionStatus::Waiting);
+        assert_eq!(sub.score, None);
+        let untouched = dsl::submissions
+            .find(untouched.id)
+            .first::<Submission>(&site.conn)
+            .unwrap();
+        assert_eq!(untouched.status, SubmissionStatus::Success);
+    }
+
+    #[test]
+    fn staged_task_dir() {
+        let site = FakeSite::new();
+        let task = site.make_task();
+        let source = TempDir::new().unwrap();
+        fs::write(source.path().join("task.yaml"), "name: task").unwrap();
+        let config = crate::config::get();
+
+        let staged =
+            StagedTaskDir::new(&config, source.path(), task.id, 2).unwrap();
+        let staged_path = staged.staged_path();
+        assert!(staged_path.join("task.yaml").exists());
+        assert!(!crate::task_dir(&config, task.id, 2).exists());
+        drop(staged);
+        assert!(!staged_path.exists());
+
+        let staged =
+            StagedTaskDir::new(&config, source.path(), task.id, 2).unwrap();
+        let path = staged.publish().unwrap();
+        assert_eq!(path, crate::task_dir(&config, task.id, 2));
+        assert!(path.join("task.yaml").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn graders() {
+        let dir = TempDir::new().unwrap();
+        let sol = dir.path().join("sol");
+        let attachments = dir.path().join("assets/attachments");
+        fs::create_dir_all(&sol).unwrap();
+        fs::create_dir_all(&attachments).unwrap();
+        for name in &[
+            "grader.cpp",
+            "stub.cpp",
+            "stub.c",
+            "task.h",
+            "solution.cpp",
+            "solution.h",
+        ] {
+            fs::write(sol.join(name), "stub").unwrap();
+        }
+        fs::write(attachments.join("stub.c"), "custom").unwrap();
+        assert_eq!(
+            publish_graders(dir.path(), "task").unwrap(),
+            vec!["grader.cpp", "stub.cpp", "task.h"]
+        );
+        assert!(!attachments.join("solution.cpp").exists());
+        assert!(!attachments.join("solution.h").exists());
+        assert_eq!(
+            fs::read_to_string(attachments.join("stub.c")).unwrap(),
+            "custom"
+        );
+    }
+
+    #[test]
+    fn extract_tar_gz_wrapped() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("task.tar.gz");
+        {
+            let gz = GzEncoder::new(
+                fs::File::create(&archive).unwrap(),
+                Compression::default(),
+            );
+            let mut tar = tar::Builder::new(gz);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, "task/task.yaml", &b"name"[..])
+                .unwrap();
+            tar.into_inner().unwrap().finish().unwrap();
+        }
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        let root = extract_archive(&archive, &dest).unwrap();
+        assert_eq!(root, dest.join("task"));
+        assert_eq!(fs::read_to_string(root.join("task.yaml")).unwrap(), "name");
+    }
+
+    #[test]
+    fn extract_zip_unsafe_path() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("task.zip");
+        {
+            let mut zip = ZipWriter::new(fs::File::create(&archive).unwrap());
+            zip.start_file("task.yaml", FileOptions::default()).unwrap();
+            zip.write_all(b"name").unwrap();
+            zip.start_file("../evil", FileOptions::default()).unwrap();
+            zip.write_all(b"evil").unwrap();
+            zip.finish().unwrap();
+        }
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        let err = extract_archive(&archive, &dest).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the archive contains an unsafe entry: ../evil"
+        );
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn extract_zip_too_large() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("task.zip");
+        {
+            let mut zip = ZipWriter::new(fs::File::create(&archive).unwrap());
+            zip.start_file("task.yaml", FileOptions::default()).unwrap();
+            zip.write_all(&[b'a'; 1000]).unwrap();
+            zip.finish().unwrap();
+        }
+        // declare a size of a single byte, in the local and central headers
+        let mut bytes = fs::read(&archive).unwrap();
+        for (signature, offset) in &[(b"PK\x03\x04", 22), (b"PK\x01\x02", 24)] {
+            let start =
+                bytes.windows(4).position(|w| w == &signature[..]).unwrap();
+            bytes[start + offset..start + offset + 4]
+                .copy_from_slice(&[1, 0, 0, 0]);
+        }
+        fs::write(&archive, &bytes).unwrap();
+
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        let err = extract_zip(&archive, &dest, 100).unwrap_err();
+        assert_eq!(err.to_string(), "the archive is larger than 100 bytes");
+        assert!(fs::metadata(dest.join("task.yaml")).unwrap().len() <= 101);
+    }
+
+    #[test]
+    fn validate_task_crash() {
+        let report = validate_task("false", Path::new(".")).unwrap();
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn validation_report() {
+        let lines = vec![
+            r#"{"action": "statement-compilation", "state": "START",
+                "data": {"language": "it"}}"#,
+            r#"{"action": "statement-compilation", "state": "SUCCESS",
+                "data": {"language": "it"}}"#,
+            r#"{"action": "sanity-check-solution", "state": "FAILURE",
+                "data": {"sample_testcase": 0}}"#,
+            r#"{"action": "warning", "state": "WARNING",
+                "data": {"message": "missing checker"}}"#,
+        ];
+        let mut report = ValidationReport::default();
+        for line in lines {
+            report.add_message(serde_json::from_str(line).unwrap());
+        }
+        assert_eq!(report.statements.len(), 1);
+        assert_eq!(report.statements[0].state, State::Success);
+        assert_eq!(report.sanity_checks.len(), 1);
+        assert_eq!(report.warnings, vec!["missing checker"]);
+        assert!(report.errors.is_empty());
+        assert!(!report.is_valid());
+    }
+}
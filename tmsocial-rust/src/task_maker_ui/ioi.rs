@@ -9,6 +9,7 @@ use std::collections::HashMap;
 pub enum IOITaskType {
     Batch,
     Communication,
+    OutputOnly,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
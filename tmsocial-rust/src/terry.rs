@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::process::{output_with_timeout, run_with_timeout};
+
+/// Time given to a participant to submit the output of a generated input.
+pub const INPUT_DURATION_MINUTES: i64 = 20;
+
+/// Time given to each run of the generator, the validator and the checker.
+pub const MANAGER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Fail)]
+pub enum TerryError {
+    #[fail(display = "the generator failed: {}", stderr)]
+    GenerationFailed { stderr: String },
+    #[fail(display = "the validator rejected the input: {}", stderr)]
+    ValidationFailed { stderr: String },
+    #[fail(display = "the checker failed: {}", stderr)]
+    CheckerFailed { stderr: String },
+}
+
+/// Outcome of a testcase of the output, as printed by the checker.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeedbackCase {
+    pub correct: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Feedback {
+    #[serde(default)]
+    pub cases: Vec<FeedbackCase>,
+}
+
+/// The JSON printed by the checker of a Terry task, the score is between 0
+/// and 1.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckerOutcome {
+    pub score: f64,
+    #[serde(default)]
+    pub feedback: Feedback,
+}
+
+/// Path of a manager of the task, like `managers/generator.linux.x86_64`.
+fn manager(task_dir: &Path, name: &str) -> PathBuf {
+    task_dir
+        .join("managers")
+        .join(format!("{}.linux.x86_64", name))
+}
+
+/// A fresh seed for a generated input.
+pub fn random_seed() -> i64 {
+    rand::random::<u32>().into()
+}
+
+/// Generate the input of the task with the given seed into `dest`, checking
+/// it with the validator if the task has one. Each of them is killed after
+/// `MANAGER_TIMEOUT`.
+pub fn generate_input(
+    task_dir: &Path,
+    seed: i64,
+    dest: &Path,
+) -> Result<(), Error> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let output = run_with_timeout(
+        Command::new(manager(task_dir, "generator"))
+            .arg(seed.to_string())
+            .arg("0")
+            .stdin(Stdio::null())
+            .stdout(fs::File::create(dest)?)
+            .stderr(Stdio::piped()),
+        MANAGER_TIMEOUT,
+    )?;
+    if !output.status.success() {
+        return Err(TerryError::GenerationFailed {
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        }
+        .into());
+    }
+    let validator = manager(task_dir, "validator");
+    if validator.is_file() {
+        let output = run_with_timeout(
+            Command::new(validator)
+                .arg(dest)
+                .arg("0")
+                .stdin(fs::File::open(dest)?)
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+            MANAGER_TIMEOUT,
+        )?;
+        if !output.status.success() {
+            return Err(TerryError::ValidationFailed {
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Check the output of a participant against the input it was produced for,
+/// killing the checker after `MANAGER_TIMEOUT`.
+pub fn check_output(
+    task_dir: &Path,
+    input: &Path,
+    output: &Path,
+) -> Result<CheckerOutcome, Error> {
+    let result = output_with_timeout(
+        Command::new(manager(task_dir, "checker"))
+            .arg(input)
+            .arg(output),
+        MANAGER_TIMEOUT,
+    )?;
+    if !result.status.success() {
+        return Err(TerryError::CheckerFailed {
+            stderr: String::from_utf8_lossy(&result.stderr).to_string(),
+        }
+        .into());
+    }
+    parse_checker_outcome(&String::from_utf8_lossy(&result.stdout))
+}
+
+/// Parse the output of the checker, clamping the score between 0 and 1.
+///
+/// # Example
+/// ```
+/// use tmsocial::terry::parse_checker_outcome;
+///
+/// let outcome = parse_checker_outcome(
+///     r#"{"score": 0.5, "feedback": {"cases": [{"correct": true}]}}"#,
+/// ).unwrap();
+/// assert_eq!(outcome.score, 0.5);
+/// assert_eq!(outcome.feedback.cases.len(), 1);
+/// ```
+pub fn parse_checker_outcome(stdout: &str) -> Result<CheckerOutcome, Error> {
+    let mut outcome: CheckerOutcome = serde_json::from_str(stdout)?;
+    outcome.score = outcome.score.max(0.0).min(1.0);
+    Ok(outcome)
+}
@@ -1,10 +1,22 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use actix_web::test::TestServer;
+use actix_web::{http, Form, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::pg::PgConnection;
-use diesel::{ExpressionMethods, RunQueryDsl};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde_json::json;
+use url::Url;
 
 use crate::establish_connection;
 use crate::models::*;
+use crate::oidc::{code_challenge, Discovery, TokenRequest};
 
 /// Fake Site container, this will wrap a Site and a DB connection. When this
 /// object is dropped the site will be deleted.
@@ -16,7 +28,7 @@ use crate::models::*;
 /// use tmsocial::schema::sites::dsl::sites;
 /// use tmsocial::models::Site;
 ///
-/// let conn = tmsocial::establish_connection();
+/// let conn = tmsocial::establish_connection(&tmsocial::config::get());
 /// let mut site_id = 0;
 /// {
 ///     let site = FakeSite::new();
@@ -37,9 +49,13 @@ impl FakeSite {
     pub fn new() -> FakeSite {
         let domain = random_string();
         println!("Creating fake site with domain {}", domain);
-        let conn = establish_connection();
+        let conn = establish_connection(&crate::config::get());
         let site = diesel::insert_into(crate::schema::sites::dsl::sites)
-            .values(NewSite { domain: domain })
+            .values(NewSite {
+                domain: domain,
+                cookie_secure: true,
+                cookie_same_site: SameSitePolicy::Strict,
+            })
             .get_result::<Site>(&conn)
             .expect("Failed to create fake site");
         FakeSite { site, conn }
@@ -51,6 +67,49 @@ impl FakeSite {
             .values(NewContest {
                 site_id: self.site.id,
                 name: name.to_string(),
+                format: ContestFormat::IOI,
+                start_time: None,
+                end_time: None,
+                freeze_minutes: 60,
+                penalty_minutes: 20,
+            })
+            .get_result::<Contest>(&self.conn)
+            .unwrap()
+    }
+
+    /// Create a fake contest in this site that ended an hour ago
+    pub fn ended_contest(self: &Self, name: &str) -> Contest {
+        let now = Utc::now().naive_utc();
+        diesel::insert_into(crate::schema::contests::dsl::contests)
+            .values(NewContest {
+                site_id: self.site.id,
+                name: name.to_string(),
+                format: ContestFormat::IOI,
+                start_time: Some(now - Duration::hours(3)),
+                end_time: Some(now - Duration::hours(1)),
+                freeze_minutes: 60,
+                penalty_minutes: 20,
+            })
+            .get_result::<Contest>(&self.conn)
+            .unwrap()
+    }
+
+    /// Create a fake ICPC contest in this site
+    pub fn icpc_contest(
+        self: &Self,
+        name: &str,
+        start_time: NaiveDateTime,
+        end_time: NaiveDateTime,
+    ) -> Contest {
+        diesel::insert_into(crate::schema::contests::dsl::contests)
+            .values(NewContest {
+                site_id: self.site.id,
+                name: name.to_string(),
+                format: ContestFormat::ICPC,
+                start_time: Some(start_time),
+                end_time: Some(end_time),
+                freeze_minutes: 60,
+                penalty_minutes: 20,
             })
             .get_result::<Contest>(&self.conn)
             .unwrap()
@@ -68,6 +127,19 @@ impl FakeSite {
             .unwrap()
     }
 
+    /// Create a fake admin of this site
+    pub fn admin(self: &Self, username: &str) -> User {
+        diesel::insert_into(crate::schema::users::dsl::users)
+            .values((
+                crate::schema::users::dsl::username.eq(username),
+                crate::schema::users::dsl::site_id.eq(self.site.id),
+                crate::schema::users::dsl::login_token.eq(random_string()),
+                crate::schema::users::dsl::is_admin.eq(true),
+            ))
+            .get_result::<User>(&self.conn)
+            .unwrap()
+    }
+
     /// Create a fake participation
     pub fn participation(
         self: &Self,
@@ -78,6 +150,23 @@ impl FakeSite {
             .values(NewParticipation {
                 contest_id: contest.id,
                 user_id: user.id,
+                practice: false,
+            })
+            .get_result::<Participation>(&self.conn)
+            .unwrap()
+    }
+
+    /// Create a fake practice participation, used for the archive
+    pub fn practice_participation(
+        self: &Self,
+        contest: &Contest,
+        user: &User,
+    ) -> Participation {
+        diesel::insert_into(crate::schema::participations::dsl::participations)
+            .values(NewParticipation {
+                contest_id: contest.id,
+                user_id: user.id,
+                practice: true,
             })
             .get_result::<Participation>(&self.conn)
             .unwrap()
@@ -102,11 +191,25 @@ impl FakeSite {
                 contest_id: contest.id,
                 format: TaskFormat::IOI,
                 max_score: 100.0,
+                tags: vec![],
+                difficulty: None,
+                statement: "",
+                task_type: TaskType::Batch,
             })
             .get_result::<Task>(&self.conn)
             .unwrap()
     }
 
+    /// Create a fake task published in the archive
+    pub fn archived_task(self: &Self, contest: &Contest, name: &str) -> Task {
+        use crate::schema::tasks::dsl;
+        let task = self.task(contest, name);
+        diesel::update(dsl::tasks.find(task.id))
+            .set(dsl::archived.eq(true))
+            .get_result::<Task>(&self.conn)
+            .unwrap()
+    }
+
     /// Create a fake task and a contest
     pub fn make_task(self: &Self) -> Task {
         let contest = self.contest(&random_string());
@@ -130,6 +233,47 @@ impl FakeSite {
             .unwrap()
     }
 
+    /// Create a fake submission that has already been evaluated
+    pub fn evaluated_submission(
+        self: &Self,
+        task: &Task,
+        part: &Participation,
+        score: f64,
+        accepted: bool,
+        created_at: NaiveDateTime,
+    ) -> Submission {
+        use crate::schema::submissions::dsl;
+        let sub = self.submission(task, part);
+        diesel::update(dsl::submissions.find(sub.id))
+            .set((
+                dsl::status.eq(SubmissionStatus::Success),
+                dsl::score.eq(score),
+                dsl::accepted.eq(accepted),
+                dsl::created_at.eq(created_at),
+            ))
+            .get_result::<Submission>(&self.conn)
+            .unwrap()
+    }
+
+    /// Create an OpenID Connect provider of this site backed by the mock
+    /// issuer
+    pub fn oidc_provider(
+        self: &Self,
+        name: &str,
+        issuer: &MockIssuer,
+    ) -> OidcProvider {
+        diesel::insert_into(crate::schema::oidc_providers::dsl::oidc_providers)
+            .values(NewOidcProvider {
+                site_id: self.site.id,
+                name: name.to_string(),
+                issuer: issuer.issuer.clone(),
+                client_id: MOCK_CLIENT_ID.to_string(),
+                client_secret: MOCK_CLIENT_SECRET.to_string(),
+            })
+            .get_result::<OidcProvider>(&self.conn)
+            .unwrap()
+    }
+
     /// Create a fake submission and a contest, a task, a user and a
     /// participation
     pub fn make_submission(self: &Self) -> Submission {
@@ -156,3 +300,157 @@ impl Drop for FakeSite {
 fn random_string() -> String {
     thread_rng().sample_iter(&Alphanumeric).take(30).collect()
 }
+
+/// Write in `dir` a fake task-maker running the shell command, returning its
+/// path.
+pub fn fake_task_maker(dir: &Path, command: &str) -> String {
+    let path = dir.join("task-maker");
+    fs::write(&path, format!("#!/bin/sh\n{}\n", command)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+pub const MOCK_CLIENT_ID: &str = "tmsocial";
+pub const MOCK_CLIENT_SECRET: &str = "secret";
+
+/// A login authorized by the mock issuer, waiting for its code.
+struct MockCode {
+    redirect_uri: String,
+    challenge: String,
+    nonce: String,
+    subject: String,
+    username: String,
+}
+
+#[derive(Clone)]
+struct MockIssuerState {
+    codes: Arc<Mutex<HashMap<String, MockCode>>>,
+}
+
+/// A local OpenID Connect provider, running while this object is alive. The
+/// users log in with `authorize` instead of a login page.
+pub struct MockIssuer {
+    pub issuer: String,
+    codes: Arc<Mutex<HashMap<String, MockCode>>>,
+    _server: TestServer,
+}
+
+impl MockIssuer {
+    pub fn new() -> MockIssuer {
+        let codes = Arc::new(Mutex::new(HashMap::new()));
+        let state = MockIssuerState {
+            codes: codes.clone(),
+        };
+        let server =
+            TestServer::build_with_state(move || state.clone()).start(|app| {
+                app.resource("/.well-known/openid-configuration", |r| {
+                    r.f(mock_discovery)
+                });
+                app.resource("/token", |r| {
+                    r.method(http::Method::POST).with(mock_token)
+                });
+            });
+        MockIssuer {
+            issuer: format!("http://{}", server.addr()),
+            codes,
+            _server: server,
+        }
+    }
+
+    /// Log in the user `subject` with the authorization URL the site sent
+    /// them to, returning the code and the state to send to the callback.
+    pub fn authorize(
+        self: &Self,
+        authorization_url: &str,
+        subject: &str,
+        username: &str,
+    ) -> (String, String) {
+        let url = Url::parse(authorization_url).expect("Invalid URL");
+        assert!(url.as_str().starts_with(&self.issuer));
+        let params: HashMap<String, String> =
+            url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], MOCK_CLIENT_ID);
+        assert_eq!(params["code_challenge_method"], "S256");
+        let code = random_string();
+        self.codes.lock().unwrap().insert(
+            code.clone(),
+            MockCode {
+                redirect_uri: params["redirect_uri"].clone(),
+                challenge: params["code_challenge"].clone(),
+                nonce: params["nonce"].clone(),
+                subject: subject.to_string(),
+                username: username.to_string(),
+            },
+        );
+        (code, params["state"].clone())
+    }
+}
+
+/// An ID token with these claims, signed by nobody.
+pub fn fake_id_token(claims: &serde_json::Value) -> String {
+    let part = |value: &serde_json::Value| {
+        base64::encode_config(
+            &serde_json::to_vec(value).unwrap(),
+            base64::URL_SAFE_NO_PAD,
+        )
+    };
+    format!(
+        "{}.{}.signature",
+        part(&json!({ "alg": "RS256", "typ": "JWT" })),
+        part(claims)
+    )
+}
+
+/// The issuer is the address the mock is reached at.
+fn mock_issuer(req: &HttpRequest<MockIssuerState>) -> String {
+    format!("http://{}", req.connection_info().host())
+}
+
+fn mock_discovery(req: &HttpRequest<MockIssuerState>) -> HttpResponse {
+    let issuer = mock_issuer(req);
+    HttpResponse::Ok().json(Discovery {
+        authorization_endpoint: format!("{}/authorize", issuer),
+        token_endpoint: format!("{}/token", issuer),
+        issuer,
+    })
+}
+
+fn mock_token(
+    req: HttpRequest<MockIssuerState>,
+    form: Form<TokenRequest>,
+) -> HttpResponse {
+    let code = req.state().codes.lock().unwrap().remove(&form.code);
+    let code = match code {
+        Some(code) => code,
+        None => {
+            return HttpResponse::BadRequest()
+                .json(json!({ "error": "invalid_grant" }))
+        }
+    };
+    if form.grant_type != "authorization_code"
+        || form.client_id != MOCK_CLIENT_ID
+        || form.client_secret != MOCK_CLIENT_SECRET
+        || form.redirect_uri != code.redirect_uri
+        || code_challenge(&form.code_verifier) != code.challenge
+    {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "invalid_grant" }));
+    }
+    let now = Utc::now().timestamp();
+    let claims = json!({
+        "iss": mock_issuer(&req),
+        "sub": code.subject,
+        "aud": MOCK_CLIENT_ID,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": code.nonce,
+        "preferred_username": code.username,
+    });
+    HttpResponse::Ok().json(json!({
+        "access_token": random_string(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": fake_id_token(&claims),
+    }))
+}
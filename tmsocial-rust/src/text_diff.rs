@@ -0,0 +1,195 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Lines of context kept around the changes.
+pub const CONTEXT_LINES: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    Context,
+    Added,
+    Removed,
+}
+
+/// A line of a hunk with its 1-based numbers in the old and in the new file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+/// A group of nearby changes, like a `@@ -old_start,old_lines
+/// +new_start,new_lines @@` block of a unified diff.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+/// Compute the hunks changing `old` into `new`, with `context` lines around
+/// each change.
+///
+/// # Example
+/// ```
+/// use tmsocial::text_diff::{diff_hunks, LineKind};
+///
+/// let hunks = diff_hunks("a\nb\nc\n", "a\nB\nc\n", 1);
+/// assert_eq!(hunks.len(), 1);
+/// let kinds: Vec<LineKind> = hunks[0].lines.iter().map(|l| l.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     vec![LineKind::Context, LineKind::Removed, LineKind::Added, LineKind::Context]
+/// );
+/// ```
+pub fn diff_hunks(old: &str, new: &str, context: usize) -> Vec<Hunk> {
+    let mut lines = vec![];
+    let (mut old_line, mut new_line) = (0, 0);
+    for res in diff::lines(old, new) {
+        let line = match res {
+            diff::Result::Left(text) => {
+                old_line += 1;
+                DiffLine {
+                    kind: LineKind::Removed,
+                    old_line: Some(old_line),
+                    new_line: None,
+                    text: text.to_string(),
+                }
+            }
+            diff::Result::Right(text) => {
+                new_line += 1;
+                DiffLine {
+                    kind: LineKind::Added,
+                    old_line: None,
+                    new_line: Some(new_line),
+                    text: text.to_string(),
+                }
+            }
+            diff::Result::Both(text, _) => {
+                old_line += 1;
+                new_line += 1;
+                DiffLine {
+                    kind: LineKind::Context,
+                    old_line: Some(old_line),
+                    new_line: Some(new_line),
+                    text: text.to_string(),
+                }
+            }
+        };
+        lines.push(line);
+    }
+
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.kind != LineKind::Context)
+        .map(|(i, _)| i)
+        .collect();
+    let mut hunks = vec![];
+    let mut i = 0;
+    while i < changes.len() {
+        let start = changes[i].saturating_sub(context);
+        let mut end = (changes[i] + context + 1).min(lines.len());
+        i += 1;
+        // merge the changes whose context overlaps
+        while i < changes.len() && changes[i].saturating_sub(context) <= end {
+            end = (changes[i] + context + 1).min(lines.len());
+            i += 1;
+        }
+        hunks.push(make_hunk(&lines, start, end));
+    }
+    hunks
+}
+
+fn make_hunk(lines: &Vec<DiffLine>, start: usize, end: usize) -> Hunk {
+    let count = |lines: &[DiffLine], skip: LineKind| {
+        lines.iter().filter(|l| l.kind != skip).count()
+    };
+    let old_before = count(&lines[..start], LineKind::Added);
+    let new_before = count(&lines[..start], LineKind::Removed);
+    let old_lines = count(&lines[start..end], LineKind::Added);
+    let new_lines = count(&lines[start..end], LineKind::Removed);
+    // an empty range starts at the line before it
+    Hunk {
+        old_start: if old_lines == 0 {
+            old_before
+        } else {
+            old_before + 1
+        },
+        old_lines,
+        new_start: if new_lines == 0 {
+            new_before
+        } else {
+            new_before + 1
+        },
+        new_lines,
+        lines: lines[start..end].to_vec(),
+    }
+}
+
+/// Format the hunks as a unified diff between the two files.
+pub fn unified_diff(
+    old_name: &str,
+    new_name: &str,
+    hunks: &Vec<Hunk>,
+) -> String {
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", old_name, new_name);
+    for hunk in hunks {
+        diff += &format!(
+            "@@ -{},{} +{},{} @@\n",
+            hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines
+        );
+        for line in &hunk.lines {
+            let prefix = match line.kind {
+                LineKind::Context => ' ',
+                LineKind::Added => '+',
+                LineKind::Removed => '-',
+            };
+            diff.push(prefix);
+            diff += &line.text;
+            diff.push('\n');
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        let hunks = diff_hunks(old, new, 1);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            unified_diff("sol.cpp", "sol.cpp", &hunks),
+            "--- a/sol.cpp\n+++ b/sol.cpp\n\
+             @@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+             @@ -10,1 +10,2 @@\n 10\n+11\n"
+        );
+    }
+
+    #[test]
+    fn merged_hunks() {
+        let hunks = diff_hunks("a\nb\nc\nd\n", "A\nb\nc\nD\n", 1);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_lines), (1, 4));
+    }
+
+    #[test]
+    fn new_file() {
+        let hunks = diff_hunks("", "a\nb\n", CONTEXT_LINES);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_lines), (0, 0));
+        assert_eq!((hunks[0].new_start, hunks[0].new_lines), (1, 2));
+        assert!(diff_hunks("same\n", "same\n", CONTEXT_LINES).is_empty());
+    }
+}
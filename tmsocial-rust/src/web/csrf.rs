@@ -0,0 +1,167 @@
+use actix_web::error::ErrorForbidden;
+use actix_web::http::header::{ORIGIN, REFERER};
+use actix_web::http::Method;
+use actix_web::middleware::{Middleware, Started};
+use actix_web::{FromRequest, HttpRequest, Result};
+use futures::future::Future;
+use log::warn;
+use url::Url;
+
+use super::extractors::{get_bearer_token, AUTH_COOKIE};
+use super::State;
+use crate::models::Site;
+
+/// Middleware rejecting the requests with side effects sent by other sites:
+/// their `Origin`, or their `Referer` when there is no `Origin`, must be the
+/// site itself, with the scheme it's served with. The requests authenticated
+/// with an API token are not checked.
+pub struct OriginCheck;
+
+/// Whether the requests with this method have no side effects.
+pub fn is_safe_method(method: &Method) -> bool {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE => true,
+        _ => false,
+    }
+}
+
+/// The `scheme://host[:port]` of an origin or of a referer, without the
+/// default port of the scheme.
+fn origin_of(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    })
+}
+
+/// The origin of the pages of the site. The sites with an insecure cookie are
+/// served over plain HTTP.
+fn site_origin(site: &Site) -> String {
+    let scheme = if site.cookie_secure { "https" } else { "http" };
+    format!("{}://{}", scheme, site.domain)
+}
+
+/// Whether a request with side effects comes from the pages of `site_origin`.
+/// The requests with neither `Origin` nor `Referer` are accepted only without
+/// the auth cookie, since the browsers send them on the cross-site requests.
+fn same_origin(
+    site_origin: &str,
+    origin: Option<&str>,
+    referer: Option<&str>,
+    has_cookie: bool,
+) -> bool {
+    match origin.or(referer) {
+        Some(source) => match (origin_of(source), origin_of(site_origin)) {
+            (Some(source), Some(site)) => source.eq_ignore_ascii_case(&site),
+            _ => false,
+        },
+        None => !has_cookie,
+    }
+}
+
+impl Middleware<State> for OriginCheck {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        // the browsers never add an API token by themselves
+        if is_safe_method(req.method()) || get_bearer_token(req).is_some() {
+            return Ok(Started::Done);
+        }
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let origin = header(ORIGIN);
+        let referer = header(REFERER);
+        let has_cookie = req.cookie(AUTH_COOKIE).is_some();
+        let method = req.method().clone();
+        let path = req.path().to_string();
+        // the site of the Host header, whose scheme is known only from it
+        Ok(Started::Future(Box::new(Site::extract(req).and_then(
+            move |site| {
+                let origin = origin.as_ref().map(|o| o.as_str());
+                let referer = referer.as_ref().map(|r| r.as_str());
+                let site_origin = site_origin(&site);
+                if same_origin(&site_origin, origin, referer, has_cookie) {
+                    Ok(None)
+                } else {
+                    warn!(
+                        "Cross-site {} {} from {:?} refused",
+                        method,
+                        path,
+                        origin.or(referer)
+                    );
+                    Err(ErrorForbidden("Cross-site request"))
+                }
+            },
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+
+    use super::*;
+    use crate::test_utils::FakeSite;
+    use crate::web::test_utils::TestRequestBuilder;
+    use crate::web::ErrorResponse;
+
+    #[test]
+    fn origins() {
+        let site = "https://site";
+        assert!(same_origin(site, Some("https://site"), None, true));
+        assert!(same_origin(site, Some("https://SITE:443"), None, true));
+        assert!(same_origin(
+            "http://site:8080",
+            Some("http://site:8080"),
+            None,
+            true
+        ));
+        assert!(same_origin(site, None, Some("https://site/a/b"), true));
+        assert!(!same_origin(site, Some("http://site"), None, true));
+        assert!(!same_origin(site, Some("https://site:8443"), None, true));
+        assert!(!same_origin(site, Some("https://evil"), None, true));
+        assert!(!same_origin(
+            site,
+            Some("https://evil"),
+            Some("https://site/"),
+            true
+        ));
+        assert!(!same_origin(site, Some("null"), None, false));
+        assert!(!same_origin(site, None, None, true));
+        assert!(same_origin(site, None, None, false));
+    }
+
+    #[test]
+    fn insecure_scheme_join() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let contest = site.contest("contest");
+        let url = format!("/api/contest/{}/join", contest.id);
+        let err: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .header("origin", &format!("http://{}", site.site.domain))
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Cross-site request");
+    }
+
+    #[test]
+    fn cross_site_join() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let contest = site.contest("contest");
+        let url = format!("/api/contest/{}/join", contest.id);
+        let err: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .header("origin", "https://evil.example.com")
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Cross-site request");
+    }
+}
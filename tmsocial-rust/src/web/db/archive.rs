@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+
+use actix::{Handler, Message};
+use actix_web::error::{
+    ErrorInternalServerError, ErrorNotFound, ErrorUnprocessableEntity,
+};
+use actix_web::Error;
+use chrono::Utc;
+use diesel::{
+    Connection, ExpressionMethods, PgArrayExpressionMethods, QueryDsl,
+    QueryResult, RunQueryDsl,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::models::*;
+
+use super::pagination::Page;
+use super::submission::{submissions_page, SubmissionsQuery};
+use super::Executor;
+
+pub struct GetArchive {
+    pub site_id: i32,
+    pub user_id: Option<i32>,
+    /// Keep only the tasks solved (or not solved) by the user.
+    pub solved: Option<bool>,
+    /// Keep only the tasks that have all these tags.
+    pub tags: Vec<String>,
+    pub min_difficulty: Option<i32>,
+    pub max_difficulty: Option<i32>,
+}
+
+pub struct GetArchivedTask {
+    pub task_id: i32,
+    pub site_id: i32,
+}
+
+pub struct PublishTask {
+    pub task_id: i32,
+    pub site_id: i32,
+    pub published: bool,
+    pub admin_id: i32,
+    pub client: ClientInfo,
+}
+
+pub struct GetPracticeParticipation {
+    pub contest_id: i32,
+    pub user_id: i32,
+    /// Create the participation if the user has not practiced yet.
+    pub create: bool,
+}
+
+pub struct GetPracticeSubmissions {
+    pub task_id: i32,
+    pub user_id: i32,
+    pub query: SubmissionsQuery,
+}
+
+/// Statistics of the task computed only on the practice submissions.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ArchiveStats {
+    pub submissions: usize,
+    pub solvers: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchiveTask {
+    pub task: Task,
+    pub best_score: Option<f64>,
+    pub solved: bool,
+    pub stats: ArchiveStats,
+}
+
+impl Message for GetArchive {
+    type Result = Result<Vec<ArchiveTask>, Error>;
+}
+
+impl Handler<GetArchive> for Executor {
+    type Result = Result<Vec<ArchiveTask>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetArchive,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{contests, participations, submissions, tasks};
+
+        let conn = self.conn()?;
+        let mut query = tasks::table
+            .inner_join(contests::table)
+            .filter(contests::site_id.eq(msg.site_id))
+            .filter(tasks::archived.eq(true))
+            .into_boxed();
+        if !msg.tags.is_empty() {
+            query = query.filter(tasks::tags.contains(msg.tags));
+        }
+        if let Some(min) = msg.min_difficulty {
+            query = query.filter(tasks::difficulty.ge(min));
+        }
+        if let Some(max) = msg.max_difficulty {
+            query = query.filter(tasks::difficulty.le(max));
+        }
+        let archived = query
+            .order(tasks::id)
+            .select(tasks::all_columns)
+            .load::<Task>(&conn)
+            .map_err(ErrorInternalServerError)?;
+        let task_ids: Vec<i32> = archived.iter().map(|t| t.id).collect();
+        let subs = submissions::table
+            .inner_join(participations::table)
+            .filter(submissions::task_id.eq_any(task_ids))
+            .load::<(Submission, Participation)>(&conn)
+            .map_err(ErrorInternalServerError)?;
+
+        let max_scores: HashMap<i32, f64> =
+            archived.iter().map(|t| (t.id, t.max_score)).collect();
+        let mut stats: HashMap<i32, (usize, HashSet<i32>)> = HashMap::new();
+        let mut best_scores: HashMap<i32, f64> = HashMap::new();
+        for (sub, part) in subs {
+            let score = sub.score.unwrap_or(0.0);
+            if part.practice {
+                let stat = stats.entry(sub.task_id).or_default();
+                stat.0 += 1;
+                if score >= max_scores[&sub.task_id] {
+                    stat.1.insert(part.user_id);
+                }
+            }
+            // the best score of the user counts the contest submissions too
+            if msg.user_id == Some(part.user_id) {
+                let best = best_scores.entry(sub.task_id).or_insert(score);
+                *best = best.max(score);
+            }
+        }
+
+        Ok(archived
+            .into_iter()
+            .map(|task| {
+                let best_score = best_scores.get(&task.id).cloned();
+                let (submissions, solvers) =
+                    stats.remove(&task.id).unwrap_or_default();
+                ArchiveTask {
+                    solved: best_score.map_or(false, |s| s >= task.max_score),
+                    best_score,
+                    stats: ArchiveStats {
+                        submissions,
+                        solvers: solvers.len(),
+                    },
+                    task,
+                }
+            })
+            .filter(|t| msg.solved.map_or(true, |solved| t.solved == solved))
+            .collect())
+    }
+}
+
+impl Message for GetArchivedTask {
+    type Result = Result<Task, Error>;
+}
+
+impl Handler<GetArchivedTask> for Executor {
+    type Result = Result<Task, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetArchivedTask,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{contests, tasks};
+
+        let conn = self.conn()?;
+        let task = tasks::table
+            .inner_join(contests::table)
+            .filter(tasks::id.eq(msg.task_id))
+            .filter(contests::site_id.eq(msg.site_id))
+            .filter(tasks::archived.eq(true))
+            .select(tasks::all_columns)
+            .first::<Task>(&conn);
+        match task {
+            Ok(task) => Ok(task),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorNotFound(format!("No such task")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for PublishTask {
+    type Result = Result<Task, Error>;
+}
+
+impl Handler<PublishTask> for Executor {
+    type Result = Result<Task, Error>;
+
+    fn handle(
+        &mut self,
+        msg: PublishTask,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{contests, tasks};
+
+        let conn = self.conn()?;
+        let task = tasks::table
+            .inner_join(contests::table)
+            .filter(tasks::id.eq(msg.task_id))
+            .filter(contests::site_id.eq(msg.site_id))
+            .first::<(Task, Contest)>(&conn);
+        let (task, contest) = match task {
+            Ok(task) => task,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(ErrorNotFound(format!("No such task")))
+            }
+            Err(err) => return Err(ErrorInternalServerError(err)),
+        };
+        // the contests without an end time never end
+        let ended = contest
+            .end_time
+            .map_or(false, |end| end <= Utc::now().naive_utc());
+        if msg.published && !ended {
+            return Err(ErrorUnprocessableEntity(format!(
+                "The contest is not over yet"
+            )));
+        }
+        conn.transaction(|| -> QueryResult<Task> {
+            let task = diesel::update(tasks::table.find(task.id))
+                .set(tasks::archived.eq(msg.published))
+                .get_result::<Task>(&conn)?;
+            audit::record(
+                &conn,
+                msg.site_id,
+                Some(msg.admin_id),
+                &msg.client,
+                AuditAction::PublishTask,
+                json!({ "task_id": task.id, "published": msg.published }),
+            )?;
+            Ok(task)
+        })
+        .map_err(ErrorInternalServerError)
+    }
+}
+
+impl Message for GetPracticeParticipation {
+    type Result = Result<Participation, Error>;
+}
+
+impl Handler<GetPracticeParticipation> for Executor {
+    type Result = Result<Participation, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetPracticeParticipation,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::participations::dsl::*;
+
+        let conn = self.conn()?;
+        if msg.create {
+            diesel::insert_into(participations)
+                .values(NewParticipation {
+                    contest_id: msg.contest_id,
+                    user_id: msg.user_id,
+                    practice: true,
+                })
+                .on_conflict_do_nothing()
+                .execute(&conn)
+                .map_err(ErrorInternalServerError)?;
+        }
+        let participation = participations
+            .filter(user_id.eq(&msg.user_id))
+            .filter(contest_id.eq(&msg.contest_id))
+            .filter(practice.eq(true))
+            .first::<Participation>(&conn);
+        match participation {
+            Ok(participation) => Ok(participation),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorNotFound(format!("No such participation")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for GetPracticeSubmissions {
+    type Result = Result<Page<Submission>, Error>;
+}
+
+impl Handler<GetPracticeSubmissions> for Executor {
+    type Result = Result<Page<Submission>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetPracticeSubmissions,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{participations, submissions};
+
+        let conn = self.conn()?;
+        let base = || {
+            let practice = participations::table
+                .filter(participations::user_id.eq(msg.user_id))
+                .filter(participations::practice.eq(true))
+                .select(participations::id);
+            submissions::table
+                .filter(submissions::task_id.eq(msg.task_id))
+                .filter(submissions::participation_id.eq_any(practice))
+                .into_boxed()
+        };
+        submissions_page(&conn, base, &msg.query)
+    }
+}
@@ -0,0 +1,83 @@
+use actix::{Handler, Message};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::Error;
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::models::AuditEntry;
+
+use super::pagination::*;
+use super::Executor;
+
+/// The audit log of a site, the most recent entries first.
+pub struct GetAuditLog {
+    pub site_id: i32,
+    pub query: AuditQuery,
+}
+
+/// Filters and page of the audit log.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct AuditQuery {
+    pub actor_id: Option<i32>,
+    pub action: Option<AuditAction>,
+    /// Keep the entries written from this time on.
+    pub since: Option<NaiveDateTime>,
+    /// Keep the entries written before this time.
+    pub until: Option<NaiveDateTime>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl Message for GetAuditLog {
+    type Result = Result<Page<AuditEntry>, Error>;
+}
+
+impl Handler<GetAuditLog> for Executor {
+    type Result = Result<Page<AuditEntry>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetAuditLog,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::audit_log::dsl::*;
+
+        let conn = self.conn()?;
+        let query = &msg.query;
+        let filtered = || {
+            let mut q = audit_log.filter(site_id.eq(msg.site_id)).into_boxed();
+            if let Some(actor) = query.actor_id {
+                q = q.filter(actor_id.eq(actor));
+            }
+            if let Some(act) = query.action {
+                q = q.filter(action.eq(act.name()));
+            }
+            if let Some(since) = query.since {
+                q = q.filter(created_at.ge(since));
+            }
+            if let Some(until) = query.until {
+                q = q.filter(created_at.lt(until));
+            }
+            q
+        };
+        let total = filtered()
+            .count()
+            .get_result::<i64>(&conn)
+            .map_err(ErrorInternalServerError)?;
+        let limit = page_limit(query.limit);
+        let cursor = match &query.cursor {
+            Some(c) => Some(Cursor::<NaiveDateTime>::decode(c)?),
+            None => None,
+        };
+        let items = keyset!(filtered(), created_at, id, Order::Desc, cursor)
+            .limit(limit + 1)
+            .load::<AuditEntry>(&conn)
+            .map_err(ErrorInternalServerError)?;
+        Ok(Page::new(items, limit, total, |entry| Cursor {
+            key: entry.created_at,
+            id: entry.id,
+        }))
+    }
+}
@@ -1,13 +1,23 @@
 use super::Executor;
-use crate::models::Contest;
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::icpc::IcpcScoreboard;
 use crate::models::NewParticipation;
+use crate::models::{Contest, ContestFormat};
 use actix::{Handler, Message};
 use actix_web::error::ErrorUnprocessableEntity;
 use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
 use actix_web::Error;
+use chrono::NaiveDateTime;
 use diesel::result::DatabaseErrorKind;
 use diesel::result::Error::DatabaseError;
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl,
+    QueryResult, RunQueryDsl,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::pagination::*;
 
 pub struct GetContest {
     pub id: i32,
@@ -15,11 +25,40 @@ pub struct GetContest {
 
 pub struct GetContests {
     pub site_id: i32,
+    pub query: ContestsQuery,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContestSort {
+    Id,
+    Name,
+}
+
+/// Filters, sort and page of a list of contests. By default the contests are
+/// sorted by id.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ContestsQuery {
+    /// Keep the contests starting from this time on.
+    pub since: Option<NaiveDateTime>,
+    /// Keep the contests starting before this time.
+    pub until: Option<NaiveDateTime>,
+    pub sort: Option<ContestSort>,
+    pub order: Option<Order>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
 }
 
 pub struct JoinContest {
     pub contest_id: i32,
     pub user_id: i32,
+    pub client: ClientInfo,
+}
+
+pub struct GetIcpcScoreboard {
+    pub contest: Contest,
+    /// Ignore the freeze, only admins should see this.
+    pub unfrozen: bool,
 }
 
 impl Message for GetContest {
@@ -36,7 +75,8 @@ impl Handler<GetContest> for Executor {
     ) -> Self::Result {
         use crate::schema::contests::dsl::*;
 
-        let contest = contests.find(&msg.id).first::<Contest>(&self.0);
+        let conn = self.conn()?;
+        let contest = contests.find(&msg.id).first::<Contest>(&conn);
         match contest {
             Ok(contest) => Ok(contest),
             Err(diesel::result::Error::NotFound) => {
@@ -48,24 +88,68 @@ impl Handler<GetContest> for Executor {
 }
 
 impl Message for GetContests {
-    type Result = Result<Vec<Contest>, Error>;
+    type Result = Result<Page<Contest>, Error>;
 }
 
 impl Handler<GetContests> for Executor {
-    type Result = Result<Vec<Contest>, Error>;
+    type Result = Result<Page<Contest>, Error>;
 
     fn handle(
         &mut self,
         msg: GetContests,
         _: &mut Self::Context,
     ) -> Self::Result {
-        let contests = crate::schema::contests::dsl::contests
-            .filter(crate::schema::contests::columns::site_id.eq(&msg.site_id))
-            .load::<Contest>(&self.0);
-        match contests {
-            Ok(contests) => Ok(contests),
-            Err(error) => Err(ErrorInternalServerError(error)),
-        }
+        use crate::schema::contests::dsl::*;
+
+        let conn = self.conn()?;
+        let query = &msg.query;
+        let filtered = || {
+            let mut q = contests.filter(site_id.eq(msg.site_id)).into_boxed();
+            if let Some(since) = query.since {
+                q = q.filter(start_time.ge(since));
+            }
+            if let Some(until) = query.until {
+                q = q.filter(start_time.lt(until));
+            }
+            q
+        };
+        let total = filtered()
+            .count()
+            .get_result::<i64>(&conn)
+            .map_err(ErrorInternalServerError)?;
+        let limit = page_limit(query.limit);
+        let order = query.order.unwrap_or(Order::Asc);
+        let sort = query.sort.unwrap_or(ContestSort::Id);
+        let cursor = query.cursor.as_ref().map(|c| c.as_str());
+        let q = match sort {
+            ContestSort::Id => {
+                let cursor = match cursor {
+                    Some(c) => Some(Cursor::<i32>::decode(c)?),
+                    None => None,
+                };
+                keyset!(filtered(), id, id, order, cursor)
+            }
+            ContestSort::Name => {
+                let cursor = match cursor {
+                    Some(c) => Some(Cursor::<String>::decode(c)?),
+                    None => None,
+                };
+                keyset!(filtered(), name, id, order, cursor)
+            }
+        };
+        let items = q
+            .limit(limit + 1)
+            .load::<Contest>(&conn)
+            .map_err(ErrorInternalServerError)?;
+        Ok(Page::new(items, limit, total, |contest| Cursor {
+            key: match sort {
+                ContestSort::Id => serde_json::to_value(contest.id).unwrap(),
+                ContestSort::Name => {
+                    serde_json::to_value(&contest.name).unwrap()
+                }
+            },
+            id: contest.id,
+        }))
     }
 }
 
@@ -81,14 +165,26 @@ impl Handler<JoinContest> for Executor {
         msg: JoinContest,
         _: &mut Self::Context,
     ) -> Self::Result {
-        let insert_res = diesel::insert_into(
-            crate::schema::participations::dsl::participations,
-        )
-        .values(NewParticipation {
-            contest_id: msg.contest_id,
-            user_id: msg.user_id,
-        })
-        .execute(&self.0);
+        let conn = self.conn()?;
+        let insert_res = conn.transaction(|| -> QueryResult<_> {
+            diesel::insert_into(
+                crate::schema::participations::dsl::participations,
+            )
+            .values(NewParticipation {
+                contest_id: msg.contest_id,
+                user_id: msg.user_id,
+                practice: false,
+            })
+            .execute(&conn)?;
+            audit::record(
+                &conn,
+                audit::contest_site(&conn, msg.contest_id)?,
+                Some(msg.user_id),
+                &msg.client,
+                AuditAction::JoinContest,
+                json!({ "contest_id": msg.contest_id }),
+            )
+        });
         match insert_res {
             Ok(_) => Ok(()),
             Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
@@ -100,3 +196,26 @@ impl Handler<JoinContest> for Executor {
         }
     }
 }
+
+impl Message for GetIcpcScoreboard {
+    type Result = Result<IcpcScoreboard, Error>;
+}
+
+impl Handler<GetIcpcScoreboard> for Executor {
+    type Result = Result<IcpcScoreboard, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetIcpcScoreboard,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let conn = self.conn()?;
+        if msg.contest.format != ContestFormat::ICPC {
+            return Err(ErrorUnprocessableEntity(format!(
+                "Not an ICPC contest"
+            )));
+        }
+        crate::icpc::load_scoreboard(&conn, &msg.contest, msg.unfrozen)
+            .map_err(ErrorInternalServerError)
+    }
+}
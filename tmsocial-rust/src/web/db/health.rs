@@ -0,0 +1,42 @@
+use actix::{Handler, Message};
+use actix_web::Error;
+
+use crate::health::{
+    check_database, check_storage, check_task_maker, Check, Readiness,
+    TASK_MAKER_TIMEOUT,
+};
+
+use super::Executor;
+
+/// Check the database, the number of submissions waiting for an evaluation,
+/// the storage directory and task-maker, as set in the configuration.
+pub struct CheckReadiness;
+
+impl Message for CheckReadiness {
+    type Result = Result<Readiness, Error>;
+}
+
+impl Handler<CheckReadiness> for Executor {
+    type Result = Result<Readiness, Error>;
+
+    fn handle(
+        &mut self,
+        _: CheckReadiness,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let config = self.config();
+        let (database, evaluator) = match self.conn() {
+            Ok(conn) => check_database(&conn, config.evaluation.max_backlog),
+            Err(e) => (
+                Check::failed(e.to_string()),
+                Check::failed("cannot count the waiting submissions"),
+            ),
+        };
+        Ok(Readiness::new(
+            database,
+            check_storage(&config.storage_dir),
+            check_task_maker(&config.task_maker, TASK_MAKER_TIMEOUT),
+            evaluator,
+        ))
+    }
+}
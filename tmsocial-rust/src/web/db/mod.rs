@@ -1,27 +1,63 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::logging::{self, LogContext};
+use crate::metrics;
 use crate::models::Site;
-use actix::{Actor, Handler, Message, SyncContext};
+use crate::pool::{DbPool, PooledConnection};
+use actix::{
+    Actor, Addr, Handler, MailboxError, Message, SyncArbiter, SyncContext,
+};
 use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
 use actix_web::Error;
-use diesel::pg::PgConnection;
 use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use futures::Future;
+
+#[macro_use]
+pub mod pagination;
 
+pub mod archive;
+pub mod audit;
 pub mod contest;
+pub mod health;
+pub mod oidc;
 pub mod participation;
+pub mod plagiarism;
 pub mod submission;
 pub mod task;
+pub mod terry;
+pub mod token;
 pub mod user;
 
+pub use self::archive::*;
+pub use self::audit::*;
 pub use self::contest::*;
+pub use self::health::*;
+pub use self::oidc::*;
+pub use self::pagination::*;
 pub use self::participation::*;
+pub use self::plagiarism::*;
 pub use self::submission::*;
 pub use self::task::*;
+pub use self::terry::*;
+pub use self::token::*;
 pub use self::user::*;
 
-pub struct Executor(PgConnection);
+pub struct Executor(DbPool, Arc<Config>);
 
 impl Executor {
-    pub fn new(conn: PgConnection) -> Executor {
-        Executor(conn)
+    pub fn new(pool: DbPool, config: Arc<Config>) -> Executor {
+        Executor(pool, config)
+    }
+
+    /// A connection from the pool, checked out for the current message.
+    fn conn(&self) -> Result<PooledConnection, Error> {
+        self.0.get().map_err(ErrorInternalServerError)
+    }
+
+    fn config(&self) -> &Config {
+        &self.1
     }
 }
 
@@ -29,6 +65,120 @@ impl Actor for Executor {
     type Context = SyncContext<Self>;
 }
 
+/// A message sent by a request, whose id tags the log lines of the executor.
+pub struct Traced<M> {
+    pub request_id: Option<String>,
+    pub msg: M,
+}
+
+impl<M, T> Message for Traced<M>
+where
+    M: Message<Result = Result<T, Error>>,
+    T: 'static,
+{
+    type Result = Result<T, Error>;
+}
+
+impl<M, T> Handler<Traced<M>> for Executor
+where
+    M: Message<Result = Result<T, Error>>,
+    T: 'static,
+    Executor: Handler<M, Result = Result<T, Error>>,
+{
+    type Result = Result<T, Error>;
+
+    fn handle(
+        &mut self,
+        msg: Traced<M>,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let _context = logging::scope(LogContext {
+            request_id: msg.request_id,
+            ..Default::default()
+        });
+        <Executor as Handler<M>>::handle(self, msg.msg, ctx)
+    }
+}
+
+/// Address of the executors, recording the latency and the failures of the
+/// messages sent to them. Inside the task of a request its id is sent with the
+/// messages.
+#[derive(Clone)]
+pub struct Db(Addr<Executor>);
+
+impl Db {
+    pub fn start(workers: usize, pool: DbPool, config: Arc<Config>) -> Db {
+        Db(SyncArbiter::start(workers, move || {
+            Executor::new(pool.clone(), config.clone())
+        }))
+    }
+
+    pub fn send<M, T>(
+        &self,
+        msg: M,
+    ) -> impl Future<Item = Result<T, Error>, Error = MailboxError>
+    where
+        M: Message<Result = Result<T, Error>> + Send + 'static,
+        T: Send + 'static,
+        Executor: Handler<M, Result = Result<T, Error>>,
+    {
+        self.send_request(msg, None)
+    }
+
+    /// Like `send`, failing with `MailboxError::Timeout` if the message is
+    /// not handled within `timeout`, including the wait for a free executor.
+    pub fn send_timeout<M, T>(
+        &self,
+        msg: M,
+        timeout: Duration,
+    ) -> impl Future<Item = Result<T, Error>, Error = MailboxError>
+    where
+        M: Message<Result = Result<T, Error>> + Send + 'static,
+        T: Send + 'static,
+        Executor: Handler<M, Result = Result<T, Error>>,
+    {
+        self.send_request(msg, Some(timeout))
+    }
+
+    fn send_request<M, T>(
+        &self,
+        msg: M,
+        timeout: Option<Duration>,
+    ) -> impl Future<Item = Result<T, Error>, Error = MailboxError>
+    where
+        M: Message<Result = Result<T, Error>> + Send + 'static,
+        T: Send + 'static,
+        Executor: Handler<M, Result = Result<T, Error>>,
+    {
+        let start = Instant::now();
+        let msg = Traced {
+            request_id: logging::request_id(),
+            msg,
+        };
+        let mut request = self.0.send(msg);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        request.then(move |res| {
+            metrics::DB_QUERY_DURATION
+                .observe(metrics::seconds(start.elapsed()));
+            let failed = match &res {
+                Ok(Ok(_)) => false,
+                Ok(Err(e)) => e
+                    .as_response_error()
+                    .error_response()
+                    .status()
+                    .is_server_error(),
+                Err(_) => true,
+            };
+            if failed {
+                metrics::DB_ERRORS.inc();
+            }
+            res
+        })
+    }
+}
+
 pub struct GetSite {
     pub host: String,
 }
@@ -43,8 +193,9 @@ impl Handler<GetSite> for Executor {
     fn handle(&mut self, msg: GetSite, _: &mut Self::Context) -> Self::Result {
         use crate::schema::sites::dsl::*;
 
+        let conn = self.conn()?;
         let site_or_error =
-            sites.filter(domain.eq(&msg.host)).load::<Site>(&self.0);
+            sites.filter(domain.eq(&msg.host)).load::<Site>(&conn);
 
         match site_or_error {
             Ok(mut site) => {
@@ -0,0 +1,289 @@
+use actix::{Handler, Message};
+use actix_web::error::{
+    ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+};
+use actix_web::Error;
+use chrono::{Duration, Utc};
+use diesel::pg::PgConnection;
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult,
+    RunQueryDsl,
+};
+use serde_json::json;
+
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::models::*;
+use crate::oidc::{
+    random_secret, suggested_username, IdClaims, LOGIN_TIMEOUT_SECS,
+};
+
+use super::user::{get_login_token, LoginToken};
+use super::Executor;
+
+/// The identity providers of the site.
+pub struct GetOidcProviders {
+    pub site_id: i32,
+}
+
+pub struct GetOidcProvider {
+    pub site_id: i32,
+    pub name: String,
+}
+
+/// Start a login with a provider, the identity will be linked to
+/// `link_user_id` if given.
+pub struct StartOidcLogin {
+    pub provider_id: i32,
+    pub link_user_id: Option<i32>,
+}
+
+/// The login started with this state, which cannot be used again.
+pub struct TakeOidcLogin {
+    pub provider_id: i32,
+    pub state: String,
+}
+
+/// Log in the user with a verified identity of the provider: the user linked
+/// to it, `link_user_id` which is linked now, or a new user.
+pub struct DoOidcLogin {
+    pub provider: OidcProvider,
+    pub claims: IdClaims,
+    pub link_user_id: Option<i32>,
+    pub client: ClientInfo,
+}
+
+impl Message for GetOidcProviders {
+    type Result = Result<Vec<OidcProvider>, Error>;
+}
+
+impl Handler<GetOidcProviders> for Executor {
+    type Result = Result<Vec<OidcProvider>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetOidcProviders,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::oidc_providers::dsl::*;
+
+        let conn = self.conn()?;
+        oidc_providers
+            .filter(site_id.eq(msg.site_id))
+            .order(name)
+            .load::<OidcProvider>(&conn)
+            .map_err(ErrorInternalServerError)
+    }
+}
+
+impl Message for GetOidcProvider {
+    type Result = Result<OidcProvider, Error>;
+}
+
+impl Handler<GetOidcProvider> for Executor {
+    type Result = Result<OidcProvider, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetOidcProvider,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::oidc_providers::dsl::*;
+
+        let conn = self.conn()?;
+        let provider = oidc_providers
+            .filter(site_id.eq(msg.site_id))
+            .filter(name.eq(&msg.name))
+            .first::<OidcProvider>(&conn);
+        match provider {
+            Ok(provider) => Ok(provider),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorNotFound(format!("No such provider")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for StartOidcLogin {
+    type Result = Result<OidcLogin, Error>;
+}
+
+impl Handler<StartOidcLogin> for Executor {
+    type Result = Result<OidcLogin, Error>;
+
+    fn handle(
+        &mut self,
+        msg: StartOidcLogin,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::oidc_logins::dsl::*;
+
+        let conn = self.conn()?;
+        // the logins never completed
+        diesel::delete(oidc_logins.filter(created_at.lt(login_deadline())))
+            .execute(&conn)
+            .map_err(ErrorInternalServerError)?;
+        diesel::insert_into(oidc_logins)
+            .values(&NewOidcLogin {
+                state: random_secret(),
+                provider_id: msg.provider_id,
+                code_verifier: random_secret(),
+                nonce: random_secret(),
+                link_user_id: msg.link_user_id,
+            })
+            .get_result::<OidcLogin>(&conn)
+            .map_err(ErrorInternalServerError)
+    }
+}
+
+impl Message for TakeOidcLogin {
+    type Result = Result<OidcLogin, Error>;
+}
+
+impl Handler<TakeOidcLogin> for Executor {
+    type Result = Result<OidcLogin, Error>;
+
+    fn handle(
+        &mut self,
+        msg: TakeOidcLogin,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::oidc_logins::dsl::*;
+
+        let conn = self.conn()?;
+        let login = diesel::delete(
+            oidc_logins
+                .find(&msg.state)
+                .filter(provider_id.eq(msg.provider_id))
+                .filter(created_at.ge(login_deadline())),
+        )
+        .get_result::<OidcLogin>(&conn);
+        match login {
+            Ok(login) => Ok(login),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorForbidden(format!("Invalid login state")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for DoOidcLogin {
+    type Result = Result<(User, LoginToken), Error>;
+}
+
+impl Handler<DoOidcLogin> for Executor {
+    type Result = Result<(User, LoginToken), Error>;
+
+    fn handle(
+        &mut self,
+        msg: DoOidcLogin,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{oidc_identities, users};
+
+        let conn = self.conn()?;
+        let provider = &msg.provider;
+        let site_id = provider.site_id;
+        // None when the identity is already linked to another user
+        let user = conn.transaction(|| -> QueryResult<Option<User>> {
+            let identity = oidc_identities::table
+                .filter(oidc_identities::provider_id.eq(provider.id))
+                .filter(oidc_identities::subject.eq(&msg.claims.sub))
+                .first::<OidcIdentity>(&conn)
+                .optional()?;
+            let user_id = match (identity.as_ref(), msg.link_user_id) {
+                (Some(identity), Some(link)) if identity.user_id != link => {
+                    return Ok(None)
+                }
+                (Some(identity), _) => identity.user_id,
+                (None, link) => {
+                    let (user_id, created) = match link {
+                        Some(link) => (link, false),
+                        None => {
+                            (create_user(&conn, site_id, &msg.claims)?, true)
+                        }
+                    };
+                    diesel::insert_into(oidc_identities::table)
+                        .values(&NewOidcIdentity {
+                            provider_id: provider.id,
+                            subject: msg.claims.sub.clone(),
+                            user_id,
+                        })
+                        .execute(&conn)?;
+                    audit::record(
+                        &conn,
+                        site_id,
+                        Some(user_id),
+                        &msg.client,
+                        AuditAction::LinkIdentity,
+                        json!({
+                            "provider": provider.name,
+                            "subject": msg.claims.sub,
+                            "created": created,
+                        }),
+                    )?;
+                    user_id
+                }
+            };
+            let user = users::table.find(user_id).first::<User>(&conn)?;
+            audit::record(
+                &conn,
+                site_id,
+                Some(user.id),
+                &msg.client,
+                AuditAction::Login,
+                json!({ "username": user.username, "provider": provider.name }),
+            )?;
+            Ok(Some(user))
+        });
+        match user {
+            Ok(Some(user)) => {
+                let token = get_login_token(&conn, &user)
+                    .map_err(ErrorInternalServerError)?;
+                Ok((user, token))
+            }
+            Ok(None) => Err(ErrorConflict(format!(
+                "This account is linked to another user"
+            ))),
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+/// The logins started before this time are expired.
+fn login_deadline() -> chrono::NaiveDateTime {
+    Utc::now().naive_utc() - Duration::seconds(LOGIN_TIMEOUT_SECS)
+}
+
+/// A new user of the site for the claims, with the suggested username or, if
+/// taken, with a number after it. A username taken by a concurrent login is
+/// skipped like the others, since the insert does nothing on a conflict.
+fn create_user(
+    conn: &PgConnection,
+    user_site_id: i32,
+    claims: &IdClaims,
+) -> QueryResult<i32> {
+    use crate::schema::users::dsl::*;
+
+    let base = suggested_username(claims);
+    let mut candidate = base.clone();
+    let mut n = 2;
+    loop {
+        let created = diesel::insert_into(users)
+            .values(&NewUser {
+                site_id: user_site_id,
+                username: candidate,
+                is_admin: false,
+            })
+            .on_conflict_do_nothing()
+            .returning(id)
+            .get_result(conn)
+            .optional()?;
+        if let Some(created) = created {
+            return Ok(created);
+        }
+        candidate = format!("{}-{}", base, n);
+        n += 1;
+    }
+}
@@ -0,0 +1,142 @@
+use actix_web::error::ErrorUnprocessableEntity;
+use actix_web::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+/// A page of a list. `total` counts all the items matching the filters, pass
+/// `next_cursor` back to get the following page.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Position after the last item of a page: its sort key and its id, which
+/// breaks the ties.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cursor<K> {
+    pub key: K,
+    pub id: i32,
+}
+
+impl<K: Serialize + DeserializeOwned> Cursor<K> {
+    /// Encode the cursor as an opaque url-safe string.
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Cannot encode cursor");
+        base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor<K>, Error> {
+        base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| ErrorUnprocessableEntity("Invalid cursor"))
+    }
+}
+
+impl<T> Page<T> {
+    /// Build the page from the items loaded with a limit of `limit + 1`: the
+    /// extra item, which is dropped, tells that there is a next page.
+    pub fn new<K, F>(
+        mut items: Vec<T>,
+        limit: i64,
+        total: i64,
+        key: F,
+    ) -> Page<T>
+    where
+        K: Serialize + DeserializeOwned,
+        F: Fn(&T) -> Cursor<K>,
+    {
+        let mut next_cursor = None;
+        if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            next_cursor = items.last().map(|item| key(item).encode());
+        }
+        Page {
+            items,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+/// The number of items of a page, between 1 and `MAX_LIMIT`.
+pub fn page_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT)
+}
+
+/// Keep the items of a boxed query after the cursor and sort them by the
+/// column, breaking the ties with the id.
+macro_rules! keyset {
+    ($query:expr, $column:expr, $id:expr, $order:expr, $cursor:expr) => {{
+        let mut query = $query;
+        if let Some(cursor) = $cursor {
+            query = match $order {
+                Order::Asc => query.filter(
+                    $column
+                        .gt(cursor.key.clone())
+                        .or($column.eq(cursor.key).and($id.gt(cursor.id))),
+                ),
+                Order::Desc => query.filter(
+                    $column
+                        .lt(cursor.key.clone())
+                        .or($column.eq(cursor.key).and($id.lt(cursor.id))),
+                ),
+            };
+        }
+        match $order {
+            Order::Asc => query.order(($column.asc(), $id.asc())),
+            Order::Desc => query.order(($column.desc(), $id.desc())),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_cursor() {
+        let page =
+            Page::new(vec![1, 2, 3], 2, 10, |i| Cursor { key: (), id: *i });
+        assert_eq!(page.items, vec![1, 2]);
+        assert_eq!(page.total, 10);
+        let cursor: Cursor<()> =
+            Cursor::decode(&page.next_cursor.unwrap()).unwrap();
+        assert_eq!(cursor.id, 2);
+        let page = Page::new(vec![1, 2], 2, 2, |i| Cursor { key: (), id: *i });
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn encode_cursor() {
+        let cursor = Cursor {
+            key: "name".to_string(),
+            id: 42,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn invalid_cursor() {
+        assert!(Cursor::<i32>::decode("not a cursor").is_err());
+        let cursor = Cursor {
+            key: "text".to_string(),
+            id: 1,
+        }
+        .encode();
+        assert!(Cursor::<i32>::decode(&cursor).is_err());
+    }
+}
@@ -28,10 +28,12 @@ impl Handler<GetParticipation> for Executor {
     ) -> Self::Result {
         use crate::schema::participations::dsl::*;
 
+        let conn = self.conn()?;
         let participation = participations
             .filter(user_id.eq(&msg.user_id))
             .filter(contest_id.eq(&msg.contest_id))
-            .first::<Participation>(&self.0);
+            .filter(practice.eq(false))
+            .first::<Participation>(&conn);
         match participation {
             Ok(participation) => Ok(participation),
             Err(diesel::result::Error::NotFound) => {
@@ -56,9 +58,11 @@ impl Handler<GetParticipationsByUser> for Executor {
     ) -> Self::Result {
         use crate::schema::participations::dsl::*;
 
+        let conn = self.conn()?;
         let parts = participations
             .filter(user_id.eq(&msg.user_id))
-            .load::<Participation>(&self.0);
+            .filter(practice.eq(false))
+            .load::<Participation>(&conn);
         match parts {
             Ok(parts) => Ok(parts),
             Err(err) => Err(ErrorInternalServerError(err)),
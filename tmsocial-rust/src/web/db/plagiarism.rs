@@ -0,0 +1,92 @@
+use actix::{Handler, Message};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::Error;
+use diesel::Connection;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_json::json;
+
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::models::PlagiarismPair;
+
+use super::task::site_task;
+use super::Executor;
+
+/// Compare the final submissions of a task of the site, replacing the pairs
+/// found by the previous check.
+pub struct CheckPlagiarism {
+    pub task_id: i32,
+    pub site_id: i32,
+    pub threshold: f64,
+    pub admin_id: i32,
+    pub client: ClientInfo,
+}
+
+/// The pairs found by the last check of a task of the site, the most similar
+/// first.
+pub struct GetPlagiarismPairs {
+    pub task_id: i32,
+    pub site_id: i32,
+}
+
+impl Message for CheckPlagiarism {
+    type Result = Result<Vec<PlagiarismPair>, Error>;
+}
+
+impl Handler<CheckPlagiarism> for Executor {
+    type Result = Result<Vec<PlagiarismPair>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: CheckPlagiarism,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let conn = self.conn()?;
+        let task = site_task(&conn, msg.task_id, msg.site_id)?;
+        conn.transaction(|| -> Result<_, failure::Error> {
+            let pairs = crate::plagiarism::check_task(
+                &conn,
+                self.config(),
+                task.id,
+                msg.threshold,
+            )?;
+            audit::record(
+                &conn,
+                msg.site_id,
+                Some(msg.admin_id),
+                &msg.client,
+                AuditAction::CheckPlagiarism,
+                json!({
+                    "task_id": task.id,
+                    "threshold": msg.threshold,
+                    "pairs": pairs.len(),
+                }),
+            )?;
+            Ok(pairs)
+        })
+        .map_err(ErrorInternalServerError)
+    }
+}
+
+impl Message for GetPlagiarismPairs {
+    type Result = Result<Vec<PlagiarismPair>, Error>;
+}
+
+impl Handler<GetPlagiarismPairs> for Executor {
+    type Result = Result<Vec<PlagiarismPair>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetPlagiarismPairs,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::plagiarism_pairs::dsl::*;
+
+        let conn = self.conn()?;
+        let task = site_task(&conn, msg.task_id, msg.site_id)?;
+        plagiarism_pairs
+            .filter(task_id.eq(task.id))
+            .order((similarity.desc(), id))
+            .load::<PlagiarismPair>(&conn)
+            .map_err(ErrorInternalServerError)
+    }
+}
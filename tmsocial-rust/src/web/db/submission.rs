@@ -5,34 +5,83 @@ use std::sync::Arc;
 use actix::{Handler, Message};
 use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
 use actix_web::Error;
+use chrono::NaiveDateTime;
+use diesel::pg::{Pg, PgConnection};
+use diesel::sql_types::{Double, Nullable};
 use diesel::BelongingToDsl;
 use diesel::Connection;
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
 use fs_extra::dir::CopyOptions;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
 use tempfile::TempDir;
 
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::config::Config;
 use crate::create_submission_dir;
 use crate::models::*;
+use crate::schema::submissions;
 use crate::task_maker_ui::SubtaskNum;
 use crate::task_maker_ui::TestcaseNum;
 
+use super::pagination::*;
 use super::Executor;
 
 pub struct GetSubmissions {
     pub participation_id: i32,
     pub task_id: i32,
+    pub query: SubmissionsQuery,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionSort {
+    CreatedAt,
+    /// The submissions not evaluated yet come last.
+    Score,
+}
+
+/// Filters, sort and page of a list of submissions. By default the most
+/// recent submissions come first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SubmissionsQuery {
+    pub status: Option<SubmissionStatus>,
+    pub min_score: Option<f64>,
+    pub max_score: Option<f64>,
+    /// Keep the submissions sent from this time on.
+    pub since: Option<NaiveDateTime>,
+    /// Keep the submissions sent before this time.
+    pub until: Option<NaiveDateTime>,
+    pub sort: Option<SubmissionSort>,
+    pub order: Option<Order>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+sql_function!(fn coalesce(x: Nullable<Double>, y: Double) -> Double);
+
+/// Score used to sort the submissions, lower than any real one when missing.
+const MISSING_SCORE: f64 = -1.0;
+
 pub struct GetSubmission {
     pub submission_id: i32,
 }
 
+/// A submission to a contest of the site, whoever sent it. The access of the
+/// admin is recorded in the audit log.
+pub struct GetSiteSubmission {
+    pub submission_id: i32,
+    pub site_id: i32,
+    pub admin_id: i32,
+    pub client: ClientInfo,
+}
+
 pub struct Submit {
     pub task_id: i32,
     pub participation_id: i32,
     pub files: Vec<PathBuf>,
     pub tempdir: Arc<TempDir>,
+    pub client: ClientInfo,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -56,11 +105,11 @@ pub struct GetSubmissionResult {
 }
 
 impl Message for GetSubmissions {
-    type Result = Result<Vec<Submission>, Error>;
+    type Result = Result<Page<Submission>, Error>;
 }
 
 impl Handler<GetSubmissions> for Executor {
-    type Result = Result<Vec<Submission>, Error>;
+    type Result = Result<Page<Submission>, Error>;
 
     fn handle(
         &mut self,
@@ -69,17 +118,95 @@ impl Handler<GetSubmissions> for Executor {
     ) -> Self::Result {
         use crate::schema::submissions::dsl::*;
 
-        let subs = submissions
-            .filter(participation_id.eq(&msg.participation_id))
-            .filter(task_id.eq(&msg.task_id))
-            .load::<Submission>(&self.0);
-        match subs {
-            Ok(subs) => Ok(subs),
-            Err(err) => Err(ErrorInternalServerError(err)),
-        }
+        let conn = self.conn()?;
+        let base = || {
+            submissions
+                .filter(participation_id.eq(msg.participation_id))
+                .filter(task_id.eq(msg.task_id))
+                .into_boxed()
+        };
+        submissions_page(&conn, base, &msg.query)
     }
 }
 
+/// The page of the submissions selected by `base` and by the filters of the
+/// query.
+pub fn submissions_page<F>(
+    conn: &PgConnection,
+    base: F,
+    query: &SubmissionsQuery,
+) -> Result<Page<Submission>, Error>
+where
+    F: Fn() -> submissions::BoxedQuery<'static, Pg>,
+{
+    use crate::schema::submissions::dsl::*;
+
+    let filtered = || {
+        let mut q = base();
+        if let Some(s) = &query.status {
+            q = q.filter(status.eq(s.clone()));
+        }
+        if let Some(min) = query.min_score {
+            q = q.filter(score.ge(min));
+        }
+        if let Some(max) = query.max_score {
+            q = q.filter(score.le(max));
+        }
+        if let Some(since) = query.since {
+            q = q.filter(created_at.ge(since));
+        }
+        if let Some(until) = query.until {
+            q = q.filter(created_at.lt(until));
+        }
+        q
+    };
+    let total = filtered()
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(ErrorInternalServerError)?;
+    let limit = page_limit(query.limit);
+    let order = query.order.unwrap_or(Order::Desc);
+    let sort = query.sort.unwrap_or(SubmissionSort::CreatedAt);
+    let cursor = query.cursor.as_ref().map(|c| c.as_str());
+    let q = match sort {
+        SubmissionSort::CreatedAt => {
+            let cursor = match cursor {
+                Some(c) => Some(Cursor::<NaiveDateTime>::decode(c)?),
+                None => None,
+            };
+            keyset!(filtered(), created_at, id, order, cursor)
+        }
+        SubmissionSort::Score => {
+            let cursor = match cursor {
+                Some(c) => Some(Cursor::<f64>::decode(c)?),
+                None => None,
+            };
+            keyset!(
+                filtered(),
+                coalesce(score, MISSING_SCORE),
+                id,
+                order,
+                cursor
+            )
+        }
+    };
+    let subs = q
+        .limit(limit + 1)
+        .load::<Submission>(conn)
+        .map_err(ErrorInternalServerError)?;
+    Ok(Page::new(subs, limit, total, |sub| match sort {
+        SubmissionSort::CreatedAt => Cursor {
+            key: serde_json::to_value(sub.created_at).unwrap(),
+            id: sub.id,
+        },
+        SubmissionSort::Score => Cursor {
+            key: serde_json::to_value(sub.score.unwrap_or(MISSING_SCORE))
+                .unwrap(),
+            id: sub.id,
+        },
+    }))
+}
+
 impl Message for GetSubmission {
     type Result = Result<GetSubmissionResult, Error>;
 }
@@ -94,9 +221,10 @@ impl Handler<GetSubmission> for Executor {
     ) -> Self::Result {
         use crate::schema::submissions::dsl::*;
 
+        let conn = self.conn()?;
         let sub = submissions
             .find(&msg.submission_id)
-            .first::<Submission>(&self.0);
+            .first::<Submission>(&conn);
         let sub = match sub {
             Ok(sub) => sub,
             Err(diesel::result::Error::NotFound) => {
@@ -107,14 +235,14 @@ impl Handler<GetSubmission> for Executor {
         let (subtask_results, subtasks): (Vec<SubtaskResult>, Vec<Subtask>) =
             SubtaskResult::belonging_to(&sub)
                 .inner_join(crate::schema::subtasks::dsl::subtasks)
-                .load::<(SubtaskResult, Subtask)>(&self.0)
+                .load::<(SubtaskResult, Subtask)>(&conn)
                 .map_err(|e| ErrorInternalServerError(e))?
                 .into_iter()
                 .unzip();
         let subtasks: HashMap<i32, i32> =
             subtasks.iter().map(|st| (st.id, st.num)).collect();
         let testcase_results = TestcaseResult::belonging_to(&subtask_results)
-            .load::<TestcaseResult>(&self.0)
+            .load::<TestcaseResult>(&conn)
             .map_err(|e| ErrorInternalServerError(e))?;
         let subtask_id: HashMap<i32, i32> = subtask_results
             .iter()
@@ -165,6 +293,49 @@ impl Handler<GetSubmission> for Executor {
     }
 }
 
+impl Message for GetSiteSubmission {
+    type Result = Result<Submission, Error>;
+}
+
+impl Handler<GetSiteSubmission> for Executor {
+    type Result = Result<Submission, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetSiteSubmission,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::contests;
+        use crate::schema::participations;
+        use crate::schema::submissions;
+
+        let conn = self.conn()?;
+        let sub = submissions::table
+            .inner_join(participations::table.inner_join(contests::table))
+            .filter(submissions::id.eq(msg.submission_id))
+            .filter(contests::site_id.eq(msg.site_id))
+            .select(submissions::all_columns)
+            .first::<Submission>(&conn);
+        let sub = match sub {
+            Ok(sub) => sub,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(ErrorNotFound(format!("No such submission")))
+            }
+            Err(err) => return Err(ErrorInternalServerError(err)),
+        };
+        audit::record(
+            &conn,
+            msg.site_id,
+            Some(msg.admin_id),
+            &msg.client,
+            AuditAction::ReadSubmission,
+            json!({ "submission_id": sub.id }),
+        )
+        .map_err(ErrorInternalServerError)?;
+        Ok(sub)
+    }
+}
+
 impl Message for Submit {
     type Result = Result<Submission, Error>;
 }
@@ -173,35 +344,76 @@ impl Handler<Submit> for Executor {
     type Result = Result<Submission, Error>;
 
     fn handle(&mut self, msg: Submit, _: &mut Self::Context) -> Self::Result {
-        use crate::schema::submissions::dsl::*;
+        let conn = self.conn()?;
+        conn.transaction(|| -> Result<Submission, failure::Error> {
+            let sub = insert_submission(
+                &conn,
+                self.config(),
+                msg.task_id,
+                msg.participation_id,
+                &msg.files,
+            )?;
+            audit_submission(&conn, &sub, &msg.client)?;
+            Ok(sub)
+        })
+        .map_err(ErrorInternalServerError)
+    }
+}
 
-        (&self.0)
-            .transaction(|| -> Result<Submission, failure::Error> {
-                let new_sub = NewSubmission {
-                    task_id: msg.task_id,
-                    participation_id: msg.participation_id,
-                    files: msg
-                        .files
-                        .iter()
-                        .map(|p| {
-                            p.file_name()
-                                .and_then(|s| s.to_str())
-                                .map(|s| s.to_string())
-                                .unwrap_or(format!(""))
-                        })
-                        .collect(),
-                };
-                let info = diesel::insert_into(submissions)
-                    .values(&new_sub)
-                    .get_result::<Submission>(&self.0)?;
-                let dest_path = create_submission_dir(info.id);
-                fs_extra::move_items(
-                    &msg.files,
-                    dest_path,
-                    &CopyOptions::new(),
-                )?;
-                Ok(info)
+/// Insert a new submission with the given files, moving them into the
+/// directory of the submission.
+pub fn insert_submission(
+    conn: &PgConnection,
+    config: &Config,
+    task_id: i32,
+    participation_id: i32,
+    files: &Vec<PathBuf>,
+) -> Result<Submission, failure::Error> {
+    use crate::schema::submissions::dsl::submissions;
+
+    let new_sub = NewSubmission {
+        task_id,
+        participation_id,
+        files: files
+            .iter()
+            .map(|p| {
+                p.file_name()
+                    .and_then(|s| s.to_str())
+                    .map(|s| s.to_string())
+                    .unwrap_or(format!(""))
             })
-            .map_err(ErrorInternalServerError)
-    }
+            .collect(),
+    };
+    let info = diesel::insert_into(submissions)
+        .values(&new_sub)
+        .get_result::<Submission>(conn)?;
+    let dest_path = create_submission_dir(config, info.id);
+    fs_extra::move_items(files, dest_path, &CopyOptions::new())?;
+    Ok(info)
+}
+
+/// Record in the audit log the submission sent by the participant.
+pub fn audit_submission(
+    conn: &PgConnection,
+    submission: &Submission,
+    client: &ClientInfo,
+) -> Result<(), failure::Error> {
+    use crate::schema::participations::dsl::participations;
+
+    let participation = participations
+        .find(submission.participation_id)
+        .first::<Participation>(conn)?;
+    audit::record(
+        conn,
+        audit::contest_site(conn, participation.contest_id)?,
+        Some(participation.user_id),
+        client,
+        AuditAction::Submit,
+        json!({
+            "submission_id": submission.id,
+            "task_id": submission.task_id,
+            "files": submission.files,
+        }),
+    )?;
+    Ok(())
 }
@@ -1,9 +1,21 @@
+use std::collections::HashMap;
+
 use super::Executor;
-use crate::models::Task;
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::metadata::TaskMetadata;
+use crate::models::{StoredTaskMetadata, Task};
 use actix::{Handler, Message};
-use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
+use actix_web::error::{
+    ErrorInternalServerError, ErrorNotFound, ErrorUnprocessableEntity,
+};
 use actix_web::Error;
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Float, Integer, Text};
+use diesel::{
+    Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
 
 pub struct GetTask {
     pub id: i32,
@@ -22,12 +34,13 @@ impl Handler<GetTask> for Executor {
     type Result = Result<Task, Error>;
 
     fn handle(&mut self, msg: GetTask, _: &mut Self::Context) -> Self::Result {
+        let conn = self.conn()?;
         let task = crate::schema::tasks::dsl::tasks
             .filter(crate::schema::tasks::columns::id.eq(&msg.id))
             .filter(
                 crate::schema::tasks::columns::contest_id.eq(&msg.contest_id),
             )
-            .first::<Task>(&self.0);
+            .first::<Task>(&conn);
         match task {
             Ok(task) => Ok(task),
             Err(diesel::result::Error::NotFound) => {
@@ -50,14 +63,301 @@ impl Handler<GetTaskByContest> for Executor {
         msg: GetTaskByContest,
         _: &mut Self::Context,
     ) -> Self::Result {
+        let conn = self.conn()?;
         let tasks = crate::schema::tasks::dsl::tasks
             .filter(
                 crate::schema::tasks::columns::contest_id.eq(&msg.contest_id),
             )
-            .get_results::<Task>(&self.0);
+            .order(crate::schema::tasks::columns::id)
+            .get_results::<Task>(&conn);
         match tasks {
             Ok(tasks) => Ok(tasks),
             Err(err) => Err(ErrorInternalServerError(err)),
         }
     }
 }
+
+pub struct GetTaskMetadata {
+    pub task_id: i32,
+}
+
+pub struct SearchTasks {
+    pub site_id: i32,
+    pub user_id: Option<i32>,
+    pub query: String,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+pub struct UpdateTaskClassification {
+    pub task_id: i32,
+    pub site_id: i32,
+    /// The current tags and difficulty are kept if missing.
+    pub tags: Option<Vec<String>>,
+    /// `Some(None)` clears the difficulty.
+    pub difficulty: Option<Option<i32>>,
+    /// Compute the difficulty from the solve rate instead.
+    pub derive_difficulty: bool,
+    pub admin_id: i32,
+    pub client: ClientInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResultItem {
+    pub task: Task,
+    pub rank: f32,
+    pub best_score: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResult {
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+    pub results: Vec<SearchResultItem>,
+}
+
+#[derive(QueryableByName)]
+struct SearchHit {
+    #[sql_type = "Integer"]
+    id: i32,
+    #[sql_type = "Float"]
+    rank: f32,
+}
+
+#[derive(QueryableByName)]
+struct SearchCount {
+    #[sql_type = "BigInt"]
+    total: i64,
+}
+
+/// The tasks visible to the user that match the query: the ones in the
+/// archive and the ones of the contests the user takes part in.
+const SEARCH_FROM: &'static str = "
+    FROM tasks
+    INNER JOIN contests ON contests.id = tasks.contest_id,
+    plainto_tsquery('simple', $1) query
+    WHERE contests.site_id = $2
+    AND task_search_document(tasks.title, tasks.tags, tasks.statement) @@ query
+    AND (tasks.archived OR tasks.contest_id IN (
+        SELECT contest_id FROM participations
+        WHERE user_id = $3 AND NOT practice))";
+
+impl Message for GetTaskMetadata {
+    type Result = Result<TaskMetadata, Error>;
+}
+
+impl Handler<GetTaskMetadata> for Executor {
+    type Result = Result<TaskMetadata, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetTaskMetadata,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::task_metadata;
+
+        let conn = self.conn()?;
+        let metadata = task_metadata::table
+            .find(msg.task_id)
+            .first::<StoredTaskMetadata>(&conn);
+        match metadata {
+            Ok(metadata) => serde_json::from_value(metadata.metadata)
+                .map_err(ErrorInternalServerError),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorNotFound(format!("No metadata for this task")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for SearchTasks {
+    type Result = Result<SearchResult, Error>;
+}
+
+impl Handler<SearchTasks> for Executor {
+    type Result = Result<SearchResult, Error>;
+
+    fn handle(
+        &mut self,
+        msg: SearchTasks,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{participations, submissions, tasks};
+
+        let conn = self.conn()?;
+        // assuming no user has negative id
+        let user_id = msg.user_id.unwrap_or(-1);
+        let total = diesel::sql_query(format!(
+            "SELECT COUNT(*) AS total {}",
+            SEARCH_FROM
+        ))
+        .bind::<Text, _>(&msg.query)
+        .bind::<Integer, _>(msg.site_id)
+        .bind::<Integer, _>(user_id)
+        .get_result::<SearchCount>(&conn)
+        .map_err(ErrorInternalServerError)?
+        .total;
+        let hits = diesel::sql_query(format!(
+            "SELECT tasks.id AS id, ts_rank(task_search_document(\
+             tasks.title, tasks.tags, tasks.statement), query) AS rank {} \
+             ORDER BY rank DESC, tasks.id LIMIT $4 OFFSET $5",
+            SEARCH_FROM
+        ))
+        .bind::<Text, _>(&msg.query)
+        .bind::<Integer, _>(msg.site_id)
+        .bind::<Integer, _>(user_id)
+        .bind::<BigInt, _>(msg.per_page)
+        .bind::<BigInt, _>(msg.page * msg.per_page)
+        .load::<SearchHit>(&conn)
+        .map_err(ErrorInternalServerError)?;
+
+        let ids: Vec<i32> = hits.iter().map(|h| h.id).collect();
+        let mut found: HashMap<i32, Task> = tasks::table
+            .filter(tasks::id.eq_any(&ids))
+            .load::<Task>(&conn)
+            .map_err(ErrorInternalServerError)?
+            .into_iter()
+            .map(|t| (t.id, t))
+            .collect();
+        let mut best_scores: HashMap<i32, f64> = HashMap::new();
+        let scores = submissions::table
+            .inner_join(participations::table)
+            .filter(participations::user_id.eq(user_id))
+            .filter(submissions::task_id.eq_any(&ids))
+            .select((submissions::task_id, submissions::score))
+            .load::<(i32, Option<f64>)>(&conn)
+            .map_err(ErrorInternalServerError)?;
+        for (task, score) in scores {
+            let score = score.unwrap_or(0.0);
+            let best = best_scores.entry(task).or_insert(score);
+            *best = best.max(score);
+        }
+
+        Ok(SearchResult {
+            total,
+            page: msg.page,
+            per_page: msg.per_page,
+            results: hits
+                .into_iter()
+                .filter_map(|hit| {
+                    found.remove(&hit.id).map(|task| SearchResultItem {
+                        best_score: best_scores.get(&task.id).cloned(),
+                        rank: hit.rank,
+                        task,
+                    })
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The task with the given id, if it belongs to a contest of the site.
+pub fn site_task(
+    conn: &PgConnection,
+    task_id: i32,
+    site_id: i32,
+) -> Result<Task, Error> {
+    use crate::schema::{contests, tasks};
+
+    let task = tasks::table
+        .inner_join(contests::table)
+        .filter(tasks::id.eq(task_id))
+        .filter(contests::site_id.eq(site_id))
+        .select(tasks::all_columns)
+        .first::<Task>(conn);
+    match task {
+        Ok(task) => Ok(task),
+        Err(diesel::result::Error::NotFound) => {
+            Err(ErrorNotFound(format!("No such task")))
+        }
+        Err(err) => Err(ErrorInternalServerError(err)),
+    }
+}
+
+impl Message for UpdateTaskClassification {
+    type Result = Result<Task, Error>;
+}
+
+impl Handler<UpdateTaskClassification> for Executor {
+    type Result = Result<Task, Error>;
+
+    fn handle(
+        &mut self,
+        msg: UpdateTaskClassification,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{participations, submissions, tasks};
+
+        let conn = self.conn()?;
+        let task = site_task(&conn, msg.task_id, msg.site_id)?;
+
+        let difficulty = if msg.derive_difficulty {
+            let scores = submissions::table
+                .inner_join(participations::table)
+                .filter(submissions::task_id.eq(task.id))
+                .select((participations::user_id, submissions::score))
+                .load::<(i32, Option<f64>)>(&conn)
+                .map_err(ErrorInternalServerError)?;
+            match derive_difficulty(task.max_score, &scores) {
+                Some(difficulty) => Some(difficulty),
+                None => {
+                    return Err(ErrorUnprocessableEntity(format!(
+                        "Nobody has tried this task yet"
+                    )))
+                }
+            }
+        } else {
+            msg.difficulty.unwrap_or(task.difficulty)
+        };
+        if let Some(difficulty) = difficulty {
+            if difficulty < 1 || difficulty > 10 {
+                return Err(ErrorUnprocessableEntity(format!(
+                    "The difficulty must be between 1 and 10"
+                )));
+            }
+        }
+
+        let tags = msg.tags.unwrap_or(task.tags);
+        conn.transaction(|| -> QueryResult<Task> {
+            let task = diesel::update(tasks::table.find(task.id))
+                .set((tasks::tags.eq(tags), tasks::difficulty.eq(difficulty)))
+                .get_result::<Task>(&conn)?;
+            audit::record(
+                &conn,
+                msg.site_id,
+                Some(msg.admin_id),
+                &msg.client,
+                AuditAction::ClassifyTask,
+                json!({
+                    "task_id": task.id,
+                    "tags": task.tags,
+                    "difficulty": task.difficulty,
+                }),
+            )?;
+            Ok(task)
+        })
+        .map_err(ErrorInternalServerError)
+    }
+}
+
+/// Map the fraction of the users that solved the task, among the ones that
+/// tried it, to a difficulty between 1 and 10.
+fn derive_difficulty(
+    max_score: f64,
+    scores: &Vec<(i32, Option<f64>)>,
+) -> Option<i32> {
+    let mut best: HashMap<i32, f64> = HashMap::new();
+    for (user, score) in scores {
+        let score = score.unwrap_or(0.0);
+        let entry = best.entry(*user).or_insert(score);
+        *entry = entry.max(score);
+    }
+    if best.is_empty() {
+        return None;
+    }
+    let solvers = best.values().filter(|s| **s >= max_score).count();
+    let rate = solvers as f64 / best.len() as f64;
+    Some(1 + ((1.0 - rate) * 9.0).round() as i32)
+}
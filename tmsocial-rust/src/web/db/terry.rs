@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix::{Handler, Message};
+use actix_web::error::{
+    ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    ErrorUnprocessableEntity,
+};
+use actix_web::Error;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use diesel::Connection;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::warn;
+use tempfile::TempDir;
+
+use crate::audit::ClientInfo;
+use crate::models::*;
+use crate::terry::{generate_input, random_seed, INPUT_DURATION_MINUTES};
+
+use super::submission::{audit_submission, insert_submission};
+use super::Executor;
+
+/// Generate a new input of the Terry task for the participation. The input
+/// expires after `INPUT_DURATION_MINUTES`, or at the end of the contest.
+pub struct RequestInput {
+    pub participation_id: i32,
+    pub task: Task,
+    pub contest_end: Option<NaiveDateTime>,
+}
+
+pub struct GetInputs {
+    pub participation_id: i32,
+    pub task_id: i32,
+}
+
+pub struct GetInput {
+    pub participation_id: i32,
+    pub task_id: i32,
+    pub input_id: i32,
+}
+
+/// Submit the output (and the source) produced for an input.
+pub struct SubmitOutput {
+    pub input: TerryInput,
+    pub files: Vec<PathBuf>,
+    pub tempdir: Arc<TempDir>,
+    pub client: ClientInfo,
+}
+
+impl Message for RequestInput {
+    type Result = Result<TerryInput, Error>;
+}
+
+impl Handler<RequestInput> for Executor {
+    type Result = Result<TerryInput, Error>;
+
+    fn handle(
+        &mut self,
+        msg: RequestInput,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::terry_inputs::dsl::*;
+
+        let now = Utc::now().naive_utc();
+        let mut deadline = now + Duration::minutes(INPUT_DURATION_MINUTES);
+        if let Some(end) = msg.contest_end {
+            if end <= now {
+                return Err(ErrorForbidden("The contest is over"));
+            }
+            deadline = deadline.min(end);
+        }
+        // generated before the transaction, which would be kept open by a slow
+        // generator; the file is deleted if the input is not stored
+        let config = self.config();
+        let inputs_dir = crate::terry_inputs_dir(config);
+        let seed = random_seed();
+        let generated = fs::create_dir_all(&inputs_dir)
+            .and_then(|_| {
+                tempfile::Builder::new()
+                    .prefix(".input")
+                    .tempfile_in(&inputs_dir)
+            })
+            .map_err(ErrorInternalServerError)?;
+        generate_input(
+            &crate::task_dir(config, msg.task.id, msg.task.version),
+            seed,
+            generated.path(),
+        )
+        .map_err(ErrorInternalServerError)?;
+
+        let conn = self.conn()?;
+        conn.transaction(|| -> Result<TerryInput, failure::Error> {
+            diesel::update(
+                terry_inputs
+                    .filter(participation_id.eq(msg.participation_id))
+                    .filter(task_id.eq(msg.task.id))
+                    .filter(closed.eq(false))
+                    .filter(expires_at.le(now)),
+            )
+            .set(closed.eq(true))
+            .execute(&conn)?;
+            // fails if another input is still in progress
+            let input = diesel::insert_into(terry_inputs)
+                .values(NewTerryInput {
+                    participation_id: msg.participation_id,
+                    task_id: msg.task.id,
+                    seed,
+                    expires_at: deadline,
+                })
+                .get_result::<TerryInput>(&conn)?;
+            generated
+                .persist(crate::terry_input_path(config, input.id))
+                .map_err(|err| err.error)?;
+            Ok(input)
+        })
+        .map_err(|err| match err.downcast_ref() {
+            Some(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                ErrorConflict("An input is already in progress")
+            }
+            _ => ErrorInternalServerError(err),
+        })
+    }
+}
+
+impl Message for GetInputs {
+    type Result = Result<Vec<TerryInput>, Error>;
+}
+
+impl Handler<GetInputs> for Executor {
+    type Result = Result<Vec<TerryInput>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetInputs,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::terry_inputs::dsl::*;
+
+        let conn = self.conn()?;
+        terry_inputs
+            .filter(participation_id.eq(msg.participation_id))
+            .filter(task_id.eq(msg.task_id))
+            .order(created_at.desc())
+            .load::<TerryInput>(&conn)
+            .map_err(ErrorInternalServerError)
+    }
+}
+
+impl Message for GetInput {
+    type Result = Result<TerryInput, Error>;
+}
+
+impl Handler<GetInput> for Executor {
+    type Result = Result<TerryInput, Error>;
+
+    fn handle(&mut self, msg: GetInput, _: &mut Self::Context) -> Self::Result {
+        use crate::schema::terry_inputs::dsl::*;
+
+        let conn = self.conn()?;
+        let input = terry_inputs
+            .find(msg.input_id)
+            .filter(participation_id.eq(msg.participation_id))
+            .filter(task_id.eq(msg.task_id))
+            .first::<TerryInput>(&conn);
+        match input {
+            Ok(input) => Ok(input),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorNotFound(format!("No such input")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for SubmitOutput {
+    type Result = Result<Submission, Error>;
+}
+
+impl Handler<SubmitOutput> for Executor {
+    type Result = Result<Submission, Error>;
+
+    fn handle(
+        &mut self,
+        msg: SubmitOutput,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::terry_inputs::dsl::*;
+
+        let conn = self.conn()?;
+        if msg.input.submission_id.is_some() {
+            return Err(ErrorUnprocessableEntity(
+                "The output of this input was already submitted",
+            ));
+        }
+        if msg.input.expires_at <= Utc::now().naive_utc() {
+            return Err(ErrorUnprocessableEntity("The input has expired"));
+        }
+        conn.transaction(|| -> Result<Submission, failure::Error> {
+            let sub = insert_submission(
+                &conn,
+                self.config(),
+                msg.input.task_id,
+                msg.input.participation_id,
+                &msg.files,
+            )?;
+            // the input may have been used by a concurrent request
+            let updated = diesel::update(
+                terry_inputs
+                    .find(msg.input.id)
+                    .filter(submission_id.is_null()),
+            )
+            .set((submission_id.eq(sub.id), closed.eq(true)))
+            .execute(&conn)?;
+            if updated == 0 {
+                return Err(diesel::result::Error::RollbackTransaction.into());
+            }
+            audit_submission(&conn, &sub, &msg.client)?;
+            Ok(sub)
+        })
+        .map_err(|err| match err.downcast_ref() {
+            Some(diesel::result::Error::RollbackTransaction) => {
+                ErrorUnprocessableEntity(
+                    "The output of this input was already submitted",
+                )
+            }
+            _ => ErrorInternalServerError(err),
+        })
+        .map(|sub| {
+            // the evaluation generates the input again from its seed
+            let path = crate::terry_input_path(self.config(), msg.input.id);
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Cannot remove the input {:?}: {}", path, err);
+            }
+            sub
+        })
+    }
+}
@@ -0,0 +1,249 @@
+use actix::{Handler, Message};
+use actix_web::error::{
+    ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    ErrorUnprocessableEntity,
+};
+use actix_web::Error;
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::{
+    Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl,
+};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::audit::{self, AuditAction, ClientInfo};
+use crate::models::*;
+
+use super::Executor;
+
+/// Prefix of the API tokens, to recognize them when leaked.
+const TOKEN_PREFIX: &str = "tms_";
+
+/// A new API token of the user. The secret is returned only now.
+pub struct CreateApiToken {
+    pub site_id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub scope: ApiTokenScope,
+    pub client: ClientInfo,
+}
+
+/// The API tokens of the user, revoked ones included.
+pub struct GetApiTokens {
+    pub user_id: i32,
+}
+
+pub struct RevokeApiToken {
+    pub site_id: i32,
+    pub user_id: i32,
+    pub token_id: i32,
+    pub client: ClientInfo,
+}
+
+/// The user of a valid API token of the site, recording its use. `write`
+/// tells that the request has side effects, which read-only tokens can't have.
+pub struct GetUserByApiToken {
+    pub site_id: i32,
+    pub token: String,
+    pub write: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedApiToken {
+    pub token: ApiToken,
+    /// To be sent as `Authorization: Bearer <secret>`.
+    pub secret: String,
+}
+
+impl Message for CreateApiToken {
+    type Result = Result<CreatedApiToken, Error>;
+}
+
+impl Handler<CreateApiToken> for Executor {
+    type Result = Result<CreatedApiToken, Error>;
+
+    fn handle(
+        &mut self,
+        msg: CreateApiToken,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::api_tokens::dsl::*;
+
+        if msg.name.trim().is_empty() {
+            return Err(ErrorUnprocessableEntity("The name cannot be empty"));
+        }
+        let conn = self.conn()?;
+        let secret = gen_api_token();
+        let created = conn.transaction(|| -> QueryResult<ApiToken> {
+            let token = diesel::insert_into(api_tokens)
+                .values(&NewApiToken {
+                    user_id: msg.user_id,
+                    name: msg.name.clone(),
+                    token_hash: hash_api_token(&secret),
+                    scope: msg.scope,
+                })
+                .get_result::<ApiToken>(&conn)?;
+            audit::record(
+                &conn,
+                msg.site_id,
+                Some(msg.user_id),
+                &msg.client,
+                AuditAction::CreateApiToken,
+                json!({
+                    "token_id": token.id,
+                    "name": token.name,
+                    "scope": token.scope,
+                }),
+            )?;
+            Ok(token)
+        });
+        match created {
+            Ok(token) => Ok(CreatedApiToken { token, secret }),
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(ErrorUnprocessableEntity(format!(
+                    "A token with this name already exists"
+                )))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for GetApiTokens {
+    type Result = Result<Vec<ApiToken>, Error>;
+}
+
+impl Handler<GetApiTokens> for Executor {
+    type Result = Result<Vec<ApiToken>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetApiTokens,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::api_tokens::dsl::*;
+
+        let conn = self.conn()?;
+        api_tokens
+            .filter(user_id.eq(msg.user_id))
+            .order(id)
+            .load::<ApiToken>(&conn)
+            .map_err(ErrorInternalServerError)
+    }
+}
+
+impl Message for RevokeApiToken {
+    type Result = Result<ApiToken, Error>;
+}
+
+impl Handler<RevokeApiToken> for Executor {
+    type Result = Result<ApiToken, Error>;
+
+    fn handle(
+        &mut self,
+        msg: RevokeApiToken,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::api_tokens::dsl::*;
+
+        let conn = self.conn()?;
+        let revoked = conn.transaction(|| -> QueryResult<ApiToken> {
+            let token = diesel::update(
+                api_tokens
+                    .find(msg.token_id)
+                    .filter(user_id.eq(msg.user_id))
+                    .filter(revoked_at.is_null()),
+            )
+            .set(revoked_at.eq(Utc::now().naive_utc()))
+            .get_result::<ApiToken>(&conn)?;
+            audit::record(
+                &conn,
+                msg.site_id,
+                Some(msg.user_id),
+                &msg.client,
+                AuditAction::RevokeApiToken,
+                json!({ "token_id": token.id, "name": token.name }),
+            )?;
+            Ok(token)
+        });
+        match revoked {
+            Ok(token) => Ok(token),
+            Err(diesel::result::Error::NotFound) => {
+                Err(ErrorNotFound(format!("No such token")))
+            }
+            Err(err) => Err(ErrorInternalServerError(err)),
+        }
+    }
+}
+
+impl Message for GetUserByApiToken {
+    type Result = Result<User, Error>;
+}
+
+impl Handler<GetUserByApiToken> for Executor {
+    type Result = Result<User, Error>;
+
+    fn handle(
+        &mut self,
+        msg: GetUserByApiToken,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use crate::schema::{api_tokens, users};
+
+        let conn = self.conn()?;
+        let token = api_tokens::table
+            .inner_join(users::table)
+            .filter(api_tokens::token_hash.eq(hash_api_token(&msg.token)))
+            .filter(api_tokens::revoked_at.is_null())
+            .filter(users::site_id.eq(msg.site_id))
+            .first::<(ApiToken, User)>(&conn);
+        let (token, user) = match token {
+            Ok(token) => token,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(ErrorForbidden(format!("Invalid API token")))
+            }
+            Err(err) => return Err(ErrorInternalServerError(err)),
+        };
+        if msg.write && token.scope == ApiTokenScope::ReadOnly {
+            return Err(ErrorForbidden(format!("Read-only API token")));
+        }
+        diesel::update(api_tokens::table.find(token.id))
+            .set(api_tokens::last_used_at.eq(Utc::now().naive_utc()))
+            .execute(&conn)
+            .map_err(ErrorInternalServerError)?;
+        Ok(user)
+    }
+}
+
+/// A new random API token.
+fn gen_api_token() -> String {
+    let mut arr = [0u8; 30];
+    rand::thread_rng().fill(&mut arr[..]);
+    format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        base64::encode_config(&arr, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// The hash of an API token, the only thing stored in the database.
+fn hash_api_token(token: &str) -> String {
+    base64::encode(&Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_tokens() {
+        let token = gen_api_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_ne!(token, gen_api_token());
+        assert_eq!(hash_api_token(&token), hash_api_token(&token));
+        assert_ne!(hash_api_token(&token), token);
+    }
+}
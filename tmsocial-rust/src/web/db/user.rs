@@ -1,14 +1,19 @@
 use actix::{Handler, Message};
 use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
 use actix_web::Error;
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::pg::PgConnection;
+use diesel::{
+    Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl,
+};
 use rand::Rng;
+use serde_json::json;
 
+use crate::audit::{self, AuditAction, ClientInfo};
 use crate::models::User;
 
 use super::Executor;
 
-type LoginToken = String;
+pub type LoginToken = String;
 
 pub struct GetUser {
     pub id: i32,
@@ -27,6 +32,15 @@ pub struct GetUserByToken {
 pub struct DoLogin {
     pub site_id: i32,
     pub username: String,
+    pub client: ClientInfo,
+}
+
+/// Invalidate the login token of the user, a new one is generated at the next
+/// login.
+pub struct DoLogout {
+    pub site_id: i32,
+    pub user_id: i32,
+    pub client: ClientInfo,
 }
 
 impl Message for GetUser {
@@ -51,7 +65,8 @@ impl Handler<GetUser> for Executor {
     fn handle(&mut self, msg: GetUser, _: &mut Self::Context) -> Self::Result {
         use crate::schema::users::dsl::*;
 
-        let user = users.find(&msg.id).first::<User>(&self.0);
+        let conn = self.conn()?;
+        let user = users.find(&msg.id).first::<User>(&conn);
 
         match user {
             Ok(user) => Ok(user),
@@ -73,10 +88,11 @@ impl Handler<GetUserByUsername> for Executor {
     ) -> Self::Result {
         use crate::schema::users::dsl::*;
 
+        let conn = self.conn()?;
         let user = users
             .filter(site_id.eq(&msg.site_id))
             .filter(username.eq(&msg.username))
-            .first::<User>(&self.0);
+            .first::<User>(&conn);
 
         match user {
             Ok(user) => Ok(user),
@@ -98,9 +114,10 @@ impl Handler<GetUserByToken> for Executor {
     ) -> Self::Result {
         use crate::schema::users::dsl::*;
 
+        let conn = self.conn()?;
         let user = users
             .filter(login_token.eq(&msg.login_token))
-            .first::<User>(&self.0);
+            .first::<User>(&conn);
 
         match user {
             Ok(user) => Ok(user),
@@ -118,23 +135,28 @@ impl Handler<DoLogin> for Executor {
     fn handle(&mut self, msg: DoLogin, _: &mut Self::Context) -> Self::Result {
         use crate::schema::users::dsl::*;
 
+        let conn = self.conn()?;
         let user = users
             .filter(site_id.eq(&msg.site_id))
             .filter(username.eq(&msg.username))
-            .first::<User>(&self.0);
+            .first::<User>(&conn);
+        let (actor, action) = match &user {
+            Ok(user) => (Some(user.id), AuditAction::Login),
+            Err(_) => (None, AuditAction::LoginFailed),
+        };
+        audit::record(
+            &conn,
+            msg.site_id,
+            actor,
+            &msg.client,
+            action,
+            json!({ "username": msg.username }),
+        )
+        .map_err(ErrorInternalServerError)?;
         match user {
             Ok(user) => {
-                let token = if let Some(token) = user.login_token.clone() {
-                    token
-                } else {
-                    let token = gen_token();
-                    diesel::update(users)
-                        .set(login_token.eq(&token))
-                        .filter(id.eq(user.id))
-                        .execute(&self.0)
-                        .map_err(|e| ErrorInternalServerError(e))?;
-                    token
-                };
+                let token = get_login_token(&conn, &user)
+                    .map_err(|e| ErrorInternalServerError(e))?;
                 Ok((user, token))
             }
             Err(diesel::result::Error::NotFound) => {
@@ -145,6 +167,53 @@ impl Handler<DoLogin> for Executor {
     }
 }
 
+impl Message for DoLogout {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<DoLogout> for Executor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: DoLogout, _: &mut Self::Context) -> Self::Result {
+        use crate::schema::users::dsl::*;
+
+        let conn = self.conn()?;
+        conn.transaction(|| -> QueryResult<()> {
+            diesel::update(users.find(msg.user_id))
+                .set(login_token.eq(None::<String>))
+                .execute(&conn)?;
+            audit::record(
+                &conn,
+                msg.site_id,
+                Some(msg.user_id),
+                &msg.client,
+                AuditAction::Logout,
+                json!({}),
+            )?;
+            Ok(())
+        })
+        .map_err(ErrorInternalServerError)
+    }
+}
+
+/// The login token of the user, generated if they are logged out.
+pub fn get_login_token(
+    conn: &PgConnection,
+    user: &User,
+) -> QueryResult<LoginToken> {
+    use crate::schema::users::dsl::*;
+
+    if let Some(token) = user.login_token.clone() {
+        return Ok(token);
+    }
+    let token = gen_token();
+    diesel::update(users)
+        .set(login_token.eq(&token))
+        .filter(id.eq(user.id))
+        .execute(conn)?;
+    Ok(token)
+}
+
 fn gen_token() -> String {
     let mut arr = [0u8; 31];
     rand::thread_rng().fill(&mut arr[..]);
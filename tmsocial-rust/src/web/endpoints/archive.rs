@@ -0,0 +1,444 @@
+use actix_web::error::ErrorNotFound;
+use actix_web::fs::NamedFile;
+use actix_web::{
+    AsyncResponder, Error, Form, FromRequest, HttpRequest, HttpResponse, Json,
+    Path, Query, State,
+};
+use futures::future;
+use futures::future::{result, Future};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audit::ClientInfo;
+use crate::models::*;
+use crate::web::db::*;
+use crate::web::endpoints::contest::{open_task_asset, submit_files};
+use crate::web::endpoints::submission::{open_submission_file, zip_submission};
+use crate::web::endpoints::{
+    get_accept_languages, get_path_tail, split_tags, AsyncJsonResponse,
+};
+use crate::web::extractors::{Admin, ArchivedTask};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveQuery {
+    pub solved: Option<bool>,
+    /// Comma separated list of tags.
+    pub tags: Option<String>,
+    pub min_difficulty: Option<i32>,
+    pub max_difficulty: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublishForm {
+    pub published: bool,
+}
+
+pub fn get_archive(
+    state: State<crate::web::State>,
+    site: Site,
+    user: Option<User>,
+    query: Query<ArchiveQuery>,
+) -> AsyncJsonResponse<Vec<ArchiveTask>> {
+    Box::new(
+        state
+            .db
+            .send(GetArchive {
+                site_id: site.id,
+                user_id: user.map(|u| u.id),
+                solved: query.solved,
+                tags: split_tags(query.tags.as_ref().map_or("", |t| t)),
+                min_difficulty: query.min_difficulty,
+                max_difficulty: query.max_difficulty,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|t| Json(t))).responder()),
+    )
+}
+
+pub fn get_archived_task(task: ArchivedTask) -> AsyncJsonResponse<Task> {
+    Box::new(futures::future::done(Ok(Json(task.0))))
+}
+
+pub fn publish_task(
+    state: State<crate::web::State>,
+    admin: Admin,
+    client: ClientInfo,
+    task_id: Path<i32>,
+    form: Form<PublishForm>,
+) -> AsyncJsonResponse<Task> {
+    Box::new(
+        state
+            .db
+            .send(PublishTask {
+                task_id: *task_id,
+                site_id: admin.0.site_id,
+                published: form.published,
+                admin_id: admin.0.id,
+                client,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|t| Json(t))).responder()),
+    )
+}
+
+pub fn submit(
+    state: State<crate::web::State>,
+    task: ArchivedTask,
+    user: User,
+    req: HttpRequest<crate::web::State>,
+) -> AsyncJsonResponse<Submission> {
+    let state: crate::web::State = (*state).clone();
+    let task = task.0;
+    Box::new(
+        state
+            .db
+            .send(GetPracticeParticipation {
+                contest_id: task.contest_id,
+                user_id: user.id,
+                create: true,
+            })
+            .from_err()
+            .and_then(|res| res)
+            .and_then(move |participation| {
+                submit_files(&state, &req, task, participation)
+            }),
+    )
+}
+
+pub fn get_submissions(
+    state: State<crate::web::State>,
+    task: ArchivedTask,
+    user: User,
+    query: Query<SubmissionsQuery>,
+) -> AsyncJsonResponse<Page<Submission>> {
+    Box::new(
+        state
+            .db
+            .send(GetPracticeSubmissions {
+                task_id: task.0.id,
+                user_id: user.id,
+                query: query.into_inner(),
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|s| Json(s))).responder()),
+    )
+}
+
+pub fn get_submission(
+    state: State<crate::web::State>,
+    task: ArchivedTask,
+    user: User,
+    path: Path<(i32, i32)>,
+) -> AsyncJsonResponse<GetSubmissionResult> {
+    Box::new(
+        practice_submission(&state, task.0, user, path.1).map(|res| Json(res)),
+    )
+}
+
+/// Download a file of a submission sent in practice mode.
+pub fn get_submission_file(
+    state: State<crate::web::State>,
+    task: ArchivedTask,
+    user: User,
+    path: Path<(i32, i32, String)>,
+) -> Box<Future<Item = NamedFile, Error = Error>> {
+    let name = path.2.clone();
+    let config = state.config.clone();
+    Box::new(practice_submission(&state, task.0, user, path.1).and_then(
+        move |res| open_submission_file(&config, &res.submission, &name),
+    ))
+}
+
+/// Download all the files of a submission sent in practice mode.
+pub fn get_submission_zip(
+    state: State<crate::web::State>,
+    task: ArchivedTask,
+    user: User,
+    path: Path<(i32, i32)>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let config = state.config.clone();
+    Box::new(
+        practice_submission(&state, task.0, user, path.1)
+            .and_then(move |res| zip_submission(&config, &res.submission)),
+    )
+}
+
+/// A submission of the practice participation of the user to the task.
+fn practice_submission(
+    state: &State<crate::web::State>,
+    task: Task,
+    user: User,
+    submission_id: i32,
+) -> Box<Future<Item = GetSubmissionResult, Error = Error>> {
+    let db = state.db.clone();
+    Box::new(
+        state
+            .db
+            .send(GetPracticeParticipation {
+                contest_id: task.contest_id,
+                user_id: user.id,
+                create: false,
+            })
+            .from_err()
+            .and_then(|res| res)
+            .and_then(move |participation| {
+                db.send(GetSubmission { submission_id })
+                    .from_err()
+                    .and_then(|res| res)
+                    .and_then(move |res| {
+                        if res.submission.task_id != task.id
+                            || res.submission.participation_id
+                                != participation.id
+                        {
+                            return Err(ErrorNotFound("No such submission"));
+                        }
+                        Ok(res)
+                    })
+            }),
+    )
+}
+
+pub fn handle_archived_task_assets(
+    req: &HttpRequest<crate::web::State>,
+) -> Box<Future<Item = NamedFile, Error = Error>> {
+    let task = ArchivedTask::extract(req);
+    let path = match get_path_tail(req) {
+        Ok(path) => path,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let languages = get_accept_languages(req);
+    let config = req.state().config.clone();
+    Box::new(task.and_then(move |task| {
+        open_task_asset(&config, &task.0, &path, &languages)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use chrono::{Duration, Utc};
+
+    use crate::test_utils::*;
+    use crate::web::endpoints::task::TaskForm;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    #[test]
+    fn get_archive() {
+        let site = FakeSite::new();
+        let other_site = FakeSite::new();
+        let contest = site.contest("contest");
+        let task = site.archived_task(&contest, "task");
+        site.task(&contest, "not archived");
+        let other_contest = other_site.contest("contest");
+        other_site.archived_task(&other_contest, "task");
+        let res: Vec<ArchiveTask> =
+            TestRequestBuilder::new(&site, "/api/archive").finish();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].task.id, task.id);
+        assert_eq!(res[0].best_score, None);
+        assert!(!res[0].solved);
+    }
+
+    #[test]
+    fn get_archive_solved() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        let solved = site.archived_task(&contest, "solved");
+        let unsolved = site.archived_task(&contest, "unsolved");
+        let user = site.user("username");
+        let part = site.practice_participation(&contest, &user);
+        let now = Utc::now().naive_utc();
+        site.evaluated_submission(&solved, &part, 100.0, true, now);
+        site.evaluated_submission(&unsolved, &part, 30.0, false, now);
+        let res: Vec<ArchiveTask> =
+            TestRequestBuilder::new(&site, "/api/archive?solved=true")
+                .auth(&user)
+                .finish();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].task.id, solved.id);
+        assert_eq!(res[0].stats.submissions, 1);
+        assert_eq!(res[0].stats.solvers, 1);
+        let res: Vec<ArchiveTask> =
+            TestRequestBuilder::new(&site, "/api/archive?solved=false")
+                .auth(&user)
+                .finish();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].task.id, unsolved.id);
+        assert_eq!(res[0].best_score, Some(30.0));
+    }
+
+    #[test]
+    fn get_archive_contest_stats_are_separate() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        let task = site.archived_task(&contest, "task");
+        let user = site.user("username");
+        let part = site.participation(&contest, &user);
+        let now = Utc::now().naive_utc();
+        site.evaluated_submission(&task, &part, 100.0, true, now);
+        let res: Vec<ArchiveTask> =
+            TestRequestBuilder::new(&site, "/api/archive")
+                .auth(&user)
+                .finish();
+        assert_eq!(res[0].stats.submissions, 0);
+        assert_eq!(res[0].stats.solvers, 0);
+        assert!(res[0].solved);
+    }
+
+    #[test]
+    fn get_archive_tags_and_difficulty() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let graph = site.archived_task(&contest, "graph");
+        let dp = site.archived_task(&contest, "dp");
+        for (task, tags, difficulty) in
+            vec![(&graph, "graphs,bfs", 3), (&dp, "dp", 7)]
+        {
+            TestRequestBuilder::new(
+                &site,
+                &format!("/api/admin/task/{}", task.id),
+            )
+            .method(Method::POST)
+            .auth(&admin)
+            .form::<_, Task>(TaskForm {
+                tags: Some(tags.to_string()),
+                difficulty: Some(Some(difficulty)),
+                derive_difficulty: None,
+            });
+        }
+        let res: Vec<ArchiveTask> =
+            TestRequestBuilder::new(&site, "/api/archive?tags=bfs,graphs")
+                .finish();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].task.id, graph.id);
+        let res: Vec<ArchiveTask> =
+            TestRequestBuilder::new(&site, "/api/archive?min_difficulty=5")
+                .finish();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].task.id, dp.id);
+    }
+
+    #[test]
+    fn get_archived_task_not_archived() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}", task.id),
+        )
+        .status(StatusCode::NOT_FOUND)
+        .finish::<ErrorResponse>();
+    }
+
+    #[test]
+    fn publish_task() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.ended_contest("contest");
+        let task = site.task(&contest, "task");
+        let res: Task = TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}/publish", task.id),
+        )
+        .method(Method::POST)
+        .auth(&admin)
+        .form(PublishForm { published: true });
+        assert!(res.archived);
+    }
+
+    #[test]
+    fn publish_task_contest_running() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let now = Utc::now().naive_utc();
+        let contest = site.icpc_contest(
+            "contest",
+            now - Duration::hours(1),
+            now + Duration::hours(1),
+        );
+        let task = site.task(&contest, "task");
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}/publish", task.id),
+        )
+        .method(Method::POST)
+        .auth(&admin)
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .form::<_, ErrorResponse>(PublishForm { published: true });
+    }
+
+    #[test]
+    fn publish_task_contest_without_end() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}/publish", task.id),
+        )
+        .method(Method::POST)
+        .auth(&admin)
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .form::<_, ErrorResponse>(PublishForm { published: true });
+    }
+
+    #[test]
+    fn publish_task_not_admin() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}/publish", task.id),
+        )
+        .method(Method::POST)
+        .auth(&user)
+        .status(StatusCode::FORBIDDEN)
+        .form::<_, ErrorResponse>(PublishForm { published: true });
+    }
+
+    #[test]
+    fn get_submissions_only_practice() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        let task = site.archived_task(&contest, "task");
+        let user = site.user("username");
+        let part = site.participation(&contest, &user);
+        let practice = site.practice_participation(&contest, &user);
+        site.submission(&task, &part);
+        let sub = site.submission(&task, &practice);
+        let res: Page<Submission> = TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}/submissions", task.id),
+        )
+        .auth(&user)
+        .finish();
+        assert_eq!(res.total, 1);
+        assert_eq!(res.items[0].id, sub.id);
+    }
+
+    #[test]
+    fn get_submission_wrong_user() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        let task = site.archived_task(&contest, "task");
+        let user = site.user("username");
+        let user2 = site.user("username2");
+        site.practice_participation(&contest, &user);
+        let practice = site.practice_participation(&contest, &user2);
+        let sub = site.submission(&task, &practice);
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/archive/task/{}/submission/{}", task.id, sub.id),
+        )
+        .auth(&user)
+        .status(StatusCode::NOT_FOUND)
+        .finish::<ErrorResponse>();
+    }
+}
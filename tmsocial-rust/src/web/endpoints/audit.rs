@@ -0,0 +1,155 @@
+use actix_web::{AsyncResponder, Json, Query, State};
+use futures::future::{result, Future};
+
+use crate::models::*;
+use crate::web::db::*;
+use crate::web::endpoints::AsyncJsonResponse;
+use crate::web::extractors::Admin;
+
+/// The audit log of the site of the admin, the most recent entries first.
+pub fn get_audit_log(
+    state: State<crate::web::State>,
+    admin: Admin,
+    query: Query<AuditQuery>,
+) -> AsyncJsonResponse<Page<AuditEntry>> {
+    Box::new(
+        state
+            .db
+            .send(GetAuditLog {
+                site_id: admin.0.site_id,
+                query: query.into_inner(),
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|p| Json(p))).responder()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::{Method, StatusCode};
+
+    use crate::test_utils::*;
+    use crate::web::endpoints::user::LoginForm;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    #[test]
+    fn forwarded_address() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let user = site.user("user");
+        let login = |state: crate::web::State| {
+            TestRequestBuilder::new(&site, "/api/login")
+                .method(Method::POST)
+                .header("x-forwarded-for", "10.0.0.1, 10.0.0.2")
+                .state(state)
+                .form::<_, User>(LoginForm {
+                    username: user.username.clone(),
+                });
+        };
+        // the address is forwarded only by the trusted proxies
+        login(crate::web::State::new(crate::config::get()));
+        let mut config = (*crate::config::get()).clone();
+        config.web.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        login(crate::web::State::new(Arc::new(config)));
+
+        let log: Page<AuditEntry> =
+            TestRequestBuilder::new(&site, "/api/admin/audit?action=login")
+                .auth(&admin)
+                .finish();
+        assert_eq!(log.total, 2);
+        assert_eq!(log.items[0].ip, Some("10.0.0.2".to_string()));
+        assert_eq!(log.items[1].ip, Some("127.0.0.1".to_string()));
+    }
+
+    #[test]
+    fn logins() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let user = site.user("user");
+        TestRequestBuilder::new(&site, "/api/login")
+            .method(Method::POST)
+            .header("user-agent", "test-agent")
+            .form::<_, User>(LoginForm {
+                username: user.username.clone(),
+            });
+        TestRequestBuilder::new(&site, "/api/login")
+            .method(Method::POST)
+            .status(StatusCode::NOT_FOUND)
+            .form::<_, ErrorResponse>(LoginForm {
+                username: "nobody".to_string(),
+            });
+
+        let log: Page<AuditEntry> =
+            TestRequestBuilder::new(&site, "/api/admin/audit")
+                .auth(&admin)
+                .finish();
+        assert_eq!(log.total, 2);
+        assert_eq!(log.items[0].action, "login_failed");
+        assert_eq!(log.items[0].actor_id, None);
+        assert_eq!(log.items[0].details["username"], "nobody");
+        assert_eq!(log.items[1].action, "login");
+        assert_eq!(log.items[1].actor_id, Some(user.id));
+        assert_eq!(log.items[1].user_agent, Some("test-agent".to_string()));
+        assert!(log.items[1].ip.is_some());
+
+        let url = format!("/api/admin/audit?action=login&actor_id={}", user.id);
+        let log: Page<AuditEntry> =
+            TestRequestBuilder::new(&site, &url).auth(&admin).finish();
+        assert_eq!(log.total, 1);
+    }
+
+    #[test]
+    fn join_contest() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let user = site.user("user");
+        let contest = site.contest("contest");
+        let url = format!("/api/contest/{}/join", contest.id);
+        TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .finish::<()>();
+
+        let log: Page<AuditEntry> =
+            TestRequestBuilder::new(&site, "/api/admin/audit?limit=1")
+                .auth(&admin)
+                .finish();
+        assert_eq!(log.items.len(), 1);
+        assert_eq!(log.items[0].action, "join_contest");
+        assert_eq!(log.items[0].details["contest_id"], contest.id);
+        assert_eq!(log.next_cursor, None);
+    }
+
+    #[test]
+    fn other_site() {
+        let site = FakeSite::new();
+        let other_site = FakeSite::new();
+        let admin = site.admin("admin");
+        let user = other_site.user("user");
+        TestRequestBuilder::new(&other_site, "/api/login")
+            .method(Method::POST)
+            .form::<_, User>(LoginForm {
+                username: user.username.clone(),
+            });
+        let log: Page<AuditEntry> =
+            TestRequestBuilder::new(&site, "/api/admin/audit")
+                .auth(&admin)
+                .finish();
+        assert_eq!(log.total, 0);
+    }
+
+    #[test]
+    fn not_admin() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        TestRequestBuilder::new(&site, "/api/admin/audit")
+            .auth(&user)
+            .status(StatusCode::FORBIDDEN)
+            .finish::<ErrorResponse>();
+    }
+}
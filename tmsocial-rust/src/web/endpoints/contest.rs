@@ -1,19 +1,21 @@
-use std::collections::HashSet;
-use std::env;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use actix_web::error::ErrorNotFound;
 use actix_web::error::{
     ErrorBadRequest, ErrorInternalServerError, MultipartError, PayloadError,
 };
+use actix_web::error::{
+    ErrorForbidden, ErrorNotFound, ErrorServiceUnavailable,
+    ErrorUnprocessableEntity,
+};
 use actix_web::fs::NamedFile;
 use actix_web::{
     dev, multipart, AsyncResponder, Error, FromRequest, HttpMessage,
-    HttpRequest, Json, State,
+    HttpRequest, HttpResponse, Json, Query, State,
 };
 use futures::future;
 use futures::future::{result, Future};
@@ -21,11 +23,17 @@ use futures::stream::Stream;
 use serde_derive::{Deserialize, Serialize};
 use tempfile::TempDir;
 
+use crate::audit::ClientInfo;
+use crate::config::Config;
+use crate::icpc::IcpcScoreboard;
+use crate::metadata::{AssetFile, Scorable, SubmissionForm};
 use crate::models::*;
 use crate::web::db::*;
+use crate::web::endpoints::submission::{open_submission_file, zip_submission};
 use crate::web::endpoints::{
     get_accept_languages, get_path_tail, match_file, AsyncJsonResponse,
 };
+use crate::web::extractors::{client_info, Admin};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetContestsResponseItem {
@@ -43,8 +51,12 @@ pub fn get_contests(
     state: State<crate::web::State>,
     site: Site,
     user: Option<User>,
-) -> AsyncJsonResponse<Vec<GetContestsResponseItem>> {
-    let contests = state.db.send(GetContests { site_id: site.id });
+    query: Query<ContestsQuery>,
+) -> AsyncJsonResponse<Page<GetContestsResponseItem>> {
+    let contests = state.db.send(GetContests {
+        site_id: site.id,
+        query: query.into_inner(),
+    });
     let participations = state.db.send(GetParticipationsByUser {
         // assuming no user has negative id
         user_id: user.map(|user| user.id).unwrap_or(-1),
@@ -54,13 +66,18 @@ pub fn get_contests(
             (Ok(contests), Ok(participations)) => {
                 let participations: HashSet<i32> =
                     participations.iter().map(|p| p.contest_id).collect();
-                Ok(contests
-                    .into_iter()
-                    .map(|c| GetContestsResponseItem {
-                        participating: participations.contains(&c.id),
-                        contest: c,
-                    })
-                    .collect())
+                Ok(Page {
+                    items: contests
+                        .items
+                        .into_iter()
+                        .map(|c| GetContestsResponseItem {
+                            participating: participations.contains(&c.id),
+                            contest: c,
+                        })
+                        .collect(),
+                    total: contests.total,
+                    next_cursor: contests.next_cursor,
+                })
             }
             (Err(err), _) => Err(err),
             (_, Err(err)) => Err(err),
@@ -106,6 +123,7 @@ pub fn join_contest(
     state: State<crate::web::State>,
     contest: Contest,
     user: User,
+    client: ClientInfo,
 ) -> AsyncJsonResponse<()> {
     Box::new(
         state
@@ -113,6 +131,7 @@ pub fn join_contest(
             .send(JoinContest {
                 contest_id: contest.id,
                 user_id: user.id,
+                client,
             })
             .from_err()
             .and_then(|res| result(res.map(|u| Json(u))).responder()),
@@ -126,17 +145,121 @@ pub fn get_task(
     Box::new(futures::future::done(Ok(Json(task))))
 }
 
+/// A statement or an attachment of the task, resolved according to the
+/// languages of the request.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResolvedAsset {
+    pub name: String,
+    pub content_type: String,
+    pub url: String,
+    /// The language of the file at `url`, `None` if it's not localized.
+    pub language: Option<String>,
+    /// The urls of all the localized versions, by language.
+    pub variants: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskMetadataResponse {
+    pub title: String,
+    pub statements: Vec<ResolvedAsset>,
+    pub attachments: Vec<ResolvedAsset>,
+    pub submission_form: SubmissionForm,
+    pub scorables: Vec<Scorable>,
+}
+
+pub fn get_task_metadata(
+    state: State<crate::web::State>,
+    task: Task,
+    _participation: Participation,
+    req: HttpRequest<crate::web::State>,
+) -> AsyncJsonResponse<TaskMetadataResponse> {
+    let languages = get_accept_languages(&req);
+    let config = state.config.clone();
+    Box::new(
+        state
+            .db
+            .send(GetTaskMetadata { task_id: task.id })
+            .from_err()
+            .and_then(|res| res)
+            .and_then(move |metadata| {
+                let base_url = format!(
+                    "/api/contest/{}/task/{}/assets",
+                    task.contest_id, task.id
+                );
+                let assets = task_assets_dir(&config, &task);
+                let resolve = |files: &Vec<AssetFile>| {
+                    files
+                        .iter()
+                        .filter_map(|file| {
+                            resolve_asset(&assets, &base_url, file, &languages)
+                        })
+                        .collect()
+                };
+                Ok(Json(TaskMetadataResponse {
+                    statements: resolve(&metadata.statements),
+                    attachments: resolve(&metadata.attachments),
+                    title: metadata.title,
+                    submission_form: metadata.submission_form,
+                    scorables: metadata.scorables,
+                }))
+            }),
+    )
+}
+
+/// Pick the version of the file to serve with `match_file`, skipping the
+/// files missing from the disk.
+fn resolve_asset(
+    assets: &Path,
+    base_url: &str,
+    file: &AssetFile,
+    languages: &Vec<String>,
+) -> Option<ResolvedAsset> {
+    let localized = |lang: &str| {
+        let path = Path::new(&file.path);
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        path.with_extension(format!("{}.{}", lang, ext))
+            .to_string_lossy()
+            .to_string()
+    };
+    let resolved = match_file(assets.join(&file.path), languages)?;
+    let language = file
+        .languages
+        .iter()
+        .find(|lang| assets.join(localized(lang.as_str())) == resolved)
+        .cloned();
+    let url = match &language {
+        Some(lang) => format!("{}/{}", base_url, localized(lang.as_str())),
+        None => format!("{}/{}", base_url, file.path),
+    };
+    Some(ResolvedAsset {
+        name: file.name.clone(),
+        content_type: file.content_type.clone(),
+        url,
+        language,
+        variants: file
+            .languages
+            .iter()
+            .map(|lang| {
+                let url = format!("{}/{}", base_url, localized(lang.as_str()));
+                (lang.clone(), url)
+            })
+            .collect(),
+    })
+}
+
 pub fn get_submissions(
     state: State<crate::web::State>,
     participation: Participation,
     task: Task,
-) -> AsyncJsonResponse<Vec<Submission>> {
+    query: Query<SubmissionsQuery>,
+) -> AsyncJsonResponse<Page<Submission>> {
     Box::new(
         state
             .db
             .send(GetSubmissions {
                 participation_id: participation.id,
                 task_id: task.id,
+                query: query.into_inner(),
             })
             .from_err()
             .and_then(|res| result(res.map(|u| Json(u))).responder()),
@@ -149,63 +272,229 @@ pub fn get_submission(
     Box::new(future::done(Ok(Json(submission))))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct FileName {
+    pub name: String,
+}
+
+/// Download a file of a submission of the participation.
+pub fn get_submission_file(
+    state: State<crate::web::State>,
+    submission: GetSubmissionResult,
+    path: actix_web::Path<FileName>,
+) -> Result<NamedFile, Error> {
+    open_submission_file(&state.config, &submission.submission, &path.name)
+}
+
+/// Download all the files of a submission of the participation.
+pub fn get_submission_zip(
+    state: State<crate::web::State>,
+    submission: GetSubmissionResult,
+) -> Result<HttpResponse, Error> {
+    zip_submission(&state.config, &submission.submission)
+}
+
 pub fn submit(
     state: State<crate::web::State>,
     participation: Participation,
     task: Task,
     req: HttpRequest<crate::web::State>,
 ) -> AsyncJsonResponse<Submission> {
-    let tempdir = TempDir::new();
-    if tempdir.is_err() {
-        return Box::new(future::err(ErrorInternalServerError(
-            tempdir.err().unwrap(),
+    submit_files(&state, &req, task, participation)
+}
+
+/// Store the files of the multipart request as a new submission of the
+/// participation and send it to the evaluator.
+pub fn submit_files(
+    state: &crate::web::State,
+    req: &HttpRequest<crate::web::State>,
+    task: Task,
+    participation: Participation,
+) -> AsyncJsonResponse<Submission> {
+    if let Err(e) = check_accepting_submissions(state) {
+        return Box::new(future::err(e));
+    }
+    if task.format == TaskFormat::Terry {
+        return Box::new(future::err(ErrorUnprocessableEntity(
+            "Submit the output of a Terry task through its input",
         )));
     }
-    let tempdir = Arc::new(tempdir.unwrap());
+    let tempdir = match TempDir::new() {
+        Ok(tempdir) => Arc::new(tempdir),
+        Err(e) => return Box::new(future::err(ErrorInternalServerError(e))),
+    };
     let tempdir2 = tempdir.clone();
     let user_id = participation.user_id;
-    let event_manager = state.event_manager.clone().recipient();
-    let evaluator = state.evaluator.clone();
+    let client = client_info(req);
+    let state = state.clone();
     let db = state.db.clone();
+    // the outputs are told apart by their field, whatever their name
+    let names = if task.task_type == TaskType::OutputOnly {
+        FileNames::ByField(is_output_field)
+    } else {
+        FileNames::Sent
+    };
     Box::new(
-        req.multipart()
-            .map_err(ErrorInternalServerError)
-            .map(move |item| handle_multipart_item(tempdir.clone(), item))
-            .flatten()
-            .collect()
+        receive_files(req, tempdir, names)
             .and_then(move |files| {
                 db.send(Submit {
                     task_id: task.id,
                     participation_id: participation.id,
                     files: files,
                     tempdir: tempdir2,
+                    client: client,
                 })
                 .from_err()
             })
             .and_then(|sub| sub)
             .and_then(move |sub| {
-                evaluator.do_send(crate::evaluation::Evaluate {
-                    submission: sub.clone(),
-                    user_id: user_id,
-                    notify: event_manager,
-                });
+                send_to_evaluator(&state, &sub, user_id);
                 Ok(sub)
             })
             .and_then(|sub| result(Ok(Json(sub)))),
     )
 }
 
+/// How the files of a multipart request are named.
+#[derive(Clone, Copy)]
+pub enum FileNames {
+    /// After the name sent by the client.
+    Sent,
+    /// After their field, keeping their extension. The fields not accepted by
+    /// the function are refused.
+    ByField(fn(&str) -> bool),
+}
+
+/// The fields of the outputs of an output-only task.
+fn is_output_field(name: &str) -> bool {
+    name == "outputs" || crate::evaluation::output_testcase(name).is_some()
+}
+
+/// Whether the file can be saved with this name inside the directory of the
+/// submission.
+fn is_safe_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && !name.contains('/')
+        && !name.contains('\\')
+        && !name.contains("..")
+}
+
+/// Save the files of the multipart request inside `tempdir`, refusing the
+/// unsafe names with 400.
+pub fn receive_files(
+    req: &HttpRequest<crate::web::State>,
+    tempdir: Arc<TempDir>,
+    names: FileNames,
+) -> Box<Future<Item = Vec<PathBuf>, Error = Error>> {
+    Box::new(
+        req.multipart()
+            .map_err(ErrorInternalServerError)
+            .map(move |item| {
+                handle_multipart_item(tempdir.clone(), item, names)
+            })
+            .flatten()
+            .collect(),
+    )
+}
+
+/// Refuse the new submissions while the server is shutting down.
+pub fn check_accepting_submissions(
+    state: &crate::web::State,
+) -> Result<(), Error> {
+    if state.shutdown.is_started() {
+        Err(ErrorServiceUnavailable("The server is shutting down"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Send a new submission to the evaluator.
+pub fn send_to_evaluator(
+    state: &crate::web::State,
+    submission: &Submission,
+    user_id: i32,
+) {
+    crate::metrics::EVALUATION_QUEUE.inc();
+    state.evaluator.do_send(crate::evaluation::Evaluate {
+        submission: submission.clone(),
+        user_id: user_id,
+        request_id: crate::logging::request_id(),
+        notify: state.event_manager.clone().recipient(),
+        contest_notify: state.event_manager.clone().recipient(),
+    });
+}
+
+pub fn get_scoreboard(
+    state: State<crate::web::State>,
+    contest: Contest,
+) -> AsyncJsonResponse<IcpcScoreboard> {
+    Box::new(
+        state
+            .db
+            .send(GetIcpcScoreboard {
+                contest,
+                unfrozen: false,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|s| Json(s))).responder()),
+    )
+}
+
+pub fn get_unfrozen_scoreboard(
+    state: State<crate::web::State>,
+    contest: Contest,
+    admin: Admin,
+) -> AsyncJsonResponse<IcpcScoreboard> {
+    if admin.0.site_id != contest.site_id {
+        return Box::new(future::err(ErrorForbidden("Not an admin")));
+    }
+    Box::new(
+        state
+            .db
+            .send(GetIcpcScoreboard {
+                contest,
+                unfrozen: true,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|s| Json(s))).responder()),
+    )
+}
+
 fn handle_multipart_item(
     temp: Arc<TempDir>,
     item: multipart::MultipartItem<dev::Payload>,
+    names: FileNames,
 ) -> Box<Stream<Item = PathBuf, Error = Error>> {
     match item {
         multipart::MultipartItem::Field(field) => {
-            let filename = field
-                .content_disposition()
-                .map(|f| f.get_filename().map(|f| f.to_string()));
+            let filename = field.content_disposition().map(|f| {
+                let filename = f.get_filename().map(|f| f.to_string());
+                match (names, f.get_name(), filename) {
+                    (FileNames::ByField(expected), Some(name), Some(_))
+                        if !expected(name) || !is_safe_file_name(name) =>
+                    {
+                        Err(ErrorBadRequest(format!(
+                            "Unexpected field {:?}",
+                            name
+                        )))
+                    }
+                    (FileNames::ByField(_), Some(name), Some(filename)) => {
+                        Ok(match Path::new(&filename).extension() {
+                            Some(ext) => Some(format!(
+                                "{}.{}",
+                                name,
+                                ext.to_string_lossy()
+                            )),
+                            None => Some(name.to_string()),
+                        })
+                    }
+                    (_, _, filename) => Ok(filename),
+                }
+            });
             let filename = match filename {
-                Some(Some(filename)) => filename,
+                Some(Ok(Some(filename))) => filename,
+                Some(Err(e)) => return Box::new(future::err(e).into_stream()),
                 _ => {
                     return Box::new(
                         future::err(ErrorBadRequest("Missing file name"))
@@ -213,11 +502,19 @@ fn handle_multipart_item(
                     )
                 }
             };
+            if !is_safe_file_name(&filename) {
+                return Box::new(
+                    future::err(ErrorBadRequest("Invalid file name"))
+                        .into_stream(),
+                );
+            }
             Box::new(save_file(field, temp.clone(), filename).into_stream())
         }
         multipart::MultipartItem::Nested(mp) => Box::new(
             mp.map_err(ErrorInternalServerError)
-                .map(move |item| handle_multipart_item(temp.clone(), item))
+                .map(move |item| {
+                    handle_multipart_item(temp.clone(), item, names)
+                })
                 .flatten(),
         ),
     }
@@ -257,10 +554,8 @@ pub fn handle_contest_assets(
         Err(e) => return Box::new(future::err(e)),
     };
     let languages = get_accept_languages(req);
+    let storage_dir = req.state().config.storage_dir.clone();
     Box::new(contest.and_then(move |contest| {
-        let storage_dir = PathBuf::new().join(Path::new(
-            &env::var("STORAGE_DIR").expect("STORAGE_DIR must be set"),
-        ));
         let path = storage_dir
             .join(Path::new("contests"))
             .join(Path::new(&contest.id.to_string()))
@@ -283,30 +578,43 @@ pub fn handle_task_assets(
         Err(e) => return Box::new(future::err(e)),
     };
     let languages = get_accept_languages(req);
+    let config = req.state().config.clone();
     Box::new(task.and_then(move |task| {
-        let storage_dir = PathBuf::new().join(Path::new(
-            &env::var("STORAGE_DIR").expect("STORAGE_DIR must be set"),
-        ));
-        let path = storage_dir
-            .join(Path::new("tasks"))
-            .join(Path::new(&task.id.to_string()))
-            .join(Path::new("assets"))
-            .join(&path);
-        match match_file(path, &languages) {
-            Some(path) => Ok(NamedFile::open(path)
-                .map_err(|_e| ErrorNotFound("No such asset"))?),
-            _ => Err(ErrorNotFound("No such asset")),
-        }
+        open_task_asset(&config, &task, &path, &languages)
     }))
 }
 
+/// The directory with the assets of the task.
+fn task_assets_dir(config: &Config, task: &Task) -> PathBuf {
+    crate::task_dir(config, task.id, task.version).join(Path::new("assets"))
+}
+
+/// Open the asset of the task at the given path, picking the localized
+/// version according to the languages.
+pub fn open_task_asset(
+    config: &Config,
+    task: &Task,
+    path: &str,
+    languages: &Vec<String>,
+) -> Result<NamedFile, Error> {
+    let path = task_assets_dir(config, task).join(path);
+    match match_file(path, languages) {
+        Some(path) => Ok(NamedFile::open(path)
+            .map_err(|_e| ErrorNotFound("No such asset"))?),
+        _ => Err(ErrorNotFound("No such asset")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
     use actix_web::http::{Method, StatusCode};
+    use chrono::{Duration, Utc};
     use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+    use scopeguard::defer;
 
+    use crate::metadata::TaskMetadata;
     use crate::test_utils::*;
     use crate::web::db::submission::GetSubmissionResult;
     use crate::web::test_utils::*;
@@ -327,9 +635,11 @@ mod tests {
                 .map(|c| (c.id, c))
                 .collect();
         other_site.contest("nothing to see here");
-        let res: Vec<GetContestsResponseItem> =
+        let res: Page<GetContestsResponseItem> =
             TestRequestBuilder::new(&site, "/api/contests").finish();
-        for contest in res {
+        assert_eq!(res.total, 2);
+        assert_eq!(res.next_cursor, None);
+        for contest in res.items {
             assert_eq!(
                 contest.contest.name,
                 contests.get(&contest.contest.id).expect("wrong data").name
@@ -340,6 +650,28 @@ mod tests {
         assert!(contests.is_empty());
     }
 
+    #[test]
+    fn get_contests_paginated() {
+        let site = FakeSite::new();
+        for name in &["contest_b", "contest_c", "contest_a"] {
+            site.contest(name);
+        }
+        let url = "/api/contests?sort=name&order=desc&limit=2";
+        let res: Page<GetContestsResponseItem> =
+            TestRequestBuilder::new(&site, url).finish();
+        assert_eq!(res.total, 3);
+        let names: Vec<&str> =
+            res.items.iter().map(|c| c.contest.name.as_str()).collect();
+        assert_eq!(names, vec!["contest_c", "contest_b"]);
+        let url =
+            format!("{}&cursor={}", url, res.next_cursor.expect("no cursor"));
+        let res: Page<GetContestsResponseItem> =
+            TestRequestBuilder::new(&site, &url).finish();
+        assert_eq!(res.items.len(), 1);
+        assert_eq!(res.items[0].contest.name, "contest_a");
+        assert_eq!(res.next_cursor, None);
+    }
+
     #[test]
     fn get_contests_auth() {
         let site = FakeSite::new();
@@ -350,11 +682,11 @@ mod tests {
                 .collect();
         let user = site.user("user");
         let part = site.participation(contests.values().next().unwrap(), &user);
-        let res: Vec<GetContestsResponseItem> =
+        let res: Page<GetContestsResponseItem> =
             TestRequestBuilder::new(&site, "/api/contests")
                 .auth(&user)
                 .finish();
-        for contest in res {
+        for contest in res.items {
             assert_eq!(
                 contest.contest.name,
                 contests.get(&contest.contest.id).expect("wrong data").name
@@ -525,6 +857,224 @@ mod tests {
         .finish::<ErrorResponse>();
     }
 
+    #[test]
+    fn get_task_metadata() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let root =
+            crate::task_dir(&crate::config::get(), task.id, task.version);
+        let dir = task_assets_dir(&crate::config::get(), &task);
+        fs::create_dir_all(dir.join("attachments")).unwrap();
+        fs::write(dir.join("statement.pdf"), "").unwrap();
+        fs::write(dir.join("statement.it.pdf"), "").unwrap();
+        fs::write(dir.join("attachments/grader.cpp"), "").unwrap();
+        defer! {{
+            fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        }}
+        let asset = |name: &str, path: &str, languages: Vec<&str>| AssetFile {
+            name: name.to_string(),
+            path: path.to_string(),
+            content_type: "".to_string(),
+            languages: languages.into_iter().map(|l| l.to_string()).collect(),
+        };
+        let metadata = TaskMetadata {
+            title: "The Task".to_string(),
+            statements: vec![
+                asset("statement.html", "statement.html", vec!["it"]),
+                asset("statement.pdf", "statement.pdf", vec!["it"]),
+            ],
+            attachments: vec![asset(
+                "grader.cpp",
+                "attachments/grader.cpp",
+                vec![],
+            )],
+            submission_form: SubmissionForm { fields: vec![] },
+            scorables: vec![],
+        };
+        diesel::insert_into(crate::schema::task_metadata::table)
+            .values(StoredTaskMetadata {
+                task_id: task.id,
+                metadata: serde_json::to_value(metadata).unwrap(),
+            })
+            .execute(&site.conn)
+            .unwrap();
+        let url = format!("/api/contest/{}/task/{}", contest.id, task.id);
+        let res: TaskMetadataResponse =
+            TestRequestBuilder::new(&site, &format!("{}/metadata", url))
+                .auth(&user)
+                .header("Accept-Language", "it")
+                .finish();
+        assert_eq!(res.title, "The Task");
+        // the html statement is missing from the disk
+        assert_eq!(res.statements.len(), 1);
+        assert_eq!(res.statements[0].language, Some("it".to_string()));
+        assert_eq!(
+            res.statements[0].url,
+            format!("{}/assets/statement.it.pdf", url)
+        );
+        assert_eq!(res.attachments.len(), 1);
+        assert_eq!(res.attachments[0].language, None);
+        let res: TaskMetadataResponse =
+            TestRequestBuilder::new(&site, &format!("{}/metadata", url))
+                .auth(&user)
+                .header("Accept-Language", "en")
+                .finish();
+        assert_eq!(res.statements[0].language, None);
+        assert_eq!(
+            res.statements[0].url,
+            format!("{}/assets/statement.pdf", url)
+        );
+        assert_eq!(
+            res.statements[0].variants["it"],
+            format!("{}/assets/statement.it.pdf", url)
+        );
+    }
+
+    #[test]
+    fn get_task_assets_before_versions() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        // the layout of the tasks imported before the versions were added
+        let root = crate::config::get()
+            .storage_dir
+            .join("tasks")
+            .join(task.id.to_string());
+        fs::create_dir_all(root.join("assets")).unwrap();
+        fs::write(root.join("assets/statement.pdf"), "statement").unwrap();
+        defer! {{
+            fs::remove_dir_all(&root).unwrap();
+        }}
+        assert_eq!(crate::task_dir(&crate::config::get(), task.id, 1), root);
+        let (body, _) = TestRequestBuilder::new(
+            &site,
+            &format!(
+                "/api/contest/{}/task/{}/assets/statement.pdf",
+                contest.id, task.id
+            ),
+        )
+        .auth(&user)
+        .finish_raw();
+        assert_eq!(body, b"statement");
+    }
+
+    #[test]
+    fn submit_output_only() {
+        use crate::schema::tasks::dsl;
+
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let task = diesel::update(dsl::tasks.find(task.id))
+            .set(dsl::task_type.eq(TaskType::OutputOnly))
+            .get_result::<Task>(&site.conn)
+            .unwrap();
+        site.participation(&contest, &user);
+        // the browser sends the name of the file chosen by the user
+        let sub: Submission = TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/task/{}/submit", contest.id, task.id),
+        )
+        .method(Method::POST)
+        .auth(&user)
+        .multipart(vec![("output_1", "my answer.out", &b"42\n"[..])]);
+        assert_eq!(sub.files, vec!["output_1.out"]);
+        assert_eq!(crate::evaluation::output_testcase(&sub.files[0]), Some(1));
+        let stored = crate::submission_dir(&crate::config::get(), sub.id)
+            .join("output_1.out");
+        assert_eq!(fs::read(stored).unwrap(), b"42\n");
+    }
+
+    #[test]
+    fn submit_unsafe_names() {
+        use crate::schema::tasks::dsl;
+
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let url =
+            format!("/api/contest/{}/task/{}/submit", contest.id, task.id);
+        let submit = |field, filename| -> ErrorResponse {
+            TestRequestBuilder::new(&site, &url)
+                .method(Method::POST)
+                .auth(&user)
+                .status(StatusCode::BAD_REQUEST)
+                .multipart(vec![(field, filename, &b"42\n"[..])])
+        };
+        let res = submit("source", "../../source.cpp");
+        assert_eq!(res.error, "Invalid file name");
+        let res = submit("source", "..\\source.cpp");
+        assert_eq!(res.error, "Invalid file name");
+
+        diesel::update(dsl::tasks.find(task.id))
+            .set(dsl::task_type.eq(TaskType::OutputOnly))
+            .execute(&site.conn)
+            .unwrap();
+        let res = submit("../../output_1", "output.txt");
+        assert_eq!(res.error, "Unexpected field \"../../output_1\"");
+        let res = submit("source", "source.cpp");
+        assert_eq!(res.error, "Unexpected field \"source\"");
+        let count: i64 = crate::schema::submissions::table
+            .count()
+            .get_result(&site.conn)
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn submit_during_shutdown() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let state = crate::web::State::new(crate::config::get());
+        state.shutdown.start();
+        let res: ErrorResponse = TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/task/{}/submit", contest.id, task.id),
+        )
+        .method(Method::POST)
+        .auth(&user)
+        .state(state)
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .multipart(vec![(
+            "source",
+            "source.cpp",
+            &b"int main() {}"[..],
+        )]);
+        assert_eq!(res.error, "The server is shutting down");
+        let count: i64 = crate::schema::submissions::table
+            .count()
+            .get_result(&site.conn)
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn get_task_metadata_missing() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/task/{}/metadata", contest.id, task.id),
+        )
+        .auth(&user)
+        .status(StatusCode::NOT_FOUND)
+        .finish::<ErrorResponse>();
+    }
+
     #[test]
     fn get_task_not_found() {
         let site = FakeSite::new();
@@ -570,6 +1120,49 @@ mod tests {
         .finish::<ErrorResponse>();
     }
 
+    #[test]
+    fn get_submissions_filtered() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let part = site.participation(&contest, &user);
+        let now = Utc::now().naive_utc();
+        let subs: Vec<Submission> = vec![10.0, 30.0, 20.0, 0.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, score)| {
+                let time = now - Duration::minutes(i as i64);
+                site.evaluated_submission(&task, &part, score, false, time)
+            })
+            .collect();
+        let url = format!(
+            "/api/contest/{}/task/{}/submissions?min_score=5&sort=score&limit=2",
+            contest.id, task.id
+        );
+        let res: Page<Submission> =
+            TestRequestBuilder::new(&site, &url).auth(&user).finish();
+        assert_eq!(res.total, 3);
+        let ids: Vec<i32> = res.items.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![subs[1].id, subs[2].id]);
+        let url =
+            format!("{}&cursor={}", url, res.next_cursor.expect("no cursor"));
+        let res: Page<Submission> =
+            TestRequestBuilder::new(&site, &url).auth(&user).finish();
+        assert_eq!(res.items.len(), 1);
+        assert_eq!(res.items[0].id, subs[0].id);
+        assert_eq!(res.next_cursor, None);
+        let url = format!(
+            "/api/contest/{}/task/{}/submissions?cursor=invalid",
+            contest.id, task.id
+        );
+        let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .auth(&user)
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .finish();
+        assert_eq!(res.error, "Invalid cursor");
+    }
+
     #[test]
     fn get_submissions() {
         let site = FakeSite::new();
@@ -582,7 +1175,7 @@ mod tests {
                 .into_iter()
                 .map(|s| (s.id, s))
                 .collect();
-        let res: Vec<Submission> = TestRequestBuilder::new(
+        let res: Page<Submission> = TestRequestBuilder::new(
             &site,
             &format!(
                 "/api/contest/{}/task/{}/submissions",
@@ -591,7 +1184,8 @@ mod tests {
         )
         .auth(&user)
         .finish();
-        for sub in res {
+        assert_eq!(res.total, 2);
+        for sub in res.items {
             assert!(submissions.contains_key(&sub.id));
             submissions.remove(&sub.id);
         }
@@ -769,4 +1363,110 @@ mod tests {
         .status(StatusCode::NOT_FOUND)
         .finish::<ErrorResponse>();
     }
+
+    #[test]
+    fn get_scoreboard() {
+        let site = FakeSite::new();
+        let now = Utc::now().naive_utc();
+        let contest = site.icpc_contest(
+            "contest",
+            now - Duration::hours(2),
+            now + Duration::hours(3),
+        );
+        let task = site.task(&contest, "task");
+        let user = site.user("username");
+        let part = site.participation(&contest, &user);
+        site.evaluated_submission(
+            &task,
+            &part,
+            0.0,
+            false,
+            now - Duration::minutes(90),
+        );
+        site.evaluated_submission(
+            &task,
+            &part,
+            100.0,
+            true,
+            now - Duration::minutes(60),
+        );
+        let res: IcpcScoreboard = TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/scoreboard", contest.id),
+        )
+        .finish();
+        assert!(!res.frozen);
+        assert_eq!(res.rows[0].user_id, user.id);
+        assert_eq!(res.rows[0].solved, 1);
+        assert_eq!(res.rows[0].penalty, 60 + 20);
+    }
+
+    #[test]
+    fn get_scoreboard_not_icpc() {
+        let site = FakeSite::new();
+        let contest = site.contest("contest");
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/scoreboard", contest.id),
+        )
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .finish::<ErrorResponse>();
+    }
+
+    #[test]
+    fn get_scoreboard_frozen() {
+        let site = FakeSite::new();
+        let now = Utc::now().naive_utc();
+        let contest = site.icpc_contest(
+            "contest",
+            now - Duration::hours(4),
+            now + Duration::minutes(30),
+        );
+        let task = site.task(&contest, "task");
+        let user = site.user("username");
+        let admin = site.admin("admin");
+        let part = site.participation(&contest, &user);
+        site.evaluated_submission(
+            &task,
+            &part,
+            100.0,
+            true,
+            now - Duration::minutes(10),
+        );
+        let res: IcpcScoreboard = TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/scoreboard", contest.id),
+        )
+        .finish();
+        assert!(res.frozen);
+        assert_eq!(res.rows[0].solved, 0);
+        assert_eq!(res.rows[0].tasks[&task.id].pending, 1);
+        let res: IcpcScoreboard = TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/scoreboard/unfrozen", contest.id),
+        )
+        .auth(&admin)
+        .finish();
+        assert!(!res.frozen);
+        assert_eq!(res.rows[0].solved, 1);
+    }
+
+    #[test]
+    fn get_unfrozen_scoreboard_not_admin() {
+        let site = FakeSite::new();
+        let now = Utc::now().naive_utc();
+        let contest = site.icpc_contest(
+            "contest",
+            now - Duration::hours(4),
+            now + Duration::minutes(30),
+        );
+        let user = site.user("username");
+        TestRequestBuilder::new(
+            &site,
+            &format!("/api/contest/{}/scoreboard/unfrozen", contest.id),
+        )
+        .auth(&user)
+        .status(StatusCode::FORBIDDEN)
+        .finish::<ErrorResponse>();
+    }
 }
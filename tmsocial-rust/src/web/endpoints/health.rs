@@ -0,0 +1,112 @@
+use actix::MailboxError;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{Error, HttpResponse, Json, State};
+use futures::future::Future;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::health::{Check, Readiness, READINESS_TIMEOUT};
+use crate::web::db::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Health {
+    pub status: String,
+}
+
+/// Tell that the process is alive, without checking its dependencies.
+pub fn healthz(_: State<crate::web::State>) -> Json<Health> {
+    Json(Health {
+        status: "ok".to_string(),
+    })
+}
+
+/// Check the dependencies of the server, answering 503 if some of them are
+/// not usable or if the checks don't complete within `READINESS_TIMEOUT`, as
+/// when all the database executors are busy.
+pub fn readyz(
+    state: State<crate::web::State>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    Box::new(
+        state
+            .db
+            .send_timeout(CheckReadiness, READINESS_TIMEOUT)
+            .then(|res| match res {
+                Ok(res) => res,
+                Err(MailboxError::Timeout) => {
+                    let timed_out = || {
+                        Check::failed(format!(
+                            "not checked within {:?}",
+                            READINESS_TIMEOUT
+                        ))
+                    };
+                    Ok(Readiness::new(
+                        timed_out(),
+                        timed_out(),
+                        timed_out(),
+                        timed_out(),
+                    ))
+                }
+                Err(e) => Err(ErrorInternalServerError(e)),
+            })
+            .map(|readiness| {
+                if readiness.ready {
+                    HttpResponse::Ok().json(readiness)
+                } else {
+                    HttpResponse::ServiceUnavailable().json(readiness)
+                }
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::test_utils::{fake_task_maker, FakeSite};
+    use crate::web::test_utils::TestRequestBuilder;
+
+    #[test]
+    fn healthz() {
+        let site = FakeSite::new();
+        let res: Health = TestRequestBuilder::new(&site, "/healthz").finish();
+        assert_eq!(res.status, "ok");
+    }
+
+    #[test]
+    fn readyz() {
+        let site = FakeSite::new();
+        let dir = TempDir::new().unwrap();
+        let mut config = (*crate::config::get()).clone();
+        config.task_maker = fake_task_maker(dir.path(), "echo task-maker 1.2");
+        config.storage_dir = dir.path().to_owned();
+        config.evaluation.max_backlog = i64::max_value();
+        let res: Readiness = TestRequestBuilder::new(&site, "/readyz")
+            .state(crate::web::State::new(Arc::new(config)))
+            .finish();
+        assert!(res.ready);
+        assert!(res.database.ok);
+        assert!(res.storage.ok);
+        assert!(res.evaluator.ok);
+        assert_eq!(res.task_maker.detail, "task-maker 1.2");
+    }
+
+    #[test]
+    fn readyz_not_ready() {
+        let site = FakeSite::new();
+        let dir = TempDir::new().unwrap();
+        let mut config = (*crate::config::get()).clone();
+        config.task_maker = "/nonexistent/task-maker".to_string();
+        config.storage_dir = dir.path().join("missing");
+        let res: Readiness = TestRequestBuilder::new(&site, "/readyz")
+            .state(crate::web::State::new(Arc::new(config)))
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .finish();
+        assert!(!res.ready);
+        assert!(res.database.ok);
+        assert!(!res.storage.ok);
+        assert!(!res.task_maker.ok);
+    }
+}
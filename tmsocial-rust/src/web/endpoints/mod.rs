@@ -7,8 +7,16 @@ use actix_web::{Error, Json};
 use futures::Future;
 use log::warn;
 
+pub mod archive;
+pub mod audit;
 pub mod contest;
+pub mod health;
+pub mod oidc;
 pub mod site;
+pub mod submission;
+pub mod task;
+pub mod terry;
+pub mod token;
 pub mod user;
 
 pub type AsyncJsonResponse<T> = Box<Future<Item = Json<T>, Error = Error>>;
@@ -27,6 +35,15 @@ fn get_path_tail<S>(req: &HttpRequest<S>) -> Result<String, Error> {
     Ok(path)
 }
 
+/// Split a comma separated list of tags, dropping the empty ones.
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
 fn get_accept_languages<S>(req: &HttpRequest<S>) -> Vec<String> {
     let val = match req.headers().get(ACCEPT_LANGUAGE) {
         Some(val) => val.to_str(),
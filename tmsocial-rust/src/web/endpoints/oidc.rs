@@ -0,0 +1,569 @@
+use actix_web::client;
+use actix_web::error::{ErrorBadGateway, ErrorForbidden};
+use actix_web::http::header::LOCATION;
+use actix_web::http::Cookie;
+use actix_web::{
+    AsyncResponder, Error, HttpMessage, HttpRequest, HttpResponse, Json, Path,
+    Query, State,
+};
+use chrono::Utc;
+use cookie::SameSite;
+use futures::future::{self, result, Future};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audit::ClientInfo;
+use crate::models::*;
+use crate::oidc::{
+    authorization_url, check_secure_url, decode_id_token, discovery_url,
+    validate_claims, Discovery, OidcError, TokenRequest, TokenResponse,
+};
+use crate::web::db::*;
+use crate::web::endpoints::AsyncJsonResponse;
+use crate::web::extractors::{auth_cookie, SessionUser};
+
+/// Cookie binding a login to the browser that started it.
+pub const OIDC_COOKIE: &str = "oidc_state";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CallbackQuery {
+    pub state: String,
+    pub code: Option<String>,
+    /// Set by the provider when the login failed.
+    pub error: Option<String>,
+}
+
+/// The identity providers the users of the site can log in with.
+pub fn get_providers(
+    state: State<crate::web::State>,
+    site: Site,
+) -> AsyncJsonResponse<Vec<OidcProvider>> {
+    Box::new(
+        state
+            .db
+            .send(GetOidcProviders { site_id: site.id })
+            .from_err()
+            .and_then(|res| result(res.map(|p| Json(p))).responder()),
+    )
+}
+
+/// Redirect the user to the provider to log in.
+pub fn login(
+    state: State<crate::web::State>,
+    site: Site,
+    name: Path<String>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    start_login(&state, site, name.into_inner(), None)
+}
+
+/// Redirect the user to the provider to link its account to the current
+/// user. A POST, so that other sites cannot link their own account to the
+/// user.
+pub fn link(
+    state: State<crate::web::State>,
+    site: Site,
+    user: SessionUser,
+    name: Path<String>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    if user.0.site_id != site.id {
+        return Box::new(future::err(ErrorForbidden("Logged out")));
+    }
+    start_login(&state, site, name.into_inner(), Some(user.0.id))
+}
+
+/// Redirect the user to the provider, remembering the login in the database
+/// and in a cookie of the browser.
+fn start_login(
+    state: &crate::web::State,
+    site: Site,
+    name: String,
+    link_user_id: Option<i32>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = state.db.clone();
+    Box::new(get_provider_discovery(state, &site, name).and_then(
+        move |(provider, discovery)| {
+            db.send(StartOidcLogin {
+                provider_id: provider.id,
+                link_user_id,
+            })
+            .from_err()
+            .and_then(|res| res)
+            .and_then(
+                move |login| -> Result<HttpResponse, Error> {
+                    let url = authorization_url(
+                        &discovery,
+                        &provider.client_id,
+                        &redirect_uri(&site, &provider),
+                        &login.state,
+                        &login.nonce,
+                        &login.code_verifier,
+                    )
+                    .map_err(|_| {
+                        ErrorBadGateway("Invalid identity provider")
+                    })?;
+                    Ok(HttpResponse::Found()
+                        .header(LOCATION, url)
+                        .cookie(state_cookie(&site, login.state))
+                        .finish())
+                },
+            )
+        },
+    ))
+}
+
+/// Where the provider sends the user back: exchange the code for the
+/// identity of the user and log them in.
+pub fn callback(
+    state: State<crate::web::State>,
+    site: Site,
+    client: ClientInfo,
+    name: Path<String>,
+    query: Query<CallbackQuery>,
+    req: HttpRequest<crate::web::State>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let query = query.into_inner();
+    let cookie_state = req.cookie(OIDC_COOKIE).map(|c| c.value().to_string());
+    if cookie_state.as_ref() != Some(&query.state) {
+        warn!("OpenID Connect callback from another browser");
+        return Box::new(future::err(ErrorForbidden("Invalid login state")));
+    }
+    if let Some(error) = query.error {
+        return Box::new(future::err(ErrorForbidden(format!(
+            "Login refused by the provider: {}",
+            error
+        ))));
+    }
+    let code = match query.code {
+        Some(code) => code,
+        None => return Box::new(future::err(ErrorForbidden("Missing code"))),
+    };
+    let db = state.db.clone();
+    let login_db = state.db.clone();
+    let login_state = query.state;
+    Box::new(
+        get_provider_discovery(&state, &site, name.into_inner())
+            .and_then(move |(provider, discovery)| {
+                db.send(TakeOidcLogin {
+                    provider_id: provider.id,
+                    state: login_state,
+                })
+                .from_err()
+                .and_then(|res| res)
+                .and_then(move |login| {
+                    let token_request = TokenRequest {
+                        grant_type: "authorization_code".to_string(),
+                        code,
+                        redirect_uri: redirect_uri(&site, &provider),
+                        client_id: provider.client_id.clone(),
+                        client_secret: provider.client_secret.clone(),
+                        code_verifier: login.code_verifier.clone(),
+                    };
+                    exchange_code(&discovery, token_request).and_then(
+                        move |id_token| -> Result<_, Error> {
+                            let claims = decode_id_token(&id_token)
+                                .and_then(|claims| {
+                                    validate_claims(
+                                        &claims,
+                                        &provider.issuer,
+                                        &provider.client_id,
+                                        &login.nonce,
+                                        Utc::now().timestamp(),
+                                    )
+                                    .map(|_| claims)
+                                })
+                                .map_err(|err| {
+                                    warn!(
+                                        "Login with {} failed: {}",
+                                        provider.name, err
+                                    );
+                                    ErrorForbidden("Invalid identity")
+                                })?;
+                            Ok((site, provider, login, claims))
+                        },
+                    )
+                })
+            })
+            .and_then(move |(site, provider, login, claims)| {
+                login_db
+                    .send(DoOidcLogin {
+                        provider,
+                        claims,
+                        link_user_id: login.link_user_id,
+                        client,
+                    })
+                    .from_err()
+                    .and_then(|res| res)
+                    .map(move |(_user, token)| {
+                        HttpResponse::Found()
+                            .header(LOCATION, "/")
+                            .cookie(auth_cookie(&site, token))
+                            .del_cookie(&state_cookie(&site, String::new()))
+                            .finish()
+                    })
+            }),
+    )
+}
+
+/// The provider of the site with this name and its metadata.
+fn get_provider_discovery(
+    state: &crate::web::State,
+    site: &Site,
+    name: String,
+) -> Box<Future<Item = (OidcProvider, Discovery), Error = Error>> {
+    Box::new(
+        state
+            .db
+            .send(GetOidcProvider {
+                site_id: site.id,
+                name,
+            })
+            .from_err()
+            .and_then(|res| res)
+            .and_then(|provider| {
+                discover(&provider.issuer).and_then(move |discovery| {
+                    if discovery.issuer.trim_end_matches('/')
+                        != provider.issuer.trim_end_matches('/')
+                    {
+                        warn!(
+                            "Provider {} has issuer {}",
+                            provider.issuer, discovery.issuer
+                        );
+                        return Err(ErrorBadGateway(
+                            "Invalid identity provider",
+                        ));
+                    }
+                    Ok((provider, discovery))
+                })
+            }),
+    )
+}
+
+/// The metadata published by the provider, which must be reached over HTTPS.
+fn discover(issuer: &str) -> Box<Future<Item = Discovery, Error = Error>> {
+    if let Err(err) = check_secure_url(issuer) {
+        return Box::new(future::err(insecure_provider(err)));
+    }
+    let url = discovery_url(issuer);
+    let request = match client::get(&url).finish() {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    Box::new(
+        request
+            .send()
+            .map_err(move |err| {
+                warn!("Cannot reach {}: {}", url, err);
+                ErrorBadGateway("Identity provider unavailable")
+            })
+            .and_then(|response| {
+                if !response.status().is_success() {
+                    return future::Either::A(future::err(ErrorBadGateway(
+                        "Identity provider unavailable",
+                    )));
+                }
+                future::Either::B(
+                    response
+                        .json::<Discovery>()
+                        .map_err(|_| {
+                            ErrorBadGateway("Invalid identity provider")
+                        })
+                        .and_then(|discovery| -> Result<Discovery, Error> {
+                            check_secure_url(&discovery.authorization_endpoint)
+                                .and_then(|_| {
+                                    check_secure_url(&discovery.token_endpoint)
+                                })
+                                .map_err(insecure_provider)?;
+                            Ok(discovery)
+                        }),
+                )
+            }),
+    )
+}
+
+/// The ID token for the code of a login. The token endpoint must be reached
+/// over HTTPS, since the signature of the token is not checked.
+fn exchange_code(
+    discovery: &Discovery,
+    token_request: TokenRequest,
+) -> Box<Future<Item = String, Error = Error>> {
+    if let Err(err) = check_secure_url(&discovery.token_endpoint) {
+        return Box::new(future::err(insecure_provider(err)));
+    }
+    let request =
+        match client::post(&discovery.token_endpoint).form(token_request) {
+            Ok(request) => request,
+            Err(err) => return Box::new(future::err(err)),
+        };
+    Box::new(
+        request
+            .send()
+            .map_err(|err| {
+                warn!("Cannot reach the token endpoint: {}", err);
+                ErrorBadGateway("Identity provider unavailable")
+            })
+            .and_then(|response| {
+                if !response.status().is_success() {
+                    warn!("Code refused with status {}", response.status());
+                    return future::Either::A(future::err(ErrorForbidden(
+                        "Login refused by the provider",
+                    )));
+                }
+                future::Either::B(
+                    response
+                        .json::<TokenResponse>()
+                        .map(|token| token.id_token)
+                        .map_err(|_| {
+                            ErrorBadGateway("Invalid identity provider")
+                        }),
+                )
+            }),
+    )
+}
+
+/// The error of a provider not reached over HTTPS.
+fn insecure_provider(err: OidcError) -> Error {
+    warn!("Refusing the identity provider: {}", err);
+    ErrorBadGateway("Invalid identity provider")
+}
+
+/// Where the provider sends back the users of the site. The sites with an
+/// insecure cookie are served over plain HTTP.
+fn redirect_uri(site: &Site, provider: &OidcProvider) -> String {
+    let scheme = if site.cookie_secure { "https" } else { "http" };
+    format!(
+        "{}://{}/api/oidc/{}/callback",
+        scheme, site.domain, provider.name
+    )
+}
+
+/// The cookie with the state of the login. It's `SameSite=Lax` since the
+/// provider sends the user back with a cross-site navigation.
+fn state_cookie(site: &Site, state: String) -> Cookie<'static> {
+    Cookie::build(OIDC_COOKIE, state)
+        .path("/api/oidc")
+        .http_only(true)
+        .secure(site.cookie_secure)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::client::ClientResponse;
+    use actix_web::http::{Method, StatusCode};
+    use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+    use crate::test_utils::*;
+    use crate::web::extractors::AUTH_COOKIE;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    /// Log in with the provider as `subject`, returning the response of the
+    /// callback.
+    fn oidc_login(
+        site: &FakeSite,
+        issuer: &MockIssuer,
+        user: Option<&User>,
+        subject: &str,
+        username: &str,
+    ) -> ClientResponse {
+        let (_, response) = match user {
+            Some(user) => {
+                TestRequestBuilder::new(site, "/api/oidc/school/link")
+                    .method(Method::POST)
+                    .auth(user)
+            }
+            None => TestRequestBuilder::new(site, "/api/oidc/school/login"),
+        }
+        .status(StatusCode::FOUND)
+        .finish_raw();
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        let cookie = response.cookie(OIDC_COOKIE).expect("No state cookie");
+        let (code, state) = issuer.authorize(location, subject, username);
+        assert_eq!(cookie.value(), state);
+        let url =
+            format!("/api/oidc/school/callback?code={}&state={}", code, state);
+        let (_, response) = TestRequestBuilder::new(site, &url)
+            .header("cookie", &format!("{}={}", OIDC_COOKIE, state))
+            .status(StatusCode::FOUND)
+            .finish_raw();
+        response
+    }
+
+    fn user_of(site: &FakeSite, response: &ClientResponse) -> User {
+        use crate::schema::users::dsl::*;
+
+        let token = response.cookie(AUTH_COOKIE).expect("Not logged in");
+        users
+            .filter(login_token.eq(token.value()))
+            .first::<User>(&site.conn)
+            .expect("No user with the token")
+    }
+
+    #[test]
+    fn providers() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let providers: Vec<serde_json::Value> =
+            TestRequestBuilder::new(&site, "/api/oidc/providers").finish();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0]["name"], "school");
+        assert_eq!(providers[0]["client_secret"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn insecure_issuer() {
+        use crate::schema::oidc_providers::dsl::*;
+
+        let site = FakeSite::new();
+        let mock = MockIssuer::new();
+        let provider = site.oidc_provider("school", &mock);
+        diesel::update(oidc_providers.find(provider.id))
+            .set(issuer.eq("http://accounts.example.com"))
+            .execute(&site.conn)
+            .unwrap();
+        let res: ErrorResponse =
+            TestRequestBuilder::new(&site, "/api/oidc/school/login")
+                .status(StatusCode::BAD_GATEWAY)
+                .finish();
+        assert_eq!(res.error, "Invalid identity provider");
+    }
+
+    #[test]
+    fn new_user() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let response = oidc_login(&site, &issuer, None, "1234", "mario");
+        assert_eq!(response.headers()[LOCATION], "/");
+        let user = user_of(&site, &response);
+        assert_eq!(user.username, "mario");
+        assert!(!user.is_admin);
+        // the second time the same user logs in
+        let response = oidc_login(&site, &issuer, None, "1234", "mario");
+        assert_eq!(user_of(&site, &response).id, user.id);
+    }
+
+    #[test]
+    fn username_taken() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let existing = site.user("mario");
+        let response = oidc_login(&site, &issuer, None, "1234", "mario");
+        let user = user_of(&site, &response);
+        assert_ne!(user.id, existing.id);
+        assert_eq!(user.username, "mario-2");
+        let response = oidc_login(&site, &issuer, None, "5678", "mario");
+        assert_eq!(user_of(&site, &response).username, "mario-3");
+    }
+
+    #[test]
+    fn link() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let user = site.user("user");
+        let response = oidc_login(&site, &issuer, Some(&user), "1234", "x");
+        assert_eq!(user_of(&site, &response).id, user.id);
+        let response = oidc_login(&site, &issuer, None, "1234", "x");
+        assert_eq!(user_of(&site, &response).id, user.id);
+        // the account cannot be linked to someone else
+        let other = site.user("other");
+        let (_, response) =
+            TestRequestBuilder::new(&site, "/api/oidc/school/link")
+                .method(Method::POST)
+                .auth(&other)
+                .status(StatusCode::FOUND)
+                .finish_raw();
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        let (code, state) = issuer.authorize(location, "1234", "x");
+        let url =
+            format!("/api/oidc/school/callback?code={}&state={}", code, state);
+        TestRequestBuilder::new(&site, &url)
+            .header("cookie", &format!("{}={}", OIDC_COOKIE, state))
+            .status(StatusCode::CONFLICT)
+            .finish::<ErrorResponse>();
+    }
+
+    #[test]
+    fn login_does_not_link() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let user = site.user("user");
+        let (_, response) =
+            TestRequestBuilder::new(&site, "/api/oidc/school/login")
+                .auth(&user)
+                .status(StatusCode::FOUND)
+                .finish_raw();
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        let (code, state) = issuer.authorize(location, "1234", "mario");
+        let url =
+            format!("/api/oidc/school/callback?code={}&state={}", code, state);
+        let (_, response) = TestRequestBuilder::new(&site, &url)
+            .header("cookie", &format!("{}={}", OIDC_COOKIE, state))
+            .status(StatusCode::FOUND)
+            .finish_raw();
+        let logged = user_of(&site, &response);
+        assert_ne!(logged.id, user.id);
+        assert_eq!(logged.username, "mario");
+    }
+
+    #[test]
+    fn cross_site_link() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let user = site.user("user");
+        let err: ErrorResponse =
+            TestRequestBuilder::new(&site, "/api/oidc/school/link")
+                .method(Method::POST)
+                .auth(&user)
+                .header("origin", "https://evil.example.com")
+                .status(StatusCode::FORBIDDEN)
+                .finish();
+        assert_eq!(err.error, "Cross-site request");
+    }
+
+    #[test]
+    fn other_browser() {
+        let site = FakeSite::new();
+        let issuer = MockIssuer::new();
+        site.oidc_provider("school", &issuer);
+        let (_, response) =
+            TestRequestBuilder::new(&site, "/api/oidc/school/login")
+                .status(StatusCode::FOUND)
+                .finish_raw();
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        let (code, state) = issuer.authorize(location, "1234", "mario");
+        let url =
+            format!("/api/oidc/school/callback?code={}&state={}", code, state);
+        let err: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Invalid login state");
+        // the browser that started the login can still complete it, once
+        let cookie = format!("{}={}", OIDC_COOKIE, state);
+        TestRequestBuilder::new(&site, &url)
+            .header("cookie", &cookie)
+            .status(StatusCode::FOUND)
+            .finish_raw();
+        let err: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .header("cookie", &cookie)
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Invalid login state");
+    }
+
+    #[test]
+    fn unknown_provider() {
+        let site = FakeSite::new();
+        TestRequestBuilder::new(&site, "/api/oidc/school/login")
+            .status(StatusCode::NOT_FOUND)
+            .finish::<ErrorResponse>();
+    }
+}
@@ -1,5 +1,4 @@
-use std::env;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use actix_web::error::ErrorNotFound;
 use actix_web::fs::NamedFile;
@@ -18,10 +17,8 @@ pub fn handle_site_assets(
         Err(e) => return Box::new(future::err(e)),
     };
     let languages = get_accept_languages(req);
+    let storage_dir = req.state().config.storage_dir.clone();
     Box::new(site.and_then(move |site| {
-        let storage_dir = PathBuf::new().join(Path::new(
-            &env::var("STORAGE_DIR").expect("STORAGE_DIR must be set"),
-        ));
         let path = storage_dir
             .join(Path::new("sites"))
             .join(Path::new(&site.id.to_string()))
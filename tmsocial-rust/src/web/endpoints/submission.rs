@@ -0,0 +1,457 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{Cursor, Write};
+
+use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
+use actix_web::fs::NamedFile;
+use actix_web::http::header::CONTENT_DISPOSITION;
+use actix_web::{Error, HttpResponse, Json, Path, Query, State};
+use futures::future::Future;
+use serde_derive::{Deserialize, Serialize};
+use zip::write::{FileOptions, ZipWriter};
+
+use crate::audit::ClientInfo;
+use crate::config::Config;
+use crate::models::*;
+use crate::task_maker_ui::SubtaskNum;
+use crate::text_diff::{diff_hunks, unified_diff, Hunk, CONTEXT_LINES};
+use crate::web::db::*;
+use crate::web::endpoints::AsyncJsonResponse;
+use crate::web::extractors::Admin;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffQuery {
+    pub from: i32,
+    pub to: i32,
+}
+
+/// The changes to a file, the name is missing if the file is not present in
+/// one of the submissions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub old_name: Option<String>,
+    pub new_name: Option<String>,
+    pub unified: String,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubtaskDelta {
+    pub subtask: SubtaskNum,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    pub delta: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubmissionDiff {
+    pub from: Submission,
+    pub to: Submission,
+    pub files: Vec<FileDiff>,
+    pub score_delta: f64,
+    pub subtasks: Vec<SubtaskDelta>,
+}
+
+/// Open one of the files of the submission. Only the files listed in the
+/// submission can be opened.
+pub fn open_submission_file(
+    config: &Config,
+    submission: &Submission,
+    name: &str,
+) -> Result<NamedFile, Error> {
+    if !submission.files.iter().any(|f| f == name) {
+        return Err(ErrorNotFound("No such file"));
+    }
+    NamedFile::open(crate::submission_dir(config, submission.id).join(name))
+        .map_err(|_e| ErrorNotFound("No such file"))
+}
+
+/// A zip with all the files of the submission.
+pub fn zip_submission(
+    config: &Config,
+    submission: &Submission,
+) -> Result<HttpResponse, Error> {
+    let dir = crate::submission_dir(config, submission.id);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for name in &submission.files {
+        let content = fs::read(dir.join(name))
+            .map_err(|_e| ErrorNotFound("No such file"))?;
+        zip.start_file(name.as_str(), FileOptions::default())
+            .map_err(ErrorInternalServerError)?;
+        zip.write_all(&content).map_err(ErrorInternalServerError)?;
+    }
+    let zip = zip.finish().map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .header(
+            CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"submission-{}.zip\"",
+                submission.id
+            ),
+        )
+        .body(zip.into_inner()))
+}
+
+/// Compare two submissions of the participation to the task.
+pub fn diff(
+    state: State<crate::web::State>,
+    participation: Participation,
+    task: Task,
+    query: Query<DiffQuery>,
+) -> AsyncJsonResponse<SubmissionDiff> {
+    let from = state.db.send(GetSubmission {
+        submission_id: query.from,
+    });
+    let to = state.db.send(GetSubmission {
+        submission_id: query.to,
+    });
+    let config = state.config.clone();
+    Box::new(
+        from.join(to)
+            .from_err()
+            .and_then(move |(from, to)| {
+                let (from, to) = (from?, to?);
+                for sub in &[&from.submission, &to.submission] {
+                    if sub.task_id != task.id
+                        || sub.participation_id != participation.id
+                    {
+                        return Err(ErrorNotFound("No such submission"));
+                    }
+                }
+                diff_submissions(&config, &from, &to)
+            })
+            .map(|diff| Json(diff)),
+    )
+}
+
+/// Diff the files of the submissions and compare their scores.
+fn diff_submissions(
+    config: &Config,
+    from: &GetSubmissionResult,
+    to: &GetSubmissionResult,
+) -> Result<SubmissionDiff, Error> {
+    let read = |sub: &Submission, name: &Option<String>| match name {
+        Some(name) => {
+            fs::read(crate::submission_dir(config, sub.id).join(name))
+                .map(|content| String::from_utf8_lossy(&content).to_string())
+                .map_err(|_e| ErrorNotFound("No such file"))
+        }
+        None => Ok(String::new()),
+    };
+    let mut files = vec![];
+    for (old_name, new_name) in
+        pair_files(&from.submission.files, &to.submission.files)
+    {
+        let hunks = diff_hunks(
+            &read(&from.submission, &old_name)?,
+            &read(&to.submission, &new_name)?,
+            CONTEXT_LINES,
+        );
+        let unified = unified_diff(
+            old_name.as_ref().map_or("/dev/null", |n| n.as_str()),
+            new_name.as_ref().map_or("/dev/null", |n| n.as_str()),
+            &hunks,
+        );
+        files.push(FileDiff {
+            old_name,
+            new_name,
+            unified,
+            hunks,
+        });
+    }
+    let subtasks: BTreeSet<SubtaskNum> = from
+        .results
+        .keys()
+        .chain(to.results.keys())
+        .cloned()
+        .collect();
+    Ok(SubmissionDiff {
+        from: from.submission.clone(),
+        to: to.submission.clone(),
+        files,
+        score_delta: to.submission.score.unwrap_or(0.0)
+            - from.submission.score.unwrap_or(0.0),
+        subtasks: subtasks
+            .into_iter()
+            .map(|st| {
+                let from = from.results.get(&st).map(|r| r.score);
+                let to = to.results.get(&st).map(|r| r.score);
+                SubtaskDelta {
+                    subtask: st,
+                    from,
+                    to,
+                    delta: to.unwrap_or(0.0) - from.unwrap_or(0.0),
+                }
+            })
+            .collect(),
+    })
+}
+
+/// Match the files of two submissions by name. Two submissions of a single
+/// file are always compared, even if the file was renamed.
+fn pair_files(
+    from: &Vec<String>,
+    to: &Vec<String>,
+) -> Vec<(Option<String>, Option<String>)> {
+    if from.len() == 1 && to.len() == 1 {
+        return vec![(Some(from[0].clone()), Some(to[0].clone()))];
+    }
+    let names: BTreeSet<&String> = from.iter().chain(to.iter()).collect();
+    names
+        .into_iter()
+        .map(|name| {
+            let find = |files: &Vec<String>| files.iter().find(|f| *f == name);
+            (find(from).cloned(), find(to).cloned())
+        })
+        .collect()
+}
+
+fn get_site_submission(
+    state: &State<crate::web::State>,
+    admin: &Admin,
+    client: ClientInfo,
+    submission_id: i32,
+) -> Box<Future<Item = Submission, Error = Error>> {
+    Box::new(
+        state
+            .db
+            .send(GetSiteSubmission {
+                submission_id,
+                site_id: admin.0.site_id,
+                admin_id: admin.0.id,
+                client,
+            })
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+/// Download a file of any submission of the site.
+pub fn get_file(
+    state: State<crate::web::State>,
+    admin: Admin,
+    client: ClientInfo,
+    path: Path<(i32, String)>,
+) -> Box<Future<Item = NamedFile, Error = Error>> {
+    let name = path.1.clone();
+    let config = state.config.clone();
+    Box::new(
+        get_site_submission(&state, &admin, client, path.0)
+            .and_then(move |sub| open_submission_file(&config, &sub, &name)),
+    )
+}
+
+/// Download all the files of any submission of the site.
+pub fn get_zip(
+    state: State<crate::web::State>,
+    admin: Admin,
+    client: ClientInfo,
+    path: Path<i32>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let config = state.config.clone();
+    Box::new(
+        get_site_submission(&state, &admin, client, *path)
+            .and_then(move |sub| zip_submission(&config, &sub)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use actix_web::http::StatusCode;
+    use scopeguard::defer;
+    use zip::ZipArchive;
+
+    use crate::test_utils::*;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    /// Store the files of the submission on disk.
+    fn store_files(submission: &Submission) {
+        let dir =
+            crate::create_submission_dir(&crate::config::get(), submission.id);
+        for name in &submission.files {
+            fs::write(dir.join(name), format!("content of {}", name)).unwrap();
+        }
+    }
+
+    #[test]
+    fn get_contest_file() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let part = site.participation(&contest, &user);
+        let sub = site.submission(&task, &part);
+        store_files(&sub);
+        defer! {{
+            fs::remove_dir_all(crate::submission_dir(
+                &crate::config::get(),
+                sub.id,
+            ))
+            .unwrap();
+        }}
+        let url = format!(
+            "/api/contest/{}/task/{}/submission/{}/files",
+            contest.id, task.id, sub.id
+        );
+        let (body, _) =
+            TestRequestBuilder::new(&site, &format!("{}/file.cpp", url))
+                .auth(&user)
+                .finish_raw();
+        assert_eq!(body, b"content of file.cpp");
+        let res: ErrorResponse =
+            TestRequestBuilder::new(&site, &format!("{}/other.cpp", url))
+                .auth(&user)
+                .status(StatusCode::NOT_FOUND)
+                .finish();
+        assert_eq!(res.error, "No such file");
+        let (body, response) = TestRequestBuilder::new(&site, &url)
+            .auth(&user)
+            .finish_raw();
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/zip"
+        );
+        let mut zip = ZipArchive::new(Cursor::new(body)).unwrap();
+        assert_eq!(zip.len(), 1);
+        let mut content = String::new();
+        zip.by_name("file.cpp")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "content of file.cpp");
+    }
+
+    #[test]
+    fn get_contest_file_wrong_user() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let other = site.user("other");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let part = site.participation(&contest, &user);
+        site.participation(&contest, &other);
+        let sub = site.submission(&task, &part);
+        let url = format!(
+            "/api/contest/{}/task/{}/submission/{}/files/file.cpp",
+            contest.id, task.id, sub.id
+        );
+        let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .auth(&other)
+            .status(StatusCode::NOT_FOUND)
+            .finish();
+        assert_eq!(res.error, "No such submission");
+    }
+
+    #[test]
+    fn diff_two_submissions() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let other = site.user("other");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let part = site.participation(&contest, &user);
+        let other_part = site.participation(&contest, &other);
+        let now = chrono::Utc::now().naive_utc();
+        let from = site.evaluated_submission(&task, &part, 30.0, false, now);
+        let to = site.evaluated_submission(&task, &part, 50.0, false, now);
+        let hidden = site.submission(&task, &other_part);
+        let from_dir =
+            crate::create_submission_dir(&crate::config::get(), from.id);
+        let to_dir = crate::create_submission_dir(&crate::config::get(), to.id);
+        defer! {{
+            fs::remove_dir_all(crate::submission_dir(
+                &crate::config::get(),
+                from.id,
+            ))
+            .unwrap();
+            fs::remove_dir_all(crate::submission_dir(
+                &crate::config::get(),
+                to.id,
+            ))
+            .unwrap();
+        }}
+        fs::write(from_dir.join("file.cpp"), "int main() {\n}\n").unwrap();
+        fs::write(to_dir.join("file.cpp"), "int main() {\n  return 0;\n}\n")
+            .unwrap();
+        let url = format!("/api/contest/{}/task/{}/diff", contest.id, task.id);
+        let res: SubmissionDiff = TestRequestBuilder::new(
+            &site,
+            &format!("{}?from={}&to={}", url, from.id, to.id),
+        )
+        .auth(&user)
+        .finish();
+        assert_eq!(res.score_delta, 20.0);
+        assert_eq!(res.files.len(), 1);
+        assert_eq!(
+            res.files[0].unified,
+            "--- a/file.cpp\n+++ b/file.cpp\n\
+             @@ -1,2 +1,3 @@\n int main() {\n+  return 0;\n }\n"
+        );
+        let res: ErrorResponse = TestRequestBuilder::new(
+            &site,
+            &format!("{}?from={}&to={}", url, from.id, hidden.id),
+        )
+        .auth(&user)
+        .status(StatusCode::NOT_FOUND)
+        .finish();
+        assert_eq!(res.error, "No such submission");
+    }
+
+    #[test]
+    fn pair_renamed_files() {
+        let files = |names: &[&str]| -> Vec<String> {
+            names.iter().map(|n| n.to_string()).collect()
+        };
+        assert_eq!(
+            pair_files(&files(&["a.cpp"]), &files(&["b.cpp"])),
+            vec![(Some("a.cpp".to_string()), Some("b.cpp".to_string()))]
+        );
+        assert_eq!(
+            pair_files(&files(&["a.cpp", "b.h"]), &files(&["a.cpp"])),
+            vec![
+                (Some("a.cpp".to_string()), Some("a.cpp".to_string())),
+                (Some("b.h".to_string()), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_admin_file() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let user = site.user("username");
+        let sub = site.make_submission();
+        store_files(&sub);
+        defer! {{
+            fs::remove_dir_all(crate::submission_dir(
+                &crate::config::get(),
+                sub.id,
+            ))
+            .unwrap();
+        }}
+        let url = format!("/api/admin/submission/{}/files", sub.id);
+        let (body, _) =
+            TestRequestBuilder::new(&site, &format!("{}/file.cpp", url))
+                .auth(&admin)
+                .finish_raw();
+        assert_eq!(body, b"content of file.cpp");
+        let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .auth(&user)
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(res.error, "Not an admin");
+        // the submissions of the other sites are hidden
+        let other_site = FakeSite::new();
+        let other_admin = other_site.admin("admin");
+        TestRequestBuilder::new(&other_site, &url)
+            .auth(&other_admin)
+            .status(StatusCode::NOT_FOUND)
+            .finish::<ErrorResponse>();
+    }
+}
@@ -0,0 +1,378 @@
+use actix_web::error::ErrorBadRequest;
+use actix_web::{AsyncResponder, Form, Json, Path, Query, State};
+use futures::future;
+use futures::future::{result, Future};
+use serde::{Deserializer, Serializer};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audit::ClientInfo;
+use crate::models::*;
+use crate::plagiarism::DEFAULT_THRESHOLD;
+use crate::web::db::*;
+use crate::web::endpoints::{split_tags, AsyncJsonResponse};
+use crate::web::extractors::Admin;
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskForm {
+    /// Comma separated list of tags, the current ones are kept if missing.
+    pub tags: Option<String>,
+    /// The current difficulty is kept if missing, an empty value clears it.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        serialize_with = "serialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub difficulty: Option<Option<i32>>,
+    pub derive_difficulty: Option<bool>,
+}
+
+/// A form field that is set only when present, and set to null when empty.
+fn deserialize_nullable<'de, D>(
+    deserializer: D,
+) -> Result<Option<Option<i32>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = serde::Deserialize::deserialize(deserializer)?;
+    if value.is_empty() {
+        return Ok(Some(None));
+    }
+    value
+        .parse()
+        .map(|v| Some(Some(v)))
+        .map_err(serde::de::Error::custom)
+}
+
+fn serialize_nullable<S>(
+    value: &Option<Option<i32>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(Some(value)) => serializer.serialize_i32(*value),
+        _ => serializer.serialize_str(""),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlagiarismForm {
+    /// Minimum similarity of the reported pairs, between 0 and 1.
+    pub threshold: Option<f64>,
+}
+
+pub fn search(
+    state: State<crate::web::State>,
+    site: Site,
+    user: Option<User>,
+    query: Query<SearchQuery>,
+) -> AsyncJsonResponse<SearchResult> {
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .max(1)
+        .min(MAX_PER_PAGE);
+    Box::new(
+        state
+            .db
+            .send(SearchTasks {
+                site_id: site.id,
+                user_id: user.map(|u| u.id),
+                query: query.q.clone(),
+                page: query.page.unwrap_or(0).max(0),
+                per_page,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|r| Json(r))).responder()),
+    )
+}
+
+pub fn update_task(
+    state: State<crate::web::State>,
+    admin: Admin,
+    client: ClientInfo,
+    task_id: Path<i32>,
+    form: Form<TaskForm>,
+) -> AsyncJsonResponse<Task> {
+    Box::new(
+        state
+            .db
+            .send(UpdateTaskClassification {
+                task_id: *task_id,
+                site_id: admin.0.site_id,
+                tags: form.tags.as_ref().map(|t| split_tags(t)),
+                difficulty: form.difficulty,
+                derive_difficulty: form.derive_difficulty.unwrap_or(false),
+                admin_id: admin.0.id,
+                client,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|t| Json(t))).responder()),
+    )
+}
+
+/// Look for similar final submissions of the task, replacing the previous
+/// results.
+pub fn check_plagiarism(
+    state: State<crate::web::State>,
+    admin: Admin,
+    client: ClientInfo,
+    task_id: Path<i32>,
+    form: Form<PlagiarismForm>,
+) -> AsyncJsonResponse<Vec<PlagiarismPair>> {
+    let threshold = form.threshold.unwrap_or(DEFAULT_THRESHOLD);
+    if !(threshold >= 0.0 && threshold <= 1.0) {
+        return Box::new(future::err(ErrorBadRequest(
+            "The threshold must be between 0 and 1",
+        )));
+    }
+    Box::new(
+        state
+            .checks_db
+            .send(CheckPlagiarism {
+                task_id: *task_id,
+                site_id: admin.0.site_id,
+                threshold,
+                admin_id: admin.0.id,
+                client,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|p| Json(p))).responder()),
+    )
+}
+
+/// The suspicious pairs found by the last plagiarism check of the task.
+pub fn get_plagiarism(
+    state: State<crate::web::State>,
+    admin: Admin,
+    task_id: Path<i32>,
+) -> AsyncJsonResponse<Vec<PlagiarismPair>> {
+    Box::new(
+        state
+            .db
+            .send(GetPlagiarismPairs {
+                task_id: *task_id,
+                site_id: admin.0.site_id,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|p| Json(p))).responder()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use chrono::Utc;
+    use scopeguard::defer;
+
+    use crate::test_utils::*;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    #[test]
+    fn search_visible_tasks() {
+        let site = FakeSite::new();
+        let other_site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let joined = site.contest("joined");
+        site.participation(&joined, &user);
+        let archived = site.archived_task(&contest, "shortest path");
+        let running = site.task(&joined, "longest path");
+        site.task(&contest, "hidden path");
+        let other_contest = other_site.contest("contest");
+        other_site.archived_task(&other_contest, "path");
+        let res: SearchResult =
+            TestRequestBuilder::new(&site, "/api/search?q=path")
+                .auth(&user)
+                .finish();
+        assert_eq!(res.total, 2);
+        let mut ids: Vec<i32> = res.results.iter().map(|r| r.task.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![archived.id, running.id]);
+    }
+
+    #[test]
+    fn search_best_score() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.archived_task(&contest, "task");
+        let part = site.practice_participation(&contest, &user);
+        let now = Utc::now().naive_utc();
+        site.evaluated_submission(&task, &part, 42.0, false, now);
+        let res: SearchResult =
+            TestRequestBuilder::new(&site, "/api/search?q=task")
+                .auth(&user)
+                .finish();
+        assert_eq!(res.results.len(), 1);
+        assert_eq!(res.results[0].best_score, Some(42.0));
+    }
+
+    #[test]
+    fn update_task() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let res: Task = TestRequestBuilder::new(
+            &site,
+            &format!("/api/admin/task/{}", task.id),
+        )
+        .method(Method::POST)
+        .auth(&admin)
+        .form(TaskForm {
+            tags: Some("greedy, sorting".to_string()),
+            difficulty: Some(Some(4)),
+            derive_difficulty: None,
+        });
+        assert_eq!(res.tags, vec!["greedy", "sorting"]);
+        assert_eq!(res.difficulty, Some(4));
+    }
+
+    #[test]
+    fn update_task_only_tags() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let url = format!("/api/admin/task/{}", task.id);
+        let update = |tags: &str, difficulty| -> Task {
+            TestRequestBuilder::new(&site, &url)
+                .method(Method::POST)
+                .auth(&admin)
+                .form(TaskForm {
+                    tags: Some(tags.to_string()),
+                    difficulty,
+                    derive_difficulty: None,
+                })
+        };
+        update("greedy", Some(Some(4)));
+        let res = update("greedy, sorting", None);
+        assert_eq!(res.tags, vec!["greedy", "sorting"]);
+        assert_eq!(res.difficulty, Some(4));
+        // an empty difficulty clears it
+        let res = update("greedy", Some(None));
+        assert_eq!(res.difficulty, None);
+    }
+
+    #[test]
+    fn update_task_derive_difficulty() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let now = Utc::now().naive_utc();
+        for (i, score) in vec![100.0, 0.0].into_iter().enumerate() {
+            let user = site.user(&format!("user{}", i));
+            let part = site.participation(&contest, &user);
+            site.evaluated_submission(&task, &part, score, score > 0.0, now);
+        }
+        let res: Task = TestRequestBuilder::new(
+            &site,
+            &format!("/api/admin/task/{}", task.id),
+        )
+        .method(Method::POST)
+        .auth(&admin)
+        .form(TaskForm {
+            tags: None,
+            difficulty: None,
+            derive_difficulty: Some(true),
+        });
+        assert_eq!(res.difficulty, Some(6));
+    }
+
+    #[test]
+    fn update_task_not_admin() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        TestRequestBuilder::new(&site, &format!("/api/admin/task/{}", task.id))
+            .method(Method::POST)
+            .auth(&user)
+            .status(StatusCode::FORBIDDEN)
+            .form::<_, ErrorResponse>(TaskForm {
+                tags: None,
+                difficulty: Some(Some(3)),
+                derive_difficulty: None,
+            });
+    }
+
+    #[test]
+    fn check_plagiarism() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let source =
+            "int main() {\n  int a, b;\n  scanf(\"%d%d\", &a, &b);\n  \
+                      printf(\"%d\\n\", a + b);\n  return 0;\n}\n";
+        let mut subs = vec![];
+        for i in 0..3 {
+            let user = site.user(&format!("user{}", i));
+            let part = site.participation(&contest, &user);
+            let sub = site.submission(&task, &part);
+            let dir =
+                crate::create_submission_dir(&crate::config::get(), sub.id);
+            let content = if i < 2 { source } else { "print(input())\n" };
+            std::fs::write(dir.join("file.cpp"), content).unwrap();
+            subs.push(sub);
+        }
+        defer! {{
+            for sub in &subs {
+                std::fs::remove_dir_all(crate::submission_dir(
+                    &crate::config::get(),
+                    sub.id,
+                ))
+                .unwrap();
+            }
+        }}
+        let url = format!("/api/admin/task/{}/plagiarism", task.id);
+        let pairs: Vec<PlagiarismPair> = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&admin)
+            .form(PlagiarismForm { threshold: None });
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].first_submission_id, subs[0].id);
+        assert_eq!(pairs[0].second_submission_id, subs[1].id);
+        assert_eq!(pairs[0].similarity, 1.0);
+        let stored: Vec<PlagiarismPair> =
+            TestRequestBuilder::new(&site, &url).auth(&admin).finish();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].id, pairs[0].id);
+    }
+
+    #[test]
+    fn check_plagiarism_bad_threshold() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        let url = format!("/api/admin/task/{}/plagiarism", task.id);
+        for threshold in vec![-0.5, 1.5] {
+            let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+                .method(Method::POST)
+                .auth(&admin)
+                .status(StatusCode::BAD_REQUEST)
+                .form(PlagiarismForm {
+                    threshold: Some(threshold),
+                });
+            assert_eq!(res.error, "The threshold must be between 0 and 1");
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::sync::Arc;
+
+use actix_web::error::{
+    ErrorInternalServerError, ErrorNotFound, ErrorUnprocessableEntity,
+};
+use actix_web::fs::NamedFile;
+use actix_web::{AsyncResponder, Error, HttpRequest, Json, Path, State};
+use futures::future;
+use futures::future::{result, Future};
+use serde_derive::Deserialize;
+use tempfile::TempDir;
+
+use crate::models::*;
+use crate::web::db::*;
+use crate::web::endpoints::contest::{
+    check_accepting_submissions, receive_files, send_to_evaluator, FileNames,
+};
+use crate::web::endpoints::AsyncJsonResponse;
+use crate::web::extractors::client_info;
+
+#[derive(Deserialize, Debug)]
+pub struct InputID {
+    pub input_id: i32,
+}
+
+/// The fields of the submission of an input.
+fn is_terry_field(name: &str) -> bool {
+    name == "output" || name == "source"
+}
+
+fn check_terry_task(task: &Task) -> Result<(), Error> {
+    match task.format {
+        TaskFormat::Terry => Ok(()),
+        _ => Err(ErrorUnprocessableEntity("Not a Terry task")),
+    }
+}
+
+/// Generate a fresh input of the task for the participation.
+pub fn request_input(
+    state: State<crate::web::State>,
+    contest: Contest,
+    participation: Participation,
+    task: Task,
+) -> AsyncJsonResponse<TerryInput> {
+    if let Err(e) = check_terry_task(&task) {
+        return Box::new(future::err(e));
+    }
+    Box::new(
+        state
+            .db
+            .send(RequestInput {
+                participation_id: participation.id,
+                task,
+                contest_end: contest.end_time,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|i| Json(i))).responder()),
+    )
+}
+
+/// The inputs generated for the participation, the most recent first.
+pub fn get_inputs(
+    state: State<crate::web::State>,
+    participation: Participation,
+    task: Task,
+) -> AsyncJsonResponse<Vec<TerryInput>> {
+    Box::new(
+        state
+            .db
+            .send(GetInputs {
+                participation_id: participation.id,
+                task_id: task.id,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|i| Json(i))).responder()),
+    )
+}
+
+/// Download the file of an input of the participation.
+pub fn download_input(
+    state: State<crate::web::State>,
+    participation: Participation,
+    task: Task,
+    path: Path<InputID>,
+) -> Box<Future<Item = NamedFile, Error = Error>> {
+    let config = state.config.clone();
+    Box::new(
+        state
+            .db
+            .send(GetInput {
+                participation_id: participation.id,
+                task_id: task.id,
+                input_id: path.input_id,
+            })
+            .from_err()
+            .and_then(|res| res)
+            .and_then(|input| {
+                NamedFile::open(crate::terry_input_path(&config, input.id))
+                    .map_err(|_e| ErrorNotFound("No such input"))
+            }),
+    )
+}
+
+/// Submit the output produced for an input, in the `output` field, and the
+/// source that produced it, in the `source` field.
+pub fn submit_output(
+    state: State<crate::web::State>,
+    participation: Participation,
+    task: Task,
+    path: Path<InputID>,
+    req: HttpRequest<crate::web::State>,
+) -> AsyncJsonResponse<Submission> {
+    if let Err(e) = check_terry_task(&task)
+        .and_then(|_| check_accepting_submissions(&state))
+    {
+        return Box::new(future::err(e));
+    }
+    let tempdir = match TempDir::new() {
+        Ok(tempdir) => Arc::new(tempdir),
+        Err(e) => return Box::new(future::err(ErrorInternalServerError(e))),
+    };
+    let user_id = participation.user_id;
+    let client = client_info(&req);
+    let state: crate::web::State = (*state).clone();
+    let db = state.db.clone();
+    let input = state.db.send(GetInput {
+        participation_id: participation.id,
+        task_id: task.id,
+        input_id: path.input_id,
+    });
+    Box::new(
+        input
+            .from_err()
+            .and_then(|res| res)
+            .join(receive_files(
+                &req,
+                tempdir.clone(),
+                FileNames::ByField(is_terry_field),
+            ))
+            .and_then(move |(input, files)| {
+                let has_output = files
+                    .iter()
+                    .any(|f| f.file_stem().map_or(false, |s| s == "output"));
+                if !has_output {
+                    return future::Either::A(future::err(
+                        ErrorUnprocessableEntity("Missing output file"),
+                    ));
+                }
+                future::Either::B(
+                    db.send(SubmitOutput {
+                        input,
+                        files,
+                        tempdir,
+                        client,
+                    })
+                    .from_err()
+                    .and_then(|res| res),
+                )
+            })
+            .and_then(move |sub| {
+                send_to_evaluator(&state, &sub, user_id);
+                Ok(Json(sub))
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use actix_web::http::{Method, StatusCode};
+    use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+    use scopeguard::defer;
+
+    use crate::test_utils::*;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    /// Make the task a Terry task whose generator prints the seed.
+    fn terry_task(site: &FakeSite, task: &Task) -> Task {
+        use crate::schema::tasks::dsl;
+        let managers =
+            crate::task_dir(&crate::config::get(), task.id, task.version)
+                .join("managers");
+        fs::create_dir_all(&managers).unwrap();
+        let generator = managers.join("generator.linux.x86_64");
+        fs::write(&generator, "#!/bin/sh\necho $1\n").unwrap();
+        fs::set_permissions(&generator, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        diesel::update(dsl::tasks.find(task.id))
+            .set(dsl::format.eq(TaskFormat::Terry))
+            .get_result::<Task>(&site.conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn request_input() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let task = terry_task(&site, &task);
+        let root =
+            crate::task_dir(&crate::config::get(), task.id, task.version);
+        defer! {{
+            fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        }}
+        let url = format!("/api/contest/{}/task/{}/input", contest.id, task.id);
+        let input: TerryInput = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .finish();
+        let path = crate::terry_input_path(&crate::config::get(), input.id);
+        defer! {{
+            fs::remove_file(&path).unwrap();
+        }}
+        assert_eq!(input.submission_id, None);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", input.seed)
+        );
+        let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .status(StatusCode::CONFLICT)
+            .finish();
+        assert_eq!(res.error, "An input is already in progress");
+        let inputs: Vec<TerryInput> =
+            TestRequestBuilder::new(&site, &url).auth(&user).finish();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].id, input.id);
+    }
+
+    #[test]
+    fn request_input_after_expiry() {
+        use crate::schema::terry_inputs::dsl;
+
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let task = terry_task(&site, &task);
+        let root =
+            crate::task_dir(&crate::config::get(), task.id, task.version);
+        defer! {{
+            fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        }}
+        let url = format!("/api/contest/{}/task/{}/input", contest.id, task.id);
+        let request = || -> TerryInput {
+            TestRequestBuilder::new(&site, &url)
+                .method(Method::POST)
+                .auth(&user)
+                .finish()
+        };
+        let first = request();
+        diesel::update(dsl::terry_inputs.find(first.id))
+            .set(dsl::expires_at.eq(first.created_at))
+            .execute(&site.conn)
+            .unwrap();
+        let second = request();
+        defer! {{
+            fs::remove_file(crate::terry_input_path(
+                &crate::config::get(),
+                first.id,
+            ))
+            .unwrap();
+            fs::remove_file(crate::terry_input_path(
+                &crate::config::get(),
+                second.id,
+            ))
+            .unwrap();
+        }}
+        assert!(!second.closed);
+        let first = dsl::terry_inputs
+            .find(first.id)
+            .first::<TerryInput>(&site.conn)
+            .unwrap();
+        assert!(first.closed);
+    }
+
+    #[test]
+    fn submit_output() {
+        use crate::schema::terry_inputs::dsl;
+
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let task = terry_task(&site, &task);
+        let root =
+            crate::task_dir(&crate::config::get(), task.id, task.version);
+        defer! {{
+            fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        }}
+        let url = format!("/api/contest/{}/task/{}/input", contest.id, task.id);
+        let input: TerryInput = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .finish();
+        let path = crate::terry_input_path(&crate::config::get(), input.id);
+        assert!(path.exists());
+        let sub: Submission = TestRequestBuilder::new(
+            &site,
+            &format!("{}/{}/submit", url, input.id),
+        )
+        .method(Method::POST)
+        .auth(&user)
+        .multipart(vec![
+            ("output", "my output.txt", &b"42\n"[..]),
+            ("source", "sol.cpp", &b"int main() {}"[..]),
+        ]);
+        assert_eq!(sub.files, vec!["output.txt", "source.cpp"]);
+        let input = dsl::terry_inputs
+            .find(input.id)
+            .first::<TerryInput>(&site.conn)
+            .unwrap();
+        assert_eq!(input.submission_id, Some(sub.id));
+        // the input is generated again from the seed by the evaluation
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn submit_without_input() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let task = terry_task(&site, &task);
+        let root =
+            crate::task_dir(&crate::config::get(), task.id, task.version);
+        defer! {{
+            fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        }}
+        let url =
+            format!("/api/contest/{}/task/{}/submit", contest.id, task.id);
+        let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .multipart(vec![("output", "output.txt", &b"42\n"[..])]);
+        assert_eq!(
+            res.error,
+            "Submit the output of a Terry task through its input"
+        );
+    }
+
+    #[test]
+    fn request_input_not_terry() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let url = format!("/api/contest/{}/task/{}/input", contest.id, task.id);
+        let res: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .auth(&user)
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .finish();
+        assert_eq!(res.error, "Not a Terry task");
+    }
+
+    #[test]
+    fn download_missing_input() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let contest = site.contest("contest");
+        let task = site.task(&contest, "task");
+        site.participation(&contest, &user);
+        let url = format!(
+            "/api/contest/{}/task/{}/input/{}",
+            contest.id, task.id, -1
+        );
+        TestRequestBuilder::new(&site, &url)
+            .auth(&user)
+            .status(StatusCode::NOT_FOUND)
+            .finish::<ErrorResponse>();
+    }
+}
@@ -0,0 +1,202 @@
+use actix_web::{AsyncResponder, Form, Json, Path, State};
+use futures::future::{result, Future};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audit::ClientInfo;
+use crate::models::*;
+use crate::web::db::*;
+use crate::web::endpoints::AsyncJsonResponse;
+use crate::web::extractors::SessionUser;
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTokenForm {
+    pub name: String,
+    pub scope: ApiTokenScope,
+}
+
+/// The API tokens of the user, revoked ones included.
+pub fn get_tokens(
+    state: State<crate::web::State>,
+    user: SessionUser,
+) -> AsyncJsonResponse<Vec<ApiToken>> {
+    Box::new(
+        state
+            .db
+            .send(GetApiTokens { user_id: user.0.id })
+            .from_err()
+            .and_then(|res| result(res.map(|t| Json(t))).responder()),
+    )
+}
+
+/// Create a new API token, the secret is shown only in this response.
+pub fn create_token(
+    state: State<crate::web::State>,
+    user: SessionUser,
+    client: ClientInfo,
+    form: Form<CreateTokenForm>,
+) -> AsyncJsonResponse<CreatedApiToken> {
+    let form = form.into_inner();
+    Box::new(
+        state
+            .db
+            .send(CreateApiToken {
+                site_id: user.0.site_id,
+                user_id: user.0.id,
+                name: form.name,
+                scope: form.scope,
+                client,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|t| Json(t))).responder()),
+    )
+}
+
+pub fn revoke_token(
+    state: State<crate::web::State>,
+    user: SessionUser,
+    client: ClientInfo,
+    token_id: Path<i32>,
+) -> AsyncJsonResponse<ApiToken> {
+    Box::new(
+        state
+            .db
+            .send(RevokeApiToken {
+                site_id: user.0.site_id,
+                user_id: user.0.id,
+                token_id: token_id.into_inner(),
+                client,
+            })
+            .from_err()
+            .and_then(|res| result(res.map(|t| Json(t))).responder()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+
+    use crate::test_utils::*;
+    use crate::web::test_utils::*;
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    fn create(site: &FakeSite, user: &User, scope: ApiTokenScope) -> String {
+        let created: CreatedApiToken =
+            TestRequestBuilder::new(site, "/api/tokens")
+                .method(Method::POST)
+                .auth(user)
+                .form(CreateTokenForm {
+                    name: "editor".to_string(),
+                    scope,
+                });
+        assert_eq!(created.token.user_id, user.id);
+        assert_eq!(created.token.last_used_at, None);
+        format!("Bearer {}", created.secret)
+    }
+
+    #[test]
+    fn bearer() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let contest = site.contest("contest");
+        let bearer = create(&site, &user, ApiTokenScope::Submit);
+        let url = format!("/api/contest/{}/join", contest.id);
+        // no origin check for the requests with an API token
+        TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .header("authorization", &bearer)
+            .header("origin", "https://editor.example.com")
+            .finish::<()>();
+
+        let tokens: Vec<ApiToken> =
+            TestRequestBuilder::new(&site, "/api/tokens")
+                .auth(&user)
+                .finish();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].name, "editor");
+        assert!(tokens[0].last_used_at.is_some());
+    }
+
+    #[test]
+    fn read_only() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let contest = site.contest("contest");
+        let bearer = create(&site, &user, ApiTokenScope::ReadOnly);
+        let url = format!("/api/contest/{}", contest.id);
+        TestRequestBuilder::new(&site, &url)
+            .header("authorization", &bearer)
+            .finish_raw();
+        let url = format!("/api/contest/{}/join", contest.id);
+        let err: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .header("authorization", &bearer)
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Read-only API token");
+    }
+
+    #[test]
+    fn revoke() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let other = site.user("other");
+        let contest = site.contest("contest");
+        let bearer = create(&site, &user, ApiTokenScope::Submit);
+        let tokens: Vec<ApiToken> =
+            TestRequestBuilder::new(&site, "/api/tokens")
+                .auth(&user)
+                .finish();
+        let url = format!("/api/token/{}", tokens[0].id);
+        TestRequestBuilder::new(&site, &url)
+            .method(Method::DELETE)
+            .auth(&other)
+            .status(StatusCode::NOT_FOUND)
+            .finish::<ErrorResponse>();
+        let token: ApiToken = TestRequestBuilder::new(&site, &url)
+            .method(Method::DELETE)
+            .auth(&user)
+            .finish();
+        assert!(token.revoked_at.is_some());
+
+        let url = format!("/api/contest/{}/join", contest.id);
+        let err: ErrorResponse = TestRequestBuilder::new(&site, &url)
+            .method(Method::POST)
+            .header("authorization", &bearer)
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Invalid API token");
+    }
+
+    #[test]
+    fn other_site() {
+        let site = FakeSite::new();
+        let other_site = FakeSite::new();
+        let user = site.user("user");
+        let contest = other_site.contest("contest");
+        let bearer = create(&site, &user, ApiTokenScope::Submit);
+        let url = format!("/api/contest/{}/join", contest.id);
+        let err: ErrorResponse = TestRequestBuilder::new(&other_site, &url)
+            .method(Method::POST)
+            .header("authorization", &bearer)
+            .status(StatusCode::FORBIDDEN)
+            .finish();
+        assert_eq!(err.error, "Invalid API token");
+    }
+
+    #[test]
+    fn session_only() {
+        let site = FakeSite::new();
+        let admin = site.admin("admin");
+        let bearer = create(&site, &admin, ApiTokenScope::Submit);
+        TestRequestBuilder::new(&site, "/api/tokens")
+            .header("authorization", &bearer)
+            .status(StatusCode::FORBIDDEN)
+            .finish::<ErrorResponse>();
+        TestRequestBuilder::new(&site, "/api/admin/audit")
+            .header("authorization", &bearer)
+            .status(StatusCode::FORBIDDEN)
+            .finish::<ErrorResponse>();
+    }
+}
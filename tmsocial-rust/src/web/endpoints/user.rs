@@ -1,19 +1,21 @@
-use actix_web::http::Cookie;
 use actix_web::AsyncResponder;
 use actix_web::Error;
 use actix_web::Form;
 use actix_web::HttpResponse;
 use actix_web::Json;
 use actix_web::{Path, State};
-use futures::future::result;
 use futures::future::Future;
+use futures::future::{self, result};
 use serde_derive::{Deserialize, Serialize};
 
+use crate::audit::ClientInfo;
 use crate::models::*;
 use crate::web::db::user::DoLogin;
+use crate::web::db::user::DoLogout;
 use crate::web::db::user::GetUserByUsername;
 use crate::web::endpoints::AsyncJsonResponse;
-use crate::web::extractors::AUTH_COOKIE;
+use crate::web::extractors::{auth_cookie, SessionUser};
+use crate::web::ratelimit::{check_failures, record_failure, Limited};
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginForm {
@@ -23,24 +25,35 @@ pub struct LoginForm {
 pub fn login(
     state: State<crate::web::State>,
     site: Site,
+    client: ClientInfo,
     form: Form<LoginForm>,
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    // the failed attempts on a username are limited from any address
+    let key = format!("username:{}:{}", site.id, form.username);
+    if let Err(e) = check_failures(&state, Limited::Login, &key) {
+        return Box::new(future::err(e));
+    }
+    let web_state = (*state).clone();
     Box::new(
         state
             .db
             .send(DoLogin {
                 site_id: site.id,
                 username: form.username.clone(),
+                client,
             })
             .from_err()
-            .and_then(|res| {
+            .and_then(move |res| {
+                if let Err(e) = &res {
+                    let status =
+                        e.as_response_error().error_response().status();
+                    if status.is_client_error() {
+                        record_failure(&web_state, Limited::Login, key);
+                    }
+                }
                 result(res.map(|(user, token)| {
                     HttpResponse::Ok()
-                        .cookie(
-                            Cookie::build(AUTH_COOKIE, token)
-                                .path("/")
-                                .finish(),
-                        )
+                        .cookie(auth_cookie(&site, token))
                         .content_type("application/json")
                         .body(
                             serde_json::to_string(&user)
@@ -52,6 +65,34 @@ pub fn login(
     )
 }
 
+/// Invalidate the login token of the user, logging out all their sessions,
+/// and clear the auth cookie.
+pub fn logout(
+    state: State<crate::web::State>,
+    site: Site,
+    user: SessionUser,
+    client: ClientInfo,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    Box::new(
+        state
+            .db
+            .send(DoLogout {
+                site_id: site.id,
+                user_id: user.0.id,
+                client,
+            })
+            .from_err()
+            .and_then(move |res| {
+                result(res.map(|_| {
+                    HttpResponse::Ok()
+                        .del_cookie(&auth_cookie(&site, String::new()))
+                        .json(())
+                }))
+                .responder()
+            }),
+    )
+}
+
 pub fn get_user(
     state: State<crate::web::State>,
     site: Site,
@@ -73,6 +114,7 @@ pub fn get_user(
 mod tests {
     use actix_web::client::ClientResponse;
     use actix_web::http::StatusCode;
+    use cookie::SameSite;
     use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
 
     use crate::models::*;
@@ -101,6 +143,47 @@ mod tests {
         assert_eq!(u.login_token, None);
     }
 
+    #[test]
+    fn login_cookie_attributes() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let (_, res): (User, ClientResponse) =
+            TestRequestBuilder::new(&site, "/api/login")
+                .method(actix_web::http::Method::POST)
+                .form_with_response(LoginForm {
+                    username: user.username,
+                });
+        let cookie = res.cookie(AUTH_COOKIE).expect("Cookie not set");
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+    }
+
+    #[test]
+    fn logout() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let (_, res): ((), ClientResponse) =
+            TestRequestBuilder::new(&site, "/api/logout")
+                .method(actix_web::http::Method::POST)
+                .auth(&user)
+                .finish_with_response();
+        let cookie = res.cookie(AUTH_COOKIE).expect("Cookie not cleared");
+        assert_eq!(cookie.value(), "");
+        let login_token = crate::schema::users::dsl::users
+            .find(&user.id)
+            .select(crate::schema::users::dsl::login_token)
+            .first::<Option<String>>(&site.conn)
+            .expect("Where is the user?");
+        assert_eq!(login_token, None);
+        // the old token is not valid anymore
+        TestRequestBuilder::new(&site, "/api/logout")
+            .method(actix_web::http::Method::POST)
+            .auth(&user)
+            .status(StatusCode::FORBIDDEN)
+            .finish_raw();
+    }
+
     #[test]
     fn login_new_user() {
         let site = FakeSite::new();
@@ -1,19 +1,26 @@
 use std::fmt::Display;
+use std::net::IpAddr;
 
 use actix_web::error::{ErrorBadRequest, ErrorForbidden, ResponseError};
-use actix_web::http::header::HOST;
+use actix_web::http::header::{AUTHORIZATION, HOST, USER_AGENT};
 use actix_web::http::Cookie;
 use actix_web::http::StatusCode;
 use actix_web::{Error, FromRequest, HttpRequest, HttpResponse, Path};
+use cookie::SameSite;
 use failure::Fail;
 use futures::{future, Future};
 use log::warn;
 use serde_derive::Deserialize;
 
+use crate::audit::ClientInfo;
 use crate::models::*;
 use crate::web::db::user::GetUserByToken;
 
-use super::db::{GetContest, GetParticipation, GetSite, GetTask};
+use super::csrf::is_safe_method;
+use super::db::{
+    GetArchivedTask, GetContest, GetParticipation, GetSite, GetTask,
+    GetUserByApiToken,
+};
 use super::State;
 use crate::web::db::submission::GetSubmission;
 use crate::web::db::submission::GetSubmissionResult;
@@ -41,6 +48,21 @@ impl FromRequest<State> for Site {
 
 pub const AUTH_COOKIE: &'static str = "auth";
 
+/// The auth cookie holding the login token, with the attributes chosen by the
+/// site. It is never readable by the scripts of the page.
+pub fn auth_cookie(site: &Site, token: String) -> Cookie<'static> {
+    Cookie::build(AUTH_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .secure(site.cookie_secure)
+        .same_site(match site.cookie_same_site {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        })
+        .finish()
+}
+
 #[derive(Debug)]
 struct ForbiddenResetCookie;
 
@@ -69,6 +91,20 @@ impl FromRequest<State> for User {
         req: &HttpRequest<State>,
         _cfg: &Self::Config,
     ) -> Self::Result {
+        // an API token takes precedence over the cookie of the browser
+        if let Some(token) = get_bearer_token(req) {
+            let db = req.state().db.clone();
+            let write = !is_safe_method(req.method());
+            return Box::new(Site::extract(req).and_then(move |site| {
+                db.send(GetUserByApiToken {
+                    site_id: site.id,
+                    token,
+                    write,
+                })
+                .from_err()
+                .and_then(|res| res)
+            }));
+        }
         match get_auth_token(req) {
             Some(token) => Box::new(
                 req.state()
@@ -83,6 +119,49 @@ impl FromRequest<State> for User {
     }
 }
 
+/// A user logged in with the auth cookie: API tokens are not accepted, so
+/// that a leaked token cannot be used to manage the account.
+pub struct SessionUser(pub User);
+
+impl FromRequest<State> for SessionUser {
+    type Config = ();
+    type Result = Box<Future<Item = Self, Error = Error>>;
+    fn from_request(
+        req: &HttpRequest<State>,
+        _cfg: &Self::Config,
+    ) -> Self::Result {
+        if get_bearer_token(req).is_some() {
+            return Box::new(future::err(ErrorForbidden(
+                "API tokens are not accepted here",
+            )));
+        }
+        Box::new(User::extract(req).map(SessionUser))
+    }
+}
+
+/// A logged in user that is an administrator of the current site.
+pub struct Admin(pub User);
+
+impl FromRequest<State> for Admin {
+    type Config = ();
+    type Result = Box<Future<Item = Self, Error = Error>>;
+    fn from_request(
+        req: &HttpRequest<State>,
+        _cfg: &Self::Config,
+    ) -> Self::Result {
+        let user = SessionUser::extract(req);
+        let site = Site::extract(req);
+        Box::new(user.join(site).and_then(|(SessionUser(user), site)| {
+            if user.is_admin && user.site_id == site.id {
+                Ok(Admin(user))
+            } else {
+                warn!("User {} is not an admin of site {}", user.id, site.id);
+                Err(ErrorForbidden("Not an admin"))
+            }
+        }))
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct ContestID {
     pub contest_id: i32,
@@ -119,32 +198,15 @@ impl FromRequest<State> for Participation {
         let contest_id = Path::<ContestID>::extract(req)
             .expect("Asking for contest on a path with no contest_id param!")
             .contest_id;
-        let token = match get_auth_token(req) {
-            Some(token) => token,
-            None => {
-                return Box::new(future::err(Error::from(
-                    ForbiddenResetCookie {},
-                )))
-            }
-        };
         let db = req.state().db.clone();
-        let user = db.send(GetUserByToken { login_token: token });
-        let participation = user
-            .map_err(|x| x.into())
-            .and_then(|res| match res {
-                Ok(r) => future::done(Ok(r)),
-                Err(_) => {
-                    future::done(Err(Error::from(ForbiddenResetCookie {})))
-                }
+        let participation = User::extract(req).and_then(move |user| {
+            db.send(GetParticipation {
+                user_id: user.id,
+                contest_id: contest_id,
             })
-            .and_then(move |user| {
-                db.send(GetParticipation {
-                    user_id: user.id,
-                    contest_id: contest_id,
-                })
-                .from_err()
-                .and_then(|res| res)
-            });
+            .from_err()
+            .and_then(|res| res)
+        });
         Box::new(participation)
     }
 }
@@ -180,6 +242,33 @@ impl FromRequest<State> for Task {
     }
 }
 
+/// A task published in the archive of the current site.
+pub struct ArchivedTask(pub Task);
+
+impl FromRequest<State> for ArchivedTask {
+    type Config = ();
+    type Result = Box<Future<Item = Self, Error = Error>>;
+    fn from_request(
+        req: &HttpRequest<State>,
+        _cfg: &Self::Config,
+    ) -> Self::Result {
+        let task_id = Path::<TaskID>::extract(req)
+            .expect("Asking for task on a path with no task_id param!")
+            .task_id;
+        let db = req.state().db.clone();
+        let task = Site::extract(req).and_then(move |site| {
+            db.send(GetArchivedTask {
+                task_id: task_id,
+                site_id: site.id,
+            })
+            .from_err()
+            .and_then(|res| res)
+            .map(ArchivedTask)
+        });
+        Box::new(task)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct SubmissionID {
     pub submission_id: i32,
@@ -228,6 +317,48 @@ impl FromRequest<State> for GetSubmissionResult {
     }
 }
 
+impl FromRequest<State> for ClientInfo {
+    type Config = ();
+    type Result = Self;
+    fn from_request(req: &HttpRequest<State>, _cfg: &Self::Config) -> Self {
+        client_info(req)
+    }
+}
+
+/// Header with the addresses of the client and of the proxies in between.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The address of the client: the peer of the connection, or the address it
+/// forwarded if it's a trusted proxy.
+pub fn client_ip(req: &HttpRequest<State>) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    if !req.state().config.web.trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+    // the proxy appends the address it got the request from, the ones before
+    // are sent by the client
+    let forwarded = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|ip| ip.trim().parse().ok());
+    Some(forwarded.unwrap_or(peer))
+}
+
+/// The address and the user agent of the client.
+pub fn client_info(req: &HttpRequest<State>) -> ClientInfo {
+    let ip = client_ip(req).map(|ip| ip.to_string());
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(|ua| ua.to_string());
+    ClientInfo { ip, user_agent }
+}
+
 /// Extract the current host from the request, will fail if no Host is given
 /// or if it's invalid.
 fn get_current_host(
@@ -245,6 +376,20 @@ fn get_current_host(
 }
 
 /// Extract the auth token from the cookies of the request.
-fn get_auth_token(req: &HttpRequest<State>) -> Option<String> {
+pub fn get_auth_token(req: &HttpRequest<State>) -> Option<String> {
     req.cookie(AUTH_COOKIE).map(|c| c.value().to_string())
 }
+
+/// Extract the API token from the `Authorization: Bearer` header.
+pub fn get_bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = header.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token))
+            if scheme.eq_ignore_ascii_case("bearer") =>
+        {
+            Some(token.trim().to_string())
+        }
+        _ => None,
+    }
+}
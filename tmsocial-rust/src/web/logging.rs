@@ -0,0 +1,158 @@
+use std::time::Instant;
+
+use actix_web::http::header::HeaderValue;
+use actix_web::middleware::{Finished, Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use log::info;
+
+use crate::logging::{new_request_id, scope, set_request_id, LogContext};
+use crate::metrics::seconds;
+
+/// Header with the id of the request, taken from the request if the proxy
+/// already set it and sent back with the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The id of the request, kept in its extensions.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// When the request was received, kept in its extensions.
+struct RequestStart(Instant);
+
+/// Middleware giving an id to each request, tagging with it the log lines
+/// written while answering, and logging the answered requests.
+pub struct RequestLogger;
+
+/// Whether the request id sent by the client can be trusted in the logs.
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+impl<S> Middleware<S> for RequestLogger {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| valid_request_id(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(new_request_id);
+        set_request_id(Some(request_id.clone()));
+        req.extensions_mut().insert(RequestId(request_id));
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+        Ok(Started::Done)
+    }
+
+    fn response(
+        &self,
+        req: &HttpRequest<S>,
+        mut resp: HttpResponse,
+    ) -> Result<Response> {
+        if let Some(id) = req.extensions().get::<RequestId>() {
+            if let Ok(value) = HeaderValue::from_str(&id.0) {
+                resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+        }
+        Ok(Response::Done(resp))
+    }
+
+    fn finish(&self, req: &HttpRequest<S>, resp: &HttpResponse) -> Finished {
+        let extensions = req.extensions();
+        let _context = scope(LogContext {
+            request_id: extensions.get::<RequestId>().map(|id| id.0.clone()),
+            ..Default::default()
+        });
+        let elapsed = extensions
+            .get::<RequestStart>()
+            .map(|start| seconds(start.0.elapsed()) * 1000.0)
+            .unwrap_or(0.0);
+        info!(
+            "{} {} {} {:.3}ms",
+            req.method(),
+            req.path(),
+            resp.status().as_u16(),
+            elapsed
+        );
+        Finished::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, Once};
+
+    use actix_web::http::StatusCode;
+    use lazy_static::lazy_static;
+    use log::{Log, Metadata, Record};
+
+    use super::*;
+    use crate::logging::current_context;
+    use crate::test_utils::FakeSite;
+    use crate::web::test_utils::TestRequestBuilder;
+    use crate::web::ErrorResponse;
+
+    lazy_static! {
+        static ref LINES: Mutex<Vec<(String, LogContext)>> = Mutex::new(vec![]);
+    }
+
+    /// Logger keeping the lines with their context in LINES.
+    struct CapturingLogger;
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let line = (record.args().to_string(), current_context());
+            LINES.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_max_level(log::LevelFilter::Trace);
+            log::set_boxed_logger(Box::new(CapturingLogger))
+                .expect("Logger already set");
+        });
+    }
+
+    #[test]
+    fn request_id_in_handler_logs() {
+        capture_logs();
+        let site = FakeSite::new();
+        let user = site.user("username");
+        TestRequestBuilder::new(&site, "/api/admin/audit")
+            .auth(&user)
+            .header(REQUEST_ID_HEADER, "handler-log-test")
+            .status(StatusCode::FORBIDDEN)
+            .finish::<ErrorResponse>();
+        let message = format!("User {} is not an admin", user.id);
+        let lines = LINES.lock().unwrap();
+        let (_, context) = lines
+            .iter()
+            .find(|(line, _)| line.starts_with(&message))
+            .expect("The handler did not log");
+        assert_eq!(context.request_id, Some("handler-log-test".to_string()));
+    }
+
+    #[test]
+    fn request_id_header() {
+        let site = FakeSite::new();
+        let (_, response) = TestRequestBuilder::new(&site, "/healthz")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .finish_raw();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+
+        let (_, response) = TestRequestBuilder::new(&site, "/healthz")
+            .header(REQUEST_ID_HEADER, "not valid!")
+            .finish_raw();
+        let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(id.len(), 16);
+    }
+}
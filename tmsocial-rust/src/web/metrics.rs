@@ -0,0 +1,116 @@
+use std::time::Instant;
+
+use actix_web::error::{ErrorForbidden, ErrorUnauthorized};
+use actix_web::middleware::{Finished, Middleware, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+
+use crate::metrics::{
+    observe_pool, render, seconds, HTTP_REQUESTS, HTTP_REQUEST_DURATION,
+};
+use crate::web::extractors::get_bearer_token;
+
+/// When the request was received, kept in its extensions.
+struct RequestStart(Instant);
+
+/// Middleware counting the requests by route and status, and timing them.
+pub struct RequestMetrics;
+
+impl<S> Middleware<S> for RequestMetrics {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+        Ok(Started::Done)
+    }
+
+    fn finish(&self, req: &HttpRequest<S>, resp: &HttpResponse) -> Finished {
+        // the pattern of the resource, to keep the number of labels bounded
+        let route = req
+            .resource()
+            .rdef()
+            .map(|rdef| rdef.pattern().to_string())
+            .unwrap_or_else(|| "other".to_string());
+        HTTP_REQUESTS
+            .with_label_values(&[
+                &route,
+                req.method().as_str(),
+                resp.status().as_str(),
+            ])
+            .inc();
+        if let Some(start) = req.extensions().get::<RequestStart>() {
+            HTTP_REQUEST_DURATION
+                .with_label_values(&[&route])
+                .observe(seconds(start.0.elapsed()));
+        }
+        Finished::Done
+    }
+}
+
+/// The metrics of the server, in the Prometheus text format. They require
+/// the token of the configuration, if any, or a local client.
+pub fn get_metrics(req: HttpRequest<super::State>) -> Result<HttpResponse> {
+    let state = req.state();
+    match &state.config.web.metrics_token {
+        Some(token) => {
+            if get_bearer_token(&req).as_ref() != Some(token) {
+                return Err(ErrorUnauthorized("Invalid metrics token"));
+            }
+        }
+        None => {
+            // the peer of the connection, not the address forwarded by it
+            let local = req.peer_addr().map_or(false, |a| a.ip().is_loopback());
+            if !local {
+                return Err(ErrorForbidden("The metrics are only local"));
+            }
+        }
+    }
+    observe_pool(&state.pool.stats());
+    let (content_type, body) = render();
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::header::CONTENT_TYPE;
+    use actix_web::http::StatusCode;
+
+    use crate::test_utils::FakeSite;
+    use crate::web::test_utils::TestRequestBuilder;
+    use crate::web::ErrorResponse;
+
+    #[test]
+    fn get_metrics() {
+        let site = FakeSite::new();
+        TestRequestBuilder::new(&site, "/api/contests")
+            .finish::<serde_json::Value>();
+        let (body, response) =
+            TestRequestBuilder::new(&site, "/metrics").finish_raw();
+        let body = String::from_utf8(body).unwrap();
+        assert!(response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        assert!(body.contains("tmsocial_db_query_duration_seconds_count"));
+        assert!(body.contains("tmsocial_db_pool_connections"));
+        assert!(body.contains("tmsocial_evaluation_queue_length"));
+    }
+
+    #[test]
+    fn get_metrics_with_token() {
+        let site = FakeSite::new();
+        let mut config = (*crate::config::get()).clone();
+        config.web.metrics_token = Some("secret".to_string());
+        let state = crate::web::State::new(Arc::new(config));
+        let res: ErrorResponse = TestRequestBuilder::new(&site, "/metrics")
+            .state(state.clone())
+            .status(StatusCode::UNAUTHORIZED)
+            .finish();
+        assert_eq!(res.error, "Invalid metrics token");
+        let (body, _) = TestRequestBuilder::new(&site, "/metrics")
+            .state(state)
+            .header("Authorization", "Bearer secret")
+            .finish_raw();
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("tmsocial_db_pool_connections"));
+    }
+}
@@ -1,7 +1,6 @@
 extern crate listenfd;
 
 use std::collections::HashSet;
-use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -9,71 +8,119 @@ use std::time::Duration;
 use actix::{Actor, Addr, Arbiter, AsyncContext, Recipient, SyncArbiter};
 use actix_web::http::header::HeaderValue;
 use actix_web::middleware::{ErrorHandlers, Response};
-use actix_web::{
-    fs, http, middleware, server, App, HttpRequest, HttpResponse, Result,
-};
+use actix_web::Body;
+use actix_web::{fs, http, server, App, HttpRequest, HttpResponse, Result};
 use failure::Error;
 use listenfd::ListenFd;
+use log::info;
 use serde_derive::{Deserialize, Serialize};
 
+use crate::config::Config;
+use crate::pool::DbPool;
+use crate::shutdown::Shutdown;
+
+use self::ratelimit::{Limited, RateLimitMiddleware};
+
+mod csrf;
 mod db;
 mod endpoints;
 mod extractors;
+mod logging;
+mod metrics;
+mod ratelimit;
+mod shutdown;
 mod ws;
 
 #[derive(Clone)]
 pub struct State {
-    db: Addr<db::Executor>,
+    config: Arc<Config>,
+    pool: DbPool,
+    db: db::Db,
+    /// An executor of its own for the slow checks, like the plagiarism ones,
+    /// which would otherwise hold the executors of the requests.
+    checks_db: db::Db,
     event_manager: Addr<super::events::EventManager>,
     evaluator: Addr<crate::evaluation::Evaluator>,
+    shutdown: Shutdown,
+    in_evaluation: Arc<Mutex<HashSet<i32>>>,
+    rate_limiter: Arc<ratelimit::RateLimiter>,
 }
 
 impl State {
-    pub fn new() -> State {
-        let db_addr = SyncArbiter::start(3, || {
-            db::Executor::new(crate::establish_connection())
-        });
+    pub fn new(config: Arc<Config>) -> State {
+        let pool = crate::pool::establish_pool(&config.database);
+        let db =
+            db::Db::start(config.web.db_workers, pool.clone(), config.clone());
+        let checks_db = db::Db::start(1, pool.clone(), config.clone());
+        let history = config.events_history();
         let event_manager =
-            Arbiter::start(|_| super::events::EventManager::new());
+            Arbiter::start(move |_| super::events::EventManager::new(history));
+        let shutdown = Shutdown::new();
         let in_evaluation = Arc::new(Mutex::new(HashSet::<i32>::new()));
-        let evaluator_addr = SyncArbiter::start(3, move || {
-            crate::evaluation::Evaluator::new(
-                crate::establish_connection(),
-                Arc::clone(&in_evaluation),
-            )
-        });
-        let check_pending =
-            crate::evaluation::CheckPending(evaluator_addr.clone()).start();
+        let evaluator_pool = pool.clone();
+        let evaluator_config = config.clone();
+        let evaluator_shutdown = shutdown.clone();
+        let evaluator_in_evaluation = in_evaluation.clone();
+        let evaluator_addr =
+            SyncArbiter::start(config.evaluation.workers, move || {
+                crate::evaluation::Evaluator::new(
+                    evaluator_pool.clone(),
+                    evaluator_config.clone(),
+                    evaluator_shutdown.clone(),
+                    Arc::clone(&evaluator_in_evaluation),
+                )
+            });
+        let check_pending = crate::evaluation::CheckPending(
+            evaluator_addr.clone(),
+            pool.clone(),
+        )
+        .start();
         let periodic_evaluator = PeriodicEvaluator {
+            interval: config.evaluation_check_interval(),
             check_pending: check_pending,
+            shutdown: shutdown.clone(),
             event_manager: event_manager.clone().recipient(),
+            contest_manager: event_manager.clone().recipient(),
         };
         periodic_evaluator.start();
         State {
-            db: db_addr.clone(),
+            config: config,
+            pool: pool,
+            db: db,
+            checks_db: checks_db,
             event_manager: event_manager.clone(),
             evaluator: evaluator_addr.clone(),
+            shutdown: shutdown,
+            in_evaluation: in_evaluation,
+            rate_limiter: Arc::new(ratelimit::RateLimiter::default()),
         }
     }
 }
 
 struct PeriodicEvaluator {
+    interval: Duration,
     check_pending: Addr<crate::evaluation::CheckPending>,
+    shutdown: Shutdown,
     event_manager: Recipient<super::events::SubmissionUpdate>,
+    contest_manager: Recipient<super::events::ContestUpdate>,
 }
 
-const EVALUATION_CHECK_INTERVAL: Duration = Duration::from_secs(20);
-
 impl Actor for PeriodicEvaluator {
     type Context = actix::Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
         let check_pending = self.check_pending.clone();
         let event_manager = self.event_manager.clone();
-        ctx.run_interval(EVALUATION_CHECK_INTERVAL, move |_, _| {
-            check_pending.do_send(crate::evaluation::EvaluatePending(
-                event_manager.clone(),
-            ))
+        let contest_manager = self.contest_manager.clone();
+        let shutdown = self.shutdown.clone();
+        ctx.run_interval(self.interval, move |_, _| {
+            if shutdown.is_started() {
+                return;
+            }
+            check_pending.do_send(crate::evaluation::EvaluatePending {
+                notify: event_manager.clone(),
+                contest_notify: contest_manager.clone(),
+            })
         });
     }
 }
@@ -87,6 +134,10 @@ fn render_error<S>(
     _: &HttpRequest<S>,
     mut resp: HttpResponse,
 ) -> Result<Response> {
+    // responses with a body of their own, like the readiness checks
+    if let (None, Body::Binary(_)) = (resp.error(), resp.body()) {
+        return Ok(Response::Done(resp));
+    }
     let error = resp
         .error()
         .map(|e| e.to_string())
@@ -106,21 +157,59 @@ fn create_app(web_root: &PathBuf, state: State) -> App<State> {
         http::StatusCode::UNAUTHORIZED,
         http::StatusCode::FORBIDDEN,
         http::StatusCode::NOT_FOUND,
+        http::StatusCode::CONFLICT,
         http::StatusCode::UNPROCESSABLE_ENTITY,
+        http::StatusCode::TOO_MANY_REQUESTS,
         http::StatusCode::INTERNAL_SERVER_ERROR,
+        http::StatusCode::BAD_GATEWAY,
+        http::StatusCode::SERVICE_UNAVAILABLE,
     ];
 
-    let mut app =
-        App::with_state(state).middleware(middleware::Logger::default());
+    let mut app = App::with_state(state)
+        .middleware(logging::RequestLogger)
+        .middleware(metrics::RequestMetrics);
     for error_code in error_codes {
         app = app
             .middleware(ErrorHandlers::new().handler(error_code, render_error));
     }
+    // after the error handlers, which render its errors
+    app = app.middleware(csrf::OriginCheck);
 
     app.resource("/api/login", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Login));
         r.method(http::Method::POST).with(endpoints::user::login)
     })
+    .resource("/api/logout", |r| {
+        r.method(http::Method::POST).with(endpoints::user::logout)
+    })
+    .resource("/api/oidc/providers", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::oidc::get_providers)
+    })
+    .resource("/api/oidc/{provider}/login", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Login));
+        r.method(http::Method::GET).with(endpoints::oidc::login)
+    })
+    .resource("/api/oidc/{provider}/link", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Login));
+        r.method(http::Method::POST).with(endpoints::oidc::link)
+    })
+    .resource("/api/oidc/{provider}/callback", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Login));
+        r.method(http::Method::GET).with(endpoints::oidc::callback)
+    })
+    .resource("/api/tokens", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::token::get_tokens);
+        r.method(http::Method::POST)
+            .with(endpoints::token::create_token)
+    })
+    .resource("/api/token/{token_id}", |r| {
+        r.method(http::Method::DELETE)
+            .with(endpoints::token::revoke_token)
+    })
     .resource("/api/user/{username}", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Lookup));
         r.method(http::Method::GET).with(endpoints::user::get_user)
     })
     .resource("/api/events", |r| r.with(ws::events_handler))
@@ -145,6 +234,7 @@ fn create_app(web_root: &PathBuf, state: State) -> App<State> {
             .with(endpoints::contest::get_task)
     })
     .resource("/api/contest/{contest_id}/task/{task_id}/submit", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Submit));
         r.method(http::Method::POST)
             .with(endpoints::contest::submit)
     })
@@ -152,6 +242,32 @@ fn create_app(web_root: &PathBuf, state: State) -> App<State> {
         "/api/contest/{contest_id}/task/{task_id}/assets",
         endpoints::contest::handle_task_assets,
     )
+    .resource("/api/contest/{contest_id}/task/{task_id}/input", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Submit));
+        r.method(http::Method::GET)
+            .with(endpoints::terry::get_inputs);
+        r.method(http::Method::POST)
+            .with(endpoints::terry::request_input)
+    })
+    .resource(
+        "/api/contest/{contest_id}/task/{task_id}/input/{input_id}",
+        |r| {
+            r.method(http::Method::GET)
+                .with(endpoints::terry::download_input)
+        },
+    )
+    .resource(
+        "/api/contest/{contest_id}/task/{task_id}/input/{input_id}/submit",
+        |r| {
+            r.middleware(RateLimitMiddleware(Limited::Submit));
+            r.method(http::Method::POST)
+                .with(endpoints::terry::submit_output)
+        },
+    )
+    .resource("/api/contest/{contest_id}/task/{task_id}/metadata", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::contest::get_task_metadata)
+    })
     .resource(
         "/api/contest/{contest_id}/task/{task_id}/submissions",
         |r| {
@@ -166,6 +282,112 @@ fn create_app(web_root: &PathBuf, state: State) -> App<State> {
                 .with(endpoints::contest::get_submission)
         },
     )
+    .resource(
+        "/api/contest/{contest_id}/task/{task_id}/submission/{submission_id}/files",
+        |r| {
+            r.method(http::Method::GET)
+                .with(endpoints::contest::get_submission_zip)
+        },
+    )
+    .resource(
+        "/api/contest/{contest_id}/task/{task_id}/submission/{submission_id}/files/{name}",
+        |r| {
+            r.method(http::Method::GET)
+                .with(endpoints::contest::get_submission_file)
+        },
+    )
+    .resource("/api/contest/{contest_id}/task/{task_id}/diff", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::submission::diff)
+    })
+    .resource("/api/contest/{contest_id}/scoreboard", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::contest::get_scoreboard)
+    })
+    .resource("/api/contest/{contest_id}/scoreboard/unfrozen", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::contest::get_unfrozen_scoreboard)
+    })
+    .resource("/api/archive", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::archive::get_archive)
+    })
+    .resource("/api/archive/task/{task_id}", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::archive::get_archived_task)
+    })
+    .resource("/api/archive/task/{task_id}/publish", |r| {
+        r.method(http::Method::POST)
+            .with(endpoints::archive::publish_task)
+    })
+    .resource("/api/archive/task/{task_id}/submit", |r| {
+        r.middleware(RateLimitMiddleware(Limited::Submit));
+        r.method(http::Method::POST)
+            .with(endpoints::archive::submit)
+    })
+    .handler(
+        "/api/archive/task/{task_id}/assets",
+        endpoints::archive::handle_archived_task_assets,
+    )
+    .resource("/api/archive/task/{task_id}/submissions", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::archive::get_submissions)
+    })
+    .resource(
+        "/api/archive/task/{task_id}/submission/{submission_id}",
+        |r| {
+            r.method(http::Method::GET)
+                .with(endpoints::archive::get_submission)
+        },
+    )
+    .resource(
+        "/api/archive/task/{task_id}/submission/{submission_id}/files",
+        |r| {
+            r.method(http::Method::GET)
+                .with(endpoints::archive::get_submission_zip)
+        },
+    )
+    .resource(
+        "/api/archive/task/{task_id}/submission/{submission_id}/files/{name}",
+        |r| {
+            r.method(http::Method::GET)
+                .with(endpoints::archive::get_submission_file)
+        },
+    )
+    .resource("/api/search", |r| {
+        r.method(http::Method::GET).with(endpoints::task::search)
+    })
+    .resource("/api/admin/task/{task_id}", |r| {
+        r.method(http::Method::POST)
+            .with(endpoints::task::update_task)
+    })
+    .resource("/api/admin/task/{task_id}/plagiarism", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::task::get_plagiarism);
+        r.method(http::Method::POST)
+            .with(endpoints::task::check_plagiarism)
+    })
+    .resource("/api/admin/audit", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::audit::get_audit_log)
+    })
+    .resource("/api/admin/submission/{submission_id}/files", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::submission::get_zip)
+    })
+    .resource("/api/admin/submission/{submission_id}/files/{name}", |r| {
+        r.method(http::Method::GET)
+            .with(endpoints::submission::get_file)
+    })
+    .resource("/healthz", |r| {
+        r.method(http::Method::GET).with(endpoints::health::healthz)
+    })
+    .resource("/readyz", |r| {
+        r.method(http::Method::GET).with(endpoints::health::readyz)
+    })
+    .resource("/metrics", |r| {
+        r.method(http::Method::GET).with(metrics::get_metrics)
+    })
     .handler("/api/assets", endpoints::site::handle_site_assets)
     .handler(
         "/",
@@ -175,25 +397,37 @@ fn create_app(web_root: &PathBuf, state: State) -> App<State> {
     )
 }
 
-pub fn web_main(
-    web_root: PathBuf,
-    addr: IpAddr,
-    port: u16,
-) -> Result<(), Error> {
+pub fn web_main(config: Arc<Config>) -> Result<(), Error> {
     let mut listenfd = ListenFd::from_env();
     let sys = actix::System::new("tmsocial");
 
-    let state = State::new();
+    let web_root = config.web.root.clone();
+    let (addr, port) = (config.web.address, config.web.port);
+    let shutdown_timeout = config.shutdown_timeout();
+    let state = State::new(config);
+    let shutdown = state.shutdown.clone();
+    let in_evaluation = state.in_evaluation.clone();
+    let event_manager = state.event_manager.clone();
+    // the signals are handled by the ShutdownManager
     let mut server =
-        server::new(move || create_app(&web_root, state.clone()).finish());
+        server::new(move || create_app(&web_root, state.clone()).finish())
+            .disable_signals();
 
     server = if let Some(lfd) = listenfd.take_tcp_listener(0)? {
         server.listen(lfd)
     } else {
         server.bind((addr, port))?
     };
-    server.start();
-    println!("Started tmsocial");
+    let server = server.start();
+    shutdown::ShutdownManager::new(
+        shutdown,
+        in_evaluation,
+        event_manager,
+        server,
+        shutdown_timeout,
+    )
+    .start();
+    info!("Started tmsocial");
     let _ = sys.run();
     Ok(())
 }
@@ -221,6 +455,9 @@ pub mod test_utils {
         pub method: http::Method,
         pub status: http::StatusCode,
         pub login_token: Option<String>,
+        pub headers: Vec<(&'static str, String)>,
+        /// The state of the server, a new one if missing.
+        pub state: Option<super::State>,
     }
 
     impl<'a, 'b> TestRequestBuilder<'a, 'b> {
@@ -234,6 +471,8 @@ pub mod test_utils {
                 method: http::Method::GET,
                 status: http::StatusCode::OK,
                 login_token: None,
+                headers: vec![],
+                state: None,
             }
         }
 
@@ -254,6 +493,18 @@ pub mod test_utils {
             TestRequestBuilder { status, ..self }
         }
 
+        pub fn state(self: Self, state: super::State) -> Self {
+            TestRequestBuilder {
+                state: Some(state),
+                ..self
+            }
+        }
+
+        pub fn header(mut self: Self, name: &'static str, value: &str) -> Self {
+            self.headers.push((name, value.to_string()));
+            self
+        }
+
         pub fn finish<T>(self: Self) -> T
         where
             T: serde::de::DeserializeOwned,
@@ -265,13 +516,14 @@ pub mod test_utils {
         where
             T: serde::de::DeserializeOwned,
         {
-            let mut srv = get_test_server();
+            let mut srv = get_test_server(&self.state);
             let mut request = fake_request(
                 &srv,
                 self.site,
                 self.method,
                 self.path,
                 self.login_token,
+                self.headers,
             );
             let request = request.finish().unwrap();
             let response = fake_response(&mut srv, request);
@@ -281,6 +533,101 @@ pub mod test_utils {
             (get_json_body(&response), response)
         }
 
+        /// Send the request returning the raw body of the response.
+        pub fn finish_raw(self: Self) -> (Vec<u8>, ClientResponse) {
+            let mut srv = get_test_server(&self.state);
+            let mut request = fake_request(
+                &srv,
+                self.site,
+                self.method,
+                self.path,
+                self.login_token,
+                self.headers,
+            );
+            let request = request.finish().unwrap();
+            let response = fake_response(&mut srv, request);
+            // will be printed only on errors
+            println!("The response was: {:?}", response);
+            assert_eq!(response.status(), self.status);
+            let body = response
+                .body()
+                .limit(16 * 1024 * 1024)
+                .wait()
+                .expect("Missing response body");
+            (body.to_vec(), response)
+        }
+
+        /// Send the form `times` times to the same server, returning the
+        /// responses without checking their status.
+        pub fn repeat_form<F>(
+            self: Self,
+            form: F,
+            times: usize,
+        ) -> Vec<ClientResponse>
+        where
+            F: serde::Serialize,
+        {
+            let mut srv = get_test_server(&self.state);
+            (0..times)
+                .map(|_| {
+                    let mut request = fake_request(
+                        &srv,
+                        self.site,
+                        self.method.clone(),
+                        self.path,
+                        self.login_token.clone(),
+                        self.headers.clone(),
+                    );
+                    let request = request.form(&form).unwrap();
+                    fake_response(&mut srv, request)
+                })
+                .collect()
+        }
+
+        /// Send a multipart form with the given files, each one as
+        /// `(field, filename, content)`.
+        pub fn multipart<T>(self: Self, files: Vec<(&str, &str, &[u8])>) -> T
+        where
+            T: serde::de::DeserializeOwned,
+        {
+            let boundary = "tmsocial-test-boundary";
+            let mut body = vec![];
+            for (field, filename, content) in files {
+                body.extend_from_slice(
+                    format!(
+                        "--{}\r\nContent-Disposition: form-data; \
+                         name=\"{}\"; filename=\"{}\"\r\n\
+                         Content-Type: application/octet-stream\r\n\r\n",
+                        boundary, field, filename
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(content);
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+            let mut srv = get_test_server(&self.state);
+            let mut request = fake_request(
+                &srv,
+                self.site,
+                self.method,
+                self.path,
+                self.login_token,
+                self.headers,
+            );
+            request.set_header(
+                http::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            );
+            let request = request.body(body).unwrap();
+            let response = fake_response(&mut srv, request);
+            // will be printed only on errors
+            println!("The response was: {:?}", response);
+            assert_eq!(response.status(), self.status);
+            get_json_body(&response)
+        }
+
         pub fn form<F, T>(self: Self, form: F) -> T
         where
             T: serde::de::DeserializeOwned,
@@ -297,13 +644,14 @@ pub mod test_utils {
             T: serde::de::DeserializeOwned,
             F: serde::Serialize,
         {
-            let mut srv = get_test_server();
+            let mut srv = get_test_server(&self.state);
             let mut request = fake_request(
                 &srv,
                 self.site,
                 self.method,
                 self.path,
                 self.login_token,
+                self.headers,
             );
             let request = request.form(form).unwrap();
             let response = fake_response(&mut srv, request);
@@ -314,19 +662,28 @@ pub mod test_utils {
         }
     }
 
-    fn get_test_server() -> TestServer {
-        let state = super::State::new();
+    /// A server of the app with the given state.
+    pub fn test_server(state: super::State) -> TestServer {
         TestServer::with_factory(move || {
             create_app(&PathBuf::new().join("/tmp"), state.clone())
         })
     }
 
+    fn get_test_server(state: &Option<super::State>) -> TestServer {
+        test_server(
+            state
+                .clone()
+                .unwrap_or_else(|| super::State::new(crate::config::get())),
+        )
+    }
+
     fn fake_request(
         srv: &TestServer,
         site: &FakeSite,
         method: http::Method,
         path: &str,
         login_token: Option<String>,
+        headers: Vec<(&'static str, String)>,
     ) -> ClientRequestBuilder {
         let mut client = srv.client(method, path);
         client.set_header(
@@ -334,6 +691,11 @@ pub mod test_utils {
             http::header::HeaderValue::from_str(&site.site.domain)
                 .expect("The domain is not valid"),
         );
+        // like a browser on a page of the site
+        client.set_header("Origin", format!("https://{}", site.site.domain));
+        for (name, value) in headers {
+            client.set_header(name, value);
+        }
         if login_token.is_some() {
             client.cookie(
                 Cookie::build(AUTH_COOKIE, login_token.unwrap())
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::middleware::{Middleware, Started};
+use actix_web::{
+    error::ResponseError, Error, FromRequest, HttpRequest, HttpResponse, Result,
+};
+use failure::Fail;
+use futures::future::Future;
+use log::warn;
+
+use crate::config::RateLimit;
+use crate::models::User;
+
+use super::csrf::is_safe_method;
+use super::extractors::client_ip;
+use super::State;
+
+/// Maximum number of buckets of a route kept in memory.
+const MAX_BUCKETS: usize = 10_000;
+/// When the buckets are too many, the full ones are dropped, since they are
+/// the same as new ones, and then the least recently used down to this
+/// number, so that they are not scanned again at every request.
+const PRUNED_BUCKETS: usize = MAX_BUCKETS * 9 / 10;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Add the tokens earned since the last update, up to the burst.
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let rate = f64::from(limit.per_minute) / 60.0;
+        let elapsed = now.duration_since(self.updated);
+        let elapsed =
+            elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        self.tokens =
+            (self.tokens + elapsed * rate).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+
+    /// How long to wait for the next token, if there is none now.
+    fn wait(&self, limit: &RateLimit) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            let rate = f64::from(limit.per_minute) / 60.0;
+            Some(Duration::from_secs(
+                ((1.0 - self.tokens) / rate).ceil() as u64
+            ))
+        }
+    }
+
+    /// Take a token, or tell how long to wait for the next one.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        self.refill(limit, now);
+        let wait = self.wait(limit);
+        if wait.is_none() {
+            self.tokens -= 1.0;
+        }
+        wait
+    }
+
+    fn is_full(&self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs() as f64;
+        self.tokens + elapsed * f64::from(limit.per_minute) / 60.0
+            >= f64::from(limit.burst)
+    }
+}
+
+/// The routes sharing a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limited {
+    Login,
+    Lookup,
+    Submit,
+}
+
+impl Limited {
+    fn limit<'a>(&self, state: &'a State) -> &'a RateLimit {
+        let config = &state.config.rate_limit;
+        match self {
+            Limited::Login => &config.login,
+            Limited::Lookup => &config.lookup,
+            Limited::Submit => &config.submit,
+        }
+    }
+}
+
+/// The token buckets of the clients, shared by all the workers. Every route
+/// has buckets of its own, so that the clients of one cannot evict the buckets
+/// of the others.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<Limited, HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    /// Take a token from the bucket of every key in order, or tell how long
+    /// to wait before the request is allowed. The keys after the first one
+    /// refusing the request are not charged.
+    fn check(
+        &self,
+        limited: Limited,
+        limit: &RateLimit,
+        keys: Vec<String>,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut routes = self.buckets.lock().unwrap();
+        let buckets = routes.entry(limited).or_insert_with(HashMap::new);
+        for key in keys {
+            if !buckets.contains_key(&key) && buckets.len() >= MAX_BUCKETS {
+                prune(buckets, limit, now);
+            }
+            let wait = buckets
+                .entry(key)
+                .or_insert_with(|| Bucket {
+                    tokens: f64::from(limit.burst),
+                    updated: now,
+                })
+                .take(limit, now);
+            if wait.is_some() {
+                return wait;
+            }
+        }
+        None
+    }
+
+    /// Tell how long to wait for a token of the key, without taking it.
+    fn peek(
+        &self,
+        limited: Limited,
+        limit: &RateLimit,
+        key: &str,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut routes = self.buckets.lock().unwrap();
+        let bucket = routes.get_mut(&limited)?.get_mut(key)?;
+        bucket.refill(limit, now);
+        bucket.wait(limit)
+    }
+}
+
+/// Drop the full buckets of a route and then the least recently used ones,
+/// down to PRUNED_BUCKETS.
+fn prune(
+    buckets: &mut HashMap<String, Bucket>,
+    limit: &RateLimit,
+    now: Instant,
+) {
+    buckets.retain(|_, bucket| !bucket.is_full(limit, now));
+    if buckets.len() <= PRUNED_BUCKETS {
+        return;
+    }
+    let mut oldest: Vec<_> = buckets
+        .iter()
+        .map(|(key, bucket)| (bucket.updated, key.clone()))
+        .collect();
+    oldest.sort_by_key(|(updated, _)| *updated);
+    let excess = buckets.len() - PRUNED_BUCKETS;
+    for (_, key) in oldest.into_iter().take(excess) {
+        buckets.remove(&key);
+    }
+}
+
+/// Refuse the request if the failures on a key known only to the handler,
+/// like the username of a login, used up its bucket. Only the failures are
+/// charged, with `record_failure`, so that the others cannot lock the key
+/// out.
+pub fn check_failures(
+    state: &State,
+    limited: Limited,
+    key: &str,
+) -> Result<(), Error> {
+    if !state.config.rate_limit.enabled {
+        return Ok(());
+    }
+    let limit = limited.limit(state);
+    match state.rate_limiter.peek(limited, limit, key, Instant::now()) {
+        None => Ok(()),
+        Some(retry_after) => {
+            warn!("Rate limited {:?} for {}", limited, key);
+            Err(TooManyRequests { retry_after }.into())
+        }
+    }
+}
+
+/// Charge a failure to the bucket of a key checked with `check_failures`.
+pub fn record_failure(state: &State, limited: Limited, key: String) {
+    if !state.config.rate_limit.enabled {
+        return;
+    }
+    let limit = limited.limit(state);
+    state
+        .rate_limiter
+        .check(limited, limit, vec![key], Instant::now());
+}
+
+#[derive(Debug)]
+struct TooManyRequests {
+    retry_after: Duration,
+}
+
+impl Display for TooManyRequests {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Too many requests")
+    }
+}
+
+impl Fail for TooManyRequests {}
+
+impl ResponseError for TooManyRequests {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, self.retry_after.as_secs().to_string())
+            .finish()
+    }
+}
+
+/// Middleware limiting the requests to a route, per IP and, for the
+/// submissions, per logged in user. The user is looked up only for the
+/// requests allowed for their IP. Only the requests with side effects count
+/// for the submissions, the other routes have a single method. The failed
+/// logins are limited per username too, by their handler.
+pub struct RateLimitMiddleware(pub Limited);
+
+impl Middleware<State> for RateLimitMiddleware {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        let state = req.state();
+        if !state.config.rate_limit.enabled {
+            return Ok(Started::Done);
+        }
+        if self.0 == Limited::Submit && is_safe_method(req.method()) {
+            return Ok(Started::Done);
+        }
+        let ip = client_ip(req).map(|ip| ip.to_string()).unwrap_or_default();
+        let limit = self.0.limit(state);
+        let keys = vec![format!("ip:{}", ip)];
+        if let Some(retry_after) =
+            state
+                .rate_limiter
+                .check(self.0, limit, keys, Instant::now())
+        {
+            warn!("Rate limited {:?} {} from {}", self.0, req.path(), ip);
+            return Err(TooManyRequests { retry_after }.into());
+        }
+        if self.0 != Limited::Submit {
+            return Ok(Started::Done);
+        }
+        let limited = self.0;
+        let limit = limit.clone();
+        let limiter = state.rate_limiter.clone();
+        let path = req.path().to_string();
+        // the invalid credentials are refused by the handler itself
+        Ok(Started::Future(Box::new(User::extract(req).then(
+            move |user| {
+                let user = match user {
+                    Ok(user) => user,
+                    Err(_) => return Ok(None),
+                };
+                let keys = vec![format!("user:{}", user.id)];
+                match limiter.check(limited, &limit, keys, Instant::now()) {
+                    None => Ok(None),
+                    Some(retry_after) => {
+                        warn!(
+                            "Rate limited {:?} {} for user {}",
+                            limited, path, user.id
+                        );
+                        Err(TooManyRequests { retry_after }.into())
+                    }
+                }
+            },
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::{Method, StatusCode};
+
+    use crate::test_utils::FakeSite;
+    use crate::web::endpoints::user::LoginForm;
+    use crate::web::test_utils::{get_json_body, TestRequestBuilder};
+    use crate::web::ErrorResponse;
+
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let limit = RateLimit {
+            burst: 2,
+            per_minute: 60,
+        };
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        let check = |key: &str, after| {
+            limiter.check(
+                Limited::Submit,
+                &limit,
+                vec!["ip:1".to_string(), key.to_string()],
+                now + Duration::from_millis(after),
+            )
+        };
+        assert_eq!(check("user:a", 0), None);
+        assert_eq!(check("user:b", 0), None);
+        // the IP is out of tokens, the next one comes in a second
+        assert_eq!(check("user:c", 0), Some(Duration::from_secs(1)));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets[&Limited::Submit].contains_key("user:c"));
+        drop(buckets);
+        assert_eq!(check("user:a", 500), Some(Duration::from_secs(1)));
+        assert_eq!(check("user:a", 1000), None);
+        // after a long time the bucket is full, but not above the burst
+        assert_eq!(check("user:a", 100_000), None);
+        assert_eq!(check("user:a", 100_000), None);
+        assert!(check("user:a", 100_000).is_some());
+    }
+
+    #[test]
+    fn capped() {
+        let limit = RateLimit {
+            burst: 2,
+            per_minute: 1,
+        };
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        limiter.check(Limited::Login, &limit, vec!["ip:1".into()], now);
+        for i in 0..MAX_BUCKETS + 10 {
+            let after = now + Duration::from_millis(i as u64);
+            limiter.check(Limited::Submit, &limit, vec![i.to_string()], after);
+            let buckets = limiter.buckets.lock().unwrap();
+            assert!(buckets[&Limited::Submit].len() <= MAX_BUCKETS);
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        // the least recently used are dropped
+        assert!(!buckets[&Limited::Submit].contains_key("0"));
+        let last = (MAX_BUCKETS + 9).to_string();
+        assert!(buckets[&Limited::Submit].contains_key(&last));
+        // the buckets of the other routes are kept
+        assert!(buckets[&Limited::Login].contains_key("ip:1"));
+    }
+
+    #[test]
+    fn peek() {
+        let limit = RateLimit {
+            burst: 1,
+            per_minute: 60,
+        };
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        let key = "username:1:user";
+        assert_eq!(limiter.peek(Limited::Login, &limit, key, now), None);
+        assert_eq!(limiter.peek(Limited::Login, &limit, key, now), None);
+        limiter.check(Limited::Login, &limit, vec![key.to_string()], now);
+        assert_eq!(
+            limiter.peek(Limited::Login, &limit, key, now),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    /// Send a login for each username, each one from a different address
+    /// as forwarded by the proxy.
+    fn forwarded_logins(
+        site: &FakeSite,
+        state: crate::web::State,
+        usernames: Vec<String>,
+    ) -> Vec<StatusCode> {
+        usernames
+            .into_iter()
+            .enumerate()
+            .map(|(i, username)| {
+                TestRequestBuilder::new(site, "/api/login")
+                    .method(Method::POST)
+                    .header("x-forwarded-for", &format!("10.0.0.{}", i))
+                    .state(state.clone())
+                    .repeat_form(LoginForm { username }, 1)
+                    .remove(0)
+                    .status()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn login_spoofed_address() {
+        let site = FakeSite::new();
+        let burst = crate::config::get().rate_limit.login.burst as usize;
+        let usernames = (0..=burst).map(|i| format!("user{}", i)).collect();
+        let state = crate::web::State::new(crate::config::get());
+        let statuses = forwarded_logins(&site, state, usernames);
+        assert!(statuses[..burst]
+            .iter()
+            .all(|s| *s != StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(statuses[burst], StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    /// A state trusting the proxy of the test requests.
+    fn proxied_state() -> crate::web::State {
+        let mut config = (*crate::config::get()).clone();
+        config.web.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        crate::web::State::new(Arc::new(config))
+    }
+
+    #[test]
+    fn login_per_username() {
+        let site = FakeSite::new();
+        let burst = crate::config::get().rate_limit.login.burst as usize;
+        let usernames = vec!["nobody".to_string(); burst + 1];
+        let statuses = forwarded_logins(&site, proxied_state(), usernames);
+        assert!(statuses[..burst]
+            .iter()
+            .all(|s| *s == StatusCode::NOT_FOUND));
+        assert_eq!(statuses[burst], StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn login_not_locked_out() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let burst = crate::config::get().rate_limit.login.burst as usize;
+        let usernames = vec![user.username.clone(); burst + 1];
+        let statuses = forwarded_logins(&site, proxied_state(), usernames);
+        assert!(statuses.iter().all(|s| *s == StatusCode::OK));
+    }
+
+    #[test]
+    fn login() {
+        let site = FakeSite::new();
+        let user = site.user("user");
+        let burst = crate::config::get().rate_limit.login.burst as usize;
+        let responses = TestRequestBuilder::new(&site, "/api/login")
+            .method(Method::POST)
+            .repeat_form(
+                LoginForm {
+                    username: user.username.clone(),
+                },
+                burst + 1,
+            );
+        assert!(responses[..burst]
+            .iter()
+            .all(|res| res.status() == StatusCode::OK));
+        let last = &responses[burst];
+        assert_eq!(last.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = last.headers()[RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0);
+        let err: ErrorResponse = get_json_body(last);
+        assert_eq!(err.error, "Too many requests");
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use actix_web::server::{Server, StopServer};
+use log::{info, warn};
+
+use crate::events::{CloseSessions, EventManager};
+use crate::shutdown::Shutdown;
+
+/// How often the running evaluations are checked while draining.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the killed evaluations are given to be put back in the queue.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Actor handling the termination signals: it stops accepting submissions,
+/// closes the websockets, waits for the running evaluations up to the timeout
+/// and then stops the server.
+pub struct ShutdownManager {
+    shutdown: Shutdown,
+    in_evaluation: Arc<Mutex<HashSet<i32>>>,
+    event_manager: Addr<EventManager>,
+    server: Addr<Server>,
+    timeout: Duration,
+    drain: Option<SpawnHandle>,
+}
+
+impl Actor for ShutdownManager {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        System::current()
+            .registry()
+            .get::<ProcessSignals>()
+            .do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl ShutdownManager {
+    pub fn new(
+        shutdown: Shutdown,
+        in_evaluation: Arc<Mutex<HashSet<i32>>>,
+        event_manager: Addr<EventManager>,
+        server: Addr<Server>,
+        timeout: Duration,
+    ) -> ShutdownManager {
+        ShutdownManager {
+            shutdown,
+            in_evaluation,
+            event_manager,
+            server,
+            timeout,
+            drain: None,
+        }
+    }
+
+    fn begin(&mut self, ctx: &mut Context<Self>) {
+        info!("Shutting down, waiting for the running evaluations");
+        self.shutdown.start();
+        self.event_manager.do_send(CloseSessions);
+
+        let deadline = Instant::now() + self.timeout;
+        let mut killed = false;
+        let drain = ctx.run_interval(DRAIN_CHECK_INTERVAL, move |act, ctx| {
+            let running = act.in_evaluation.lock().unwrap().len();
+            let now = Instant::now();
+            if running > 0 && now < deadline + KILL_GRACE {
+                if now >= deadline && !killed {
+                    warn!(
+                        "{} evaluations still running, killing them",
+                        running
+                    );
+                    act.shutdown.kill_children();
+                    killed = true;
+                }
+                return;
+            }
+            if running > 0 {
+                warn!("Stopping with {} evaluations still running", running);
+            }
+            act.stop_server(ctx);
+        });
+        self.drain = Some(drain);
+    }
+
+    fn stop_server(&mut self, ctx: &mut Context<Self>) {
+        if let Some(drain) = self.drain.take() {
+            ctx.cancel_future(drain);
+        }
+        info!("Stopping the server");
+        self.server
+            .send(StopServer { graceful: true })
+            .into_actor(self)
+            .then(|_, _, _| {
+                System::current().stop();
+                actix::fut::ok(())
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Handler<Signal> for ShutdownManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) {
+        match msg.0 {
+            SignalType::Int | SignalType::Term | SignalType::Quit => {
+                if self.shutdown.is_started() {
+                    warn!("Shutdown already in progress, stopping now");
+                    System::current().stop();
+                } else {
+                    self.begin(ctx);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,14 +1,19 @@
 use super::State;
 use crate::events::*;
 use actix::prelude::*;
+use actix_web::error::ErrorServiceUnavailable;
 use actix_web::{ws, Error, HttpRequest, HttpResponse};
-use log::{error, warn};
+use log::{debug, error, warn};
+use serde_derive::Serialize;
 use std::time::{Duration, Instant};
 
 /// How often heartbeat pings are sent
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Reason of the close frame sent when shutting down, telling the clients
+/// when to reconnect.
+const RECONNECT_HINT: &str = "{\"reconnect_after_ms\":5000}";
 
 struct UserEventSession {
     id: usize,
@@ -39,7 +44,9 @@ impl Actor for UserEventSession {
             .event_manager
             .send(Connect {
                 user_id: self.user_id,
-                rcp: addr.recipient(),
+                rcp: addr.clone().recipient(),
+                balloon_rcp: addr.clone().recipient(),
+                close_rcp: addr.recipient(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -76,9 +83,40 @@ impl Handler<Event> for UserEventSession {
     }
 }
 
+/// Balloons are wrapped so that clients can tell them apart from the
+/// submission events.
+#[derive(Serialize)]
+struct BalloonMessage<'a> {
+    balloon: &'a Balloon,
+}
+
+impl Handler<Balloon> for UserEventSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Balloon, ctx: &mut Self::Context) {
+        let json = serde_json::to_string(&BalloonMessage { balloon: &msg });
+        match json {
+            Ok(js) => ctx.text(js),
+            Err(e) => error!("Error during serialization: {}", e),
+        }
+    }
+}
+
+impl Handler<CloseSession> for UserEventSession {
+    type Result = ();
+
+    fn handle(&mut self, _: CloseSession, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: Some(RECONNECT_HINT.to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
 impl StreamHandler<ws::Message, ws::ProtocolError> for UserEventSession {
     fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
-        println!("WEBSOCKET MESSAGE: {:?}", msg);
+        debug!("Websocket message: {:?}", msg);
         match msg {
             ws::Message::Ping(msg) => {
                 self.hb = Instant::now();
@@ -104,6 +142,10 @@ pub fn events_handler(
     req: HttpRequest<State>,
     user: crate::models::User,
 ) -> Result<HttpResponse, Error> {
+    // the sessions were closed asking the clients to reconnect later
+    if req.state().shutdown.is_started() {
+        return Err(ErrorServiceUnavailable("The server is shutting down"));
+    }
     ws::start(
         &req,
         UserEventSession {
@@ -113,3 +155,61 @@ pub fn events_handler(
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use actix_web::http::{Cookie, StatusCode};
+    use futures::Stream;
+
+    use super::*;
+    use crate::test_utils::FakeSite;
+    use crate::web::extractors::AUTH_COOKIE;
+    use crate::web::test_utils::test_server;
+
+    #[test]
+    fn close_on_shutdown() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let state = State::new(crate::config::get());
+        let mut srv = test_server(state.clone());
+        let client = ws::Client::new(srv.url("/api/events"))
+            .header("Host", site.site.domain.clone())
+            .cookie(Cookie::new(AUTH_COOKIE, user.login_token.unwrap()));
+        let (reader, _writer) = srv.execute(client.connect()).unwrap();
+        // let the session register itself to the event manager
+        thread::sleep(Duration::from_millis(200));
+        state.event_manager.do_send(CloseSessions);
+        let (message, _) = srv
+            .execute(reader.into_future())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        match message {
+            Some(ws::Message::Close(Some(reason))) => {
+                assert_eq!(reason.code, ws::CloseCode::Restart);
+                assert_eq!(reason.description.unwrap(), RECONNECT_HINT);
+            }
+            other => panic!("Expected a close frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refuse_during_shutdown() {
+        let site = FakeSite::new();
+        let user = site.user("username");
+        let state = State::new(crate::config::get());
+        state.shutdown.start();
+        let mut srv = test_server(state);
+        let client = ws::Client::new(srv.url("/api/events"))
+            .header("Host", site.site.domain.clone())
+            .cookie(Cookie::new(AUTH_COOKIE, user.login_token.unwrap()));
+        match srv.execute(client.connect()) {
+            Err(ws::ClientError::InvalidResponseStatus(status)) => {
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            }
+            Err(e) => panic!("Expected a 503, got {}", e),
+            Ok(_) => panic!("Expected a 503, got a websocket"),
+        }
+    }
+}